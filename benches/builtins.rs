@@ -0,0 +1,158 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks of the native object and JSON builtins against their pure Rego
+//! equivalents, on large objects.
+//!
+//! The policy is compiled with `opa build` if it was not built yet with
+//! `make build-bench`. Without `opa`, only the native builtins are
+//! benchmarked.
+
+use std::{path::Path, process::Command};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use opa_wasm::{
+    builtins::impls::{json, object},
+    read_bundle, Policy, Runtime,
+};
+use serde_json::{json, Value};
+use wasmtime::{Config, Engine, Module, Store};
+
+const SIZES: &[usize] = &[100, 1_000, 10_000];
+
+const BUNDLE: &str = "benches/fixtures/object.rego.tar.gz";
+
+const ENTRYPOINTS: &[&str] = &["union_n", "filter", "remove", "subset"];
+
+fn input(size: usize) -> Value {
+    let object: serde_json::Map<_, _> = (0..size)
+        .map(|i| {
+            (
+                format!("key-{i}"),
+                json!({"index": i, "name": format!("value-{i}")}),
+            )
+        })
+        .collect();
+    let keys: Vec<_> = (0..size).step_by(2).map(|i| format!("key-{i}")).collect();
+    let sub: serde_json::Map<_, _> = object
+        .iter()
+        .step_by(2)
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+
+    json!({
+        "objects": [object.clone(), sub.clone()],
+        "object": object,
+        "keys": keys,
+        "sub": sub,
+    })
+}
+
+/// Compile the benchmark policy like `make build-bench` does, returning
+/// whether it is available
+fn build_bundle() -> bool {
+    if Path::new(BUNDLE).exists() {
+        return true;
+    }
+
+    let mut command = Command::new("opa");
+    command.args([
+        "build",
+        "benches/fixtures/object.rego",
+        "-t",
+        "wasm",
+        "-o",
+        BUNDLE,
+    ]);
+    for name in ENTRYPOINTS {
+        command.args(["-e", &format!("bench/{name}")]);
+        command.args(["-e", &format!("bench/{name}_native")]);
+    }
+
+    let error = match command.status() {
+        Ok(status) if status.success() => return true,
+        Ok(status) => format!("opa build exited with {status}"),
+        Err(e) => format!("could not run opa: {e}"),
+    };
+    eprintln!("{error}, only benchmarking the native builtins");
+    false
+}
+
+fn policy(rt: &tokio::runtime::Runtime) -> (Store<()>, Policy<opa_wasm::DefaultContext>) {
+    rt.block_on(async {
+        let module = read_bundle(BUNDLE).await.unwrap();
+
+        let mut config = Config::new();
+        config.async_support(true);
+        let engine = Engine::new(&config).unwrap();
+        let module = Module::new(&engine, module).unwrap();
+        let mut store = Store::new(&engine, ());
+
+        let runtime = Runtime::new(&mut store, &module).await.unwrap();
+        let policy = runtime.without_data(&mut store).await.unwrap();
+        (store, policy)
+    })
+}
+
+fn builtins(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let mut policy = build_bundle().then(|| policy(&rt));
+
+    for &name in ENTRYPOINTS {
+        let mut group = c.benchmark_group(name);
+
+        for &size in SIZES {
+            let input = input(size);
+
+            group.bench_with_input(BenchmarkId::new("native", size), &input, |b, input| {
+                b.iter(|| match name {
+                    "union_n" => {
+                        object::union_n(input["objects"].as_array().unwrap().clone()).unwrap()
+                    }
+                    "filter" => json::filter(
+                        input["object"].clone(),
+                        input["keys"].as_array().unwrap().clone(),
+                    )
+                    .unwrap(),
+                    "remove" => json::remove(
+                        input["object"].clone(),
+                        input["keys"].as_array().unwrap().clone(),
+                    )
+                    .unwrap(),
+                    _ => object::subset(input["object"].clone(), input["sub"].clone()).into(),
+                });
+            });
+
+            let Some((store, policy)) = &mut policy else {
+                continue;
+            };
+            for (id, entrypoint) in [
+                ("policy-native", format!("bench/{name}_native")),
+                ("policy-rego", format!("bench/{name}")),
+            ] {
+                group.bench_with_input(BenchmarkId::new(id, size), &input, |b, input| {
+                    b.iter(|| {
+                        rt.block_on(policy.evaluate::<_, Value, _>(&mut *store, &entrypoint, input))
+                            .unwrap()
+                    });
+                });
+            }
+        }
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, builtins);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+package bench
+
+import future.keywords.every
+import future.keywords.if
+import future.keywords.in
+
+# Pure Rego equivalents of the native builtins, used as a baseline
+
+union_n := {k: v |
+	some object in input.objects
+	some k, _ in object
+	values := [x | some o in input.objects; x := o[k]]
+	v := values[count(values) - 1]
+}
+
+union_n_native := object.union_n(input.objects)
+
+filter := {k: v |
+	some k in input.keys
+	v := input.object[k]
+}
+
+filter_native := json.filter(input.object, input.keys)
+
+remove := {k: v |
+	some k, v in input.object
+	not k in {key | some key in input.keys}
+}
+
+remove_native := json.remove(input.object, input.keys)
+
+subset if {
+	every k, v in input.sub {
+		input.object[k] == v
+	}
+}
+
+subset_native := object.subset(input.object, input.sub)
@@ -0,0 +1,86 @@
+/* Generated with cbindgen from src/capi.rs, run `make capi-header` to update */
+
+#ifndef OPA_WASM_H
+#define OPA_WASM_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A loaded policy, with its own store.
+ *
+ * A policy must not be evaluated from multiple threads at the same time.
+ */
+typedef struct OpaWasmPolicy OpaWasmPolicy;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Load a policy from a compiled WASM module, with a JSON data document.
+ *
+ * `data_json` may be `NULL` to load the policy without data. The returned
+ * policy must be freed with [`opa_wasm_free`].
+ *
+ * # Safety
+ *
+ * `wasm` must point to `wasm_len` readable bytes, and `data_json` must be
+ * `NULL` or point to a NUL-terminated string.
+ */
+OpaWasmPolicy *opa_wasm_load(const uint8_t *wasm, uintptr_t wasm_len, const char *data_json);
+
+/**
+ * Evaluate an entrypoint of the policy with a JSON input document, returning
+ * the JSON-encoded result set.
+ *
+ * `input_json` may be `NULL` to evaluate without input. The returned string
+ * must be freed with [`opa_wasm_string_free`].
+ *
+ * # Safety
+ *
+ * `policy` must have been returned by [`opa_wasm_load`] and not freed yet,
+ * and not be used by another thread concurrently. `entrypoint` must point to
+ * a NUL-terminated string, and `input_json` must be `NULL` or point to a
+ * NUL-terminated string.
+ */
+char *opa_wasm_eval_json(OpaWasmPolicy *policy, const char *entrypoint, const char *input_json);
+
+/**
+ * Free a policy returned by [`opa_wasm_load`]. Does nothing if `policy` is
+ * `NULL`.
+ *
+ * # Safety
+ *
+ * `policy` must be `NULL` or have been returned by [`opa_wasm_load`], and
+ * not be freed already.
+ */
+void opa_wasm_free(OpaWasmPolicy *policy);
+
+/**
+ * Free a string returned by [`opa_wasm_eval_json`]. Does nothing if `string`
+ * is `NULL`.
+ *
+ * # Safety
+ *
+ * `string` must be `NULL` or have been returned by [`opa_wasm_eval_json`],
+ * and not be freed already.
+ */
+void opa_wasm_string_free(char *string);
+
+/**
+ * The message of the last error which happened on this thread, or `NULL` if
+ * there was none.
+ *
+ * The message is owned by the library, and is valid until the next call to
+ * a function of this library on the same thread.
+ */
+const char *opa_wasm_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* OPA_WASM_H */
@@ -0,0 +1,295 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A Kubernetes validating and mutating admission webhook backed by a policy
+
+use std::sync::Arc;
+
+use axum::{extract::State, routing::post, Json, Router};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{EvaluationContext, PolicyPool, ResultSet};
+
+/// The `AdmissionReview` sent by the API server, and sent back with a
+/// response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionReview {
+    /// The API version of the review, usually `admission.k8s.io/v1`
+    pub api_version: String,
+
+    /// Always `AdmissionReview`
+    pub kind: String,
+
+    /// The request, sent by the API server. This is left as raw JSON, as
+    /// the policy gets the whole review as input.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
+
+    /// The response, sent back by the webhook
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<AdmissionResponse>,
+}
+
+/// The response of an [`AdmissionReview`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionResponse {
+    /// The UID of the request
+    pub uid: String,
+
+    /// Whether the request is admitted
+    pub allowed: bool,
+
+    /// Why the request was denied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<AdmissionStatus>,
+
+    /// Warnings returned to the client
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+
+    /// Always `JSONPatch` when a patch is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patch_type: Option<String>,
+
+    /// The base64-encoded JSON patch to apply to the object
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patch: Option<String>,
+}
+
+/// The status of a denied [`AdmissionResponse`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdmissionStatus {
+    /// The HTTP status code returned to the client
+    pub code: u16,
+
+    /// The message returned to the client
+    pub message: String,
+}
+
+fn denied(uid: String, code: u16, message: String) -> AdmissionResponse {
+    AdmissionResponse {
+        uid,
+        allowed: false,
+        status: Some(AdmissionStatus { code, message }),
+        ..AdmissionResponse::default()
+    }
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|value| {
+            value
+                .as_str()
+                .map_or_else(|| value.to_string(), ToOwned::to_owned)
+        })
+        .collect()
+}
+
+/// Build the admission response from the policy result, which can be:
+///
+///  - a boolean, admitting the request or not
+///  - a list of violation messages, denying the request when not empty
+///  - an object with an `allowed` field, and optional `message`, `code`,
+///    `warnings` and `patch` (a list of JSON patch operations) fields
+fn response(uid: String, result: &Value) -> AdmissionResponse {
+    let empty = Map::new();
+    let decision = match result {
+        Value::Bool(true) => &empty,
+        Value::Array(violations) if violations.is_empty() => &empty,
+        Value::Array(_) => {
+            let message = strings(Some(result)).join(", ");
+            return denied(uid, 403, message);
+        }
+        Value::Object(decision) if decision.get("allowed") == Some(&Value::Bool(true)) => decision,
+        Value::Object(decision) => {
+            let code = decision
+                .get("code")
+                .and_then(Value::as_u64)
+                .and_then(|code| u16::try_from(code).ok())
+                .unwrap_or(403);
+            let message = decision
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("denied by policy")
+                .to_owned();
+            let mut response = denied(uid, code, message);
+            response.warnings = strings(decision.get("warnings"));
+            return response;
+        }
+        _ => return denied(uid, 403, "denied by policy".to_owned()),
+    };
+
+    let patch = decision
+        .get("patch")
+        .filter(|patch| patch.as_array().is_some_and(|ops| !ops.is_empty()))
+        .map(|patch| STANDARD.encode(patch.to_string()));
+
+    AdmissionResponse {
+        uid,
+        allowed: true,
+        status: None,
+        warnings: strings(decision.get("warnings")),
+        patch_type: patch.as_ref().map(|_| "JSONPatch".to_owned()),
+        patch,
+    }
+}
+
+/// An admission webhook evaluating a policy entrypoint for each
+/// [`AdmissionReview`], with the whole review as input.
+///
+/// Requests fail closed: if the evaluation fails, the request is denied.
+pub struct AdmissionWebhook<C> {
+    pool: Arc<PolicyPool<C>>,
+    entrypoint: String,
+}
+
+impl<C> std::fmt::Debug for AdmissionWebhook<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdmissionWebhook")
+            .field("entrypoint", &self.entrypoint)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: EvaluationContext> AdmissionWebhook<C> {
+    /// Review requests with the given entrypoint, evaluated on the instances
+    /// of the pool
+    #[must_use]
+    pub fn new(pool: Arc<PolicyPool<C>>, entrypoint: impl Into<String>) -> Self {
+        Self {
+            pool,
+            entrypoint: entrypoint.into(),
+        }
+    }
+
+    /// Review a request, returning the review with its response
+    pub async fn review(&self, mut review: AdmissionReview) -> AdmissionReview {
+        let uid = review
+            .request
+            .as_ref()
+            .and_then(|request| request.get("uid"))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned();
+
+        let response = match self.evaluate(&review).await {
+            Ok(result) => response(uid, &result),
+            Err(e) => {
+                tracing::error!(
+                    error = &e as &dyn std::error::Error,
+                    "failed to evaluate the policy"
+                );
+                denied(uid, 500, "failed to evaluate the policy".to_owned())
+            }
+        };
+
+        review.request = None;
+        review.response = Some(response);
+        review
+    }
+
+    async fn evaluate(&self, review: &AdmissionReview) -> Result<Value, crate::Error> {
+        let results: ResultSet = self.pool.evaluate(&self.entrypoint, review).await?;
+
+        // An undefined result denies the request
+        Ok(results.into_optional()?.unwrap_or(Value::Null))
+    }
+
+    /// A router serving the webhook on the given path
+    pub fn router(self, path: &str) -> Router {
+        Router::new()
+            .route(path, post(handler::<C>))
+            .with_state(Arc::new(self))
+    }
+}
+
+async fn handler<C: EvaluationContext>(
+    State(webhook): State<Arc<AdmissionWebhook<C>>>,
+    Json(review): Json<AdmissionReview>,
+) -> Json<AdmissionReview> {
+    Json(webhook.review(review).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn admission_response() {
+        let res = response("1".to_owned(), &json!(true));
+        assert!(res.allowed);
+        assert_eq!(res.patch, None);
+
+        let res = response("1".to_owned(), &json!(["missing label", "bad image"]));
+        assert!(!res.allowed);
+        assert_eq!(res.status.unwrap().message, "missing label, bad image");
+
+        let res = response(
+            "1".to_owned(),
+            &json!({
+                "allowed": true,
+                "warnings": ["deprecated field"],
+                "patch": [{"op": "add", "path": "/metadata/labels/team", "value": "a"}],
+            }),
+        );
+        assert!(res.allowed);
+        assert_eq!(res.warnings, ["deprecated field"]);
+        assert_eq!(res.patch_type.as_deref(), Some("JSONPatch"));
+        let patch = STANDARD.decode(res.patch.unwrap()).unwrap();
+        let patch: Value = serde_json::from_slice(&patch).unwrap();
+        assert_eq!(patch[0]["path"], "/metadata/labels/team");
+
+        let res = response(
+            "1".to_owned(),
+            &json!({"allowed": false, "code": 422, "message": "invalid"}),
+        );
+        assert_eq!(
+            res.status,
+            Some(AdmissionStatus {
+                code: 422,
+                message: "invalid".to_owned()
+            })
+        );
+
+        let res = response("1".to_owned(), &Value::Null);
+        assert!(!res.allowed);
+    }
+
+    #[test]
+    fn review_serialization() {
+        let review = AdmissionReview {
+            api_version: "admission.k8s.io/v1".to_owned(),
+            kind: "AdmissionReview".to_owned(),
+            request: None,
+            response: Some(response("abc".to_owned(), &json!(true))),
+        };
+        assert_eq!(
+            serde_json::to_value(review).unwrap(),
+            json!({
+                "apiVersion": "admission.k8s.io/v1",
+                "kind": "AdmissionReview",
+                "response": {"uid": "abc", "allowed": true},
+            })
+        );
+    }
+}
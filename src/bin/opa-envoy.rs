@@ -0,0 +1,109 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(clippy::pedantic)]
+
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use camino::Utf8PathBuf;
+use clap::{ArgGroup, Parser};
+use opa_wasm::{
+    envoy::{AuthorizationServer, ExtAuthz},
+    DefaultContext, PolicyPool,
+};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Registry};
+use wasmtime::{Config, Engine, Module};
+
+/// Serves the Envoy external authorization gRPC API with an OPA policy
+/// compiled as a WASM module
+#[derive(Parser)]
+#[clap(group(
+    ArgGroup::new("policy")
+        .required(true)
+))]
+struct Cli {
+    /// Path to the WASM module
+    #[arg(short, long, group = "policy")]
+    module: Option<Utf8PathBuf>,
+
+    /// Path to the OPA bundle
+    #[arg(short, long, group = "policy")]
+    bundle: Option<Utf8PathBuf>,
+
+    /// Entrypoint to evaluate for each check request
+    #[arg(short, long, default_value = "envoy/authz/allow")]
+    entrypoint: String,
+
+    /// Path to a JSON file to load as data
+    #[arg(short = 'D', long, value_name = "PATH")]
+    data_path: Option<Utf8PathBuf>,
+
+    /// Address to listen on
+    #[arg(short, long, default_value = "0.0.0.0:9191")]
+    addr: SocketAddr,
+
+    /// Number of policy instances evaluating requests concurrently
+    #[arg(short, long, default_value_t = 4)]
+    instances: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    Registry::default()
+        .with(tracing_subscriber::fmt::layer())
+        .with(EnvFilter::from_default_env())
+        .init();
+
+    let cli = Cli::parse();
+
+    let data = if let Some(path) = cli.data_path {
+        let content = tokio::fs::read(path).await?;
+        serde_json::from_slice(&content)?
+    } else {
+        serde_json::Value::Object(serde_json::Map::default())
+    };
+
+    let module = if let Some(path) = cli.module {
+        tokio::fs::read(path).await?
+    } else if let Some(path) = cli.bundle {
+        opa_wasm::read_bundle(path).await?
+    } else {
+        // This should be enforced by clap
+        unreachable!()
+    };
+
+    let mut config = Config::new();
+    config.async_support(true);
+    let engine = Engine::new(&config)?;
+    let module = Module::new(&engine, module)?;
+
+    let pool = PolicyPool::new(
+        &engine,
+        &module,
+        &data,
+        cli.instances,
+        DefaultContext::default,
+    )
+    .await?;
+    let service = ExtAuthz::new(Arc::new(pool), cli.entrypoint);
+
+    tracing::info!(addr = %cli.addr, "serving the ext_authz API");
+    tonic::transport::Server::builder()
+        .add_service(AuthorizationServer::new(service))
+        .serve(cli.addr)
+        .await?;
+
+    Ok(())
+}
@@ -65,12 +65,12 @@ async fn main() -> Result<()> {
         .with(EnvFilter::from_default_env())
         .init();
 
-    let (data, input, module, entrypoint) = (async move {
+    let (data_path, data, input, module, entrypoint) = (async move {
         let cli = Cli::parse();
 
-        let data = if let Some(path) = cli.data_path {
-            let content = tokio::fs::read(path).await?;
-            serde_json::from_slice(&content)?
+        // Data files are streamed to the policy memory when loading it
+        let data = if cli.data_path.is_some() {
+            serde_json::Value::Null
         } else if let Some(data) = cli.data_value {
             data
         } else {
@@ -98,7 +98,7 @@ async fn main() -> Result<()> {
         };
 
         let entrypoint = cli.entrypoint;
-        Ok::<_, anyhow::Error>((data, input, module, entrypoint))
+        Ok::<_, anyhow::Error>((cli.data_path, data, input, module, entrypoint))
     })
     .instrument(tracing::info_span!("load_args"))
     .await?;
@@ -125,10 +125,17 @@ async fn main() -> Result<()> {
         .instrument(tracing::info_span!("instanciate_module"))
         .await?;
 
-    let policy = runtime
-        .with_data(&mut store, &data)
-        .instrument(tracing::info_span!("load_data"))
-        .await?;
+    let policy = if let Some(path) = data_path {
+        runtime
+            .with_data_file(&mut store, path)
+            .instrument(tracing::info_span!("load_data"))
+            .await?
+    } else {
+        runtime
+            .with_data(&mut store, &data)
+            .instrument(tracing::info_span!("load_data"))
+            .await?
+    };
 
     // Evaluate the policy
     let res: serde_json::Value = policy
@@ -0,0 +1,108 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `bench` subcommand
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use clap::Args;
+use wasmtime::Config;
+
+use crate::PolicyOptions;
+
+/// Amount of fuel given to each evaluation. This is not meant as a limit, only
+/// to measure how much of it gets consumed.
+const FUEL: u64 = 1 << 62;
+
+/// Size of a WebAssembly page, in bytes
+const PAGE_SIZE: u64 = 64 * 1024;
+
+#[derive(Args)]
+pub struct Options {
+    #[command(flatten)]
+    policy: PolicyOptions,
+
+    /// Number of measured evaluations
+    #[arg(short = 'n', long, default_value_t = 1000)]
+    iterations: u32,
+
+    /// Number of evaluations to run before measuring
+    #[arg(short, long, default_value_t = 100)]
+    warmup: u32,
+}
+
+/// Get the value at the given percentile of a sorted list, using the
+/// nearest-rank method
+fn percentile(sorted: &[Duration], percentile: usize) -> Duration {
+    let rank = (sorted.len() * percentile + 99) / 100;
+    sorted[rank.saturating_sub(1)]
+}
+
+pub async fn run(options: &Options, mut config: Config) -> Result<()> {
+    anyhow::ensure!(options.iterations > 0, "iterations must be positive");
+
+    config.consume_fuel(true);
+    let mut loaded = options.policy.load(&config, Some(FUEL)).await?;
+    let initial_pages = loaded.policy.memory_pages(&loaded.store);
+
+    for _ in 0..options.warmup {
+        let _: (serde_json::Value, u64) = loaded
+            .policy
+            .evaluate_with_fuel(&mut loaded.store, &loaded.entrypoint, &loaded.input, FUEL)
+            .await?;
+    }
+
+    let warm_pages = loaded.policy.memory_pages(&loaded.store);
+    let mut latencies = Vec::with_capacity(options.iterations as usize);
+    let mut total_fuel: u128 = 0;
+
+    for _ in 0..options.iterations {
+        let start = Instant::now();
+        let (_, fuel): (serde_json::Value, u64) = loaded
+            .policy
+            .evaluate_with_fuel(&mut loaded.store, &loaded.entrypoint, &loaded.input, FUEL)
+            .await?;
+        latencies.push(start.elapsed());
+        total_fuel += u128::from(fuel);
+    }
+
+    let final_pages = loaded.policy.memory_pages(&loaded.store);
+    latencies.sort_unstable();
+
+    let total: Duration = latencies.iter().sum();
+
+    println!(
+        "entrypoint: {} ({} iterations, {} warmup)",
+        loaded.entrypoint, options.iterations, options.warmup
+    );
+    println!(
+        "latency:    mean {:?}, p50 {:?}, p99 {:?}",
+        total / options.iterations,
+        percentile(&latencies, 50),
+        percentile(&latencies, 99),
+    );
+    println!(
+        "fuel:       {} instructions per evaluation",
+        total_fuel / u128::from(options.iterations)
+    );
+    println!(
+        "heap:       {} KiB initially, +{} KiB during warmup, +{} KiB during measurement",
+        initial_pages * PAGE_SIZE / 1024,
+        warm_pages.saturating_sub(initial_pages) * PAGE_SIZE / 1024,
+        final_pages.saturating_sub(warm_pages) * PAGE_SIZE / 1024,
+    );
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `eval` subcommand
+
+use anyhow::Result;
+use clap::{Args, ValueEnum};
+use wasmtime::Config;
+
+use crate::PolicyOptions;
+
+/// How the result is printed
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum Format {
+    /// The whole result set, as compact JSON
+    #[default]
+    Json,
+
+    /// The whole result set, as indented JSON
+    Pretty,
+
+    /// Only the values of the results, one per line, with strings printed
+    /// without quotes
+    Raw,
+}
+
+#[derive(Args)]
+pub struct Options {
+    #[command(flatten)]
+    policy: PolicyOptions,
+
+    /// How to print the result
+    #[arg(short, long, value_enum, default_value_t)]
+    format: Format,
+}
+
+fn format(result: &serde_json::Value, format: Format) -> Result<String> {
+    let output = match format {
+        Format::Json => serde_json::to_string(result)?,
+        Format::Pretty => serde_json::to_string_pretty(result)?,
+        Format::Raw => {
+            let values: Vec<String> = result
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.get("result"))
+                .map(|value| match value {
+                    serde_json::Value::String(s) => s.clone(),
+                    value => value.to_string(),
+                })
+                .collect();
+            values.join("\n")
+        }
+    };
+    Ok(output)
+}
+
+pub async fn run(options: &Options, config: &Config) -> Result<()> {
+    let mut loaded = options.policy.load(config, None).await?;
+    let result: serde_json::Value = loaded
+        .policy
+        .evaluate(&mut loaded.store, &loaded.entrypoint, &loaded.input)
+        .await?;
+
+    println!("{}", format(&result, options.format)?);
+    Ok(())
+}
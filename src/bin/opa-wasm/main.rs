@@ -0,0 +1,168 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![deny(clippy::pedantic)]
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{ArgGroup, Args, Parser, Subcommand};
+use opa_wasm::{Policy, Runtime};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Registry};
+use wasmtime::{Config, Engine, Module, Store};
+
+mod bench;
+mod eval;
+mod repl;
+
+/// Evaluates OPA policies compiled as WASM modules
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Evaluate an entrypoint and print its result
+    Eval(eval::Options),
+
+    /// Evaluate an entrypoint repeatedly and report performance statistics
+    Bench(bench::Options),
+
+    /// Evaluate an entrypoint interactively, re-evaluating it when the policy,
+    /// data or input files change
+    Repl(repl::Options),
+}
+
+/// Options to load a policy, shared by all subcommands
+#[derive(Args)]
+#[clap(group(
+    ArgGroup::new("policy")
+        .required(true)
+))]
+struct PolicyOptions {
+    /// Path to the WASM module
+    #[arg(short, long, group = "policy")]
+    module: Option<Utf8PathBuf>,
+
+    /// Path to the OPA bundle
+    #[arg(short, long, group = "policy")]
+    bundle: Option<Utf8PathBuf>,
+
+    /// Path to a JSON or YAML file to load as data
+    #[arg(short, long, value_name = "PATH")]
+    data: Option<Utf8PathBuf>,
+
+    /// Entrypoint to evaluate
+    #[arg(short, long)]
+    entrypoint: String,
+
+    /// Path to a JSON or YAML file to use as input
+    #[arg(short, long, value_name = "PATH")]
+    input: Option<Utf8PathBuf>,
+}
+
+/// A policy loaded with its input, ready to be evaluated
+struct Loaded {
+    store: Store<()>,
+    policy: Policy<opa_wasm::DefaultContext>,
+    entrypoint: String,
+    input: serde_json::Value,
+}
+
+/// Read a JSON or YAML document. YAML being a superset of JSON, files which
+/// are not explicitly JSON are parsed as YAML.
+async fn read_document(path: &Utf8Path) -> Result<serde_json::Value> {
+    let content = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("could not read {path}"))?;
+    let document = if path.extension() == Some("json") {
+        serde_json::from_slice(&content)?
+    } else {
+        serde_yaml::from_slice(&content)?
+    };
+    Ok(document)
+}
+
+impl PolicyOptions {
+    /// Read the input document, or an empty object if none was given
+    async fn read_input(&self) -> Result<serde_json::Value> {
+        match &self.input {
+            Some(path) => read_document(path).await,
+            None => Ok(serde_json::Value::Object(serde_json::Map::default())),
+        }
+    }
+
+    /// Path of the module or bundle
+    fn policy_path(&self) -> &Utf8Path {
+        self.module
+            .as_deref()
+            .or(self.bundle.as_deref())
+            // This should be enforced by clap
+            .expect("either a module or a bundle must be set")
+    }
+
+    /// Load the policy. If `fuel` is set, the store is given that amount of
+    /// fuel before instantiating the module, which requires fuel consumption
+    /// to be enabled in the `config`.
+    async fn load(&self, config: &Config, fuel: Option<u64>) -> Result<Loaded> {
+        let data = match &self.data {
+            Some(path) => read_document(path).await?,
+            None => serde_json::Value::Object(serde_json::Map::default()),
+        };
+
+        let input = self.read_input().await?;
+
+        let module = if self.bundle.is_some() {
+            opa_wasm::read_bundle(self.policy_path()).await?
+        } else {
+            tokio::fs::read(self.policy_path()).await?
+        };
+
+        let engine = Engine::new(config)?;
+        let module = Module::new(&engine, module)?;
+        let mut store = Store::new(&engine, ());
+        if let Some(fuel) = fuel {
+            store.set_fuel(fuel)?;
+        }
+        let runtime = Runtime::new(&mut store, &module).await?;
+        let policy = runtime.with_data(&mut store, &data).await?;
+
+        Ok(Loaded {
+            store,
+            policy,
+            entrypoint: self.entrypoint.clone(),
+            input,
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    Registry::default()
+        .with(tracing_forest::ForestLayer::default())
+        .with(EnvFilter::from_default_env())
+        .init();
+
+    let cli = Cli::parse();
+
+    let mut config = Config::new();
+    config.async_support(true);
+
+    match cli.command {
+        Command::Eval(options) => eval::run(&options, &config).await,
+        Command::Bench(options) => bench::run(&options, config).await,
+        Command::Repl(options) => repl::run(&options, &config).await,
+    }
+}
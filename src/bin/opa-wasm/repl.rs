@@ -0,0 +1,271 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The `repl` subcommand
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use camino::Utf8Path;
+use clap::Args;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use wasmtime::Config;
+
+use crate::{Loaded, PolicyOptions};
+
+const HELP: &str = "\
+commands:
+  <empty line>       evaluate the entrypoint
+  :entrypoint NAME   switch to another entrypoint
+  :entrypoints       list the entrypoints of the policy
+  :reload            reload the policy, data and input
+  :quit              exit";
+
+#[derive(Args)]
+pub struct Options {
+    #[command(flatten)]
+    policy: PolicyOptions,
+
+    /// Interval between checks for file changes, in milliseconds
+    #[arg(long, default_value_t = 500)]
+    interval: u64,
+}
+
+/// Get the modification time of a file, if it exists
+async fn modified(path: Option<&Utf8Path>) -> Option<SystemTime> {
+    let metadata = tokio::fs::metadata(path?).await.ok()?;
+    metadata.modified().ok()
+}
+
+/// Modification times of the watched files
+#[derive(PartialEq, Eq)]
+struct Snapshot {
+    policy: Option<SystemTime>,
+    data: Option<SystemTime>,
+    input: Option<SystemTime>,
+}
+
+impl Snapshot {
+    async fn take(options: &PolicyOptions) -> Self {
+        Self {
+            policy: modified(Some(options.policy_path())).await,
+            data: modified(options.data.as_deref()).await,
+            input: modified(options.input.as_deref()).await,
+        }
+    }
+}
+
+/// Compute the differences between two JSON values, as a list of removed
+/// (`-`) and added (`+`) values with their JSON pointer
+fn diff(path: &str, old: &Value, new: &Value, out: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old) in old {
+                let path = format!("{path}/{key}");
+                match new.get(key) {
+                    Some(new) => diff(&path, old, new, out),
+                    None => out.push(format!("- {path}: {old}")),
+                }
+            }
+
+            for (key, new) in new {
+                if !old.contains_key(key) {
+                    out.push(format!("+ {path}/{key}: {new}"));
+                }
+            }
+        }
+
+        (Value::Array(old), Value::Array(new)) if old.len() == new.len() => {
+            for (index, (old, new)) in old.iter().zip(new).enumerate() {
+                diff(&format!("{path}/{index}"), old, new, out);
+            }
+        }
+
+        (old, new) if old == new => {}
+
+        (old, new) => {
+            let path = if path.is_empty() { "/" } else { path };
+            out.push(format!("- {path}: {old}"));
+            out.push(format!("+ {path}: {new}"));
+        }
+    }
+}
+
+struct Repl<'a> {
+    options: &'a PolicyOptions,
+    config: &'a Config,
+    loaded: Option<Loaded>,
+    previous: Option<Value>,
+}
+
+impl Repl<'_> {
+    /// Reload the policy, keeping the currently selected entrypoint
+    async fn reload(&mut self) {
+        let entrypoint = self.loaded.take().map(|loaded| loaded.entrypoint);
+        match self.options.load(self.config, None).await {
+            Ok(mut loaded) => {
+                if let Some(entrypoint) = entrypoint {
+                    loaded.entrypoint = entrypoint;
+                }
+                self.loaded = Some(loaded);
+            }
+            Err(e) => eprintln!("error: could not load the policy: {e:#}"),
+        }
+    }
+
+    /// Reload the input, or the whole policy if it failed to load previously
+    async fn reload_input(&mut self) {
+        let Some(loaded) = &mut self.loaded else {
+            return self.reload().await;
+        };
+
+        match self.options.read_input().await {
+            Ok(input) => loaded.input = input,
+            Err(e) => eprintln!("error: could not load the input: {e:#}"),
+        }
+    }
+
+    /// Evaluate the entrypoint, printing the whole result on the first
+    /// evaluation and the differences with the previous result afterwards
+    async fn evaluate(&mut self) {
+        let Some(loaded) = &mut self.loaded else {
+            eprintln!("error: no policy loaded");
+            return;
+        };
+
+        let result: Value = match loaded
+            .policy
+            .evaluate(&mut loaded.store, &loaded.entrypoint, &loaded.input)
+            .await
+        {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("error: {e:#}");
+                return;
+            }
+        };
+
+        match self.previous.replace(result.clone()) {
+            Some(previous) => {
+                let mut changes = Vec::new();
+                diff("", &previous, &result, &mut changes);
+                if changes.is_empty() {
+                    println!("(no changes)");
+                }
+                for change in changes {
+                    println!("{change}");
+                }
+            }
+            None => match serde_json::to_string_pretty(&result) {
+                Ok(result) => println!("{result}"),
+                Err(e) => eprintln!("error: {e}"),
+            },
+        }
+    }
+
+    fn set_entrypoint(&mut self, entrypoint: &str) {
+        let Some(loaded) = &mut self.loaded else {
+            eprintln!("error: no policy loaded");
+            return;
+        };
+
+        if !loaded.policy.entrypoints().contains(entrypoint) {
+            eprintln!("error: unknown entrypoint {entrypoint:?}");
+            return;
+        }
+
+        loaded.entrypoint = entrypoint.to_owned();
+        // Results of different entrypoints are not comparable
+        self.previous = None;
+    }
+
+    fn list_entrypoints(&self) {
+        let Some(loaded) = &self.loaded else {
+            eprintln!("error: no policy loaded");
+            return;
+        };
+
+        let mut entrypoints: Vec<_> = loaded.policy.entrypoints().into_iter().collect();
+        entrypoints.sort_unstable();
+        for entrypoint in entrypoints {
+            let marker = if entrypoint == loaded.entrypoint {
+                '*'
+            } else {
+                ' '
+            };
+            println!("{marker} {entrypoint}");
+        }
+    }
+}
+
+pub async fn run(options: &Options, config: &Config) -> Result<()> {
+    let mut repl = Repl {
+        options: &options.policy,
+        config,
+        loaded: None,
+        previous: None,
+    };
+
+    let mut snapshot = Snapshot::take(&options.policy).await;
+    repl.reload().await;
+    repl.evaluate().await;
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut ticker = tokio::time::interval(Duration::from_millis(options.interval));
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let current = Snapshot::take(&options.policy).await;
+                if current == snapshot {
+                    continue;
+                }
+
+                if current.policy != snapshot.policy || current.data != snapshot.data {
+                    println!("policy or data changed, reloading");
+                    repl.reload().await;
+                } else {
+                    println!("input changed, reloading");
+                    repl.reload_input().await;
+                }
+
+                snapshot = current;
+                repl.evaluate().await;
+            }
+
+            line = lines.next_line() => {
+                // Exit on end of input
+                let Some(line) = line? else { break };
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next()) {
+                    (None, _) => repl.evaluate().await,
+                    (Some(":entrypoint"), Some(entrypoint)) => {
+                        repl.set_entrypoint(entrypoint);
+                        repl.evaluate().await;
+                    }
+                    (Some(":entrypoints"), None) => repl.list_entrypoints(),
+                    (Some(":reload"), None) => {
+                        repl.reload().await;
+                        repl.evaluate().await;
+                    }
+                    (Some(":quit"), None) => break,
+                    _ => println!("{HELP}"),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,141 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Synchronous versions of the loading and evaluation APIs
+//!
+//! The [`wasmtime::Store`] still needs to have async support enabled, but the
+//! futures are driven to completion on the current thread, without needing an
+//! async runtime. Builtins which rely on a Tokio runtime, like `http.send`,
+//! will fail unless called from within one.
+
+use std::{
+    future::Future,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::Thread,
+};
+
+use wasmtime::{AsContextMut, Module};
+
+use crate::{DefaultContext, Error, EvaluationContext, Policy, Runtime, RuntimeBuilder};
+
+/// Wakes the thread blocked on a future
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread, parking it while the
+/// future is pending
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+impl Runtime<DefaultContext> {
+    /// Load a new WASM policy module into the given store, blocking the
+    /// current thread. See [`Runtime::new`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Runtime::new`]
+    pub fn new_blocking<T: Send>(
+        store: impl AsContextMut<Data = T>,
+        module: &Module,
+    ) -> Result<Self, Error> {
+        block_on(Self::new(store, module))
+    }
+}
+
+impl<C: EvaluationContext> RuntimeBuilder<C> {
+    /// Load a new WASM policy module into the given store, blocking the
+    /// current thread. See [`RuntimeBuilder::build`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Runtime::new_with_evaluation_context`]
+    pub fn build_blocking<T: Send>(
+        self,
+        store: impl AsContextMut<Data = T>,
+        module: &Module,
+    ) -> Result<Runtime<C>, Error> {
+        block_on(self.build(store, module))
+    }
+}
+
+impl<C> Runtime<C> {
+    /// Load the policy with the given data, blocking the current thread. See
+    /// [`Runtime::with_data`].
+    ///
+    /// # Errors
+    ///
+    /// If it failed to serialize and load the `data` object
+    pub fn with_data_blocking<V: serde::Serialize, T: Send>(
+        self,
+        store: impl AsContextMut<Data = T>,
+        data: &V,
+    ) -> Result<Policy<C>, Error> {
+        block_on(self.with_data(store, data))
+    }
+}
+
+impl<C: EvaluationContext> Policy<C> {
+    /// Evaluate a policy with the given entrypoint and input, blocking the
+    /// current thread. See [`Policy::evaluate`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy evaluation failed, or if this policy did
+    /// not belong to the given store.
+    pub fn evaluate_blocking<V: serde::Serialize, R: for<'de> serde::Deserialize<'de>, T: Send>(
+        &self,
+        store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<R, Error> {
+        block_on(self.evaluate(store, entrypoint, input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_on_wakes_up() {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            tx.send(42).unwrap();
+        });
+
+        assert_eq!(block_on(rx).unwrap(), 42);
+        sender.join().unwrap();
+    }
+}
@@ -14,10 +14,38 @@
 
 //! Builtins related to base64 encoding and decoding
 
+use anyhow::Result;
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 
+/// Deserializes the base64url encoded input string.
+///
+/// The padding is optional, so both padded and unpadded inputs are accepted.
+#[tracing::instrument(name = "base64url.decode", err)]
+pub fn decode(x: String) -> Result<String> {
+    let decoded = URL_SAFE_NO_PAD.decode(x.trim_end_matches('='))?;
+    Ok(String::from_utf8(decoded)?)
+}
+
 /// Serializes the input string into base64url encoding without padding.
 #[tracing::instrument]
 pub fn encode_no_pad(x: String) -> String {
     URL_SAFE_NO_PAD.encode(&x)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(encode_no_pad("hello?".into()), "aGVsbG8_");
+        assert_eq!(decode("aGVsbG8_".into()).unwrap(), "hello?");
+
+        assert_eq!(encode_no_pad("a".into()), "YQ");
+        assert_eq!(decode("YQ".into()).unwrap(), "a");
+        assert_eq!(decode("YQ==".into()).unwrap(), "a");
+
+        assert!(decode("YQ=a".into()).is_err());
+        assert!(decode("/w==".into()).is_err());
+    }
+}
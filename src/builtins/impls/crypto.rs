@@ -27,6 +27,14 @@ pub mod hmac {
     use anyhow::Result;
     use hmac::{Hmac, Mac};
 
+    /// Returns a boolean representing the result of comparing two MACs for
+    /// equality without leaking timing information.
+    #[tracing::instrument(name = "crypto.hmac.equal", skip_all)]
+    pub fn equal(mac1: String, mac2: String) -> bool {
+        let (mac1, mac2) = (mac1.as_bytes(), mac2.as_bytes());
+        mac1.len() == mac2.len() && mac1.iter().zip(mac2).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+
     #[cfg(feature = "crypto-md5-builtins")]
     /// Returns a string representing the MD5 HMAC of the input message using
     /// the input key.
@@ -70,6 +78,26 @@ pub mod hmac {
         let res = mac.finalize();
         Ok(hex::encode(res.into_bytes()))
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn equal_macs() {
+            let mac = "0d4d6b3a4d3ce7e9c6e8b2b5d0c2b0d8f0f7e5a6b7a8d4b6f1c2e3d4a5b6c7d8";
+            assert!(equal(mac.into(), mac.into()));
+            assert!(equal(String::new(), String::new()));
+
+            // Same length, differing in the last byte only
+            let other = "0d4d6b3a4d3ce7e9c6e8b2b5d0c2b0d8f0f7e5a6b7a8d4b6f1c2e3d4a5b6c7d9";
+            assert!(!equal(mac.into(), other.into()));
+
+            // A prefix of the MAC is not equal to it
+            assert!(!equal(mac.into(), mac[..32].into()));
+            assert!(!equal(String::new(), mac.into()));
+        }
+    }
 }
 
 /// Builtins for computing hashes
@@ -117,47 +145,3 @@ pub mod digest {
         hex::encode(res)
     }
 }
-
-/// Builtins related to X509 certificates, keys and certificate requests parsing
-/// and validation
-pub mod x509 {
-    use std::collections::HashMap;
-
-    use anyhow::{bail, Result};
-
-    type X509 = HashMap<String, serde_json::Value>;
-    type Jwk = HashMap<String, serde_json::Value>;
-
-    /// Returns one or more certificates from the given string containing PEM or
-    /// base64 encoded DER certificates after verifying the supplied
-    /// certificates form a complete certificate chain back to a trusted
-    /// root.
-    ///
-    /// The first certificate is treated as the root and the last is treated as
-    /// the leaf, with all others being treated as intermediates.
-    #[tracing::instrument(name = "crypto.x509.parse_and_verify_certificates", err)]
-    pub fn parse_and_verify_certificates(certs: String) -> Result<(bool, Vec<X509>)> {
-        bail!("not implemented");
-    }
-
-    /// Returns a PKCS #10 certificate signing request from the given
-    /// PEM-encoded PKCS#10 certificate signing request.
-    #[tracing::instrument(name = "crypto.x509.parse_certificate_request", err)]
-    pub fn parse_certificate_request(csr: String) -> Result<X509> {
-        bail!("not implemented");
-    }
-
-    /// Returns one or more certificates from the given base64 encoded string
-    /// containing DER encoded certificates that have been concatenated.
-    #[tracing::instrument(name = "crypto.x509.parse_certificates", err)]
-    pub fn parse_certificates(certs: String) -> Result<Vec<X509>> {
-        bail!("not implemented");
-    }
-
-    /// Returns a JWK for signing a JWT from the given PEM-encoded RSA private
-    /// key.
-    #[tracing::instrument(name = "crypto.x509.parse_rsa_private_key", err)]
-    pub fn parse_rsa_private_key(pem: String) -> Result<Jwk> {
-        bail!("not implemented");
-    }
-}
@@ -0,0 +1,606 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builtins related to X509 certificates, keys and certificate requests parsing
+//! and validation
+//!
+//! The certificates and requests are returned with the same structure as the
+//! JSON serialization of the Go `crypto/x509` types used by upstream OPA.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use chrono::SecondsFormat;
+use rsa::{
+    pkcs1::DecodeRsaPrivateKey,
+    pkcs1v15,
+    pkcs8::{DecodePrivateKey, DecodePublicKey},
+    signature::Verifier,
+    traits::{PrivateKeyParts, PublicKeyParts},
+    BigUint, RsaPrivateKey, RsaPublicKey,
+};
+use serde_json::{json, Map, Value};
+use sha2::{Sha256, Sha384, Sha512};
+use x509_cert::{
+    der::{
+        oid::{AssociatedOid, ObjectIdentifier},
+        pem, Decode, Encode, Reader, SliceReader,
+    },
+    ext::{
+        pkix::{
+            name::GeneralName, AuthorityKeyIdentifier, BasicConstraints, ExtendedKeyUsage,
+            KeyUsage, SubjectAltName, SubjectKeyIdentifier,
+        },
+        Extension,
+    },
+    name::Name,
+    request::{CertReq, ExtensionReq},
+    time::Time,
+    Certificate,
+};
+
+use crate::EvaluationContext;
+
+type X509 = Value;
+type Jwk = Value;
+
+/// Attributes of distinguished names which are exposed as lists of values
+const NAME_ATTRIBUTES: &[(&str, &str)] = &[
+    ("2.5.4.6", "Country"),
+    ("2.5.4.10", "Organization"),
+    ("2.5.4.11", "OrganizationalUnit"),
+    ("2.5.4.7", "Locality"),
+    ("2.5.4.8", "Province"),
+    ("2.5.4.9", "StreetAddress"),
+    ("2.5.4.17", "PostalCode"),
+];
+const COMMON_NAME: &str = "2.5.4.3";
+const SERIAL_NUMBER: &str = "2.5.4.5";
+
+/// Values of the Go `x509.SignatureAlgorithm` enum
+const SIGNATURE_ALGORITHMS: &[(&str, u8)] = &[
+    ("1.2.840.113549.1.1.4", 2),
+    ("1.2.840.113549.1.1.5", 3),
+    ("1.2.840.113549.1.1.11", 4),
+    ("1.2.840.113549.1.1.12", 5),
+    ("1.2.840.113549.1.1.13", 6),
+    ("1.2.840.10040.4.3", 7),
+    ("2.16.840.1.101.3.4.3.2", 8),
+    ("1.2.840.10045.4.1", 9),
+    ("1.2.840.10045.4.3.2", 10),
+    ("1.2.840.10045.4.3.3", 11),
+    ("1.2.840.10045.4.3.4", 12),
+    ("1.2.840.113549.1.1.10", 13),
+    ("1.3.101.112", 16),
+];
+
+/// Values of the Go `x509.PublicKeyAlgorithm` enum
+const PUBLIC_KEY_ALGORITHMS: &[(&str, u8)] = &[
+    ("1.2.840.113549.1.1.1", 1),
+    ("1.2.840.10040.4.1", 2),
+    ("1.2.840.10045.2.1", 3),
+    ("1.3.101.112", 4),
+];
+
+/// Values of the Go `x509.ExtKeyUsage` enum
+const EXT_KEY_USAGES: &[(&str, u8)] = &[
+    ("2.5.29.37.0", 0),
+    ("1.3.6.1.5.5.7.3.1", 1),
+    ("1.3.6.1.5.5.7.3.2", 2),
+    ("1.3.6.1.5.5.7.3.3", 3),
+    ("1.3.6.1.5.5.7.3.4", 4),
+    ("1.3.6.1.5.5.7.3.5", 5),
+    ("1.3.6.1.5.5.7.3.6", 6),
+    ("1.3.6.1.5.5.7.3.7", 7),
+    ("1.3.6.1.5.5.7.3.8", 8),
+    ("1.3.6.1.5.5.7.3.9", 9),
+];
+
+/// Find the Go enum value of an OID, `0` meaning unknown
+fn lookup(table: &[(&str, u8)], oid: &ObjectIdentifier) -> u8 {
+    let oid = oid.to_string();
+    table
+        .iter()
+        .find(|(candidate, _)| *candidate == oid)
+        .map_or(0, |(_, value)| *value)
+}
+
+/// Input of the builtins, once the PEM or base64 encoding has been removed
+enum Encoded {
+    /// PEM blocks, with their label
+    Pem(Vec<(String, Vec<u8>)>),
+    /// One or more concatenated DER documents
+    Der(Vec<u8>),
+}
+
+fn pem_blocks(input: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut blocks = Vec::new();
+    let mut rest = input;
+    while let Some(start) = rest.find("-----BEGIN ") {
+        let block = &rest[start..];
+        let end = block
+            .find("-----END ")
+            .and_then(|end| Some(end + 9 + block[end + 9..].find("-----")? + 5))
+            .context("unterminated PEM block")?;
+        let (label, der) =
+            pem::decode_vec(&block.as_bytes()[..end]).map_err(|e| anyhow!("invalid PEM: {e}"))?;
+        blocks.push((label.to_owned(), der));
+        rest = &block[end..];
+    }
+
+    Ok(blocks)
+}
+
+/// Decode the input, which can either be PEM, base64-encoded PEM or
+/// base64-encoded DER
+fn decode_input(input: &str) -> Result<Encoded> {
+    if input.contains("-----BEGIN ") {
+        return Ok(Encoded::Pem(pem_blocks(input)?));
+    }
+
+    let bytes = STANDARD
+        .decode(input.trim())
+        .context("input is neither PEM nor base64")?;
+    match String::from_utf8(bytes) {
+        Ok(text) if text.contains("-----BEGIN ") => Ok(Encoded::Pem(pem_blocks(&text)?)),
+        Ok(text) => Ok(Encoded::Der(text.into_bytes())),
+        Err(err) => Ok(Encoded::Der(err.into_bytes())),
+    }
+}
+
+fn decode_certificates(input: &str) -> Result<Vec<Certificate>> {
+    match decode_input(input)? {
+        Encoded::Pem(blocks) => blocks
+            .iter()
+            .filter(|(label, _)| label == "CERTIFICATE")
+            .map(|(_, der)| Ok(Certificate::from_der(der)?))
+            .collect(),
+        Encoded::Der(der) => {
+            let mut reader = SliceReader::new(&der)?;
+            let mut certificates = Vec::new();
+            while !reader.is_finished() {
+                certificates.push(Certificate::decode(&mut reader)?);
+            }
+            Ok(certificates)
+        }
+    }
+}
+
+/// Find and decode an extension
+fn extension<'a, T>(extensions: &'a [Extension]) -> Result<Option<T>>
+where
+    T: Decode<'a> + AssociatedOid,
+{
+    let Some(extension) = extensions.iter().find(|e| e.extn_id == T::OID) else {
+        return Ok(None);
+    };
+    Ok(Some(T::from_der(extension.extn_value.as_bytes())?))
+}
+
+fn name_to_json(name: &Name) -> Value {
+    let mut object = Map::new();
+    let mut common_name = String::new();
+    let mut serial_number = String::new();
+    let mut names = Vec::new();
+
+    for atv in name.0.iter().flat_map(|rdn| rdn.0.iter()) {
+        // All the string types used in names are ASCII or UTF-8 compatible
+        let Ok(value) = std::str::from_utf8(atv.value.value()) else {
+            continue;
+        };
+        names.push(json!({
+            "Type": atv.oid.arcs().collect::<Vec<_>>(),
+            "Value": value,
+        }));
+
+        let oid = atv.oid.to_string();
+        if oid == COMMON_NAME {
+            value.clone_into(&mut common_name);
+        } else if oid == SERIAL_NUMBER {
+            value.clone_into(&mut serial_number);
+        } else if let Some((_, field)) = NAME_ATTRIBUTES.iter().find(|(o, _)| *o == oid) {
+            let values = object
+                .entry(*field)
+                .or_insert_with(|| Value::Array(Vec::new()));
+            if let Value::Array(values) = values {
+                values.push(value.into());
+            }
+        }
+    }
+
+    object.insert("CommonName".into(), common_name.into());
+    object.insert("SerialNumber".into(), serial_number.into());
+    object.insert("Names".into(), names.into());
+    Value::Object(object)
+}
+
+fn time_to_json(time: &Time) -> Result<Value> {
+    let seconds = i64::try_from(time.to_unix_duration().as_secs())?;
+    let time = chrono::DateTime::from_timestamp(seconds, 0).context("time is out of range")?;
+    Ok(time.to_rfc3339_opts(SecondsFormat::Secs, true).into())
+}
+
+/// Serial numbers are numbers in the Go serialization, but they don't always
+/// fit in a JSON number, in which case they are returned as strings
+fn serial_to_json(bytes: &[u8]) -> Value {
+    let serial = BigUint::from_bytes_be(bytes);
+    let digits = serial.to_bytes_be();
+    if digits.len() <= 8 {
+        let mut buf = [0; 8];
+        buf[8 - digits.len()..].copy_from_slice(&digits);
+        u64::from_be_bytes(buf).into()
+    } else {
+        serial.to_string().into()
+    }
+}
+
+/// Add the fields derived from the extensions to a certificate or request
+fn extensions_to_json(object: &mut Map<String, Value>, extensions: &[Extension]) -> Result<()> {
+    let raw: Vec<Value> = extensions
+        .iter()
+        .map(|e| {
+            json!({
+                "Id": e.extn_id.arcs().collect::<Vec<_>>(),
+                "Critical": e.critical,
+                "Value": STANDARD.encode(e.extn_value.as_bytes()),
+            })
+        })
+        .collect();
+    object.insert("Extensions".into(), raw.into());
+
+    let mut dns_names = Vec::new();
+    let mut email_addresses = Vec::new();
+    let mut ip_addresses = Vec::new();
+    let mut uris = Vec::new();
+    if let Some(SubjectAltName(names)) = extension(extensions)? {
+        for name in names {
+            match name {
+                GeneralName::DnsName(name) => dns_names.push(name.to_string()),
+                GeneralName::Rfc822Name(email) => email_addresses.push(email.to_string()),
+                GeneralName::UniformResourceIdentifier(uri) => uris.push(uri.to_string()),
+                GeneralName::IpAddress(ip) => {
+                    let ip = match ip.as_bytes() {
+                        &[a, b, c, d] => IpAddr::from(Ipv4Addr::new(a, b, c, d)),
+                        bytes => IpAddr::from(Ipv6Addr::from(
+                            <[u8; 16]>::try_from(bytes).context("invalid IP address in SAN")?,
+                        )),
+                    };
+                    ip_addresses.push(ip.to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+    object.insert("DNSNames".into(), dns_names.into());
+    object.insert("EmailAddresses".into(), email_addresses.into());
+    object.insert("IPAddresses".into(), ip_addresses.into());
+    object.insert("URIStrings".into(), uris.into());
+
+    if let Some(KeyUsage(usage)) = extension(extensions)? {
+        object.insert("KeyUsage".into(), usage.bits().into());
+    }
+
+    if let Some(ExtendedKeyUsage(usages)) = extension(extensions)? {
+        let usages: Vec<u8> = usages
+            .iter()
+            .map(|oid| lookup(EXT_KEY_USAGES, oid))
+            .collect();
+        object.insert("ExtKeyUsage".into(), usages.into());
+    }
+
+    if let Some(constraints) = extension::<BasicConstraints>(extensions)? {
+        object.insert("BasicConstraintsValid".into(), true.into());
+        object.insert("IsCA".into(), constraints.ca.into());
+        let max_path_len = constraints.path_len_constraint.map_or(-1, i64::from);
+        object.insert("MaxPathLen".into(), max_path_len.into());
+    }
+
+    if let Some(SubjectKeyIdentifier(id)) = extension(extensions)? {
+        object.insert("SubjectKeyId".into(), STANDARD.encode(id.as_bytes()).into());
+    }
+
+    if let Some(AuthorityKeyIdentifier {
+        key_identifier: Some(id),
+        ..
+    }) = extension(extensions)?
+    {
+        object.insert(
+            "AuthorityKeyId".into(),
+            STANDARD.encode(id.as_bytes()).into(),
+        );
+    }
+
+    Ok(())
+}
+
+fn certificate_to_json(certificate: &Certificate) -> Result<X509> {
+    let tbs = &certificate.tbs_certificate;
+    let mut object = Map::new();
+    object.insert("Raw".into(), STANDARD.encode(certificate.to_der()?).into());
+    object.insert("Version".into(), (tbs.version as u8 + 1).into());
+    object.insert(
+        "SerialNumber".into(),
+        serial_to_json(tbs.serial_number.as_bytes()),
+    );
+    object.insert("Issuer".into(), name_to_json(&tbs.issuer));
+    object.insert("Subject".into(), name_to_json(&tbs.subject));
+    object.insert("NotBefore".into(), time_to_json(&tbs.validity.not_before)?);
+    object.insert("NotAfter".into(), time_to_json(&tbs.validity.not_after)?);
+    object.insert(
+        "Signature".into(),
+        STANDARD.encode(certificate.signature.raw_bytes()).into(),
+    );
+    object.insert(
+        "SignatureAlgorithm".into(),
+        lookup(SIGNATURE_ALGORITHMS, &certificate.signature_algorithm.oid).into(),
+    );
+    object.insert(
+        "PublicKeyAlgorithm".into(),
+        lookup(
+            PUBLIC_KEY_ALGORITHMS,
+            &tbs.subject_public_key_info.algorithm.oid,
+        )
+        .into(),
+    );
+
+    extensions_to_json(&mut object, tbs.extensions.as_deref().unwrap_or_default())?;
+    Ok(Value::Object(object))
+}
+
+/// Check that a certificate was signed by the issuer's key. Only RSA PKCS#1
+/// v1.5 and ECDSA P-256/P-384 signatures are supported.
+fn signed_by(certificate: &Certificate, issuer: &Certificate) -> Result<bool> {
+    let message = certificate.tbs_certificate.to_der()?;
+    let signature = certificate.signature.raw_bytes();
+    let spki = &issuer.tbs_certificate.subject_public_key_info;
+    let rsa_key =
+        || RsaPublicKey::from_public_key_der(&spki.to_der()?).map_err(anyhow::Error::from);
+    let ec_point = spki.subject_public_key.raw_bytes();
+
+    let valid = match certificate.signature_algorithm.oid.to_string().as_str() {
+        "1.2.840.113549.1.1.11" => pkcs1v15::VerifyingKey::<Sha256>::new(rsa_key()?)
+            .verify(&message, &pkcs1v15::Signature::try_from(signature)?)
+            .is_ok(),
+        "1.2.840.113549.1.1.12" => pkcs1v15::VerifyingKey::<Sha384>::new(rsa_key()?)
+            .verify(&message, &pkcs1v15::Signature::try_from(signature)?)
+            .is_ok(),
+        "1.2.840.113549.1.1.13" => pkcs1v15::VerifyingKey::<Sha512>::new(rsa_key()?)
+            .verify(&message, &pkcs1v15::Signature::try_from(signature)?)
+            .is_ok(),
+        "1.2.840.10045.4.3.2" => p256::ecdsa::VerifyingKey::from_sec1_bytes(ec_point)?
+            .verify(&message, &p256::ecdsa::Signature::from_der(signature)?)
+            .is_ok(),
+        "1.2.840.10045.4.3.3" => p384::ecdsa::VerifyingKey::from_sec1_bytes(ec_point)?
+            .verify(&message, &p384::ecdsa::Signature::from_der(signature)?)
+            .is_ok(),
+        algorithm => bail!("unsupported certificate signature algorithm {algorithm}"),
+    };
+
+    Ok(valid)
+}
+
+/// Verify that the certificates form a chain from the root (first) to the
+/// leaf (last), all valid at the given time
+fn verify_chain(certificates: &[Certificate], now: u64) -> Result<bool> {
+    if certificates.len() < 2 {
+        return Ok(false);
+    }
+
+    for (index, certificate) in certificates.iter().enumerate() {
+        let tbs = &certificate.tbs_certificate;
+        let validity = &tbs.validity;
+        if now < validity.not_before.to_unix_duration().as_secs()
+            || now > validity.not_after.to_unix_duration().as_secs()
+        {
+            return Ok(false);
+        }
+
+        let extensions = tbs.extensions.as_deref().unwrap_or_default();
+        let is_leaf = index == certificates.len() - 1;
+        if !is_leaf && !extension::<BasicConstraints>(extensions)?.is_some_and(|bc| bc.ca) {
+            return Ok(false);
+        }
+
+        if index > 0 {
+            let issuer = &certificates[index - 1];
+            if tbs.issuer != issuer.tbs_certificate.subject || !signed_by(certificate, issuer)? {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Returns one or more certificates from the given string containing PEM or
+/// base64 encoded DER certificates after verifying the supplied
+/// certificates form a complete certificate chain back to a trusted
+/// root.
+///
+/// The first certificate is treated as the root and the last is treated as
+/// the leaf, with all others being treated as intermediates.
+#[tracing::instrument(name = "crypto.x509.parse_and_verify_certificates", skip(ctx), err)]
+pub fn parse_and_verify_certificates<C: EvaluationContext>(
+    ctx: &mut C,
+    certs: String,
+) -> Result<(bool, Vec<X509>)> {
+    let certificates = decode_certificates(&certs)?;
+    let now = u64::try_from(ctx.now().timestamp())?;
+    if !verify_chain(&certificates, now)? {
+        return Ok((false, Vec::new()));
+    }
+
+    let certificates = certificates
+        .iter()
+        .map(certificate_to_json)
+        .collect::<Result<_>>()?;
+    Ok((true, certificates))
+}
+
+/// Returns a PKCS #10 certificate signing request from the given
+/// PEM-encoded PKCS#10 certificate signing request.
+#[tracing::instrument(name = "crypto.x509.parse_certificate_request", err)]
+pub fn parse_certificate_request(csr: String) -> Result<X509> {
+    let der = match decode_input(&csr)? {
+        Encoded::Pem(blocks) => blocks
+            .into_iter()
+            .find(|(label, _)| label == "CERTIFICATE REQUEST" || label == "NEW CERTIFICATE REQUEST")
+            .map(|(_, der)| der)
+            .context("no certificate request found")?,
+        Encoded::Der(der) => der,
+    };
+    let request = CertReq::from_der(&der)?;
+    let info = &request.info;
+
+    let mut object = Map::new();
+    object.insert("Raw".into(), STANDARD.encode(&der).into());
+    object.insert("Version".into(), (info.version as u8).into());
+    object.insert("Subject".into(), name_to_json(&info.subject));
+    object.insert(
+        "Signature".into(),
+        STANDARD.encode(request.signature.raw_bytes()).into(),
+    );
+    object.insert(
+        "SignatureAlgorithm".into(),
+        lookup(SIGNATURE_ALGORITHMS, &request.algorithm.oid).into(),
+    );
+    object.insert(
+        "PublicKeyAlgorithm".into(),
+        lookup(PUBLIC_KEY_ALGORITHMS, &info.public_key.algorithm.oid).into(),
+    );
+
+    let mut extensions = Vec::new();
+    for attribute in info.attributes.iter() {
+        if attribute.oid == ExtensionReq::OID {
+            for value in attribute.values.iter() {
+                extensions.extend(ExtensionReq::from_der(&value.to_der()?)?.0);
+            }
+        }
+    }
+    extensions_to_json(&mut object, &extensions)?;
+
+    Ok(Value::Object(object))
+}
+
+/// Returns one or more certificates from the given base64 encoded string
+/// containing DER encoded certificates that have been concatenated.
+#[tracing::instrument(name = "crypto.x509.parse_certificates", err)]
+pub fn parse_certificates(certs: String) -> Result<Vec<X509>> {
+    decode_certificates(&certs)?
+        .iter()
+        .map(certificate_to_json)
+        .collect()
+}
+
+/// Returns a JWK for signing a JWT from the given PEM-encoded RSA private
+/// key.
+#[tracing::instrument(name = "crypto.x509.parse_rsa_private_key", skip(pem), err)]
+pub fn parse_rsa_private_key(pem: String) -> Result<Jwk> {
+    let key = match decode_input(&pem)? {
+        Encoded::Pem(blocks) => match blocks.first() {
+            Some((label, der)) if label == "RSA PRIVATE KEY" => RsaPrivateKey::from_pkcs1_der(der)?,
+            Some((label, der)) if label == "PRIVATE KEY" => RsaPrivateKey::from_pkcs8_der(der)?,
+            Some((label, _)) => bail!("unsupported PEM label {label:?}"),
+            None => bail!("no private key found"),
+        },
+        Encoded::Der(der) => {
+            RsaPrivateKey::from_pkcs1_der(&der).or_else(|_| RsaPrivateKey::from_pkcs8_der(&der))?
+        }
+    };
+
+    let encode = |n: &BigUint| URL_SAFE_NO_PAD.encode(n.to_bytes_be());
+    let [p, q] = key.primes() else {
+        bail!("multi-prime RSA keys are not supported");
+    };
+    let mut jwk = json!({
+        "kty": "RSA",
+        "n": encode(key.n()),
+        "e": encode(key.e()),
+        "d": encode(key.d()),
+        "p": encode(p),
+        "q": encode(q),
+    });
+    if let (Some(dp), Some(dq), Some(qi)) = (key.dp(), key.dq(), key.crt_coefficient()) {
+        jwk["dp"] = encode(dp).into();
+        jwk["dq"] = encode(dq).into();
+        jwk["qi"] = encode(&qi).into();
+    }
+
+    Ok(jwk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultContext;
+
+    const CHAIN: &str = "-----BEGIN CERTIFICATE-----
+MIIBVTCB/KADAgECAgEBMAoGCCqGSM49BAMCMCkxEDAOBgNVBAoMB0V4YW1wbGUx
+FTATBgNVBAMMDEV4YW1wbGUgUm9vdDAgFw0yMDAxMDEwMDAwMDBaGA8yMTIwMDEw
+MTAwMDAwMFowKTEQMA4GA1UECgwHRXhhbXBsZTEVMBMGA1UEAwwMRXhhbXBsZSBS
+b290MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEyGQblX/4dbmk8g/r3lueovbJ
+smI2DcSIpUNiKcf/AjxUz4+ar7fD9rPeym2tfbBqKFUql+aiipf4njSRKCGWc6MT
+MBEwDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiA0tnfO8KWkqOV6
+COsquAIZ8PyVNsjf27dhHv6jU0KXFgIhAPFypll8TUV2AmJP3CUQ/ANt+RNcX0kS
+LqDA5kIa68Uu
+-----END CERTIFICATE-----
+-----BEGIN CERTIFICATE-----
+MIIBcTCCARagAwIBAgIBAjAKBggqhkjOPQQDAjApMRAwDgYDVQQKDAdFeGFtcGxl
+MRUwEwYDVQQDDAxFeGFtcGxlIFJvb3QwIBcNMjAwMTAxMDAwMDAwWhgPMjEyMDAx
+MDEwMDAwMDBaMBYxFDASBgNVBAMMC2V4YW1wbGUuY29tMFkwEwYHKoZIzj0CAQYI
+KoZIzj0DAQcDQgAE7kfZ74ul5ZULhaIeCsvM9vbiBiccRzSkvfUwx0EeLDOC0h1u
+89IgjkKfFDHhJQhyq/OG4bCND6SC4eELzQ3Y1qNAMD4wJwYDVR0RBCAwHoILZXhh
+bXBsZS5jb22CD3d3dy5leGFtcGxlLmNvbTATBgNVHSUEDDAKBggrBgEFBQcDATAK
+BggqhkjOPQQDAgNJADBGAiEA3xlxJpUCtS/O4MWLhvz0jvVbboF78aq4v4eDNi8R
+hY0CIQDjzIOkKLkcO9s+A+XQ1pkExWNQYHpj8WCHJhBhoS0Abw==
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn parse() {
+        let certificates = parse_certificates(CHAIN.into()).unwrap();
+        assert_eq!(certificates.len(), 2);
+
+        let root = &certificates[0];
+        assert_eq!(root["Subject"]["CommonName"], "Example Root");
+        assert_eq!(root["Subject"]["Organization"], json!(["Example"]));
+        assert_eq!(root["IsCA"], true);
+        assert_eq!(root["NotBefore"], "2020-01-01T00:00:00Z");
+
+        let leaf = &certificates[1];
+        assert_eq!(leaf["SerialNumber"], 2);
+        assert_eq!(leaf["Issuer"]["CommonName"], "Example Root");
+        assert_eq!(leaf["DNSNames"], json!(["example.com", "www.example.com"]));
+        assert_eq!(leaf["ExtKeyUsage"], json!([1]));
+        assert_eq!(leaf["SignatureAlgorithm"], 10);
+    }
+
+    #[test]
+    fn verify() {
+        let mut ctx = DefaultContext::default();
+        ctx.evaluation_start();
+        let (valid, certificates) = parse_and_verify_certificates(&mut ctx, CHAIN.into()).unwrap();
+        assert!(valid);
+        assert_eq!(certificates.len(), 2);
+
+        // The leaf alone is not a chain
+        let leaf = &CHAIN[CHAIN.find("-----END").unwrap() + 26..];
+        let (valid, _) = parse_and_verify_certificates(&mut ctx, leaf.into()).unwrap();
+        assert!(!valid);
+    }
+}
@@ -14,7 +14,189 @@
 
 //! Builtins used when working with globs.
 
-use anyhow::Result;
+use std::{
+    iter::Peekable,
+    str::Chars,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+
+use super::lru::LruCache;
+use crate::EvaluationContext;
+
+/// Default number of compiled globs kept in a [`GlobCache`]
+pub const DEFAULT_GLOB_CACHE_CAPACITY: usize = 100;
+
+/// A least-recently-used cache of compiled globs, so that hot patterns are not
+/// compiled again on every call.
+///
+/// Cloning it is cheap, and clones share the same underlying cache.
+#[derive(Clone)]
+pub struct GlobCache {
+    inner: Arc<Mutex<LruCache<Regex>>>,
+}
+
+impl GlobCache {
+    /// Create a new cache, holding at most `capacity` compiled globs. A
+    /// capacity of `0` disables caching.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(LruCache::new("glob", capacity))),
+        }
+    }
+
+    /// Get the compiled glob for a pattern and a set of delimiters, compiling
+    /// it if it is not in the cache yet
+    fn get(&self, pattern: &str, delimiters: &[char]) -> Result<Regex> {
+        let source = glob_to_regex(pattern, delimiters)?;
+        self.inner
+            .lock()
+            .map_err(|_| anyhow!("glob cache lock poisoned"))?
+            .get_or_try_insert_with(&source, || Ok(Regex::new(&source)?))
+    }
+
+    /// Get the number of compiled globs in the cache
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.inner.lock().map_or(0, |inner| inner.len())
+    }
+
+    /// Check if the cache is empty
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for GlobCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_GLOB_CACHE_CAPACITY)
+    }
+}
+
+/// Translate a glob into an anchored regular expression.
+///
+/// `*` matches any sequence of characters except the delimiters, `**` any
+/// sequence of characters, and `?` any single character except the
+/// delimiters. Character classes (`[a-z]`, `[!a-z]`), alternatives (`{a,b}`)
+/// and escapes (`\*`) are supported as well.
+fn glob_to_regex(pattern: &str, delimiters: &[char]) -> Result<String> {
+    let any = if delimiters.is_empty() {
+        ".".to_owned()
+    } else {
+        let delimiters: String = delimiters
+            .iter()
+            .map(|c| regex::escape(&c.to_string()))
+            .collect();
+        format!("[^{delimiters}]")
+    };
+
+    let mut chars = pattern.chars().peekable();
+    let mut out = String::from("(?s)^");
+    translate(&mut chars, &any, &mut out, false)?;
+    out.push('$');
+
+    Ok(out)
+}
+
+/// Translate a glob until its end, or until the end of the current
+/// alternative if `in_alternatives` is set
+fn translate(
+    chars: &mut Peekable<Chars>,
+    any: &str,
+    out: &mut String,
+    in_alternatives: bool,
+) -> Result<()> {
+    while let Some(&c) = chars.peek() {
+        if in_alternatives && matches!(c, ',' | '}') {
+            return Ok(());
+        }
+        chars.next();
+
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                out.push_str(".*");
+            }
+            '*' => {
+                out.push_str(any);
+                out.push('*');
+            }
+            '?' => out.push_str(any),
+            '\\' => {
+                let c = chars.next().context("unterminated escape in glob")?;
+                out.push_str(&regex::escape(&c.to_string()));
+            }
+            '[' => {
+                out.push('[');
+                if chars.next_if_eq(&'!').is_some() {
+                    out.push('^');
+                }
+
+                let mut empty = true;
+                loop {
+                    match chars.next() {
+                        Some(']') if !empty => break,
+                        Some('-') if !empty && chars.peek() != Some(&']') => out.push('-'),
+                        Some(c) => {
+                            // Escape the characters which are special in classes
+                            if matches!(c, '\\' | '[' | ']' | '^' | '-' | '&' | '~') {
+                                out.push('\\');
+                            }
+                            out.push(c);
+                        }
+                        None => bail!("unterminated character class in glob"),
+                    }
+                    empty = false;
+                }
+
+                out.push(']');
+            }
+            '{' => {
+                out.push_str("(?:");
+                loop {
+                    translate(chars, any, out, true)?;
+                    match chars.next() {
+                        Some(',') => out.push('|'),
+                        Some('}') => break,
+                        _ => bail!("unterminated alternatives in glob"),
+                    }
+                }
+                out.push(')');
+            }
+            c => out.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses and matches strings against the glob notation.
+///
+/// `delimiters` default to `["."]` if empty, and a `null` delimiters means
+/// the glob is matched without any delimiter.
+#[tracing::instrument(name = "glob.match", skip(ctx), err)]
+pub fn glob_match<C: EvaluationContext>(
+    ctx: &mut C,
+    pattern: String,
+    delimiters: Option<Vec<String>>,
+    value: String,
+) -> Result<bool> {
+    let delimiters: Vec<char> = match delimiters {
+        None => Vec::new(),
+        Some(delimiters) if delimiters.is_empty() => vec!['.'],
+        Some(delimiters) => delimiters.iter().flat_map(|d| d.chars()).collect(),
+    };
+
+    let glob = match ctx.glob_cache() {
+        Some(cache) => cache.get(&pattern, &delimiters)?,
+        None => Regex::new(&glob_to_regex(&pattern, &delimiters)?)?,
+    };
+    Ok(glob.is_match(&value))
+}
 
 /// Returns a string which represents a version of the pattern where all
 /// asterisks have been escaped.
@@ -45,3 +227,58 @@ pub fn quote_meta(pattern: String) -> Result<String> {
     }
     Ok(out)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultContext;
+
+    fn matches(pattern: &str, delimiters: Option<&[&str]>, value: &str) -> bool {
+        let mut ctx = DefaultContext::default();
+        let delimiters = delimiters.map(|d| d.iter().map(ToString::to_string).collect());
+        glob_match(&mut ctx, pattern.into(), delimiters, value.into()).unwrap()
+    }
+
+    #[test]
+    fn matching() {
+        assert!(matches("*.foo.bar", Some(&["/"]), "n.goo.bar.foo.bar"));
+        assert!(!matches("*.foo.bar", Some(&["."]), "n.goo.bar.foo.bar"));
+        assert!(!matches("*.foo.bar", Some(&[]), "n.goo.bar.foo.bar"));
+        assert!(matches("*.foo.bar", None, "n.goo.bar.foo.bar"));
+        assert!(matches("**.bar", Some(&["."]), "n.goo.bar.foo.bar"));
+
+        assert!(matches("api.?.com", Some(&["."]), "api.a.com"));
+        assert!(!matches("api.?.com", Some(&["."]), "api.ab.com"));
+
+        assert!(matches("[a-c]at", None, "bat"));
+        assert!(!matches("[!a-c]at", None, "bat"));
+        assert!(matches("[!a-c]at", None, "rat"));
+
+        assert!(matches("*.{github,gitlab}.com", None, "api.github.com"));
+        assert!(!matches("*.{github,gitlab}.com", None, "api.gitea.com"));
+        assert!(matches("{a,{b,c}d}", None, "cd"));
+
+        assert!(matches("\\*", None, "*"));
+        assert!(!matches("\\*", None, "a"));
+        assert!(matches("a+b(c)", None, "a+b(c)"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(glob_to_regex("[a", &[]).is_err());
+        assert!(glob_to_regex("{a,b", &[]).is_err());
+        assert!(glob_to_regex("a\\", &[]).is_err());
+        assert!(glob_to_regex("a}", &[]).is_ok());
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(quote_meta("abc".into()).unwrap(), "abc");
+        assert_eq!(quote_meta("foobar*".into()).unwrap(), "foobar\\*");
+
+        let cache = GlobCache::default();
+        let quoted = quote_meta("*.{a,b}".into()).unwrap();
+        assert!(cache.get(&quoted, &[]).unwrap().is_match("*.{a,b}"));
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -13,13 +13,774 @@
 // limitations under the License.
 
 //! Builtins related to GraphQL schema and query parsing and validation
+//!
+//! ASTs are returned with the same structure as the ones produced by upstream
+//! OPA, with the positions, comments, null values and empty collections
+//! removed.
 
-use anyhow::{bail, Result};
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Context, Result};
+use graphql_parser::{
+    query::{self, Selection, TypeCondition},
+    schema::{self, TypeDefinition, TypeExtension},
+};
+use serde_json::{json, Map, Value};
+
+type Type<'a> = query::Type<'a, String>;
+type Directive<'a> = query::Directive<'a, String>;
+
+/// Remove null values and empty objects and arrays
+fn prune(value: Value) -> Option<Value> {
+    match value {
+        Value::Null => None,
+        Value::Array(items) => {
+            let items: Vec<_> = items.into_iter().filter_map(prune).collect();
+            (!items.is_empty()).then_some(Value::Array(items))
+        }
+        Value::Object(fields) => {
+            let fields: Map<_, _> = fields
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, prune(value)?)))
+                .collect();
+            (!fields.is_empty()).then_some(Value::Object(fields))
+        }
+        value => Some(value),
+    }
+}
+
+fn type_json(ty: &Type) -> Value {
+    match ty {
+        Type::NamedType(name) => json!({"NamedType": name, "NonNull": false}),
+        Type::ListType(elem) => json!({"NamedType": "", "Elem": type_json(elem), "NonNull": false}),
+        Type::NonNullType(ty) => {
+            let mut value = type_json(ty);
+            value["NonNull"] = true.into();
+            value
+        }
+    }
+}
+
+/// Serialize a value, `Kind` being the value of the `ast.ValueKind` Go enum
+fn value_json(value: &query::Value<String>) -> Value {
+    let (kind, raw, children): (u8, String, Vec<Value>) = match value {
+        query::Value::Variable(name) => (0, name.clone(), Vec::new()),
+        query::Value::Int(n) => (1, n.as_i64().unwrap_or_default().to_string(), Vec::new()),
+        query::Value::Float(f) => (2, f.to_string(), Vec::new()),
+        query::Value::String(s) => (3, s.clone(), Vec::new()),
+        query::Value::Boolean(b) => (5, b.to_string(), Vec::new()),
+        query::Value::Null => (6, "null".to_owned(), Vec::new()),
+        query::Value::Enum(e) => (7, e.clone(), Vec::new()),
+        query::Value::List(items) => (
+            8,
+            String::new(),
+            items
+                .iter()
+                .map(|item| json!({"Value": value_json(item)}))
+                .collect(),
+        ),
+        query::Value::Object(fields) => (
+            9,
+            String::new(),
+            fields
+                .iter()
+                .map(|(name, value)| json!({"Name": name, "Value": value_json(value)}))
+                .collect(),
+        ),
+    };
+
+    json!({"Raw": raw, "Kind": kind, "Children": children})
+}
+
+fn arguments_json(arguments: &[(String, query::Value<String>)]) -> Vec<Value> {
+    arguments
+        .iter()
+        .map(|(name, value)| json!({"Name": name, "Value": value_json(value)}))
+        .collect()
+}
+
+fn directives_json(directives: &[Directive]) -> Vec<Value> {
+    directives
+        .iter()
+        .map(|directive| {
+            json!({
+                "Name": directive.name,
+                "Arguments": arguments_json(&directive.arguments),
+            })
+        })
+        .collect()
+}
+
+fn selection_set_json(selection_set: &query::SelectionSet<String>) -> Vec<Value> {
+    selection_set
+        .items
+        .iter()
+        .map(|selection| match selection {
+            Selection::Field(field) => json!({
+                "Alias": field.alias.as_ref().unwrap_or(&field.name),
+                "Name": field.name,
+                "Arguments": arguments_json(&field.arguments),
+                "Directives": directives_json(&field.directives),
+                "SelectionSet": selection_set_json(&field.selection_set),
+            }),
+            Selection::FragmentSpread(spread) => json!({
+                "Name": spread.fragment_name,
+                "Directives": directives_json(&spread.directives),
+            }),
+            Selection::InlineFragment(fragment) => json!({
+                "TypeCondition": match &fragment.type_condition {
+                    Some(TypeCondition::On(name)) => name.as_str(),
+                    None => "",
+                },
+                "Directives": directives_json(&fragment.directives),
+                "SelectionSet": selection_set_json(&fragment.selection_set),
+            }),
+        })
+        .collect()
+}
+
+fn variable_definitions_json(definitions: &[query::VariableDefinition<String>]) -> Vec<Value> {
+    definitions
+        .iter()
+        .map(|definition| {
+            json!({
+                "Variable": definition.name,
+                "Type": type_json(&definition.var_type),
+                "DefaultValue": definition.default_value.as_ref().map(value_json),
+            })
+        })
+        .collect()
+}
+
+fn query_document_json(document: &query::Document<String>) -> Value {
+    let mut operations = Vec::new();
+    let mut fragments = Vec::new();
+    for definition in &document.definitions {
+        match definition {
+            query::Definition::Operation(query::OperationDefinition::SelectionSet(set)) => {
+                operations.push(json!({
+                    "Operation": "query",
+                    "SelectionSet": selection_set_json(set),
+                }));
+            }
+            query::Definition::Operation(query::OperationDefinition::Query(op)) => {
+                operations.push(json!({
+                    "Operation": "query",
+                    "Name": op.name,
+                    "VariableDefinitions": variable_definitions_json(&op.variable_definitions),
+                    "Directives": directives_json(&op.directives),
+                    "SelectionSet": selection_set_json(&op.selection_set),
+                }));
+            }
+            query::Definition::Operation(query::OperationDefinition::Mutation(op)) => {
+                operations.push(json!({
+                    "Operation": "mutation",
+                    "Name": op.name,
+                    "VariableDefinitions": variable_definitions_json(&op.variable_definitions),
+                    "Directives": directives_json(&op.directives),
+                    "SelectionSet": selection_set_json(&op.selection_set),
+                }));
+            }
+            query::Definition::Operation(query::OperationDefinition::Subscription(op)) => {
+                operations.push(json!({
+                    "Operation": "subscription",
+                    "Name": op.name,
+                    "VariableDefinitions": variable_definitions_json(&op.variable_definitions),
+                    "Directives": directives_json(&op.directives),
+                    "SelectionSet": selection_set_json(&op.selection_set),
+                }));
+            }
+            query::Definition::Fragment(fragment) => {
+                let TypeCondition::On(type_condition) = &fragment.type_condition;
+                fragments.push(json!({
+                    "Name": fragment.name,
+                    "TypeCondition": type_condition,
+                    "Directives": directives_json(&fragment.directives),
+                    "SelectionSet": selection_set_json(&fragment.selection_set),
+                }));
+            }
+        }
+    }
+
+    let document = json!({"Operations": operations, "Fragments": fragments});
+    prune(document).unwrap_or_else(|| json!({}))
+}
+
+fn input_values_json(values: &[schema::InputValue<String>]) -> Vec<Value> {
+    values
+        .iter()
+        .map(|value| {
+            json!({
+                "Description": value.description,
+                "Name": value.name,
+                "DefaultValue": value.default_value.as_ref().map(value_json),
+                "Type": type_json(&value.value_type),
+                "Directives": directives_json(&value.directives),
+            })
+        })
+        .collect()
+}
+
+fn fields_json(fields: &[schema::Field<String>]) -> Vec<Value> {
+    fields
+        .iter()
+        .map(|field| {
+            json!({
+                "Description": field.description,
+                "Name": field.name,
+                "Arguments": input_values_json(&field.arguments),
+                "Type": type_json(&field.field_type),
+                "Directives": directives_json(&field.directives),
+            })
+        })
+        .collect()
+}
+
+fn enum_values_json(values: &[schema::EnumValue<String>]) -> Vec<Value> {
+    values
+        .iter()
+        .map(|value| {
+            json!({
+                "Description": value.description,
+                "Name": value.name,
+                "Directives": directives_json(&value.directives),
+            })
+        })
+        .collect()
+}
+
+fn type_definition_json(definition: &TypeDefinition<String>) -> Value {
+    match definition {
+        TypeDefinition::Scalar(t) => json!({
+            "Kind": "SCALAR",
+            "Description": t.description,
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+        }),
+        TypeDefinition::Object(t) => json!({
+            "Kind": "OBJECT",
+            "Description": t.description,
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+            "Interfaces": t.implements_interfaces,
+            "Fields": fields_json(&t.fields),
+        }),
+        TypeDefinition::Interface(t) => json!({
+            "Kind": "INTERFACE",
+            "Description": t.description,
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+            "Interfaces": t.implements_interfaces,
+            "Fields": fields_json(&t.fields),
+        }),
+        TypeDefinition::Union(t) => json!({
+            "Kind": "UNION",
+            "Description": t.description,
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+            "Types": t.types,
+        }),
+        TypeDefinition::Enum(t) => json!({
+            "Kind": "ENUM",
+            "Description": t.description,
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+            "EnumValues": enum_values_json(&t.values),
+        }),
+        TypeDefinition::InputObject(t) => json!({
+            "Kind": "INPUT_OBJECT",
+            "Description": t.description,
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+            "Fields": input_values_json(&t.fields),
+        }),
+    }
+}
+
+fn type_extension_json(extension: &TypeExtension<String>) -> Value {
+    match extension {
+        TypeExtension::Scalar(t) => json!({
+            "Kind": "SCALAR",
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+        }),
+        TypeExtension::Object(t) => json!({
+            "Kind": "OBJECT",
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+            "Interfaces": t.implements_interfaces,
+            "Fields": fields_json(&t.fields),
+        }),
+        TypeExtension::Interface(t) => json!({
+            "Kind": "INTERFACE",
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+            "Interfaces": t.implements_interfaces,
+            "Fields": fields_json(&t.fields),
+        }),
+        TypeExtension::Union(t) => json!({
+            "Kind": "UNION",
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+            "Types": t.types,
+        }),
+        TypeExtension::Enum(t) => json!({
+            "Kind": "ENUM",
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+            "EnumValues": enum_values_json(&t.values),
+        }),
+        TypeExtension::InputObject(t) => json!({
+            "Kind": "INPUT_OBJECT",
+            "Name": t.name,
+            "Directives": directives_json(&t.directives),
+            "Fields": input_values_json(&t.fields),
+        }),
+    }
+}
+
+fn schema_document_json(document: &schema::Document<String>) -> Value {
+    let mut schemas = Vec::new();
+    let mut directives = Vec::new();
+    let mut definitions = Vec::new();
+    let mut extensions = Vec::new();
+    for definition in &document.definitions {
+        match definition {
+            schema::Definition::SchemaDefinition(schema) => {
+                let operation_types: Vec<Value> = [
+                    ("query", &schema.query),
+                    ("mutation", &schema.mutation),
+                    ("subscription", &schema.subscription),
+                ]
+                .into_iter()
+                .filter_map(|(operation, ty)| {
+                    Some(json!({"Operation": operation, "Type": ty.as_ref()?}))
+                })
+                .collect();
+                schemas.push(json!({
+                    "Directives": directives_json(&schema.directives),
+                    "OperationTypes": operation_types,
+                }));
+            }
+            schema::Definition::TypeDefinition(definition) => {
+                definitions.push(type_definition_json(definition));
+            }
+            schema::Definition::TypeExtension(extension) => {
+                extensions.push(type_extension_json(extension));
+            }
+            schema::Definition::DirectiveDefinition(directive) => {
+                directives.push(json!({
+                    "Description": directive.description,
+                    "Name": directive.name,
+                    "Arguments": input_values_json(&directive.arguments),
+                    "Locations": directive
+                        .locations
+                        .iter()
+                        .map(schema::DirectiveLocation::as_str)
+                        .collect::<Vec<_>>(),
+                    "IsRepeatable": directive.repeatable,
+                }));
+            }
+        }
+    }
+
+    let document = json!({
+        "Schema": schemas,
+        "Directives": directives,
+        "Definitions": definitions,
+        "Extensions": extensions,
+    });
+    prune(document).unwrap_or_else(|| json!({}))
+}
+
+/// Name of the type, without the list and non-null wrappers
+fn named_type<'a>(ty: &'a Type) -> &'a str {
+    match ty {
+        Type::NamedType(name) => name,
+        Type::ListType(ty) | Type::NonNullType(ty) => named_type(ty),
+    }
+}
+
+const BUILTIN_SCALARS: [&str; 5] = ["Int", "Float", "String", "Boolean", "ID"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Scalar,
+    Object,
+    Interface,
+    Union,
+    Enum,
+    InputObject,
+}
+
+struct FieldInfo<'a> {
+    ty: &'a Type<'a>,
+    arguments: HashSet<&'a str>,
+}
+
+struct TypeInfo<'a> {
+    kind: Kind,
+    fields: HashMap<&'a str, FieldInfo<'a>>,
+    /// Interfaces implemented by objects and interfaces
+    interfaces: Vec<&'a str>,
+    /// Members of unions
+    members: Vec<&'a str>,
+    /// All the types referenced by the fields and their arguments
+    references: Vec<&'a str>,
+}
+
+impl<'a> TypeInfo<'a> {
+    fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            fields: HashMap::new(),
+            interfaces: Vec::new(),
+            members: Vec::new(),
+            references: Vec::new(),
+        }
+    }
+
+    fn add_fields(&mut self, fields: &'a [schema::Field<'a, String>]) {
+        for field in fields {
+            self.references.push(named_type(&field.field_type));
+            for argument in &field.arguments {
+                self.references.push(named_type(&argument.value_type));
+            }
+            let arguments = field.arguments.iter().map(|a| a.name.as_str()).collect();
+            self.fields.insert(
+                &field.name,
+                FieldInfo {
+                    ty: &field.field_type,
+                    arguments,
+                },
+            );
+        }
+    }
+
+    fn add_input_fields(&mut self, fields: &'a [schema::InputValue<'a, String>]) {
+        for field in fields {
+            self.references.push(named_type(&field.value_type));
+            self.fields.insert(
+                &field.name,
+                FieldInfo {
+                    ty: &field.value_type,
+                    arguments: HashSet::new(),
+                },
+            );
+        }
+    }
+}
+
+/// The types of a schema, indexed for validating queries
+struct SchemaIndex<'a> {
+    types: HashMap<&'a str, TypeInfo<'a>>,
+    /// Root types, by operation
+    roots: HashMap<&'static str, &'a str>,
+}
+
+impl<'a> SchemaIndex<'a> {
+    /// Index the schema, checking that it is valid
+    #[allow(clippy::too_many_lines)]
+    fn new(document: &'a schema::Document<'a, String>) -> Result<Self> {
+        let mut types: HashMap<&str, TypeInfo> = BUILTIN_SCALARS
+            .into_iter()
+            .map(|name| (name, TypeInfo::new(Kind::Scalar)))
+            .collect();
+        let mut schema_definition = None;
+
+        for definition in &document.definitions {
+            match definition {
+                schema::Definition::SchemaDefinition(schema) => {
+                    if schema_definition.replace(schema).is_some() {
+                        bail!("the schema must be defined only once");
+                    }
+                }
+                schema::Definition::TypeDefinition(definition) => {
+                    let (name, info) = match definition {
+                        TypeDefinition::Scalar(t) => (&t.name, TypeInfo::new(Kind::Scalar)),
+                        TypeDefinition::Object(t) => {
+                            let mut info = TypeInfo::new(Kind::Object);
+                            info.add_fields(&t.fields);
+                            info.interfaces =
+                                t.implements_interfaces.iter().map(String::as_str).collect();
+                            (&t.name, info)
+                        }
+                        TypeDefinition::Interface(t) => {
+                            let mut info = TypeInfo::new(Kind::Interface);
+                            info.add_fields(&t.fields);
+                            info.interfaces =
+                                t.implements_interfaces.iter().map(String::as_str).collect();
+                            (&t.name, info)
+                        }
+                        TypeDefinition::Union(t) => {
+                            let mut info = TypeInfo::new(Kind::Union);
+                            info.members = t.types.iter().map(String::as_str).collect();
+                            (&t.name, info)
+                        }
+                        TypeDefinition::Enum(t) => (&t.name, TypeInfo::new(Kind::Enum)),
+                        TypeDefinition::InputObject(t) => {
+                            let mut info = TypeInfo::new(Kind::InputObject);
+                            info.add_input_fields(&t.fields);
+                            (&t.name, info)
+                        }
+                    };
+
+                    let builtin = BUILTIN_SCALARS.contains(&name.as_str());
+                    if types.insert(name, info).is_some() && !builtin {
+                        bail!("type {name:?} is defined more than once");
+                    }
+                }
+                schema::Definition::TypeExtension(_)
+                | schema::Definition::DirectiveDefinition(_) => {}
+            }
+        }
+
+        for definition in &document.definitions {
+            let schema::Definition::TypeExtension(extension) = definition else {
+                continue;
+            };
+
+            let (name, kind) = match extension {
+                TypeExtension::Scalar(t) => (&t.name, Kind::Scalar),
+                TypeExtension::Object(t) => (&t.name, Kind::Object),
+                TypeExtension::Interface(t) => (&t.name, Kind::Interface),
+                TypeExtension::Union(t) => (&t.name, Kind::Union),
+                TypeExtension::Enum(t) => (&t.name, Kind::Enum),
+                TypeExtension::InputObject(t) => (&t.name, Kind::InputObject),
+            };
+            let info = types
+                .get_mut(name.as_str())
+                .filter(|info| info.kind == kind)
+                .with_context(|| format!("cannot extend undefined type {name:?}"))?;
+
+            match extension {
+                TypeExtension::Object(t) => {
+                    info.add_fields(&t.fields);
+                    info.interfaces
+                        .extend(t.implements_interfaces.iter().map(String::as_str));
+                }
+                TypeExtension::Interface(t) => {
+                    info.add_fields(&t.fields);
+                    info.interfaces
+                        .extend(t.implements_interfaces.iter().map(String::as_str));
+                }
+                TypeExtension::Union(t) => info.members.extend(t.types.iter().map(String::as_str)),
+                TypeExtension::InputObject(t) => info.add_input_fields(&t.fields),
+                TypeExtension::Scalar(_) | TypeExtension::Enum(_) => {}
+            }
+        }
+
+        let kind_of = |name: &str| types.get(name).map(|info| info.kind);
+        for (name, info) in &types {
+            for reference in &info.references {
+                if kind_of(reference).is_none() {
+                    bail!("type {name:?} references undefined type {reference:?}");
+                }
+            }
+            for interface in &info.interfaces {
+                if kind_of(interface) != Some(Kind::Interface) {
+                    bail!("type {name:?} implements {interface:?}, which is not an interface");
+                }
+            }
+            for member in &info.members {
+                if kind_of(member) != Some(Kind::Object) {
+                    bail!("union {name:?} has member {member:?}, which is not an object type");
+                }
+            }
+        }
+
+        let mut roots = HashMap::new();
+        if let Some(schema) = schema_definition {
+            for (operation, root) in [
+                ("query", &schema.query),
+                ("mutation", &schema.mutation),
+                ("subscription", &schema.subscription),
+            ] {
+                let Some(root) = root else { continue };
+                if kind_of(root) != Some(Kind::Object) {
+                    bail!("{operation} root type {root:?} must be an object type");
+                }
+                roots.insert(operation, root.as_str());
+            }
+        } else {
+            for (operation, root) in [
+                ("query", "Query"),
+                ("mutation", "Mutation"),
+                ("subscription", "Subscription"),
+            ] {
+                if kind_of(root) == Some(Kind::Object) {
+                    roots.insert(operation, root);
+                }
+            }
+        }
+
+        if !roots.contains_key("query") {
+            bail!("the schema must define a query root type");
+        }
+
+        Ok(Self { types, roots })
+    }
+}
+
+/// Validate queries against a schema
+struct QueryValidator<'s, 'q> {
+    schema: &'s SchemaIndex<'s>,
+    fragments: HashMap<&'q str, &'q query::FragmentDefinition<'q, String>>,
+    /// Fragments being validated, to detect cycles
+    visiting: HashSet<&'q str>,
+}
+
+impl<'s, 'q> QueryValidator<'s, 'q> {
+    fn validate(
+        schema: &'s SchemaIndex<'s>,
+        document: &'q query::Document<'q, String>,
+    ) -> Result<()> {
+        let mut validator = Self {
+            schema,
+            fragments: HashMap::new(),
+            visiting: HashSet::new(),
+        };
+
+        for definition in &document.definitions {
+            if let query::Definition::Fragment(fragment) = definition {
+                if validator
+                    .fragments
+                    .insert(&fragment.name, fragment)
+                    .is_some()
+                {
+                    bail!("fragment {:?} is defined more than once", fragment.name);
+                }
+            }
+        }
+
+        for definition in &document.definitions {
+            let (operation, selection_set) = match definition {
+                query::Definition::Operation(query::OperationDefinition::SelectionSet(set)) => {
+                    ("query", set)
+                }
+                query::Definition::Operation(query::OperationDefinition::Query(op)) => {
+                    ("query", &op.selection_set)
+                }
+                query::Definition::Operation(query::OperationDefinition::Mutation(op)) => {
+                    ("mutation", &op.selection_set)
+                }
+                query::Definition::Operation(query::OperationDefinition::Subscription(op)) => {
+                    ("subscription", &op.selection_set)
+                }
+                query::Definition::Fragment(_) => continue,
+            };
+
+            let root = schema
+                .roots
+                .get(operation)
+                .with_context(|| format!("the schema does not support {operation} operations"))?;
+            validator.selection_set(root, selection_set)?;
+        }
+
+        Ok(())
+    }
+
+    fn type_condition(&self, name: &str) -> Result<()> {
+        match self.schema.types.get(name).map(|info| info.kind) {
+            Some(Kind::Object | Kind::Interface | Kind::Union) => Ok(()),
+            Some(_) => bail!("fragment cannot condition on non composite type {name:?}"),
+            None => bail!("unknown type {name:?}"),
+        }
+    }
+
+    fn selection_set(
+        &mut self,
+        parent: &str,
+        selection_set: &'q query::SelectionSet<'q, String>,
+    ) -> Result<()> {
+        let schema = self.schema;
+        let info = &schema.types[parent];
+        for selection in &selection_set.items {
+            match selection {
+                Selection::Field(field) => {
+                    // Introspection fields are not part of the schema
+                    if field.name.starts_with("__") {
+                        continue;
+                    }
+
+                    let definition = info
+                        .fields
+                        .get(field.name.as_str())
+                        .filter(|_| matches!(info.kind, Kind::Object | Kind::Interface))
+                        .with_context(|| {
+                            format!("cannot query field {:?} on type {parent:?}", field.name)
+                        })?;
+
+                    for (argument, _) in &field.arguments {
+                        if !definition.arguments.contains(argument.as_str()) {
+                            bail!(
+                                "unknown argument {argument:?} on field {:?} of type {parent:?}",
+                                field.name
+                            );
+                        }
+                    }
+
+                    let field_type = named_type(definition.ty);
+                    let has_selection = !field.selection_set.items.is_empty();
+                    match schema.types[field_type].kind {
+                        Kind::Object | Kind::Interface | Kind::Union if has_selection => {
+                            self.selection_set(field_type, &field.selection_set)?;
+                        }
+                        Kind::Object | Kind::Interface | Kind::Union => {
+                            bail!("field {:?} of type {field_type:?} must have a selection of subfields", field.name);
+                        }
+                        _ if has_selection => {
+                            bail!("field {:?} of type {field_type:?} must not have a selection since it has no subfields", field.name);
+                        }
+                        _ => {}
+                    }
+                }
+                Selection::FragmentSpread(spread) => {
+                    let name = spread.fragment_name.as_str();
+                    let fragment = *self
+                        .fragments
+                        .get(name)
+                        .with_context(|| format!("undefined fragment {name:?}"))?;
+                    if !self.visiting.insert(&fragment.name) {
+                        bail!("cannot spread fragment {name:?} within itself");
+                    }
+
+                    let TypeCondition::On(type_condition) = &fragment.type_condition;
+                    self.type_condition(type_condition)?;
+                    self.selection_set(type_condition, &fragment.selection_set)?;
+                    self.visiting.remove(name);
+                }
+                Selection::InlineFragment(fragment) => {
+                    let parent = match &fragment.type_condition {
+                        Some(TypeCondition::On(type_condition)) => {
+                            self.type_condition(type_condition)?;
+                            type_condition.as_str()
+                        }
+                        None => parent,
+                    };
+                    self.selection_set(parent, &fragment.selection_set)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a query and a schema, and validate the query against the schema
+fn parse_and_validate(query: &str, schema: &str) -> Result<(serde_json::Value, serde_json::Value)> {
+    let schema =
+        graphql_parser::parse_schema::<String>(schema).context("invalid GraphQL schema")?;
+    let query = graphql_parser::parse_query::<String>(query).context("invalid GraphQL query")?;
+    let index = SchemaIndex::new(&schema)?;
+    QueryValidator::validate(&index, &query)?;
+    Ok((query_document_json(&query), schema_document_json(&schema)))
+}
 
 /// Checks that a GraphQL query is valid against a given schema.
-#[tracing::instrument(name = "graphql.is_valid", err)]
-pub fn is_valid(query: String, schema: String) -> Result<bool> {
-    bail!("not implemented");
+#[tracing::instrument(name = "graphql.is_valid")]
+pub fn is_valid(query: String, schema: String) -> bool {
+    parse_and_validate(&query, &schema).is_ok()
 }
 
 /// Returns AST objects for a given GraphQL query and schema after validating
@@ -27,28 +788,135 @@ pub fn is_valid(query: String, schema: String) -> Result<bool> {
 /// during parsing or validation.
 #[tracing::instrument(name = "graphql.parse", err)]
 pub fn parse(query: String, schema: String) -> Result<(serde_json::Value, serde_json::Value)> {
-    bail!("not implemented");
+    parse_and_validate(&query, &schema)
 }
 
 /// Returns a boolean indicating success or failure alongside the parsed ASTs
 /// for a given GraphQL query and schema after validating the query against the
 /// schema.
-#[tracing::instrument(name = "graphql.parse_and_verify", err)]
+#[tracing::instrument(name = "graphql.parse_and_verify")]
 pub fn parse_and_verify(
     query: String,
     schema: String,
-) -> Result<(bool, serde_json::Value, serde_json::Value)> {
-    bail!("not implemented");
+) -> (bool, serde_json::Value, serde_json::Value) {
+    match parse_and_validate(&query, &schema) {
+        Ok((query, schema)) => (true, query, schema),
+        Err(_) => (false, json!({}), json!({})),
+    }
 }
 
 /// Returns an AST object for a GraphQL query.
 #[tracing::instrument(name = "graphql.parse_query", err)]
 pub fn parse_query(query: String) -> Result<serde_json::Value> {
-    bail!("not implemented");
+    let query = graphql_parser::parse_query::<String>(&query).context("invalid GraphQL query")?;
+    Ok(query_document_json(&query))
 }
 
 /// Returns an AST object for a GraphQL schema.
 #[tracing::instrument(name = "graphql.parse_schema", err)]
 pub fn parse_schema(schema: String) -> Result<serde_json::Value> {
-    bail!("not implemented");
+    let schema =
+        graphql_parser::parse_schema::<String>(&schema).context("invalid GraphQL schema")?;
+    Ok(schema_document_json(&schema))
+}
+
+/// Checks that the input is a valid GraphQL schema.
+#[tracing::instrument(name = "graphql.schema_is_valid")]
+pub fn schema_is_valid(schema: String) -> bool {
+    let Ok(document) = graphql_parser::parse_schema::<String>(&schema) else {
+        return false;
+    };
+    SchemaIndex::new(&document).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r"
+        type Query {
+            user(id: ID!): User
+            search(term: String!): [SearchResult!]!
+        }
+
+        interface Node {
+            id: ID!
+        }
+
+        type User implements Node {
+            id: ID!
+            name: String
+            friends(first: Int): [User!]!
+        }
+
+        type Post implements Node {
+            id: ID!
+            title: String!
+        }
+
+        union SearchResult = User | Post
+    ";
+
+    #[test]
+    fn validate() {
+        assert!(schema_is_valid(SCHEMA.into()));
+        assert!(!schema_is_valid("type Query { user: Missing }".into()));
+
+        let valid = r#"
+            query Q($id: ID!) {
+                user(id: $id) { ...userFields friends(first: 10) { name } }
+                search(term: "x") { __typename ... on Post { title } }
+            }
+            fragment userFields on User { id name }
+        "#;
+        assert!(is_valid(valid.into(), SCHEMA.into()));
+
+        for invalid in [
+            "{ user(id: 1) { unknown } }",
+            "{ user(id: 1) }",
+            "{ user(id: 1) { name { first } } }",
+            "{ user(name: 1) { name } }",
+            "{ search(term: \"x\") { id } }",
+            "{ user(id: 1) { ...missing } }",
+            "{ user(id: 1) { ...a } } fragment a on User { friends { ...a } }",
+            "mutation { user(id: 1) { name } }",
+        ] {
+            assert!(!is_valid(invalid.into(), SCHEMA.into()), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn ast() {
+        let query = parse_query(r#"query Q { user(id: "1") { handle: name } }"#.into()).unwrap();
+        assert_eq!(
+            query,
+            json!({
+                "Operations": [{
+                    "Operation": "query",
+                    "Name": "Q",
+                    "SelectionSet": [{
+                        "Alias": "user",
+                        "Name": "user",
+                        "Arguments": [{"Name": "id", "Value": {"Raw": "1", "Kind": 3}}],
+                        "SelectionSet": [{"Alias": "handle", "Name": "name"}],
+                    }],
+                }],
+            })
+        );
+
+        let schema = parse_schema("type Query { name: String! }".into()).unwrap();
+        assert_eq!(
+            schema,
+            json!({
+                "Definitions": [{
+                    "Kind": "OBJECT",
+                    "Name": "Query",
+                    "Fields": [{
+                        "Name": "name",
+                        "Type": {"NamedType": "String", "NonNull": true},
+                    }],
+                }],
+            })
+        );
+    }
 }
@@ -0,0 +1,405 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Record `http.send` responses to a fixture file, and replay them in later
+//! runs, so that tests of policies calling external services are fast and
+//! deterministic
+
+#![allow(clippy::module_name_repetitions)]
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use anyhow::{bail, Context, Result};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Method, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+use super::Request;
+
+/// Request headers which are never recorded, as they usually hold secrets
+const REDACTED_HEADERS: &[&str] = &["authorization", "cookie", "proxy-authorization"];
+
+/// Whether a [`Cassette`] records or replays responses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CassetteMode {
+    /// Replay the responses if the file exists, record them otherwise
+    #[default]
+    Auto,
+
+    /// Send the requests and record their responses, discarding the ones
+    /// previously recorded
+    Record,
+
+    /// Only replay the recorded responses, failing requests which were not
+    /// recorded
+    Replay,
+}
+
+/// How requests are matched against the recorded ones when replaying
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CassetteMatching {
+    /// Match the method, URL, headers and body, and replay each recorded
+    /// response once, in the order they were recorded
+    #[default]
+    Strict,
+
+    /// Only match the method and URL, and replay recorded responses any
+    /// number of times
+    Lenient,
+}
+
+/// Request as stored in the cassette
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RecordedRequest {
+    #[serde(with = "http_serde::method")]
+    method: Method,
+    url: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw_body: Option<String>,
+}
+
+impl RecordedRequest {
+    fn new(data: &Request) -> Self {
+        let headers = data
+            .headers
+            .iter()
+            .flatten()
+            .map(|(name, value)| (name.to_lowercase(), value.clone()))
+            .filter(|(name, _)| !REDACTED_HEADERS.contains(&name.as_str()))
+            .collect();
+
+        Self {
+            method: data.method.clone(),
+            url: data.url.clone(),
+            headers,
+            body: data.body.clone(),
+            raw_body: data.raw_body.clone(),
+        }
+    }
+}
+
+/// Response as stored in the cassette
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    body: String,
+}
+
+impl RecordedResponse {
+    fn new(status: StatusCode, headers: &HeaderMap, body: &str) -> Self {
+        let mut recorded: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (name, value) in headers {
+            if let Ok(value) = value.to_str() {
+                recorded
+                    .entry(name.as_str().to_owned())
+                    .or_default()
+                    .push(value.to_owned());
+            }
+        }
+
+        Self {
+            status: status.as_u16(),
+            headers: recorded,
+            body: body.to_owned(),
+        }
+    }
+
+    fn to_parts(&self) -> Result<(StatusCode, HeaderMap, String)> {
+        let mut headers = HeaderMap::new();
+        for (name, values) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())?;
+            for value in values {
+                headers.append(name.clone(), HeaderValue::from_str(value)?);
+            }
+        }
+        Ok((
+            StatusCode::from_u16(self.status)?,
+            headers,
+            self.body.clone(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug)]
+struct CassetteState {
+    interactions: Vec<Interaction>,
+    replayed: Vec<bool>,
+}
+
+/// Fixture file holding the responses to `http.send` calls, see
+/// [`super::HttpConfig::with_cassette`].
+///
+/// Files with a `.json` extension are written as JSON, others as YAML.
+/// Recorded interactions are only written to the file by [`Cassette::save`].
+///
+/// Cloning it is cheap, and clones share the same interactions.
+#[derive(Debug, Clone)]
+pub struct Cassette {
+    path: PathBuf,
+    recording: bool,
+    matching: CassetteMatching,
+    state: Arc<Mutex<CassetteState>>,
+}
+
+impl Cassette {
+    /// Open the cassette at the given path, loading the recorded interactions
+    /// unless recording
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be read or parsed, or does not exist in
+    /// [`CassetteMode::Replay`] mode
+    pub async fn open(path: impl Into<PathBuf>, mode: CassetteMode) -> Result<Self> {
+        let path = path.into();
+        let contents = match (mode, tokio::fs::read(&path).await) {
+            (CassetteMode::Record, _) => None,
+            (CassetteMode::Auto, Err(e)) if e.kind() == std::io::ErrorKind::NotFound => None,
+            (_, res) => Some(res.with_context(|| format!("failed to read cassette {path:?}"))?),
+        };
+
+        let recording = contents.is_none();
+        let file = match contents {
+            Some(contents) if is_json(&path) => serde_json::from_slice(&contents)?,
+            Some(contents) => serde_yaml::from_slice(&contents)?,
+            None => CassetteFile::default(),
+        };
+
+        Ok(Self {
+            path,
+            recording,
+            matching: CassetteMatching::default(),
+            state: Arc::new(Mutex::new(CassetteState {
+                replayed: vec![false; file.interactions.len()],
+                interactions: file.interactions,
+            })),
+        })
+    }
+
+    /// Set how requests are matched against the recorded ones
+    #[must_use]
+    pub fn with_matching(mut self, matching: CassetteMatching) -> Self {
+        self.matching = matching;
+        self
+    }
+
+    /// Whether requests are sent and recorded, instead of replayed
+    #[must_use]
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Write the recorded interactions to the file. Does nothing when
+    /// replaying.
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be written
+    pub async fn save(&self) -> Result<()> {
+        if !self.recording {
+            return Ok(());
+        }
+
+        let file = CassetteFile {
+            interactions: self
+                .state
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .interactions
+                .clone(),
+        };
+        let contents = if is_json(&self.path) {
+            serde_json::to_vec_pretty(&file)?
+        } else {
+            serde_yaml::to_string(&file)?.into_bytes()
+        };
+        tokio::fs::write(&self.path, contents)
+            .await
+            .with_context(|| format!("failed to write cassette {:?}", self.path))
+    }
+
+    /// Replay the response recorded for the request, or send it and record
+    /// the response
+    pub(super) async fn replay_or_record(
+        &self,
+        data: &Request,
+        send: impl Future<Output = Result<(StatusCode, HeaderMap, String)>>,
+    ) -> Result<(StatusCode, HeaderMap, String)> {
+        let request = RecordedRequest::new(data);
+
+        if !self.recording {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            let CassetteState {
+                interactions,
+                replayed,
+            } = &mut *state;
+            let found =
+                interactions
+                    .iter()
+                    .zip(replayed.iter_mut())
+                    .find(|(interaction, replayed)| match self.matching {
+                        CassetteMatching::Strict => !**replayed && interaction.request == request,
+                        CassetteMatching::Lenient => {
+                            interaction.request.method == request.method
+                                && interaction.request.url == request.url
+                        }
+                    });
+
+            let Some((interaction, replayed)) = found else {
+                bail!(
+                    "http.send: no recorded response for {} {} in cassette {:?}",
+                    request.method,
+                    request.url,
+                    self.path
+                );
+            };
+            *replayed = true;
+            return interaction.response.to_parts();
+        }
+
+        let (status, headers, body) = send.await?;
+        let response = RecordedResponse::new(status, &headers, &body);
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.interactions.push(Interaction { request, response });
+        state.replayed.push(true);
+        Ok((status, headers, body))
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn request(url: &str, body: serde_json::Value) -> Request {
+        serde_json::from_value(json!({
+            "method": "POST",
+            "url": url,
+            "body": body,
+            "headers": {"Authorization": "Bearer secret"},
+        }))
+        .unwrap()
+    }
+
+    async fn unreachable() -> Result<(StatusCode, HeaderMap, String)> {
+        bail!("should not be sent")
+    }
+
+    async fn respond(status: u16, body: &str) -> Result<(StatusCode, HeaderMap, String)> {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        Ok((StatusCode::from_u16(status)?, headers, body.to_owned()))
+    }
+
+    #[tokio::test]
+    async fn record_and_replay() {
+        for extension in ["json", "yaml"] {
+            let path = std::env::temp_dir().join(format!(
+                "opa-wasm-cassette-{}.{extension}",
+                std::process::id()
+            ));
+
+            let cassette = Cassette::open(&path, CassetteMode::Record).await.unwrap();
+            assert!(cassette.is_recording());
+            let a = request("https://example.com/a", json!({"n": 1}));
+            let b = request("https://example.com/b", json!({"n": 2}));
+            cassette
+                .replay_or_record(&a, respond(200, "a"))
+                .await
+                .unwrap();
+            cassette
+                .replay_or_record(&b, respond(404, "b"))
+                .await
+                .unwrap();
+            cassette.save().await.unwrap();
+
+            let contents = std::fs::read_to_string(&path).unwrap();
+            assert!(contents.contains("https://example.com/a"));
+            assert!(!contents.contains("secret"));
+
+            // Strict matching replays each response once
+            let cassette = Cassette::open(&path, CassetteMode::Auto).await.unwrap();
+            assert!(!cassette.is_recording());
+            let (status, headers, body) =
+                cassette.replay_or_record(&b, unreachable()).await.unwrap();
+            assert_eq!((status.as_u16(), body.as_str()), (404, "b"));
+            assert_eq!(headers["content-type"], "text/plain");
+            assert!(cassette
+                .replay_or_record(&b, respond(200, ""))
+                .await
+                .is_err());
+            let other = request("https://example.com/a", json!({"n": 3}));
+            assert!(cassette
+                .replay_or_record(&other, respond(200, ""))
+                .await
+                .is_err());
+
+            // Lenient matching ignores the body and replays responses again
+            let cassette = Cassette::open(&path, CassetteMode::Replay)
+                .await
+                .unwrap()
+                .with_matching(CassetteMatching::Lenient);
+            for _ in 0..2 {
+                let (_, _, body) = cassette
+                    .replay_or_record(&other, respond(200, ""))
+                    .await
+                    .unwrap();
+                assert_eq!(body, "a");
+            }
+
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        let missing = std::env::temp_dir().join("opa-wasm-missing-cassette.yaml");
+        assert!(Cassette::open(&missing, CassetteMode::Replay)
+            .await
+            .is_err());
+        assert!(Cassette::open(&missing, CassetteMode::Auto)
+            .await
+            .unwrap()
+            .is_recording());
+    }
+}
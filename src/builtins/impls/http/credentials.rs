@@ -0,0 +1,692 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Credential providers authenticating `http.send` requests, so that policies
+//! reference them by name instead of embedding secrets
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use reqwest::{
+    header::{HeaderName, HeaderValue, AUTHORIZATION},
+    Client, Url,
+};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+/// Default endpoint of the GCP metadata server returning the access token of
+/// the default service account
+pub const GCP_METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+/// Tokens are refreshed this long before they expire, so that they don't
+/// expire while the request is in flight
+const REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+/// Adds credentials to the `http.send` requests referencing it in their
+/// `auth` field
+pub trait CredentialProvider: Send + Sync {
+    /// Add the credentials to the request, right before it is sent
+    ///
+    /// # Errors
+    ///
+    /// If the credentials could not be obtained
+    fn authenticate<'a>(
+        &'a self,
+        request: &'a mut reqwest::Request,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+}
+
+/// Error returned when a request references a credential provider which was
+/// not registered
+#[derive(Debug, thiserror::Error)]
+#[error("http.send: no credential provider named {name:?}")]
+pub struct UnknownCredentialProvider {
+    name: String,
+}
+
+/// Credential providers registered by name, see
+/// [`super::HttpConfig::with_credential_provider`].
+///
+/// Cloning it is cheap, and clones share the same providers.
+#[derive(Clone, Default)]
+pub struct CredentialProviders {
+    providers: Arc<HashMap<String, Arc<dyn CredentialProvider>>>,
+}
+
+impl CredentialProviders {
+    /// Register a provider under the given name, replacing any provider
+    /// previously registered with that name
+    #[must_use]
+    pub fn with_provider(
+        mut self,
+        name: impl Into<String>,
+        provider: impl CredentialProvider + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.providers).insert(name.into(), Arc::new(provider));
+        self
+    }
+
+    pub(crate) fn get(
+        &self,
+        name: &str,
+    ) -> Result<&dyn CredentialProvider, UnknownCredentialProvider> {
+        self.providers
+            .get(name)
+            .map(AsRef::as_ref)
+            .ok_or_else(|| UnknownCredentialProvider {
+                name: name.to_owned(),
+            })
+    }
+}
+
+impl fmt::Debug for CredentialProviders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only show the names, providers hold secrets
+        f.debug_set().entries(self.providers.keys()).finish()
+    }
+}
+
+/// Set a header which is never shown in the traces
+fn set_sensitive(request: &mut reqwest::Request, name: HeaderName, value: &str) -> Result<()> {
+    let mut value = HeaderValue::from_str(value)?;
+    value.set_sensitive(true);
+    request.headers_mut().insert(name, value);
+    Ok(())
+}
+
+/// Sends a fixed secret in a header
+#[derive(Clone)]
+pub struct StaticToken {
+    header: HeaderName,
+    value: HeaderValue,
+}
+
+impl StaticToken {
+    /// Send the token as `Authorization: Bearer <token>`
+    ///
+    /// # Errors
+    ///
+    /// If the token is not a valid header value
+    pub fn bearer(token: &str) -> Result<Self> {
+        Self::header(AUTHORIZATION.as_str(), &format!("Bearer {token}"))
+    }
+
+    /// Send the value as is in the given header, for example an API key
+    ///
+    /// # Errors
+    ///
+    /// If the header name or value is not valid
+    pub fn header(name: &str, value: &str) -> Result<Self> {
+        let mut value = HeaderValue::from_str(value)?;
+        value.set_sensitive(true);
+        Ok(Self {
+            header: HeaderName::from_bytes(name.as_bytes())?,
+            value,
+        })
+    }
+}
+
+impl CredentialProvider for StaticToken {
+    fn authenticate<'a>(
+        &'a self,
+        request: &'a mut reqwest::Request,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        request
+            .headers_mut()
+            .insert(self.header.clone(), self.value.clone());
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+/// Access token returned by an `OAuth2` token endpoint or the GCP metadata
+/// server
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+/// Keeps the last access token until it is about to expire
+#[derive(Default)]
+struct TokenCache {
+    token: Mutex<Option<(String, Option<Instant>)>>,
+}
+
+impl TokenCache {
+    async fn get_or_refresh<F, Fut>(&self, refresh: F) -> Result<String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<TokenResponse>>,
+    {
+        // Keep the lock while refreshing, so that concurrent requests wait for
+        // the same token instead of all fetching one
+        let mut token = self.token.lock().await;
+        if let Some((value, expires_at)) = &*token {
+            if expires_at.map_or(true, |expires_at| {
+                Instant::now() + REFRESH_MARGIN < expires_at
+            }) {
+                return Ok(value.clone());
+            }
+        }
+
+        let response = refresh().await?;
+        let expires_at = response
+            .expires_in
+            .map(|expires_in| Instant::now() + Duration::from_secs(expires_in));
+        *token = Some((response.access_token.clone(), expires_at));
+        Ok(response.access_token)
+    }
+}
+
+/// Fetches bearer tokens with the `OAuth2` client credentials grant, refreshing
+/// them when they expire
+pub struct OAuth2ClientCredentials {
+    client: Client,
+    token_url: Url,
+    client_id: String,
+    client_secret: String,
+    scopes: Vec<String>,
+    token: TokenCache,
+}
+
+impl OAuth2ClientCredentials {
+    /// Fetch tokens from the given token endpoint, authenticating with the
+    /// client ID and secret
+    pub fn new(
+        token_url: Url,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        Self {
+            client: Client::new(),
+            token_url,
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            scopes: Vec::new(),
+            token: TokenCache::default(),
+        }
+    }
+
+    /// Set the scopes requested for the tokens
+    #[must_use]
+    pub fn with_scopes(mut self, scopes: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    async fn fetch(&self) -> Result<TokenResponse> {
+        let mut form = vec![("grant_type", "client_credentials".to_owned())];
+        if !self.scopes.is_empty() {
+            form.push(("scope", self.scopes.join(" ")));
+        }
+
+        let response = self
+            .client
+            .post(self.token_url.clone())
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+}
+
+impl CredentialProvider for OAuth2ClientCredentials {
+    fn authenticate<'a>(
+        &'a self,
+        request: &'a mut reqwest::Request,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            let token = self.token.get_or_refresh(|| self.fetch()).await?;
+            set_sensitive(request, AUTHORIZATION, &format!("Bearer {token}"))
+        })
+    }
+}
+
+/// Fetches the access token of the service account attached to the GCP
+/// instance from the metadata server, refreshing it when it expires
+pub struct GcpMetadata {
+    client: Client,
+    token_url: Url,
+    token: TokenCache,
+}
+
+impl Default for GcpMetadata {
+    fn default() -> Self {
+        Self {
+            client: Client::new(),
+            token_url: Url::parse(GCP_METADATA_TOKEN_URL).expect("valid metadata URL"),
+            token: TokenCache::default(),
+        }
+    }
+}
+
+impl GcpMetadata {
+    /// Fetch the token of the default service account from
+    /// [`GCP_METADATA_TOKEN_URL`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fetch the token from another endpoint, for example to use another
+    /// service account
+    #[must_use]
+    pub fn with_token_url(mut self, token_url: Url) -> Self {
+        self.token_url = token_url;
+        self
+    }
+
+    async fn fetch(&self) -> Result<TokenResponse> {
+        let response = self
+            .client
+            .get(self.token_url.clone())
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+}
+
+impl CredentialProvider for GcpMetadata {
+    fn authenticate<'a>(
+        &'a self,
+        request: &'a mut reqwest::Request,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            let token = self.token.get_or_refresh(|| self.fetch()).await?;
+            set_sensitive(request, AUTHORIZATION, &format!("Bearer {token}"))
+        })
+    }
+}
+
+/// Signs requests with AWS Signature Version 4
+#[cfg(feature = "http-aws-sigv4")]
+pub struct AwsSigV4 {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    region: String,
+    service: String,
+}
+
+#[cfg(feature = "http-aws-sigv4")]
+impl AwsSigV4 {
+    /// Sign the requests for the given region and service, for example
+    /// `us-east-1` and `execute-api`
+    pub fn new(
+        access_key_id: impl Into<String>,
+        secret_access_key: impl Into<String>,
+        region: impl Into<String>,
+        service: impl Into<String>,
+    ) -> Self {
+        Self {
+            access_key_id: access_key_id.into(),
+            secret_access_key: secret_access_key.into(),
+            session_token: None,
+            region: region.into(),
+            service: service.into(),
+        }
+    }
+
+    /// Read the credentials from the `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` environment variables
+    /// of the current process
+    ///
+    /// # Errors
+    ///
+    /// If the access key ID or secret access key are not set
+    pub fn from_env(region: impl Into<String>, service: impl Into<String>) -> Result<Self> {
+        Self::from_vars(|name| std::env::var(name).ok(), region, service)
+    }
+
+    /// Read the credentials from the `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN` environment variables
+    /// of the context, as set with
+    /// [`RuntimeInfo::with_env`](crate::builtins::impls::opa::RuntimeInfo::with_env)
+    ///
+    /// # Errors
+    ///
+    /// If the access key ID or secret access key are not set
+    pub fn from_context<C: crate::EvaluationContext>(
+        ctx: &C,
+        region: impl Into<String>,
+        service: impl Into<String>,
+    ) -> Result<Self> {
+        let runtime = ctx.runtime_info().cloned().unwrap_or_default();
+        Self::from_vars(|name| runtime.env_var(name), region, service)
+    }
+
+    fn from_vars(
+        var: impl Fn(&str) -> Option<String>,
+        region: impl Into<String>,
+        service: impl Into<String>,
+    ) -> Result<Self> {
+        use anyhow::Context;
+
+        let access_key_id = var("AWS_ACCESS_KEY_ID").context("AWS_ACCESS_KEY_ID is not set")?;
+        let secret_access_key =
+            var("AWS_SECRET_ACCESS_KEY").context("AWS_SECRET_ACCESS_KEY is not set")?;
+        let mut provider = Self::new(access_key_id, secret_access_key, region, service);
+        provider.session_token = var("AWS_SESSION_TOKEN");
+        Ok(provider)
+    }
+
+    /// Send a session token along with temporary credentials
+    #[must_use]
+    pub fn with_session_token(mut self, session_token: impl Into<String>) -> Self {
+        self.session_token = Some(session_token.into());
+        self
+    }
+
+    fn sign(
+        &self,
+        request: &mut reqwest::Request,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        use std::collections::BTreeMap;
+
+        use sha2::{Digest, Sha256};
+
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex::encode(Sha256::digest(
+            request
+                .body()
+                .and_then(reqwest::Body::as_bytes)
+                .unwrap_or_default(),
+        ));
+        let host = host_header(request.url())?;
+
+        let headers = request.headers_mut();
+        headers.insert("x-amz-date", HeaderValue::from_str(&amz_date)?);
+        // Only S3 requires the payload hash as a header
+        if self.service == "s3" {
+            headers.insert(
+                "x-amz-content-sha256",
+                HeaderValue::from_str(&payload_hash)?,
+            );
+        }
+        if let Some(session_token) = &self.session_token {
+            let mut value = HeaderValue::from_str(session_token)?;
+            value.set_sensitive(true);
+            headers.insert("x-amz-security-token", value);
+        }
+
+        // Sign the host and content type along with the AWS headers, but not
+        // the headers the client might add later on
+        let mut signed = BTreeMap::from([("host".to_owned(), host)]);
+        for (name, value) in request.headers() {
+            let name = name.as_str();
+            if name == "content-type" || name.starts_with("x-amz-") {
+                signed.insert(name.to_owned(), value.to_str()?.trim().to_owned());
+            }
+        }
+        let authorization = self.authorization(
+            request.method().as_str(),
+            request.url(),
+            &signed,
+            &payload_hash,
+            &amz_date,
+        );
+        set_sensitive(request, AUTHORIZATION, &authorization)
+    }
+
+    /// Compute the `Authorization` header of a request, given the headers to
+    /// sign keyed by lowercase name, the hex-encoded SHA-256 of its payload
+    /// and its `x-amz-date`
+    pub(crate) fn authorization(
+        &self,
+        method: &str,
+        url: &Url,
+        signed: &std::collections::BTreeMap<String, String>,
+        payload_hash: &str,
+        amz_date: &str,
+    ) -> String {
+        use std::fmt::Write;
+
+        use sha2::{Digest, Sha256};
+
+        let mut query: Vec<_> = url
+            .query_pairs()
+            .map(|(name, value)| (uri_encode(&name), uri_encode(&value)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&");
+        let path = url.path();
+
+        let mut canonical_headers = String::new();
+        for (name, value) in signed {
+            let _ = writeln!(canonical_headers, "{name}:{value}");
+        }
+        let signed_headers = signed
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "{method}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{payload_hash}"
+        );
+        let date = &amz_date[..8];
+        let scope = format!("{date}/{}/{}/aws4_request", self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut key = hmac_sha256(format!("AWS4{}", self.secret_access_key).as_bytes(), date);
+        for part in [self.region.as_str(), self.service.as_str(), "aws4_request"] {
+            key = hmac_sha256(&key, part);
+        }
+        let signature = hex::encode(hmac_sha256(&key, &string_to_sign));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key_id
+        )
+    }
+}
+
+#[cfg(feature = "http-aws-sigv4")]
+impl CredentialProvider for AwsSigV4 {
+    fn authenticate<'a>(
+        &'a self,
+        request: &'a mut reqwest::Request,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(std::future::ready(self.sign(request, chrono::Utc::now())))
+    }
+}
+
+/// The value of the `Host` header signed for the URL
+#[cfg(feature = "http-aws-sigv4")]
+pub(crate) fn host_header(url: &Url) -> Result<String> {
+    use anyhow::Context;
+
+    let host = url.host_str().context("missing host")?;
+    Ok(match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_owned(),
+    })
+}
+
+#[cfg(feature = "http-aws-sigv4")]
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode everything but the unreserved characters, as required by
+/// the canonical request
+#[cfg(feature = "http-aws-sigv4")]
+fn uri_encode(value: &str) -> String {
+    use std::fmt::Write;
+
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str) -> reqwest::Request {
+        reqwest::Request::new(reqwest::Method::GET, Url::parse(url).unwrap())
+    }
+
+    #[tokio::test]
+    async fn static_token() {
+        let providers = CredentialProviders::default()
+            .with_provider("token", StaticToken::bearer("secret").unwrap())
+            .with_provider("key", StaticToken::header("x-api-key", "secret").unwrap());
+        assert!(providers.get("unknown").is_err());
+        let debug = format!("{providers:?}");
+        assert!(debug.contains(r#""token""#) && !debug.contains("secret"));
+
+        let mut req = request("https://example.com/");
+        providers
+            .get("token")
+            .unwrap()
+            .authenticate(&mut req)
+            .await
+            .unwrap();
+        providers
+            .get("key")
+            .unwrap()
+            .authenticate(&mut req)
+            .await
+            .unwrap();
+        assert_eq!(req.headers()[AUTHORIZATION], "Bearer secret");
+        assert_eq!(req.headers()["x-api-key"], "secret");
+        assert!(req.headers()[AUTHORIZATION].is_sensitive());
+    }
+
+    #[tokio::test]
+    async fn token_cache_refresh() {
+        let cache = TokenCache::default();
+        let token = |value: &str, expires_in| {
+            let response = TokenResponse {
+                access_token: value.to_owned(),
+                expires_in,
+            };
+            async move { Ok(response) }
+        };
+
+        assert_eq!(
+            cache
+                .get_or_refresh(|| token("a", Some(3600)))
+                .await
+                .unwrap(),
+            "a"
+        );
+        assert_eq!(
+            cache
+                .get_or_refresh(|| token("b", Some(3600)))
+                .await
+                .unwrap(),
+            "a"
+        );
+
+        // Tokens about to expire are refreshed
+        *cache.token.lock().await = Some(("a".to_owned(), Some(Instant::now())));
+        assert_eq!(
+            cache.get_or_refresh(|| token("b", None)).await.unwrap(),
+            "b"
+        );
+        assert_eq!(
+            cache.get_or_refresh(|| token("c", None)).await.unwrap(),
+            "b"
+        );
+    }
+
+    #[cfg(feature = "http-aws-sigv4")]
+    #[test]
+    fn aws_sigv4() {
+        use chrono::TimeZone;
+
+        // Example from the AWS documentation
+        let provider = AwsSigV4::new(
+            "AKIDEXAMPLE",
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "us-east-1",
+            "iam",
+        );
+        let mut req = request("https://iam.amazonaws.com/?Version=2010-05-08&Action=ListUsers");
+        req.headers_mut().insert(
+            "content-type",
+            HeaderValue::from_static("application/x-www-form-urlencoded; charset=utf-8"),
+        );
+        let now = chrono::Utc
+            .with_ymd_and_hms(2015, 8, 30, 12, 36, 0)
+            .unwrap();
+        provider.sign(&mut req, now).unwrap();
+
+        assert_eq!(req.headers()["x-amz-date"], "20150830T123600Z");
+        assert_eq!(
+            req.headers()[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+    }
+
+    #[cfg(feature = "http-aws-sigv4")]
+    #[test]
+    fn aws_sigv4_from_context() {
+        use crate::{builtins::impls::opa::RuntimeInfo, DefaultContext};
+
+        let env = |vars: &[(&str, &str)]| {
+            let env = vars
+                .iter()
+                .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+                .collect();
+            DefaultContext::default().with_runtime_info(RuntimeInfo::default().with_env(env))
+        };
+
+        let ctx = env(&[
+            ("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE"),
+            ("AWS_SECRET_ACCESS_KEY", "secret"),
+            ("AWS_SESSION_TOKEN", "token"),
+        ]);
+        let provider = AwsSigV4::from_context(&ctx, "us-east-1", "iam").unwrap();
+        assert_eq!(provider.access_key_id, "AKIDEXAMPLE");
+        assert_eq!(provider.session_token.as_deref(), Some("token"));
+
+        let ctx = env(&[("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE")]);
+        assert!(AwsSigV4::from_context(&ctx, "us-east-1", "iam").is_err());
+    }
+}
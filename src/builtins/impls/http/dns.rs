@@ -0,0 +1,147 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Resolution of the hosts reached by `http.send`, for names the system
+//! resolver does not know about
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+
+/// Resolves host names to IP addresses for `http.send`, see
+/// [`super::HttpConfig::with_resolver`]
+pub trait HostResolver: Send + Sync {
+    /// Resolve the host name. Returning no address falls back to the system
+    /// resolver.
+    ///
+    /// # Errors
+    ///
+    /// If the host could not be resolved
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<IpAddr>>> + Send + 'a>>;
+}
+
+/// Static host mappings and custom resolver, tried in that order before the
+/// system resolver
+#[derive(Clone, Default)]
+pub(super) struct HostResolution {
+    hosts: Arc<HashMap<String, Vec<IpAddr>>>,
+    resolver: Option<Arc<dyn HostResolver>>,
+}
+
+impl fmt::Debug for HostResolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostResolution")
+            .field("hosts", &self.hosts)
+            .field("resolver", &self.resolver.is_some())
+            .finish()
+    }
+}
+
+impl HostResolution {
+    pub(super) fn with_hosts(mut self, host: &str, addrs: Vec<IpAddr>) -> Self {
+        Arc::make_mut(&mut self.hosts).insert(host.to_ascii_lowercase(), addrs);
+        self
+    }
+
+    pub(super) fn with_resolver(mut self, resolver: Arc<dyn HostResolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Whether the system resolver is not the only one
+    pub(super) fn is_custom(&self) -> bool {
+        !self.hosts.is_empty() || self.resolver.is_some()
+    }
+
+    /// Resolve the host to socket addresses with the given port
+    pub(super) async fn lookup(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+        let with_port = |ips: &[IpAddr]| -> Vec<SocketAddr> {
+            ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect()
+        };
+
+        if let Some(ips) = self.hosts.get(&host.to_ascii_lowercase()) {
+            return Ok(with_port(ips));
+        }
+
+        if let Some(resolver) = &self.resolver {
+            let ips = resolver
+                .resolve(host)
+                .await
+                .with_context(|| format!("could not resolve {host}"))?;
+            if !ips.is_empty() {
+                return Ok(with_port(&ips));
+            }
+        }
+
+        Ok(tokio::net::lookup_host((host, port))
+            .await
+            .with_context(|| format!("could not resolve {host}"))?
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Cluster;
+
+    impl HostResolver for Cluster {
+        fn resolve<'a>(
+            &'a self,
+            host: &'a str,
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<IpAddr>>> + Send + 'a>> {
+            Box::pin(async move {
+                match host {
+                    "api.default.svc" => Ok(vec![IpAddr::from([10, 0, 0, 2])]),
+                    "broken.default.svc" => anyhow::bail!("resolver failure"),
+                    _ => Ok(Vec::new()),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn lookup() {
+        let resolution = HostResolution::default()
+            .with_hosts("Backend.Internal", vec![IpAddr::from([10, 0, 0, 1])])
+            .with_resolver(Arc::new(Cluster));
+        assert!(resolution.is_custom());
+        assert!(!HostResolution::default().is_custom());
+
+        assert_eq!(
+            resolution.lookup("backend.internal", 443).await.unwrap(),
+            [SocketAddr::from(([10, 0, 0, 1], 443))]
+        );
+        assert_eq!(
+            resolution.lookup("api.default.svc", 80).await.unwrap(),
+            [SocketAddr::from(([10, 0, 0, 2], 80))]
+        );
+        assert!(resolution.lookup("broken.default.svc", 80).await.is_err());
+
+        // Unknown hosts fall back to the system resolver
+        let addrs = resolution.lookup("127.0.0.1", 80).await.unwrap();
+        assert_eq!(addrs, [SocketAddr::from(([127, 0, 0, 1], 80))]);
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-host rate limiting and circuit breaking of `http.send` requests, so
+//! that a policy evaluated at high rates cannot overwhelm the services it
+//! calls
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Error returned when a request exceeds the [`RateLimit`] of its host
+#[derive(Debug, thiserror::Error)]
+#[error("http.send: rate limit of {host} exceeded")]
+pub struct RateLimited {
+    host: String,
+}
+
+/// Error returned when the [`CircuitBreaker`] of the host is open
+#[derive(Debug, thiserror::Error)]
+#[error("http.send: circuit breaker for {host} is open")]
+pub struct CircuitOpen {
+    host: String,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket limiting the rate of requests sent to each host, see
+/// [`super::HttpConfig::with_rate_limit`].
+///
+/// Cloning it is cheap, and clones share the same buckets.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    requests_per_second: f64,
+    burst: f64,
+    max_wait: Duration,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimit {
+    /// Allow the given number of requests per second to each host, with
+    /// bursts of the same size
+    #[must_use]
+    pub fn per_second(requests: u32) -> Self {
+        Self {
+            requests_per_second: f64::from(requests.max(1)),
+            burst: f64::from(requests.max(1)),
+            max_wait: Duration::ZERO,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Set the number of requests which can be sent at once to a host after
+    /// it was idle
+    #[must_use]
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = f64::from(burst.max(1));
+        self
+    }
+
+    /// Set how long requests wait for the limit to allow them before
+    /// failing. Requests fail right away by default.
+    #[must_use]
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Take a token from the bucket of the host, waiting for one if allowed
+    pub(super) async fn acquire(&self, host: &str) -> Result<(), RateLimited> {
+        let wait = {
+            let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            let bucket = buckets.entry(host.to_owned()).or_insert(Bucket {
+                tokens: self.burst,
+                updated_at: now,
+            });
+
+            let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.requests_per_second).min(self.burst);
+            bucket.updated_at = now;
+
+            let wait =
+                Duration::from_secs_f64((1.0 - bucket.tokens).max(0.0) / self.requests_per_second);
+            if wait > self.max_wait {
+                #[cfg(feature = "metrics")]
+                crate::metrics::observe_rate_limited(host);
+                return Err(RateLimited {
+                    host: host.to_owned(),
+                });
+            }
+
+            // Take the token right away, so that waiting requests are served
+            // in order
+            bucket.tokens -= 1.0;
+            wait
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+}
+
+/// State of the circuit of a host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CircuitState {
+    /// Requests are sent
+    Closed,
+
+    /// Requests fail right away, after too many failures
+    Open,
+
+    /// A single request is sent to check whether the host recovered
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// Value of the state in the `opa_wasm_http_circuit_state` metric
+    #[cfg(feature = "metrics")]
+    fn gauge(self) -> i64 {
+        match self {
+            Self::Closed => 0,
+            Self::Open => 1,
+            Self::HalfOpen => 2,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_started_at: Option<Instant>,
+}
+
+/// Stops sending requests to a host after consecutive failures, until a
+/// probe request succeeds, see [`super::HttpConfig::with_circuit_breaker`].
+///
+/// Network errors and `5xx` responses count as failures. Once open, the
+/// circuit lets a single probe request through after the open duration.
+///
+/// Cloning it is cheap, and clones share the same circuits.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+}
+
+impl CircuitBreaker {
+    /// Open the circuit of a host after the given number of consecutive
+    /// failures, for the given duration
+    #[must_use]
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            circuits: Arc::default(),
+        }
+    }
+
+    /// Get the current state of the circuit of a host, as `host:port`
+    #[must_use]
+    pub fn state(&self, host: &str) -> CircuitState {
+        let circuits = self.circuits.lock().unwrap_or_else(PoisonError::into_inner);
+        circuits
+            .get(host)
+            .map_or(CircuitState::Closed, |circuit| self.circuit_state(circuit))
+    }
+
+    fn circuit_state(&self, circuit: &Circuit) -> CircuitState {
+        match circuit.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.open_duration => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Check whether a request can be sent to the host
+    pub(super) fn check(&self, host: &str) -> Result<(), CircuitOpen> {
+        let mut circuits = self.circuits.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(circuit) = circuits.get_mut(host) else {
+            return Ok(());
+        };
+
+        let allowed = match self.circuit_state(circuit) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                // Let another probe through if the last one never finished
+                let probing = circuit
+                    .probe_started_at
+                    .is_some_and(|started_at| started_at.elapsed() < self.open_duration);
+                if !probing {
+                    circuit.probe_started_at = Some(Instant::now());
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::set_circuit_state(host, CircuitState::HalfOpen.gauge());
+                }
+                !probing
+            }
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(CircuitOpen {
+                host: host.to_owned(),
+            })
+        }
+    }
+
+    /// Record the outcome of a request sent to the host
+    pub(super) fn record(&self, host: &str, success: bool) {
+        let mut circuits = self.circuits.lock().unwrap_or_else(PoisonError::into_inner);
+        let circuit = circuits.entry(host.to_owned()).or_default();
+
+        if success {
+            *circuit = Circuit::default();
+        } else {
+            circuit.consecutive_failures = circuit.consecutive_failures.saturating_add(1);
+            // A failed probe opens the circuit again right away
+            if circuit.probe_started_at.is_some()
+                || circuit.consecutive_failures >= self.failure_threshold
+            {
+                circuit.opened_at = Some(Instant::now());
+                circuit.probe_started_at = None;
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        crate::metrics::set_circuit_state(host, self.circuit_state(circuit).gauge());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rate_limit() {
+        let limit = RateLimit::per_second(1).with_burst(2);
+        assert!(limit.acquire("a:443").await.is_ok());
+        assert!(limit.acquire("a:443").await.is_ok());
+        assert!(limit.acquire("a:443").await.is_err());
+        // Hosts have their own buckets
+        assert!(limit.acquire("b:443").await.is_ok());
+
+        let limit = RateLimit::per_second(100)
+            .with_burst(1)
+            .with_max_wait(Duration::from_secs(1));
+        let start = Instant::now();
+        assert!(limit.acquire("a:443").await.is_ok());
+        assert!(limit.acquire("a:443").await.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(9));
+    }
+
+    #[test]
+    fn circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        assert_eq!(breaker.state("a:443"), CircuitState::Closed);
+
+        breaker.record("a:443", false);
+        assert!(breaker.check("a:443").is_ok());
+        breaker.record("a:443", false);
+        assert_eq!(breaker.state("a:443"), CircuitState::Open);
+        assert!(breaker.check("a:443").is_err());
+        assert!(breaker.check("b:443").is_ok());
+
+        // A single probe is let through once the circuit is half open
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(breaker.state("a:443"), CircuitState::HalfOpen);
+        assert!(breaker.check("a:443").is_ok());
+        assert!(breaker.check("a:443").is_err());
+
+        // A failed probe opens it again, a successful one closes it
+        breaker.record("a:443", false);
+        assert_eq!(breaker.state("a:443"), CircuitState::Open);
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.check("a:443").is_ok());
+        breaker.record("a:443", true);
+        assert_eq!(breaker.state("a:443"), CircuitState::Closed);
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Canned responses for `http.send`, so that policies calling external
+//! services can be tested without a network
+
+#![allow(clippy::module_name_repetitions)]
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use anyhow::{bail, Result};
+// Re-exported, so that rules can be built without depending on reqwest
+pub use reqwest::{header, Method, StatusCode};
+
+use self::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+
+use super::Request;
+
+/// Intercepts `http.send` calls and answers them with canned responses,
+/// see [`super::HttpConfig::with_mock_transport`].
+///
+/// Rules are matched in the order they were added, and requests matching no
+/// rule fail instead of reaching the network. Caching, egress and timeout
+/// options still apply.
+///
+/// Cloning it is cheap, and clones share the same rules and received
+/// requests.
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    inner: Arc<Mutex<MockState>>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    rules: Vec<(MockRequest, MockResponse)>,
+    received: Vec<String>,
+}
+
+impl MockTransport {
+    /// Create a transport answering no request
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer the requests matching `request` with `response`
+    #[must_use]
+    pub fn with_response(self, request: MockRequest, response: MockResponse) -> Self {
+        self.inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .rules
+            .push((request, response));
+        self
+    }
+
+    /// The requests received so far, formatted as `METHOD URL`
+    #[must_use]
+    pub fn received(&self) -> Vec<String> {
+        self.inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .received
+            .clone()
+    }
+
+    pub(super) fn respond(&self, data: &Request) -> Result<(StatusCode, HeaderMap, String)> {
+        let mut state = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        state.received.push(format!("{} {}", data.method, data.url));
+
+        let Some((_, response)) = state
+            .rules
+            .iter()
+            .find(|(request, _)| request.matches(data))
+        else {
+            bail!(
+                "http.send: no mock response for {} {}",
+                data.method,
+                data.url
+            );
+        };
+        Ok((
+            response.status,
+            response.headers.clone(),
+            response.body.clone(),
+        ))
+    }
+}
+
+/// Requests answered by a [`MockTransport`] rule
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    method: Option<Method>,
+    url: String,
+    body: Option<serde_json::Value>,
+}
+
+impl MockRequest {
+    /// Match requests with the given method and URL. A URL ending with `*`
+    /// matches any URL starting with the rest of it.
+    pub fn new(method: Method, url: impl Into<String>) -> Self {
+        Self {
+            method: Some(method),
+            ..Self::any(url)
+        }
+    }
+
+    /// Match requests with the given URL, whatever their method
+    pub fn any(url: impl Into<String>) -> Self {
+        Self {
+            method: None,
+            url: url.into(),
+            body: None,
+        }
+    }
+
+    /// Only match requests with the given JSON body
+    #[must_use]
+    pub fn with_body(mut self, body: serde_json::Value) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    fn matches(&self, data: &Request) -> bool {
+        let url_matches = match self.url.strip_suffix('*') {
+            Some(prefix) => data.url.starts_with(prefix),
+            None => data.url == self.url,
+        };
+
+        url_matches
+            && self
+                .method
+                .as_ref()
+                .map_or(true, |method| *method == data.method)
+            && self
+                .body
+                .as_ref()
+                .map_or(true, |body| data.body.as_ref() == Some(body))
+    }
+}
+
+/// Canned response returned by a [`MockTransport`] rule
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: String,
+}
+
+impl MockResponse {
+    /// A response with the given status and an empty body
+    #[must_use]
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            body: String::new(),
+        }
+    }
+
+    /// A response with the given status and JSON body
+    pub fn json(status: StatusCode, body: &serde_json::Value) -> Self {
+        Self::new(status)
+            .with_header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+            .with_body(body.to_string())
+    }
+
+    /// Set the raw body of the response, decoded according to its
+    /// `content-type` header like actual responses
+    #[must_use]
+    pub fn with_body(mut self, body: impl Into<String>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Add a header to the response
+    #[must_use]
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        builtins::impls::http::{send, BodyType, HttpConfig},
+        DefaultContext,
+    };
+
+    fn request(method: &str, url: &str, body: Option<serde_json::Value>) -> Request {
+        serde_json::from_value(json!({
+            "method": method,
+            "url": url,
+            "body": body,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn mock_transport() {
+        let mock = MockTransport::new()
+            .with_response(
+                MockRequest::new(Method::POST, "https://api.example.com/users")
+                    .with_body(json!({"name": "alice"})),
+                MockResponse::json(StatusCode::CREATED, &json!({"id": 1})),
+            )
+            .with_response(
+                MockRequest::any("https://api.example.com/*"),
+                MockResponse::new(StatusCode::NOT_FOUND).with_body("not found"),
+            );
+        let mut ctx = DefaultContext::default()
+            .with_http_config(HttpConfig::default().with_mock_transport(mock.clone()));
+
+        let created = send(
+            &mut ctx,
+            request(
+                "POST",
+                "https://api.example.com/users",
+                Some(json!({"name": "alice"})),
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(created.status_code, 201);
+        assert!(matches!(created.body, Some(BodyType::Json(body)) if body == json!({"id": 1})));
+
+        let missing = send(
+            &mut ctx,
+            request("POST", "https://api.example.com/users", None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(missing.status_code, 404);
+        assert_eq!(missing.raw_body, "not found");
+
+        assert!(send(&mut ctx, request("GET", "https://example.com/", None))
+            .await
+            .is_err());
+        assert_eq!(
+            mock.received(),
+            [
+                "POST https://api.example.com/users",
+                "POST https://api.example.com/users",
+                "GET https://example.com/",
+            ]
+        );
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Retries of failed `http.send` requests
+
+#![allow(clippy::module_name_repetitions)]
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use reqwest::{Method, StatusCode};
+
+use super::Request;
+
+/// Status codes retried by default, as they usually denote transient errors
+pub const DEFAULT_RETRY_STATUS_CODES: &[u16] = &[408, 429, 500, 502, 503, 504];
+
+/// How failed `http.send` requests are retried, see
+/// [`super::HttpConfig::with_retry_policy`].
+///
+/// Requests set the number of retries with `max_retry_atempts`, and can
+/// override the other settings with the `retry_base_delay`,
+/// `retry_max_delay`, `retry_backoff_factor`, `retry_jitter`,
+/// `retry_status_codes` and `retry_non_idempotent` fields.
+///
+/// Network errors and responses with one of the retried status codes are
+/// retried, waiting `base_delay * backoff_factor ^ attempt` between attempts,
+/// up to `max_delay`. Requests with a non-idempotent method, like `POST`, are
+/// never retried unless explicitly allowed.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    base_delay: Duration,
+    backoff_factor: f64,
+    max_delay: Duration,
+    jitter: bool,
+    status_codes: Vec<u16>,
+    non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            base_delay: Duration::from_secs(1),
+            backoff_factor: 2.0,
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            status_codes: DEFAULT_RETRY_STATUS_CODES.to_vec(),
+            non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Set the number of retries of requests not setting
+    /// `max_retry_atempts`. Requests are not retried by default.
+    #[must_use]
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Set the delay before the first retry
+    #[must_use]
+    pub fn with_base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Set the factor the delay is multiplied by after each retry. Factors
+    /// below `1` are treated as `1`.
+    #[must_use]
+    pub fn with_backoff_factor(mut self, backoff_factor: f64) -> Self {
+        self.backoff_factor = backoff_factor;
+        self
+    }
+
+    /// Set the maximum delay between two attempts
+    #[must_use]
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Wait for a random duration between zero and the computed delay, so
+    /// that clients failing at the same time don't retry at the same time
+    #[must_use]
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Set the status codes of the responses which are retried
+    #[must_use]
+    pub fn with_status_codes(mut self, status_codes: impl IntoIterator<Item = u16>) -> Self {
+        self.status_codes = status_codes.into_iter().collect();
+        self
+    }
+
+    /// Also retry requests with a non-idempotent method, like `POST` or
+    /// `PATCH`
+    #[must_use]
+    pub fn with_non_idempotent_retries(mut self, non_idempotent: bool) -> Self {
+        self.non_idempotent = non_idempotent;
+        self
+    }
+
+    /// Apply the overrides of the request
+    pub(super) fn for_request(&self, data: &Request) -> Self {
+        Self {
+            max_retries: data.max_retry_atempts.unwrap_or(self.max_retries),
+            base_delay: data
+                .retry_base_delay
+                .as_ref()
+                .map_or(self.base_delay, super::Timeout::duration),
+            backoff_factor: data.retry_backoff_factor.unwrap_or(self.backoff_factor),
+            max_delay: data
+                .retry_max_delay
+                .as_ref()
+                .map_or(self.max_delay, super::Timeout::duration),
+            jitter: data.retry_jitter.unwrap_or(self.jitter),
+            status_codes: data
+                .retry_status_codes
+                .clone()
+                .unwrap_or_else(|| self.status_codes.clone()),
+            non_idempotent: data.retry_non_idempotent.unwrap_or(self.non_idempotent),
+        }
+    }
+
+    /// How long to wait before retrying, if the outcome of the attempt
+    /// should be retried. `status` is `None` if the request failed.
+    pub(super) fn delay(
+        &self,
+        attempt: u32,
+        method: &Method,
+        status: Option<StatusCode>,
+    ) -> Option<Duration> {
+        if attempt >= self.max_retries || !(self.non_idempotent || is_idempotent(method)) {
+            return None;
+        }
+
+        if let Some(status) = status {
+            if !self.status_codes.contains(&status.as_u16()) {
+                return None;
+            }
+        }
+
+        let exponent = i32::try_from(attempt).unwrap_or(i32::MAX);
+        let mut delay = (self.base_delay.as_secs_f64()
+            * self.backoff_factor.max(1.0).powi(exponent))
+        .min(self.max_delay.as_secs_f64());
+        if self.jitter {
+            delay *= random_fraction();
+        }
+        Some(Duration::from_secs_f64(delay))
+    }
+}
+
+/// Whether sending the request more than once has the same effect as sending
+/// it once
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// A random number in `[0, 1)`, good enough to spread retries
+#[allow(clippy::cast_precision_loss)]
+fn random_fraction() -> f64 {
+    // The keys of the standard hasher are randomly seeded
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1_u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(value: serde_json::Value) -> Request {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::default()
+            .with_max_retries(5)
+            .with_base_delay(Duration::from_millis(100))
+            .with_backoff_factor(3.0)
+            .with_max_delay(Duration::from_secs(1))
+            .with_jitter(false);
+        let delays: Vec<_> = (0..6)
+            .map(|attempt| policy.delay(attempt, &Method::GET, None))
+            .collect();
+        assert_eq!(
+            delays,
+            [
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(300)),
+                Some(Duration::from_millis(900)),
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(1)),
+                None,
+            ]
+        );
+
+        let jittered = policy
+            .with_jitter(true)
+            .delay(0, &Method::GET, None)
+            .unwrap();
+        assert!(jittered <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn retried_outcomes() {
+        let policy = RetryPolicy::default()
+            .with_max_retries(1)
+            .with_status_codes([503]);
+        assert!(policy
+            .delay(0, &Method::GET, Some(StatusCode::SERVICE_UNAVAILABLE))
+            .is_some());
+        assert!(policy
+            .delay(0, &Method::GET, Some(StatusCode::BAD_GATEWAY))
+            .is_none());
+        assert!(policy.delay(0, &Method::PUT, None).is_some());
+
+        // Non-idempotent requests are only retried when allowed
+        assert!(policy.delay(0, &Method::POST, None).is_none());
+        assert!(policy
+            .clone()
+            .with_non_idempotent_retries(true)
+            .delay(0, &Method::POST, None)
+            .is_some());
+    }
+
+    #[test]
+    fn request_overrides() {
+        let defaults = RetryPolicy::default().with_max_retries(2);
+        let policy = defaults.for_request(&request(serde_json::json!({
+            "method": "POST",
+            "url": "https://example.com",
+            "max_retry_atempts": 1,
+            "retry_base_delay": "10ms",
+            "retry_jitter": false,
+            "retry_status_codes": [500],
+            "retry_non_idempotent": true,
+        })));
+        assert_eq!(
+            policy.delay(0, &Method::POST, Some(StatusCode::INTERNAL_SERVER_ERROR)),
+            Some(Duration::from_millis(10))
+        );
+        assert!(policy.delay(1, &Method::POST, None).is_none());
+        assert!(policy
+            .delay(0, &Method::POST, Some(StatusCode::BAD_GATEWAY))
+            .is_none());
+
+        let policy = defaults.for_request(&request(serde_json::json!({
+            "method": "GET",
+            "url": "https://example.com",
+        })));
+        assert!(policy.delay(1, &Method::GET, None).is_some());
+    }
+}
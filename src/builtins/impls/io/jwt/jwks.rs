@@ -0,0 +1,441 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Resolution of the keys used by `io.jwt.decode_verify` from JSON Web Key
+//! Sets fetched over HTTP
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{JsonWebKey, Key};
+use crate::replay::{BuiltinRecorder, BuiltinReplayer, ReplayMode, JWKS_KEYS};
+
+/// Default maximum age of a fetched key set
+const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Default minimum delay between two fetches of the same key set
+const DEFAULT_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Default maximum duration of a key set fetch
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default maximum size of a key set, in bytes
+const DEFAULT_MAX_SIZE: usize = 1024 * 1024;
+
+/// Fetches and caches the JSON Web Key Sets used by `io.jwt.decode_verify`
+/// when its constraints contain neither `cert` nor `secret`, so that policies
+/// don't have to embed rotating public keys in their data.
+///
+/// Keys are looked up by the `kid` header of the token. A token signed with a
+/// key missing from the cached sets triggers a refresh, at most once per
+/// [`JwksResolver::with_min_refresh_interval`].
+///
+/// Cloning it is cheap, and clones share the same cached keys.
+pub struct JwksResolver {
+    urls: Vec<String>,
+    refresh_interval: Duration,
+    min_refresh_interval: Duration,
+    timeout: Duration,
+    max_size: usize,
+    client: reqwest::Client,
+    sets: Arc<Mutex<HashMap<String, KeySet>>>,
+    /// Whether the keys are recorded or replayed, set by the runtime when an
+    /// evaluation starts
+    replay: Mutex<Option<ReplayMode>>,
+}
+
+impl Clone for JwksResolver {
+    fn clone(&self) -> Self {
+        // The replay mode belongs to the runtime using this resolver, so it
+        // isn't shared with the clones
+        Self {
+            urls: self.urls.clone(),
+            refresh_interval: self.refresh_interval,
+            min_refresh_interval: self.min_refresh_interval,
+            timeout: self.timeout,
+            max_size: self.max_size,
+            client: self.client.clone(),
+            sets: self.sets.clone(),
+            replay: Mutex::default(),
+        }
+    }
+}
+
+impl Default for JwksResolver {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            min_refresh_interval: DEFAULT_MIN_REFRESH_INTERVAL,
+            timeout: DEFAULT_TIMEOUT,
+            max_size: DEFAULT_MAX_SIZE,
+            client: reqwest::Client::new(),
+            sets: Arc::default(),
+            replay: Mutex::default(),
+        }
+    }
+}
+
+impl JwksResolver {
+    /// Fetch keys from the JSON Web Key Set at the given URL. The keys of all
+    /// the sets are candidates to verify a token.
+    #[must_use]
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.urls.push(url.into());
+        self
+    }
+
+    /// Set the maximum age of a fetched key set, after which it is fetched
+    /// again
+    #[must_use]
+    pub fn with_refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// Set the minimum delay between two fetches of the same key set. This
+    /// prevents tokens with made-up key IDs from hammering the key server.
+    #[must_use]
+    pub fn with_min_refresh_interval(mut self, interval: Duration) -> Self {
+        self.min_refresh_interval = interval;
+        self
+    }
+
+    /// Set the maximum duration of a key set fetch
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the maximum size of a key set, in bytes. Larger key sets fail to
+    /// be fetched.
+    #[must_use]
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Use the given HTTP client to fetch the key sets
+    #[must_use]
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Whether any key set is configured
+    pub(super) fn is_enabled(&self) -> bool {
+        !self.urls.is_empty()
+    }
+
+    /// Record the keys used during the next evaluations, or replay them
+    /// instead of using the key sets
+    pub(crate) fn set_replay(&self, replay: Option<ReplayMode>) {
+        *self.replay.lock().unwrap_or_else(PoisonError::into_inner) = replay;
+    }
+
+    /// Returns the candidate keys for the key ID from the cached sets, or
+    /// `None` if a key set must be fetched first
+    pub(super) fn cached_keys(&self, kid: Option<&str>, now: Instant) -> Option<Vec<Key>> {
+        if self.replayer().is_some() {
+            return None;
+        }
+
+        let sets = self.sets.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.stale_urls(&sets, kid, now).is_empty() {
+            Some(self.matching(&sets, kid))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the candidate keys for the key ID, fetching the key sets which
+    /// are missing or stale first
+    pub(super) async fn keys(&self, kid: Option<&str>) -> Result<Vec<Key>> {
+        if let Some(replayer) = self.replayer() {
+            let kid = serde_json::to_vec(&kid)?;
+            let set = replayer.replay(JWKS_KEYS, &[&kid])?;
+            return Ok(KeySet::parse(&set, Instant::now())?.matching(None));
+        }
+
+        let stale = {
+            let sets = self.sets.lock().unwrap_or_else(PoisonError::into_inner);
+            self.stale_urls(&sets, kid, Instant::now())
+        };
+
+        for url in stale {
+            let fetched = self.fetch(&url).await;
+            let mut sets = self.sets.lock().unwrap_or_else(PoisonError::into_inner);
+            match fetched {
+                Ok(set) => {
+                    sets.insert(url, set);
+                }
+                // Keep using the previous keys while the server is unavailable
+                Err(error) => {
+                    let Some(previous) = sets.get_mut(&url) else {
+                        return Err(error);
+                    };
+                    tracing::warn!(%url, error = %error, "Failed to refresh JSON Web Key Set");
+                    previous.attempted_at = Instant::now();
+                }
+            }
+        }
+
+        let sets = self.sets.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(self.matching(&sets, kid))
+    }
+
+    /// Returns the URLs of the key sets to fetch before looking up the key ID
+    fn stale_urls(
+        &self,
+        sets: &HashMap<String, KeySet>,
+        kid: Option<&str>,
+        now: Instant,
+    ) -> Vec<String> {
+        // Tokens without a key ID are checked against all the keys, so they
+        // never trigger a refresh
+        let known = kid.map_or(true, |kid| sets.values().any(|set| set.contains(kid)));
+
+        self.urls
+            .iter()
+            .filter(|url| match sets.get(url.as_str()) {
+                None => true,
+                Some(set) => {
+                    now.saturating_duration_since(set.attempted_at) >= self.min_refresh_interval
+                        && (!known
+                            || now.saturating_duration_since(set.fetched_at)
+                                >= self.refresh_interval)
+                }
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn matching(&self, sets: &HashMap<String, KeySet>, kid: Option<&str>) -> Vec<Key> {
+        let sets: Vec<&KeySet> = self.urls.iter().filter_map(|url| sets.get(url)).collect();
+
+        if let Some(ReplayMode::Record(recorder)) =
+            &*self.replay.lock().unwrap_or_else(PoisonError::into_inner)
+        {
+            let keys: Vec<&Value> = sets.iter().flat_map(|set| set.matching_jwks(kid)).collect();
+            if let Err(error) = record_keys(recorder, kid, &keys) {
+                tracing::warn!(error = %error, "Failed to record the JSON Web Keys");
+            }
+        }
+
+        sets.iter().flat_map(|set| set.matching(kid)).collect()
+    }
+
+    /// The replayer to take the keys from, if the evaluation is replayed
+    fn replayer(&self) -> Option<BuiltinReplayer> {
+        match &*self.replay.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(ReplayMode::Replay(replayer)) => Some(replayer.clone()),
+            _ => None,
+        }
+    }
+
+    #[tracing::instrument(skip(self), err)]
+    async fn fetch(&self, url: &str) -> Result<KeySet> {
+        let mut response = self
+            .client
+            .get(url)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("failed to fetch the JSON Web Key Set from {url}"))?;
+        let mut body = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .with_context(|| format!("failed to fetch the JSON Web Key Set from {url}"))?
+        {
+            if body.len() + chunk.len() > self.max_size {
+                bail!(
+                    "the JSON Web Key Set at {url} is larger than {} bytes",
+                    self.max_size
+                );
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        KeySet::parse(&body, Instant::now())
+    }
+}
+
+/// Record the JSON Web Keys returned for the key ID, as a key set
+fn record_keys(recorder: &BuiltinRecorder, kid: Option<&str>, keys: &[&Value]) -> Result<()> {
+    let kid = serde_json::to_vec(&kid)?;
+    let set = serde_json::to_vec(&serde_json::json!({ "keys": keys }))?;
+    recorder.record(JWKS_KEYS, &[&kid], &set)
+}
+
+/// Keys of a JSON Web Key Set, with their key ID and original JSON Web Key
+struct KeySet {
+    keys: Vec<(Option<String>, Key, Value)>,
+    /// When the set was last fetched
+    fetched_at: Instant,
+    /// When the set was last fetched or failed to be fetched
+    attempted_at: Instant,
+}
+
+impl KeySet {
+    /// Parse a key set, skipping the keys which can't verify signatures
+    fn parse(body: &[u8], now: Instant) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Set {
+            keys: Vec<Value>,
+        }
+
+        let set: Set = serde_json::from_slice(body).context("invalid JSON Web Key Set")?;
+        let keys = set
+            .keys
+            .into_iter()
+            .filter(|key| key.get("use").map_or(true, |usage| usage == "sig"))
+            .filter_map(|jwk| {
+                let key: JsonWebKey = serde_json::from_value(jwk.clone()).ok()?;
+                match key.public_key() {
+                    Ok(public_key) => Some((key.kid, public_key, jwk)),
+                    Err(error) => {
+                        tracing::debug!(kid = ?key.kid, %error, "Skipping JSON Web Key");
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            keys,
+            fetched_at: now,
+            attempted_at: now,
+        })
+    }
+
+    fn contains(&self, kid: &str) -> bool {
+        self.keys
+            .iter()
+            .any(|(id, _, _)| id.as_deref() == Some(kid))
+    }
+
+    fn matching(&self, kid: Option<&str>) -> Vec<Key> {
+        self.keys
+            .iter()
+            .filter(|(id, _, _)| kid.is_none() || id.as_deref() == kid)
+            .map(|(_, key, _)| key.clone())
+            .collect()
+    }
+
+    fn matching_jwks<'a>(&'a self, kid: Option<&'a str>) -> impl Iterator<Item = &'a Value> {
+        self.keys
+            .iter()
+            .filter(move |(id, _, _)| kid.is_none() || id.as_deref() == kid)
+            .map(|(_, _, jwk)| jwk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JWKS: &str = r#"{"keys": [
+        {"kty": "EC", "kid": "a", "crv": "P-256", "x": "e5riQe9LsLmpicy2KQ8Lkq_lyw_OL6tY_2ptxAJWVGQ", "y": "x-e54A0EDB0JzIxjoKzIv0naX7OZaR_31qAKLfylS_I"},
+        {"kty": "EC", "kid": "b", "use": "enc", "crv": "P-256", "x": "e5riQe9LsLmpicy2KQ8Lkq_lyw_OL6tY_2ptxAJWVGQ", "y": "x-e54A0EDB0JzIxjoKzIv0naX7OZaR_31qAKLfylS_I"},
+        {"kty": "oct", "kid": "c", "k": "c2VjcmV0"},
+        {"kty": "OKP", "crv": "Ed25519", "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"}
+    ]}"#;
+
+    fn resolver(now: Instant) -> JwksResolver {
+        let resolver = JwksResolver::default().with_url("https://example.com/jwks.json");
+        resolver.sets.lock().unwrap().insert(
+            "https://example.com/jwks.json".to_owned(),
+            KeySet::parse(JWKS.as_bytes(), now).unwrap(),
+        );
+        resolver
+    }
+
+    #[test]
+    fn parse_skips_unusable_keys() {
+        let set = KeySet::parse(JWKS.as_bytes(), Instant::now()).unwrap();
+        assert_eq!(set.keys.len(), 1);
+        assert!(set.contains("a"));
+        assert!(!set.contains("b"));
+        assert!(KeySet::parse(b"[]", Instant::now()).is_err());
+    }
+
+    #[test]
+    fn cached_keys_by_kid() {
+        let now = Instant::now();
+        let resolver = resolver(now);
+        assert!(resolver.is_enabled());
+        assert_eq!(resolver.cached_keys(Some("a"), now).unwrap().len(), 1);
+        assert_eq!(resolver.cached_keys(None, now).unwrap().len(), 1);
+
+        // Unknown key IDs only trigger a refresh after the minimum interval
+        assert_eq!(resolver.cached_keys(Some("z"), now).unwrap().len(), 0);
+        let later = now + DEFAULT_MIN_REFRESH_INTERVAL;
+        assert!(resolver.cached_keys(Some("z"), later).is_none());
+        assert!(resolver.cached_keys(Some("a"), later).is_some());
+
+        // Known keys are refreshed once the set is too old
+        let much_later = now + DEFAULT_REFRESH_INTERVAL;
+        assert!(resolver.cached_keys(Some("a"), much_later).is_none());
+
+        // Sets which were never fetched must be fetched first
+        let resolver = resolver.with_url("https://example.org/jwks.json");
+        assert!(resolver.cached_keys(Some("a"), now).is_none());
+    }
+
+    #[tokio::test]
+    async fn record_and_replay_keys() {
+        let recorder = BuiltinRecorder::new();
+        let resolver = resolver(Instant::now());
+        resolver.set_replay(Some(ReplayMode::Record(recorder.clone())));
+        assert_eq!(
+            resolver
+                .cached_keys(Some("a"), Instant::now())
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            resolver
+                .cached_keys(Some("z"), Instant::now())
+                .unwrap()
+                .len(),
+            0
+        );
+
+        // Clones don't share the replay mode
+        resolver.clone().cached_keys(None, Instant::now()).unwrap();
+
+        let recording = recorder.take();
+        assert_eq!(recording.len(), 2);
+        assert_eq!(recording.calls()[0].name, JWKS_KEYS);
+
+        // The keys are replayed without fetching the key set
+        let resolver = JwksResolver::default().with_url("http://127.0.0.1:1/jwks.json");
+        resolver.set_replay(Some(ReplayMode::Replay(BuiltinReplayer::new(recording))));
+        assert!(resolver.cached_keys(Some("a"), Instant::now()).is_none());
+        assert_eq!(resolver.keys(Some("a")).await.unwrap().len(), 1);
+        assert_eq!(resolver.keys(Some("z")).await.unwrap().len(), 0);
+        assert!(resolver.keys(Some("a")).await.is_err());
+    }
+}
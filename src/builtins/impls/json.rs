@@ -14,17 +14,251 @@
 
 //! Builtins related to JSON objects handling
 
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
 use json_patch::Patch;
+use serde_json::Value;
+
+/// A set of paths, organized as a tree of path segments
+#[derive(Default)]
+struct PathTree {
+    /// Whether a path ends at this node
+    terminal: bool,
+    children: BTreeMap<String, PathTree>,
+}
+
+impl PathTree {
+    /// Build the tree from paths given either as `/`-separated strings, or as
+    /// arrays of segments
+    fn new(paths: &[Value]) -> Result<Self> {
+        let mut tree = Self::default();
+        for path in paths {
+            let segments: Vec<String> = match path {
+                Value::String(path) => path
+                    .trim_matches('/')
+                    .split('/')
+                    .filter(|segment| !segment.is_empty())
+                    .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+                    .collect(),
+                Value::Array(segments) => segments
+                    .iter()
+                    .map(|segment| match segment {
+                        Value::String(s) => Ok(s.clone()),
+                        Value::Number(n) => Ok(n.to_string()),
+                        _ => anyhow::bail!("invalid path segment {segment}"),
+                    })
+                    .collect::<Result<_>>()?,
+                _ => anyhow::bail!("invalid path {path}"),
+            };
+
+            let mut node = &mut tree;
+            for segment in segments {
+                node = node.children.entry(segment).or_default();
+            }
+            node.terminal = true;
+        }
+
+        Ok(tree)
+    }
+
+    /// Keep only the parts of the value covered by the tree
+    fn filter(&self, value: Value) -> Option<Value> {
+        if self.terminal {
+            return Some(value);
+        }
+
+        match value {
+            Value::Object(map) => Some(Value::Object(
+                map.into_iter()
+                    .filter_map(|(k, v)| {
+                        let child = self.children.get(&k)?;
+                        Some((k, child.filter(v)?))
+                    })
+                    .collect(),
+            )),
+            Value::Array(items) => Some(Value::Array(
+                items
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, v)| self.children.get(&i.to_string())?.filter(v))
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Remove the parts of the value covered by the tree
+    fn remove(&self, value: Value) -> Option<Value> {
+        if self.terminal {
+            return None;
+        }
+
+        match value {
+            Value::Object(map) => Some(Value::Object(
+                map.into_iter()
+                    .filter_map(|(k, v)| match self.children.get(&k) {
+                        Some(child) => Some((k, child.remove(v)?)),
+                        None => Some((k, v)),
+                    })
+                    .collect(),
+            )),
+            Value::Array(items) => Some(Value::Array(
+                items
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, v)| match self.children.get(&i.to_string()) {
+                        Some(child) => child.remove(v),
+                        None => Some(v),
+                    })
+                    .collect(),
+            )),
+            value => Some(value),
+        }
+    }
+}
+
+/// Filters the object by keeping only specified paths.
+///
+/// Paths are either `/`-separated strings like `a/b/c`, or arrays of segments
+/// like `["a", "b", "c"]`.
+#[tracing::instrument(name = "json.filter", err)]
+pub fn filter(object: Value, paths: Vec<Value>) -> Result<Value> {
+    let tree = PathTree::new(&paths)?;
+    Ok(tree
+        .filter(object)
+        .unwrap_or_else(|| Value::Object(serde_json::Map::new())))
+}
+
+/// Removes paths from an object.
+///
+/// Paths are either `/`-separated strings like `a/b/c`, or arrays of segments
+/// like `["a", "b", "c"]`.
+#[tracing::instrument(name = "json.remove", err)]
+pub fn remove(object: Value, paths: Vec<Value>) -> Result<Value> {
+    let tree = PathTree::new(&paths)?;
+    Ok(tree
+        .remove(object)
+        .unwrap_or_else(|| Value::Object(serde_json::Map::new())))
+}
+
+/// Convert a path given as an array of segments, which OPA accepts in
+/// addition to JSON pointers, to a JSON pointer
+fn to_pointer(path: &mut Value) -> Result<()> {
+    let Value::Array(segments) = &*path else {
+        return Ok(());
+    };
+
+    let mut pointer = String::new();
+    for segment in segments {
+        let segment = match segment {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            _ => anyhow::bail!("invalid path segment {segment}"),
+        };
+
+        pointer.push('/');
+        pointer.push_str(&segment.replace('~', "~0").replace('/', "~1"));
+    }
+
+    *path = Value::String(pointer);
+    Ok(())
+}
 
 /// Patches an object according to RFC6902.
 /// For example: `json.patch({"a": {"foo": 1}}, [{"op": "add", "path": "/a/bar",
 /// "value": 2}])` results in `{"a": {"foo": 1, "bar": 2}`. The patches are
 /// applied atomically: if any of them fails, the result will be undefined.
-#[tracing::instrument(name = "json.patch")]
-pub fn patch(mut object: serde_json::Value, patch: Patch) -> serde_json::Value {
-    if json_patch::patch(&mut object, &patch).is_err() {
-        serde_json::Value::Object(serde_json::Map::default())
-    } else {
-        object
+///
+/// Paths can be given either as JSON pointers or as arrays of segments.
+#[tracing::instrument(name = "json.patch", err)]
+pub fn patch(mut object: Value, mut operations: Vec<Value>) -> Result<Value> {
+    for operation in &mut operations {
+        if let Value::Object(operation) = operation {
+            for key in ["path", "from"] {
+                if let Some(path) = operation.get_mut(key) {
+                    to_pointer(path)?;
+                }
+            }
+        }
+    }
+
+    let patch: Patch =
+        serde_json::from_value(Value::Array(operations)).context("invalid JSON patch")?;
+    json_patch::patch(&mut object, &patch).context("could not apply JSON patch")?;
+    Ok(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn filter_and_remove() {
+        let object = json!({"a": {"b": 1, "c": [1, 2, {"d": 3, "e": 4}]}, "f/g": 5});
+
+        assert_eq!(
+            filter(
+                object.clone(),
+                vec![json!("a/b"), json!(["a", "c", 2, "d"])]
+            )
+            .unwrap(),
+            json!({"a": {"b": 1, "c": [{"d": 3}]}})
+        );
+        assert_eq!(
+            filter(object.clone(), vec![json!("f~1g"), json!("x/y")]).unwrap(),
+            json!({"f/g": 5})
+        );
+
+        assert_eq!(
+            remove(
+                object.clone(),
+                vec![json!("a/c/0"), json!(["a", "c", 2, "e"])]
+            )
+            .unwrap(),
+            json!({"a": {"b": 1, "c": [2, {"d": 3}]}, "f/g": 5})
+        );
+        assert_eq!(
+            remove(object, vec![json!("/a"), json!("x/y")]).unwrap(),
+            json!({"f/g": 5})
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let object = json!({"a": {"foo": 1}});
+
+        assert_eq!(
+            patch(
+                object.clone(),
+                vec![json!({"op": "add", "path": "/a/bar", "value": 2})]
+            )
+            .unwrap(),
+            json!({"a": {"foo": 1, "bar": 2}})
+        );
+
+        assert_eq!(
+            patch(
+                object.clone(),
+                vec![
+                    json!({"op": "copy", "from": ["a", "foo"], "path": ["b/c"]}),
+                    json!({"op": "remove", "path": ["a"]}),
+                ]
+            )
+            .unwrap(),
+            json!({"b/c": 1})
+        );
+
+        // Patches are applied atomically
+        assert!(patch(
+            object,
+            vec![
+                json!({"op": "add", "path": "/b", "value": 2}),
+                json!({"op": "test", "path": "/a/foo", "value": 2}),
+            ]
+        )
+        .is_err());
     }
 }
@@ -0,0 +1,134 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A least-recently-used cache, used to keep compiled expressions and
+//! memoized builtin results around
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+/// A cache holding at most `capacity` values, evicting the least recently
+/// used one when full
+pub(crate) struct LruCache<V> {
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    name: &'static str,
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<String, (V, u64)>,
+}
+
+impl<V: Clone> LruCache<V> {
+    /// Create a new cache. A capacity of `0` disables caching. The name
+    /// identifies the cache in the metrics.
+    pub(crate) fn new(name: &'static str, capacity: usize) -> Self {
+        Self {
+            name,
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Get the value for a key, if it is in the cache
+    pub(crate) fn get(&mut self, key: &str) -> Option<V> {
+        self.tick += 1;
+
+        let cached = self.entries.get_mut(key);
+        #[cfg(feature = "metrics")]
+        crate::metrics::observe_cache(self.name, cached.is_some());
+
+        let (value, last_used) = cached?;
+        *last_used = self.tick;
+        Some(value.clone())
+    }
+
+    /// Insert a value, evicting the least recently used one if the cache is
+    /// full
+    pub(crate) fn insert(&mut self, key: String, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    /// Get the value for a key, building and inserting it if it is not in the
+    /// cache yet
+    #[cfg_attr(
+        not(any(feature = "glob-builtins", feature = "regex-builtins")),
+        allow(dead_code)
+    )]
+    pub(crate) fn get_or_try_insert_with(
+        &mut self,
+        key: &str,
+        build: impl FnOnce() -> Result<V>,
+    ) -> Result<V> {
+        if let Some(value) = self.get(key) {
+            return Ok(value);
+        }
+
+        let value = build()?;
+        self.insert(key.to_owned(), value.clone());
+        Ok(value)
+    }
+
+    /// Remove all the values from the cache
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Get the number of values in the cache
+    #[cfg(any(test, feature = "regex-builtins", feature = "glob-builtins"))]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eviction() {
+        let mut cache = LruCache::new("test", 2);
+        let mut insert = |key: &str| cache.get_or_try_insert_with(key, || Ok(key.len()));
+
+        insert("a").unwrap();
+        insert("bb").unwrap();
+        // Touch `a` so that `bb` is the least recently used
+        insert("a").unwrap();
+        insert("ccc").unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.entries.contains_key("a"));
+        assert!(!cache.entries.contains_key("bb"));
+
+        assert!(cache
+            .get_or_try_insert_with("d", || anyhow::bail!("failed"))
+            .is_err());
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -22,21 +22,33 @@ use anyhow::{bail, Result};
 #[cfg(feature = "base64url-builtins")]
 pub mod base64url;
 pub mod crypto;
+#[cfg(feature = "crypto-x509-builtins")]
+pub mod crypto_x509;
 #[cfg(feature = "glob-builtins")]
 pub mod glob;
 
 pub mod graph;
+#[cfg(feature = "graphql-builtins")]
 pub mod graphql;
 #[cfg(feature = "hex-builtins")]
 pub mod hex;
+#[cfg(feature = "http-builtins")]
 pub mod http;
+#[cfg(feature = "jwt-builtins")]
 pub mod io;
 #[cfg(feature = "json-builtins")]
 pub mod json;
+pub(crate) mod lru;
+#[cfg(feature = "net-builtins")]
 pub mod net;
+#[cfg(feature = "big-numbers")]
+pub mod numbers;
 #[cfg(feature = "object-builtins")]
 pub mod object;
 pub mod opa;
+pub mod print;
+#[cfg(feature = "http-aws-sigv4")]
+pub mod providers;
 #[cfg(feature = "rng")]
 pub mod rand;
 #[cfg(feature = "regex-builtins")]
@@ -45,17 +57,22 @@ pub mod regex;
 pub mod rego;
 #[cfg(feature = "semver-builtins")]
 pub mod semver;
+#[cfg(feature = "sprintf-builtins")]
+mod sprintf;
+#[cfg(feature = "strings-builtins")]
+pub mod strings;
 #[cfg(feature = "time-builtins")]
 pub mod time;
 #[cfg(feature = "units-builtins")]
 pub mod units;
 #[cfg(feature = "urlquery-builtins")]
 pub mod urlquery;
+#[cfg(feature = "uuid-builtins")]
 pub mod uuid;
 #[cfg(feature = "yaml-builtins")]
 pub mod yaml;
 #[cfg(feature = "sprintf-builtins")]
-use sprintf::{vsprintf, Printf};
+pub use self::sprintf::sprintf;
 
 /// Returns a list of all the indexes of a substring contained inside a string.
 #[tracing::instrument(err)]
@@ -63,66 +80,17 @@ pub fn indexof_n(string: String, search: String) -> Result<Vec<u32>> {
     bail!("not implemented");
 }
 
-#[cfg(feature = "sprintf-builtins")]
-/// Returns the given string, formatted.
-#[tracing::instrument(err)]
-pub fn sprintf(format: String, values: Vec<serde_json::Value>) -> Result<String> {
-    let orig_values = values.clone();
-
-    let values: Result<Vec<Box<dyn Printf>>, _> = values
-        .into_iter()
-        .map(|v| -> Result<Box<dyn Printf>, _> {
-            match v {
-                serde_json::Value::Null => Err(anyhow::anyhow!("can't format null")),
-                serde_json::Value::Bool(_) => Err(anyhow::anyhow!("can't format a boolean")),
-                serde_json::Value::Number(n) => {
-                    if let Some(n) = n.as_u64() {
-                        Ok(Box::new(n))
-                    } else if let Some(n) = n.as_i64() {
-                        Ok(Box::new(n))
-                    } else if let Some(n) = n.as_f64() {
-                        Ok(Box::new(n))
-                    } else {
-                        Err(anyhow::anyhow!("unreachable"))
-                    }
-                }
-                serde_json::Value::String(s) => Ok(Box::new(s)),
-                serde_json::Value::Array(_) => Err(anyhow::anyhow!("can't format array")),
-                serde_json::Value::Object(_) => Err(anyhow::anyhow!("can't format object")),
-            }
-        })
-        .collect();
-    let values = values?;
-    let values: Vec<&dyn Printf> = values.iter().map(std::convert::AsRef::as_ref).collect();
-
-    match vsprintf(&format, &values) {
-        Ok(str) => Ok(str),
-        Err(err) => handle_errors_like_go(err, format, orig_values),
-    }
-}
-
-#[cfg(feature = "sprintf-builtins")]
-fn handle_errors_like_go(
-    err: sprintf::PrintfError,
-    format: String,
-    values: Vec<serde_json::Value>,
-) -> Result<String> {
-    match err {
-        sprintf::PrintfError::ParseError
-        | sprintf::PrintfError::WrongType
-        | sprintf::PrintfError::TooManyArgs
-        | sprintf::PrintfError::NotEnoughArgs => Ok(format!("{format}, ({err:?}, {values:?})")),
-        sprintf::PrintfError::Unknown => anyhow::bail!("failed to call printf"),
-    }
-}
-
 /// Emits `note` as a `Note` event in the query explanation. Query explanations
 /// show the exact expressions evaluated by OPA during policy execution. For
 /// example, `trace("Hello There!")` includes `Note "Hello There!"` in the query
 /// explanation. To include variables in the message, use `sprintf`. For
 /// example, `person := "Bob"; trace(sprintf("Hello There! %v", [person]))` will
 /// emit `Note "Hello There! Bob"` inside of the explanation.
-#[tracing::instrument(err)]
-pub fn trace(note: String) -> Result<bool> {
-    bail!("not implemented");
+///
+/// Notes are collected in the [`Trace`](crate::Trace) returned by
+/// [`Policy::evaluate_with_trace`](crate::Policy::evaluate_with_trace).
+#[tracing::instrument]
+pub fn trace(note: String) -> bool {
+    tracing::debug!(%note, "trace");
+    true
 }
@@ -14,29 +14,269 @@
 
 //! Builtins related to network operations and IP handling
 
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    net::IpAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use ipnet::{IpAddrRange, IpNet, Ipv4AddrRange, Ipv6AddrRange};
+use serde_json::Value;
+
+use crate::EvaluationContext;
+
+/// Parse a CIDR, e.g. `192.168.0.0/16`
+fn parse_cidr(cidr: &str) -> Result<IpNet> {
+    cidr.parse()
+        .with_context(|| format!("invalid CIDR {cidr:?}"))
+}
+
+/// Parse a CIDR or a single IP, which is treated as a network containing only
+/// that address
+fn parse_cidr_or_ip(cidr_or_ip: &str) -> Result<IpNet> {
+    if let Ok(ip) = cidr_or_ip.parse::<IpAddr>() {
+        let prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+        return Ok(IpNet::new(ip, prefix_len)?);
+    }
+
+    cidr_or_ip
+        .parse()
+        .with_context(|| format!("invalid CIDR or IP {cidr_or_ip:?}"))
+}
+
+/// Checks if a CIDR or IP is contained within another CIDR. `output` is `true`
+/// if `cidr_or_ip` (e.g. `127.0.0.64/26` or `127.0.0.1`) is contained within
+/// `cidr` (e.g. `127.0.0.1/24`) and `false` otherwise. Supports both IPv4 and
+/// IPv6 notations.
+#[tracing::instrument(name = "net.cidr_contains", err)]
+pub fn cidr_contains(cidr: String, cidr_or_ip: String) -> Result<bool> {
+    let cidr = parse_cidr(&cidr)?;
+    let cidr_or_ip = parse_cidr_or_ip(&cidr_or_ip)?;
+    Ok(cidr.contains(&cidr_or_ip))
+}
+
+/// Extract the entries of a `net.cidr_contains_matches` operand, as pairs of
+/// keys used in the output and CIDRs or IPs.
+///
+/// Strings are their own key, elements of arrays (and sets) are keyed by their
+/// index and values of objects by their key. Elements can either be strings or
+/// arrays whose first element is the CIDR or IP, so that additional data can be
+/// attached to them.
+fn contains_matches_entries(operand: &Value) -> Result<Vec<(Value, IpNet)>> {
+    fn entry(value: &Value) -> Result<IpNet> {
+        match value {
+            Value::String(s) => parse_cidr_or_ip(s),
+            Value::Array(a) => match a.first() {
+                Some(Value::String(s)) => parse_cidr_or_ip(s),
+                _ => bail!("expected the first element of the array to be a CIDR or IP"),
+            },
+            _ => bail!("expected a CIDR or IP, or an array starting with one"),
+        }
+    }
+
+    match operand {
+        Value::String(s) => Ok(vec![(operand.clone(), parse_cidr_or_ip(s)?)]),
+        Value::Array(a) => a
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Ok((Value::from(i), entry(value)?)))
+            .collect(),
+        Value::Object(o) => o
+            .iter()
+            .map(|(key, value)| Ok((Value::from(key.clone()), entry(value)?)))
+            .collect(),
+        _ => bail!("expected a string, an array or an object"),
+    }
+}
+
+/// The bits of a network address, with the number of significant ones
+fn prefix_bits(net: &IpNet) -> (u128, u8) {
+    match net {
+        IpNet::V4(net) => (u128::from(u32::from(net.network())) << 96, net.prefix_len()),
+        IpNet::V6(net) => (u128::from(net.network()), net.prefix_len()),
+    }
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: [Option<usize>; 2],
+    /// Indexes of the entries whose network is this node
+    entries: Vec<usize>,
+}
+
+/// A binary prefix trie of the CIDRs of a `net.cidr_contains_matches`
+/// collection, so that finding the CIDRs containing a network only walks its
+/// prefix instead of comparing it against every CIDR
+pub(crate) struct CidrTrie {
+    keys: Vec<Value>,
+    /// Arena of the nodes, the roots of the IPv4 and IPv6 tries being the
+    /// first two
+    nodes: Vec<TrieNode>,
+}
+
+impl CidrTrie {
+    fn new(entries: Vec<(Value, IpNet)>) -> Self {
+        let mut trie = Self {
+            keys: Vec::with_capacity(entries.len()),
+            nodes: vec![TrieNode::default(), TrieNode::default()],
+        };
+        for (index, (key, net)) in entries.into_iter().enumerate() {
+            let (bits, len) = prefix_bits(&net);
+            let mut node = usize::from(net.addr().is_ipv6());
+            for depth in 0..len {
+                let bit = usize::from(bits & (1 << (127 - depth)) != 0);
+                node = if let Some(child) = trie.nodes[node].children[bit] {
+                    child
+                } else {
+                    trie.nodes.push(TrieNode::default());
+                    let child = trie.nodes.len() - 1;
+                    trie.nodes[node].children[bit] = Some(child);
+                    child
+                };
+            }
+            trie.nodes[node].entries.push(index);
+            trie.keys.push(key);
+        }
+        trie
+    }
+
+    /// Indexes of the entries containing the network
+    fn containing(&self, net: &IpNet) -> impl Iterator<Item = usize> + '_ {
+        let (bits, len) = prefix_bits(net);
+        let mut node = Some(usize::from(net.addr().is_ipv6()));
+        let mut depth = 0;
+        std::iter::from_fn(move || {
+            let current = node?;
+            node = if depth < len {
+                let bit = usize::from(bits & (1 << (127 - depth)) != 0);
+                depth += 1;
+                self.nodes[current].children[bit]
+            } else {
+                None
+            };
+            Some(self.nodes[current].entries.iter().copied())
+        })
+        .flatten()
+    }
+}
+
+/// Cache of the tries built by `net.cidr_contains_matches` during the current
+/// evaluation, keyed by CIDR collection, so that a policy matching many IPs
+/// against the same allowlist only builds its trie once.
+///
+/// Cloning it is cheap, and clones share the same underlying storage.
+#[derive(Clone, Default)]
+pub struct CidrTrieCache {
+    tries: Arc<Mutex<HashMap<String, Arc<CidrTrie>>>>,
+}
+
+impl CidrTrieCache {
+    /// Get the trie of a collection, building it if it is not in the cache
+    /// yet
+    fn get(&self, cidrs: &Value) -> Result<Arc<CidrTrie>> {
+        let key = cidrs.to_string();
+        let cached = self
+            .tries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+            .cloned();
+        #[cfg(feature = "metrics")]
+        crate::metrics::observe_cache("net.cidr_trie", cached.is_some());
+        if let Some(trie) = cached {
+            return Ok(trie);
+        }
+
+        let trie = Arc::new(CidrTrie::new(contains_matches_entries(cidrs)?));
+        self.tries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key, trie.clone());
+        Ok(trie)
+    }
+}
 
 /// Checks if collections of cidrs or ips are contained within another
 /// collection of cidrs and returns matches. This function is similar to
 /// `net.cidr_contains` except it allows callers to pass collections of CIDRs or
 /// IPs as arguments and returns the matches (as opposed to a boolean
 /// result indicating a match between two CIDRs/IPs).
-#[tracing::instrument(name = "net.cidr_contains_matches", err)]
-pub fn cidr_contains_matches(
-    cidrs: serde_json::Value,
-    cidrs_or_ips: serde_json::Value,
-) -> Result<serde_json::Value> {
-    bail!("not implemented");
+#[tracing::instrument(name = "net.cidr_contains_matches", skip(ctx), err)]
+pub fn cidr_contains_matches<C: EvaluationContext>(
+    ctx: &mut C,
+    cidrs: Value,
+    cidrs_or_ips: Value,
+) -> Result<Value> {
+    let trie = ctx
+        .net_cidr_trie_cache()
+        .cloned()
+        .unwrap_or_default()
+        .get(&cidrs)?;
+    let cidrs_or_ips = contains_matches_entries(&cidrs_or_ips)?;
+
+    // Sort the matches like a scan of the CIDRs, then of the CIDRs or IPs
+    let mut matches: Vec<(usize, usize)> = cidrs_or_ips
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (_, net))| trie.containing(net).map(move |cidr| (cidr, index)))
+        .collect();
+    matches.sort_unstable();
+    matches.dedup();
+
+    Ok(matches
+        .into_iter()
+        .map(|(cidr, index)| {
+            Value::Array(vec![trie.keys[cidr].clone(), cidrs_or_ips[index].0.clone()])
+        })
+        .collect())
 }
 
+/// Maximum number of host bits of the CIDRs `net.cidr_expand` accepts, i.e.
+/// at most the 65536 hosts of a `/16` IPv4 network
+const MAX_EXPANDED_HOSTS_BITS: u8 = 16;
+
 /// Expands CIDR to set of hosts  (e.g., `net.cidr_expand("192.168.0.0/30")`
 /// generates 4 hosts: `{"192.168.0.0", "192.168.0.1", "192.168.0.2",
 /// "192.168.0.3"}`).
+///
+/// CIDRs with more than 65536 hosts are rejected.
 #[tracing::instrument(name = "net.cidr_expand", err)]
 pub fn cidr_expand(cidr: String) -> Result<HashSet<String>> {
-    bail!("not implemented");
+    let net = parse_cidr(&cidr)?;
+    if net.max_prefix_len() - net.prefix_len() > MAX_EXPANDED_HOSTS_BITS {
+        bail!(
+            "CIDR {cidr:?} has more than {} hosts",
+            1_u32 << MAX_EXPANDED_HOSTS_BITS
+        );
+    }
+
+    // Unlike `IpNet::hosts`, this includes the network and broadcast addresses
+    let range = match net {
+        IpNet::V4(net) => IpAddrRange::from(Ipv4AddrRange::new(net.network(), net.broadcast())),
+        IpNet::V6(net) => IpAddrRange::from(Ipv6AddrRange::new(net.network(), net.broadcast())),
+    };
+
+    Ok(range.map(|ip| ip.to_string()).collect())
+}
+
+/// Checks if a CIDR intersects with another CIDR (e.g. `192.168.0.0/16`
+/// overlaps with `192.168.1.0/24`). Supports both IPv4 and IPv6 notations.
+#[tracing::instrument(name = "net.cidr_intersects", err)]
+pub fn cidr_intersects(cidr1: String, cidr2: String) -> Result<bool> {
+    let cidr1 = parse_cidr(&cidr1)?;
+    let cidr2 = parse_cidr(&cidr2)?;
+    // Two networks either are disjoint or one contains the other
+    Ok(cidr1.contains(&cidr2) || cidr2.contains(&cidr1))
+}
+
+/// Parses an IPv4/IPv6 CIDR and returns a boolean indicating if the provided
+/// CIDR is valid.
+#[tracing::instrument(name = "net.cidr_is_valid")]
+pub fn cidr_is_valid(cidr: String) -> bool {
+    cidr.parse::<IpNet>().is_ok()
 }
 
 /// Merges IP addresses and subnets into the smallest possible list of CIDRs
@@ -47,13 +287,213 @@ pub fn cidr_expand(cidr: String) -> Result<HashSet<String>> {
 /// Supports both IPv4 and IPv6 notations. IPv6 inputs need a prefix length
 /// (e.g. "/128").
 #[tracing::instrument(name = "net.cidr_merge", err)]
-pub fn cidr_merge(addrs: serde_json::Value) -> Result<HashSet<String>> {
-    bail!("not implemented");
+pub fn cidr_merge(addrs: Vec<String>) -> Result<HashSet<String>> {
+    let networks = addrs
+        .iter()
+        .map(|addr| parse_cidr_or_ip(addr))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(IpNet::aggregate(&networks)
+        .into_iter()
+        .map(|net| net.to_string())
+        .collect())
+}
+
+/// Default timeout of DNS lookups, see [`NetConfig::with_lookup_timeout`]
+pub const DEFAULT_LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runtime-level configuration of the network builtins
+#[derive(Debug, Clone)]
+pub struct NetConfig {
+    lookups_enabled: bool,
+    lookup_timeout: Option<Duration>,
+    hosts: HashMap<String, HashSet<IpAddr>>,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            lookups_enabled: true,
+            lookup_timeout: Some(DEFAULT_LOOKUP_TIMEOUT),
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+impl NetConfig {
+    /// Enable or disable DNS lookups. When disabled, `net.lookup_ip_addr`
+    /// fails without touching the network, which is useful for sandboxed
+    /// deployments.
+    #[must_use]
+    pub fn with_lookups_enabled(mut self, lookups_enabled: bool) -> Self {
+        self.lookups_enabled = lookups_enabled;
+        self
+    }
+
+    /// Set the maximum duration of a DNS lookup. `None` waits for the resolver
+    /// indefinitely.
+    #[must_use]
+    pub fn with_lookup_timeout(mut self, lookup_timeout: Option<Duration>) -> Self {
+        self.lookup_timeout = lookup_timeout;
+        self
+    }
+
+    /// Pre-seed the answer of DNS lookups for a host name. Seeded hosts are
+    /// resolved without touching the network, even when lookups are disabled,
+    /// so that policies can be evaluated deterministically in tests and
+    /// sandboxed environments.
+    #[must_use]
+    pub fn with_host(
+        mut self,
+        name: impl Into<String>,
+        addrs: impl IntoIterator<Item = IpAddr>,
+    ) -> Self {
+        let name = name.into().to_ascii_lowercase();
+        self.hosts.entry(name).or_default().extend(addrs);
+        self
+    }
 }
 
 /// Returns the set of IP addresses (both v4 and v6) that the passed-in `name`
 /// resolves to using the standard name resolution mechanisms available.
-#[tracing::instrument(name = "net.lookup_ip_addr", err)]
-pub async fn lookup_ip_addr(name: String) -> Result<HashSet<String>> {
-    bail!("not implemented");
+pub fn lookup_ip_addr<C: EvaluationContext>(
+    ctx: &mut C,
+    name: String,
+) -> impl Future<Output = Result<HashSet<String>>> + Send {
+    let config = ctx.net_config().cloned().unwrap_or_default();
+    lookup(config, name)
+}
+
+#[tracing::instrument(name = "net.lookup_ip_addr", skip(config), err)]
+async fn lookup(config: NetConfig, name: String) -> Result<HashSet<String>> {
+    if let Some(addrs) = config.hosts.get(&name.to_ascii_lowercase()) {
+        return Ok(addrs.iter().map(ToString::to_string).collect());
+    }
+
+    if !config.lookups_enabled {
+        bail!("DNS lookups are disabled");
+    }
+
+    // The port is required by `lookup_host` but is not used
+    let lookup = tokio::net::lookup_host((name.as_str(), 0));
+    let addrs = match config.lookup_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, lookup)
+            .await
+            .with_context(|| format!("DNS lookup of {name:?} timed out"))??,
+        None => lookup.await?,
+    };
+
+    Ok(addrs.map(|addr| addr.ip().to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn cidr() {
+        assert!(cidr_contains("10.0.0.0/8".into(), "10.1.2.0/24".into()).unwrap());
+        assert!(cidr_contains("10.0.0.0/8".into(), "10.1.2.3".into()).unwrap());
+        assert!(!cidr_contains("10.0.0.0/8".into(), "11.0.0.1".into()).unwrap());
+        assert!(cidr_contains("10.0.0.0/8".into(), "10.0.0.1/7".into()).is_ok());
+        assert!(cidr_contains("10.0.0.1".into(), "10.0.0.1".into()).is_err());
+
+        assert!(cidr_intersects("192.168.0.0/16".into(), "192.168.1.0/24".into()).unwrap());
+        assert!(!cidr_intersects("192.168.0.0/24".into(), "192.168.1.0/24".into()).unwrap());
+
+        assert!(cidr_is_valid("fd00::/8".into()));
+        assert!(!cidr_is_valid("fd00::".into()));
+
+        let expanded = cidr_expand("192.168.0.1/30".into()).unwrap();
+        assert_eq!(expanded.len(), 4);
+        assert!(expanded.contains("192.168.0.0"));
+        assert!(expanded.contains("192.168.0.3"));
+        assert_eq!(cidr_expand("10.0.0.0/16".into()).unwrap().len(), 65536);
+        assert!(cidr_expand("10.0.0.0/15".into()).is_err());
+        assert!(cidr_expand("fd00::/8".into()).is_err());
+
+        let merged = cidr_merge(vec![
+            "192.0.128.0/24".into(),
+            "192.0.129.0/24".into(),
+            "192.0.129.1".into(),
+        ])
+        .unwrap();
+        assert_eq!(merged, HashSet::from(["192.0.128.0/23".to_owned()]));
+    }
+
+    #[test]
+    fn contains_matches() {
+        let mut ctx = crate::DefaultContext::default();
+        let matches = cidr_contains_matches(
+            &mut ctx,
+            json!({"internal": "10.0.0.0/8", "loopback": ["127.0.0.0/8", "extra"]}),
+            json!(["10.1.2.3", "127.0.0.1", "8.8.8.8"]),
+        )
+        .unwrap();
+        assert_eq!(matches, json!([["internal", 0], ["loopback", 1]]));
+
+        let matches =
+            cidr_contains_matches(&mut ctx, json!("10.0.0.0/8"), json!("10.0.0.0/16")).unwrap();
+        assert_eq!(matches, json!([["10.0.0.0/8", "10.0.0.0/16"]]));
+    }
+
+    #[test]
+    fn contains_matches_trie() {
+        let cidrs = json!([
+            "0.0.0.0/0",
+            "10.0.0.0/8",
+            "10.1.0.0/16",
+            "10.1.2.3",
+            "192.168.0.0/24",
+            "2001:db8::/32",
+            "10.0.0.0/8",
+        ]);
+        let ips = json!([
+            "10.1.2.3",
+            "10.2.0.0/16",
+            "192.168.1.1",
+            "2001:db8::1",
+            "::/0"
+        ]);
+
+        // Compare with the naive scan of every pair
+        let entries = contains_matches_entries(&cidrs).unwrap();
+        let nets = contains_matches_entries(&ips).unwrap();
+        let mut expected = Vec::new();
+        for (cidr_key, cidr) in &entries {
+            for (key, net) in &nets {
+                if cidr.contains(net) {
+                    expected.push(json!([cidr_key, key]));
+                }
+            }
+        }
+
+        let mut ctx = crate::DefaultContext::default();
+        let matches = cidr_contains_matches(&mut ctx, cidrs.clone(), ips.clone()).unwrap();
+        assert_eq!(matches, Value::Array(expected));
+
+        // The second call reuses the trie built by the first one
+        let cache = ctx.net_cidr_trie_cache().unwrap().clone();
+        assert_eq!(cache.tries.lock().unwrap().len(), 1);
+        let again = cidr_contains_matches(&mut ctx, cidrs, ips).unwrap();
+        assert_eq!(again, matches);
+        assert_eq!(cache.tries.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn seeded_lookup() {
+        let config = NetConfig::default()
+            .with_lookups_enabled(false)
+            .with_host("Example.com", ["192.0.2.1".parse().unwrap()])
+            .with_host("example.com", ["2001:db8::1".parse().unwrap()]);
+
+        let addrs = lookup(config.clone(), "example.COM".into()).await.unwrap();
+        assert_eq!(
+            addrs,
+            HashSet::from(["192.0.2.1".to_owned(), "2001:db8::1".to_owned()])
+        );
+        assert!(lookup(config, "example.org".into()).await.is_err());
+    }
 }
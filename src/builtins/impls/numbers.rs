@@ -0,0 +1,274 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builtins related to numbers, and the arbitrary-precision representation
+//! used by builtins dealing with large numbers
+//!
+//! Arithmetic operators are compiled into the policy module itself; this
+//! representation only covers the host builtins taking numbers which may not
+//! fit in a `f64`, namely `format_int` and `numbers.range_step`.
+
+use std::str::FromStr;
+
+use anyhow::Result;
+#[cfg(feature = "numbers-builtins")]
+use bigdecimal::Signed;
+use bigdecimal::{num_bigint::BigInt, BigDecimal};
+use serde::{de::Error as _, ser::Error as _, Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+/// Maximum number of digits a [`BigNumber`] can have before or after its
+/// decimal point, which is enough for any `f64`. Writing a number out or
+/// truncating it materializes all these digits, so a number like
+/// `1e1000000000` would otherwise take gigabytes of memory.
+const MAX_DIGITS: i64 = 10_000;
+
+/// A JSON number keeping its exact value.
+///
+/// [`serde_json::Number`] rounds integers which don't fit in 64 bits to a
+/// `f64`, whereas OPA keeps arbitrary-precision numbers. This type is parsed
+/// from the raw JSON text of the argument so that no digit is lost. Numbers
+/// with more than ten thousand digits before or after the decimal point are
+/// rejected.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BigNumber(BigDecimal);
+
+impl BigNumber {
+    /// Returns the integer value of the number, or `None` if it has a
+    /// fractional part
+    #[must_use]
+    pub fn to_integer(&self) -> Option<BigInt> {
+        let truncated = self.truncate();
+        (truncated == self.0).then(|| truncated.into_bigint_and_exponent().0)
+    }
+
+    /// Returns the integer value of the number, truncated toward zero
+    #[must_use]
+    pub fn to_truncated_integer(&self) -> BigInt {
+        self.truncate().into_bigint_and_exponent().0
+    }
+
+    fn truncate(&self) -> BigDecimal {
+        // `with_scale` drops the extra digits, which truncates toward zero
+        self.0.with_scale(0)
+    }
+
+    /// Returns the number as an integer, or an error mentioning the operand
+    /// position if it has a fractional part
+    #[cfg(feature = "numbers-builtins")]
+    fn integer_operand(&self, position: usize) -> Result<BigInt> {
+        self.to_integer().ok_or_else(|| {
+            anyhow::anyhow!(
+                "operand {position} must be integer number but got floating-point number"
+            )
+        })
+    }
+}
+
+impl From<BigInt> for BigNumber {
+    fn from(value: BigInt) -> Self {
+        Self(BigDecimal::from(value))
+    }
+}
+
+impl FromStr for BigNumber {
+    type Err = bigdecimal::ParseBigDecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = BigDecimal::from_str(s)?;
+        // The scale is the number of digits after the decimal point, or minus
+        // the number of zeros following the digits
+        let scale = number.fractional_digit_count();
+        let integer_digits = i64::try_from(number.digits())
+            .unwrap_or(i64::MAX)
+            .saturating_sub(scale);
+        if scale > MAX_DIGITS || integer_digits > MAX_DIGITS {
+            return Err(bigdecimal::ParseBigDecimalError::Other(format!(
+                "number has more than {MAX_DIGITS} digits"
+            )));
+        }
+        Ok(Self(number))
+    }
+}
+
+impl std::fmt::Display for BigNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Integers are always written out in full, so that they round-trip
+        // through JSON as integers
+        match self.to_integer() {
+            Some(n) => write!(f, "{n}"),
+            None => write!(f, "{}", self.0.normalized()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BigNumber {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let raw = Box::<RawValue>::deserialize(deserializer)?;
+        let text = raw.get();
+        // Reject strings, which `BigDecimal` would otherwise not see quoted
+        if !text.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+            return Err(D::Error::custom(format!("expected a number, got {text}")));
+        }
+        text.parse().map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for BigNumber {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        RawValue::from_string(self.to_string())
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+/// Maximum number of elements `numbers.range_step` is allowed to return
+#[cfg(feature = "numbers-builtins")]
+const MAX_RANGE_LENGTH: u32 = 1_000_000;
+
+/// Returns an array of numbers in the given (inclusive) range incremented by a
+/// positive step.
+///
+/// If `a==b`, then `range == [a]`.
+/// If `a > b`, then the range is in descending order.
+///
+/// # Errors
+///
+/// Returns an error if an operand is not an integer, if the step is not
+/// positive, or if the range would have more than a million elements.
+#[cfg(feature = "numbers-builtins")]
+#[tracing::instrument(name = "numbers.range_step", err)]
+pub fn range_step(a: BigNumber, b: BigNumber, step: BigNumber) -> Result<Vec<BigNumber>> {
+    let a = a.integer_operand(1)?;
+    let b = b.integer_operand(2)?;
+    let step = step.integer_operand(3)?;
+    if !step.is_positive() {
+        anyhow::bail!("numbers.range_step: step must be a positive number above zero");
+    }
+
+    let length = (&a - &b).abs() / &step + 1;
+    if length > BigInt::from(MAX_RANGE_LENGTH) {
+        anyhow::bail!("numbers.range_step: range has more than {MAX_RANGE_LENGTH} elements");
+    }
+
+    let mut range = Vec::new();
+    let mut current = a.clone();
+    if a <= b {
+        while current <= b {
+            range.push(BigNumber::from(current.clone()));
+            current += &step;
+        }
+    } else {
+        while current >= b {
+            range.push(BigNumber::from(current.clone()));
+            current -= &step;
+        }
+    }
+
+    Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(s: &str) -> BigNumber {
+        serde_json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn exact_round_trip() {
+        for text in [
+            "0",
+            "-12",
+            "18446744073709551616",
+            "-123456789012345678901234567890",
+            "0.1",
+            "-2.5",
+        ] {
+            assert_eq!(serde_json::to_string(&number(text)).unwrap(), text);
+        }
+
+        // Integral values are written out as integers
+        assert_eq!(serde_json::to_string(&number("1e3")).unwrap(), "1000");
+        assert_eq!(serde_json::to_string(&number("3.0")).unwrap(), "3");
+
+        assert!(serde_json::from_str::<BigNumber>("\"1\"").is_err());
+        assert!(serde_json::from_str::<BigNumber>("null").is_err());
+    }
+
+    #[test]
+    fn huge_exponents() {
+        // Rejected before any digit is materialized
+        for text in ["1e1000000000", "-1e1000000000", "1e-1000000000", "1e10000"] {
+            assert!(serde_json::from_str::<BigNumber>(text).is_err(), "{text}");
+        }
+
+        assert_eq!(number("1e300").to_string().len(), 301);
+        assert_eq!(number("1.5e-300").to_truncated_integer(), BigInt::from(0));
+        assert_eq!(number("1e9999").to_string().len(), 10_000);
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(number("3.0").to_integer(), Some(3.into()));
+        assert_eq!(number("3.5").to_integer(), None);
+        assert_eq!(number("-3.7").to_truncated_integer(), BigInt::from(-3));
+        assert_eq!(number("3.7").to_truncated_integer(), BigInt::from(3));
+    }
+
+    #[cfg(feature = "numbers-builtins")]
+    #[test]
+    fn range_step_like_opa() {
+        let range = |a: &str, b: &str, step: &str| {
+            range_step(number(a), number(b), number(step))
+                .map(|r| serde_json::to_string(&r).unwrap())
+        };
+
+        assert_eq!(range("0", "10", "3").unwrap(), "[0,3,6,9]");
+        assert_eq!(range("0", "9", "3").unwrap(), "[0,3,6,9]");
+        assert_eq!(range("10", "0", "3").unwrap(), "[10,7,4,1]");
+        assert_eq!(range("2", "2", "5").unwrap(), "[2]");
+        assert_eq!(
+            range("18446744073709551616", "18446744073709551620", "2").unwrap(),
+            "[18446744073709551616,18446744073709551618,18446744073709551620]"
+        );
+
+        assert_eq!(
+            range("0", "10", "0").unwrap_err().to_string(),
+            "numbers.range_step: step must be a positive number above zero"
+        );
+        assert!(range("0", "10", "-1").is_err());
+        assert_eq!(
+            range("0", "1e18", "1").unwrap_err().to_string(),
+            "numbers.range_step: range has more than 1000000 elements"
+        );
+        assert_eq!(
+            range_step(number("0"), number("1e17"), number("1e12"))
+                .unwrap()
+                .len(),
+            100_001
+        );
+        assert_eq!(
+            range("0", "1.5", "1").unwrap_err().to_string(),
+            "operand 2 must be integer number but got floating-point number"
+        );
+    }
+}
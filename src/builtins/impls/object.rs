@@ -30,24 +30,82 @@ pub fn union_n(objects: Vec<Value>) -> Result<Value> {
     Ok(result)
 }
 
+/// Merge `b` into `a`: objects are merged recursively, any other value from
+/// `b` replaces the one in `a`
 fn merge_value(a: &mut Value, b: &Value) {
     match (a, b) {
         (Value::Object(ref mut a), Value::Object(b)) => {
             for (k, v) in b {
-                merge_value(a.entry(k).or_insert(Value::Null), v);
+                match a.get_mut(k) {
+                    Some(existing) => merge_value(existing, v),
+                    None => {
+                        a.insert(k.clone(), v.clone());
+                    }
+                }
             }
         }
-        (Value::Array(ref mut a), Value::Array(b)) => {
-            *a = vec![];
-            a.extend(b.clone());
-        }
-        (Value::Array(ref mut a), Value::Object(b)) => {
-            *a = vec![];
-            a.extend([Value::Object(b.clone())]);
-        }
-        (_, Value::Null) => {}
         (a, b) => {
             *a = b.clone();
         }
     }
 }
+
+/// Checks if `sub` is a subset of `superset`.
+///
+/// Objects are subsets if all their keys are in `superset` with values which
+/// are subsets as well, arrays if they appear contiguously in `superset`, and
+/// any other value if it is equal to `superset`.
+#[tracing::instrument(name = "object.subset")]
+pub fn subset(superset: Value, sub: Value) -> bool {
+    is_subset(&superset, &sub)
+}
+
+fn is_subset(sup: &Value, sub: &Value) -> bool {
+    match (sup, sub) {
+        (Value::Object(sup), Value::Object(sub)) => sub
+            .iter()
+            .all(|(k, v)| sup.get(k).map_or(false, |s| is_subset(s, v))),
+        (Value::Array(sup), Value::Array(sub)) => {
+            sub.is_empty()
+                || sup
+                    .windows(sub.len())
+                    .any(|window| window == sub.as_slice())
+        }
+        (sup, sub) => sup == sub,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn union() {
+        assert_eq!(
+            union_n(vec![
+                json!({"a": {"b": [1], "c": 1}}),
+                json!({"a": {"b": [1, 2, 3]}, "d": null}),
+                json!({"a": 1}),
+                json!({"a": {"e": 1}}),
+            ])
+            .unwrap(),
+            json!({"a": {"e": 1}, "d": null})
+        );
+    }
+
+    #[test]
+    fn subsets() {
+        let sup = json!({"a": {"b": 1, "c": [1, 2, 3]}, "d": "e"});
+        assert!(subset(sup.clone(), json!({})));
+        assert!(subset(sup.clone(), json!({"a": {"b": 1}})));
+        assert!(subset(sup.clone(), json!({"a": {"c": [2, 3]}, "d": "e"})));
+        assert!(!subset(sup.clone(), json!({"a": {"c": [1, 3]}})));
+        assert!(!subset(sup, json!({"f": 1})));
+
+        assert!(subset(json!([1, 2, 3, 4]), json!([2, 3])));
+        assert!(!subset(json!([1, 2, 3, 4]), json!([4, 1])));
+        assert!(subset(json!([1, 2]), json!([])));
+    }
+}
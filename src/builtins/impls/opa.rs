@@ -14,26 +14,99 @@
 
 //! Builtins related to the current OPA environment
 
-use std::{collections::HashMap, env};
+use std::{collections::HashMap, env, sync::Arc};
 
 use serde::Serialize;
 
-/// Metadata about the OPA runtime
-#[derive(Serialize)]
-pub struct Runtime {
-    env: HashMap<String, String>,
+use crate::EvaluationContext;
+
+/// Metadata about the OPA runtime, returned by `opa.runtime`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RuntimeInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env: Option<HashMap<String, String>>,
     version: String,
     commit: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config: Option<serde_json::Value>,
+    #[serde(skip)]
+    files: Option<Arc<HashMap<String, Vec<u8>>>>,
+}
+
+impl RuntimeInfo {
+    /// Expose the given environment variables instead of the ones of the
+    /// current process
+    #[must_use]
+    pub fn with_env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = Some(env);
+        self
+    }
+
+    /// Get an environment variable, from the environment set with
+    /// [`RuntimeInfo::with_env`] if any, or else from the current process
+    #[must_use]
+    pub fn env_var(&self, name: &str) -> Option<String> {
+        match &self.env {
+            Some(env) => env.get(name).cloned(),
+            None => env::var(name).ok(),
+        }
+    }
+
+    /// Serve the files read by builtins, like the certificates `http.send`
+    /// loads from its `tls_*_file` parameters, from the given contents keyed
+    /// by path instead of from the file system. Paths missing from it are
+    /// not found.
+    #[must_use]
+    pub fn with_files(mut self, files: HashMap<String, Vec<u8>>) -> Self {
+        self.files = Some(Arc::new(files));
+        self
+    }
+
+    /// Read a file, from the files set with [`RuntimeInfo::with_files`] if
+    /// any, or else from the file system
+    ///
+    /// # Errors
+    ///
+    /// If the file does not exist or could not be read
+    pub fn read_file(&self, path: &str) -> std::io::Result<Vec<u8>> {
+        match &self.files {
+            Some(files) => files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| std::io::ErrorKind::NotFound.into()),
+            None => std::fs::read(path),
+        }
+    }
+
+    /// Set the version reported by the runtime
+    #[must_use]
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// Set the commit reported by the runtime
+    #[must_use]
+    pub fn with_commit(mut self, commit: impl Into<String>) -> Self {
+        self.commit = commit.into();
+        self
+    }
+
+    /// Set the configuration object reported by the runtime
+    #[must_use]
+    pub fn with_config(mut self, config: serde_json::Value) -> Self {
+        self.config = Some(config);
+        self
+    }
 }
 
 /// Returns an object that describes the runtime environment where OPA is
 /// deployed.
-#[tracing::instrument(name = "opa.runtime")]
-pub fn runtime() -> Runtime {
-    let env = env::vars().collect();
-    Runtime {
-        env,
-        version: String::new(),
-        commit: String::new(),
+#[tracing::instrument(name = "opa.runtime", skip(ctx))]
+pub fn runtime<C: EvaluationContext>(ctx: &mut C) -> RuntimeInfo {
+    let mut info = ctx.runtime_info().cloned().unwrap_or_default();
+    if info.env.is_none() {
+        info.env = Some(env::vars().collect());
     }
+    info
 }
@@ -0,0 +1,144 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builtins related to the `print` statement
+
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::{builtins::traits::Builtin, PrintHook};
+
+/// Format a value like OPA does in `print` statements: strings are printed
+/// as-is, other values use the Rego syntax
+fn format_value(value: &Value, top_level: bool) -> String {
+    match value {
+        Value::String(s) if top_level => s.clone(),
+        Value::String(_) | Value::Null | Value::Bool(_) | Value::Number(_) => value.to_string(),
+        Value::Array(values) => {
+            let values: Vec<_> = values.iter().map(|v| format_value(v, false)).collect();
+            format!("[{}]", values.join(", "))
+        }
+        Value::Object(map) => {
+            let entries: Vec<_> = map
+                .iter()
+                .map(|(k, v)| format!("{}: {}", Value::from(k.as_str()), format_value(v, false)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+    }
+}
+
+/// Build the messages printed by a `print` statement.
+///
+/// Each operand is a set of the values it can take, so one message is built
+/// for each combination of them. Undefined operands are printed as
+/// `<undefined>`.
+pub(crate) fn messages(operands: &[Value]) -> Result<Vec<String>> {
+    let mut messages = vec![Vec::with_capacity(operands.len())];
+    for operand in operands {
+        let Value::Array(values) = operand else {
+            bail!("illegal argument type: expected set of values");
+        };
+
+        let formatted: Vec<_> = if values.is_empty() {
+            vec!["<undefined>".to_owned()]
+        } else {
+            values.iter().map(|v| format_value(v, true)).collect()
+        };
+
+        messages = messages
+            .into_iter()
+            .flat_map(|prefix| {
+                formatted.iter().map(move |value| {
+                    let mut message = prefix.clone();
+                    message.push(value.clone());
+                    message
+                })
+            })
+            .collect();
+    }
+
+    Ok(messages.into_iter().map(|parts| parts.join(" ")).collect())
+}
+
+/// Outputs the operands of a `print` statement as `tracing` events. A custom
+/// destination can be set with
+/// [`RuntimeBuilder::print_hook`](crate::RuntimeBuilder::print_hook).
+///
+/// # Errors
+///
+/// Returns an error if an operand is not a set of values.
+#[tracing::instrument(name = "print", skip_all, err)]
+pub fn print(operands: Vec<Value>) -> Result<bool> {
+    for message in messages(&operands)? {
+        tracing::info!("{message}");
+    }
+    Ok(true)
+}
+
+/// The `print` builtin sending the messages to a [`PrintHook`] instead of
+/// emitting them as `tracing` events
+pub(crate) struct HookedPrint {
+    hook: Arc<dyn PrintHook>,
+}
+
+impl HookedPrint {
+    pub(crate) fn new(hook: Arc<dyn PrintHook>) -> Self {
+        Self { hook }
+    }
+}
+
+impl<C> Builtin<C> for HookedPrint {
+    fn call<'a>(
+        &'a self,
+        _context: &'a mut C,
+        args: &'a [&'a [u8]],
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>>> + Send + 'a>> {
+        Box::pin(async move {
+            let [operands] = args else {
+                bail!("invalid arguments");
+            };
+            let operands: Vec<Value> =
+                serde_json::from_slice(operands).context("failed to convert operands argument")?;
+            for message in messages(&operands)? {
+                self.hook.print(&message);
+            }
+            Ok(serde_json::to_vec(&true)?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn format() {
+        let operands = [json!(["hello"]), json!([{"a": [1, "b", null]}]), json!([])];
+        assert_eq!(
+            messages(&operands).unwrap(),
+            [r#"hello {"a": [1, "b", null]} <undefined>"#]
+        );
+
+        // One message per combination of values
+        let operands = [json!(["x", "y"]), json!([1, 2])];
+        assert_eq!(messages(&operands).unwrap(), ["x 1", "x 2", "y 1", "y 2"]);
+
+        assert!(messages(&[json!("x")]).is_err());
+    }
+}
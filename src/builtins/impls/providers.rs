@@ -0,0 +1,197 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builtins related to cloud providers
+
+/// Builtins for AWS
+pub mod aws {
+    use std::collections::BTreeMap;
+
+    use anyhow::{bail, Context, Result};
+    use reqwest::Url;
+    use serde::Deserialize;
+    use serde_json::{Map, Value};
+    use sha2::{Digest, Sha256};
+
+    use crate::builtins::impls::http::credentials::{host_header, AwsSigV4};
+
+    /// Credentials and target of `providers.aws.sign_req`
+    #[derive(Deserialize)]
+    pub struct AwsConfig {
+        aws_access_key: String,
+        aws_secret_access_key: String,
+        aws_service: String,
+        aws_region: String,
+        aws_session_token: Option<String>,
+        #[serde(default)]
+        disable_payload_signing: bool,
+    }
+
+    impl std::fmt::Debug for AwsConfig {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("AwsConfig")
+                .field("aws_service", &self.aws_service)
+                .field("aws_region", &self.aws_region)
+                .finish_non_exhaustive()
+        }
+    }
+
+    /// Signs an HTTP request object for Amazon Web Services, with AWS
+    /// Signature Version 4. The request gets the `Authorization`,
+    /// `x-amz-date` and, when relevant, `x-amz-content-sha256` and
+    /// `x-amz-security-token` headers, ready to be passed to `http.send`.
+    #[tracing::instrument(name = "providers.aws.sign_req", skip(request, aws_config), err)]
+    pub fn sign_req(
+        mut request: Map<String, Value>,
+        aws_config: AwsConfig,
+        time_ns: i64,
+    ) -> Result<Map<String, Value>> {
+        let Some(Value::String(method)) = request.get("method") else {
+            bail!("request is missing a method");
+        };
+        let method = method.to_uppercase();
+        let Some(Value::String(url)) = request.get("url") else {
+            bail!("request is missing a url");
+        };
+        let url = Url::parse(url).context("invalid request url")?;
+
+        let payload_hash = if aws_config.disable_payload_signing {
+            "UNSIGNED-PAYLOAD".to_owned()
+        } else {
+            let payload = match (request.get("raw_body"), request.get("body")) {
+                (Some(Value::String(raw_body)), _) => raw_body.clone().into_bytes(),
+                (_, Some(body)) => serde_json::to_vec(body)?,
+                _ => Vec::new(),
+            };
+            hex::encode(Sha256::digest(payload))
+        };
+
+        let seconds = time_ns.div_euclid(1_000_000_000);
+        let nanoseconds = u32::try_from(time_ns.rem_euclid(1_000_000_000))?;
+        let time = chrono::DateTime::from_timestamp(seconds, nanoseconds)
+            .context("time is out of range")?;
+        let amz_date = time.format("%Y%m%dT%H%M%SZ").to_string();
+
+        let mut headers = match request.remove("headers") {
+            Some(Value::Object(headers)) => headers,
+            None | Some(Value::Null) => Map::new(),
+            Some(_) => bail!("request headers must be an object"),
+        };
+        headers.insert("x-amz-date".to_owned(), amz_date.clone().into());
+        if aws_config.aws_service == "s3" || aws_config.disable_payload_signing {
+            headers.insert(
+                "x-amz-content-sha256".to_owned(),
+                payload_hash.clone().into(),
+            );
+        }
+        if let Some(session_token) = &aws_config.aws_session_token {
+            headers.insert(
+                "x-amz-security-token".to_owned(),
+                session_token.clone().into(),
+            );
+        }
+
+        // Like OPA, sign every header of the request
+        let mut signed = BTreeMap::from([("host".to_owned(), host_header(&url)?)]);
+        for (name, value) in &headers {
+            let Value::String(value) = value else {
+                bail!("header {name:?} must be a string");
+            };
+            signed.insert(name.to_lowercase(), value.trim().to_owned());
+        }
+
+        let sig_v4 = AwsSigV4::new(
+            aws_config.aws_access_key,
+            aws_config.aws_secret_access_key,
+            aws_config.aws_region,
+            aws_config.aws_service,
+        );
+        let authorization = sig_v4.authorization(&method, &url, &signed, &payload_hash, &amz_date);
+        headers.insert("Authorization".to_owned(), authorization.into());
+        request.insert("headers".to_owned(), Value::Object(headers));
+        Ok(request)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn sign_req_example() {
+            // Example from the AWS documentation, also used for the
+            // credential provider
+            let request = serde_json::json!({
+                "method": "get",
+                "url": "https://iam.amazonaws.com/?Version=2010-05-08&Action=ListUsers",
+                "headers": {
+                    "Content-Type": "application/x-www-form-urlencoded; charset=utf-8",
+                },
+            });
+            let config = serde_json::json!({
+                "aws_access_key": "AKIDEXAMPLE",
+                "aws_secret_access_key": "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "aws_service": "iam",
+                "aws_region": "us-east-1",
+            });
+            let signed = sign_req(
+                serde_json::from_value(request).unwrap(),
+                serde_json::from_value(config).unwrap(),
+                1_440_938_160_000_000_000,
+            )
+            .unwrap();
+
+            let headers = signed["headers"].as_object().unwrap();
+            assert_eq!(headers["x-amz-date"], "20150830T123600Z");
+            assert_eq!(
+                headers["Authorization"],
+                "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+                 SignedHeaders=content-type;host;x-amz-date, \
+                 Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+            );
+            assert_eq!(signed["method"], "get");
+        }
+
+        #[test]
+        fn sign_req_s3() {
+            let request = serde_json::json!({
+                "method": "PUT",
+                "url": "https://bucket.s3.amazonaws.com/key",
+                "raw_body": "hello",
+            });
+            let config = serde_json::json!({
+                "aws_access_key": "AKIDEXAMPLE",
+                "aws_secret_access_key": "secret",
+                "aws_service": "s3",
+                "aws_region": "eu-west-1",
+                "aws_session_token": "session",
+            });
+            let signed = sign_req(
+                serde_json::from_value(request).unwrap(),
+                serde_json::from_value(config).unwrap(),
+                0,
+            )
+            .unwrap();
+
+            let headers = signed["headers"].as_object().unwrap();
+            assert_eq!(
+                headers["x-amz-content-sha256"],
+                "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+            );
+            assert_eq!(headers["x-amz-security-token"], "session");
+            assert!(headers["Authorization"].as_str().unwrap().contains(
+                "SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token"
+            ));
+        }
+    }
+}
@@ -14,25 +14,24 @@
 
 //! Builtins used to generate pseudo-random values
 
-use anyhow::{bail, Result};
+use anyhow::Result;
 use rand::Rng;
 
 use crate::EvaluationContext;
 
-/// Returns a random integer between `0` and `n` (`n` exlusive). If `n` is `0`,
+/// Returns a random integer between `0` and `n` (`n` exclusive). If `n` is `0`,
 /// then `y` is always `0`. For any given argument pair (`str`, `n`), the output
 /// will be consistent throughout a query evaluation.
+///
+/// Like in OPA, a negative `n` is replaced by its absolute value.
 #[tracing::instrument(name = "rand.intn", skip(ctx), err)]
 pub fn intn<C: EvaluationContext>(ctx: &mut C, str: String, n: i64) -> Result<i64> {
     if n == 0 {
         return Ok(0);
     }
 
-    if n < 0 {
-        bail!("rand.intn: n must be a positive integer")
-    }
-
-    let cache_key = ("rand", str, n);
+    let n = n.checked_abs().unwrap_or(i64::MAX);
+    let cache_key = ("rand.intn", str, n);
     if let Some(v) = ctx.cache_get(&cache_key)? {
         return Ok(v);
     };
@@ -42,3 +41,39 @@ pub fn intn<C: EvaluationContext>(ctx: &mut C, str: String, n: i64) -> Result<i6
     ctx.cache_set(&cache_key, &val)?;
     Ok(val)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultContext;
+
+    #[test]
+    fn cached() {
+        let mut ctx = DefaultContext::default();
+        ctx.evaluation_start();
+
+        assert_eq!(intn(&mut ctx, "zero".into(), 0).unwrap(), 0);
+
+        let value = intn(&mut ctx, "value".into(), 1_000_000).unwrap();
+        assert!((0..1_000_000).contains(&value));
+        assert_eq!(intn(&mut ctx, "value".into(), 1_000_000).unwrap(), value);
+        // The cache is shared with the absolute value of `n`
+        assert_eq!(intn(&mut ctx, "value".into(), -1_000_000).unwrap(), value);
+    }
+
+    #[test]
+    fn seeded() {
+        let mut ctx = DefaultContext::default().with_rng_seed(42);
+
+        ctx.evaluation_start();
+        let values: Vec<_> = (0..10)
+            .map(|i| intn(&mut ctx, i.to_string(), 1_000_000).unwrap())
+            .collect();
+
+        // Every evaluation yields the same sequence of values
+        ctx.evaluation_start();
+        for (i, value) in values.into_iter().enumerate() {
+            assert_eq!(intn(&mut ctx, i.to_string(), 1_000_000).unwrap(), value);
+        }
+    }
+}
@@ -14,14 +14,82 @@
 
 //! Builtins related to regular expressions
 
-use anyhow::{bail, Context, Result};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
 use regex::Regex;
 
+use super::lru::LruCache;
+use crate::EvaluationContext;
+
+/// Default number of compiled expressions kept in a [`RegexCache`]
+pub const DEFAULT_REGEX_CACHE_CAPACITY: usize = 100;
+
+/// A least-recently-used cache of compiled regular expressions, so that hot
+/// patterns are not compiled again on every call.
+///
+/// Cloning it is cheap, and clones share the same underlying cache.
+#[derive(Clone)]
+pub struct RegexCache {
+    inner: Arc<Mutex<LruCache<Regex>>>,
+}
+
+impl RegexCache {
+    /// Create a new cache, holding at most `capacity` compiled expressions.
+    /// A capacity of `0` disables caching.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(LruCache::new("regex", capacity))),
+        }
+    }
+
+    /// Get the compiled expression for a pattern, compiling it if it is not
+    /// in the cache yet
+    fn get(&self, pattern: &str) -> Result<Regex> {
+        self.inner
+            .lock()
+            .map_err(|_| anyhow!("regex cache lock poisoned"))?
+            .get_or_try_insert_with(pattern, || Ok(Regex::new(pattern)?))
+    }
+
+    /// Get the number of compiled expressions in the cache
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.inner.lock().map_or(0, |inner| inner.len())
+    }
+
+    /// Check if the cache is empty
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for RegexCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_REGEX_CACHE_CAPACITY)
+    }
+}
+
+/// Compile a pattern, through the cache of the context if it has one
+fn compile<C: EvaluationContext>(ctx: &C, pattern: &str) -> Result<Regex> {
+    match ctx.regex_cache() {
+        Some(cache) => cache.get(pattern),
+        None => Ok(Regex::new(pattern)?),
+    }
+}
+
 /// Returns the specified number of matches when matching the input against the
 /// pattern.
-#[tracing::instrument(name = "regex.find_n", err)]
-pub fn find_n(pattern: String, value: String, number: i32) -> Result<Vec<String>> {
-    let re = Regex::new(&pattern)?;
+#[tracing::instrument(name = "regex.find_n", skip(ctx), err)]
+pub fn find_n<C: EvaluationContext>(
+    ctx: &mut C,
+    pattern: String,
+    value: String,
+    number: i64,
+) -> Result<Vec<String>> {
+    let re = compile(ctx, &pattern)?;
     Ok(re
         .find_iter(&value)
         .take(usize::try_from(number).unwrap_or(usize::MAX))
@@ -40,9 +108,13 @@ pub fn globs_match(glob1: String, glob2: String) -> Result<bool> {
 }
 
 /// Splits the input string by the occurrences of the given pattern.
-#[tracing::instrument(name = "regex.split", err)]
-pub fn split(pattern: String, value: String) -> Result<Vec<String>> {
-    let re = Regex::new(&pattern)?;
+#[tracing::instrument(name = "regex.split", skip(ctx), err)]
+pub fn split<C: EvaluationContext>(
+    ctx: &mut C,
+    pattern: String,
+    value: String,
+) -> Result<Vec<String>> {
+    let re = compile(ctx, &pattern)?;
     Ok(re
         .split(&value)
         .map(ToString::to_string)
@@ -69,16 +141,25 @@ pub fn template_match(
 
 /// Find and replaces the text using the regular expression pattern.
 /// The semantics of `replace` in OPA is actually `replace_all`
-#[tracing::instrument(name = "regex.replace", err)]
-pub fn replace(s: String, pattern: String, value: String) -> Result<String> {
-    let re = Regex::new(&pattern)?;
+#[tracing::instrument(name = "regex.replace", skip(ctx), err)]
+pub fn replace<C: EvaluationContext>(
+    ctx: &mut C,
+    s: String,
+    pattern: String,
+    value: String,
+) -> Result<String> {
+    let re = compile(ctx, &pattern)?;
     Ok(re.replace_all(&s, &value).to_string())
 }
 
 /// Matches a string against a regular expression.
-#[tracing::instrument(name = "regex.match", err)]
-pub fn regex_match(pattern: String, value: String) -> Result<bool> {
-    let re = Regex::new(&pattern)?;
+#[tracing::instrument(name = "regex.match", skip(ctx), err)]
+pub fn regex_match<C: EvaluationContext>(
+    ctx: &mut C,
+    pattern: String,
+    value: String,
+) -> Result<bool> {
+    let re = compile(ctx, &pattern)?;
     Ok(re.is_match(&value))
 }
 
@@ -94,21 +175,82 @@ pub fn is_valid(pattern: String) -> bool {
 }
 
 /// Returns all successive matches of the expression.
-#[tracing::instrument(name = "regex.find_all_string_submatch_n", err)]
-pub fn find_all_string_submatch_n(
+///
+/// A negative `number` returns every match. Groups which did not participate
+/// in a match are returned as empty strings, like in Go.
+#[tracing::instrument(name = "regex.find_all_string_submatch_n", skip(ctx), err)]
+pub fn find_all_string_submatch_n<C: EvaluationContext>(
+    ctx: &mut C,
     pattern: String,
     value: String,
-    number: usize,
+    number: i64,
 ) -> Result<Vec<Vec<String>>> {
-    let re = Regex::new(&pattern)?;
+    let re = compile(ctx, &pattern)?;
     Ok(re
         .captures_iter(&value)
-        .take(number)
+        .take(usize::try_from(number).unwrap_or(usize::MAX))
         .map(|m| {
             m.iter()
-                .flatten()
-                .map(|m| m.as_str().to_string())
+                .map(|m| m.map_or_else(String::new, |m| m.as_str().to_string()))
                 .collect::<Vec<_>>()
         })
         .collect::<Vec<_>>())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DefaultContext;
+
+    #[test]
+    fn cache() {
+        let cache = RegexCache::new(2);
+        assert!(cache.is_empty());
+
+        let re = cache.get("a+").unwrap();
+        assert!(re.is_match("aaa"));
+        cache.get("a+").unwrap();
+        assert_eq!(cache.len(), 1);
+
+        assert!(cache.get("(").is_err());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn cache_eviction() {
+        let cache = RegexCache::new(2);
+        cache.get("a+").unwrap();
+        cache.get("b+").unwrap();
+        // Touch `a+` so that `b+` is the least recently used
+        cache.get("a+").unwrap();
+        cache.get("c+").unwrap();
+        assert_eq!(cache.len(), 2);
+
+        let mut inner = cache.inner.lock().unwrap();
+        assert!(inner.get("b+").is_none());
+        assert!(inner.get("a+").is_some());
+        assert!(inner.get("c+").is_some());
+    }
+
+    #[test]
+    fn submatches() {
+        let mut ctx = DefaultContext::default();
+        let matches =
+            find_all_string_submatch_n(&mut ctx, "a(x)?(b)".into(), "ab axb ab".into(), -1)
+                .unwrap();
+        assert_eq!(
+            matches,
+            vec![
+                vec!["ab".to_owned(), String::new(), "b".to_owned()],
+                vec!["axb".to_owned(), "x".to_owned(), "b".to_owned()],
+                vec!["ab".to_owned(), String::new(), "b".to_owned()],
+            ]
+        );
+
+        let matches = find_n(&mut ctx, "a.".into(), "ab ac ad".into(), 2).unwrap();
+        assert_eq!(matches, vec!["ab".to_owned(), "ac".to_owned()]);
+
+        // The compiled expressions are cached in the context
+        assert_eq!(ctx.regex_cache().unwrap().len(), 2);
+    }
+}
@@ -14,7 +14,151 @@
 
 //! Builtins related to Rego parsing
 
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+
 use anyhow::{bail, Result};
+use serde_json::Value;
+
+use crate::EvaluationContext;
+
+/// Annotations of a rule, as returned by the `rego.metadata.*` builtins
+#[derive(Debug, Clone)]
+struct RuleAnnotations {
+    chain: Vec<Value>,
+    rule: Value,
+}
+
+impl Default for RuleAnnotations {
+    fn default() -> Self {
+        Self {
+            chain: Vec::new(),
+            rule: Value::Object(serde_json::Map::new()),
+        }
+    }
+}
+
+impl RuleAnnotations {
+    /// Build the chain and rule annotations from the annotations OPA lists
+    /// for an entrypoint in the bundle manifest, from the rule to the package
+    fn from_annotations(path: &str, annotations: &[Value]) -> Self {
+        fn scope(annotation: &Value) -> Option<&str> {
+            annotation.get("scope").and_then(Value::as_str)
+        }
+
+        let rule_path: Vec<Value> = std::iter::once("data")
+            .chain(path.split('/').filter(|s| !s.is_empty()))
+            .map(Value::from)
+            .collect();
+
+        let chain = annotations
+            .iter()
+            .map(|annotation| {
+                // Package annotations apply to the parent of the rule
+                let path = match scope(annotation) {
+                    Some("package" | "subpackages") => &rule_path[..rule_path.len() - 1],
+                    _ => &rule_path[..],
+                };
+                serde_json::json!({ "path": path, "annotations": annotation })
+            })
+            .collect();
+        let rule = annotations
+            .iter()
+            .find(|annotation| matches!(scope(annotation), Some("rule" | "document")))
+            .cloned()
+            .unwrap_or_else(|| Value::Object(serde_json::Map::new()));
+
+        Self { chain, rule }
+    }
+}
+
+/// Annotations returned by the `rego.metadata.*` builtins.
+///
+/// The OPA compiler usually replaces calls to these builtins with the
+/// annotations of the calling rule, so they are only called by modules
+/// compiled without that rewrite. In that case, the annotations are looked up
+/// by the path of the evaluated entrypoint (e.g. `example/allow`), and default
+/// to the ones set with [`RegoMetadata::with_chain`] and
+/// [`RegoMetadata::with_rule`].
+///
+/// Cloning it is cheap, and clones share the same annotations.
+#[derive(Debug, Clone, Default)]
+pub struct RegoMetadata {
+    default: RuleAnnotations,
+    rules: Arc<HashMap<String, RuleAnnotations>>,
+    entrypoint: Arc<Mutex<Option<String>>>,
+}
+
+impl RegoMetadata {
+    /// Set the chain of annotations returned by `rego.metadata.chain` for
+    /// rules without annotations, from the rule to the package
+    #[must_use]
+    pub fn with_chain(mut self, chain: Vec<Value>) -> Self {
+        self.default.chain = chain;
+        self
+    }
+
+    /// Set the annotations returned by `rego.metadata.rule` for rules without
+    /// annotations
+    #[must_use]
+    pub fn with_rule(mut self, rule: Value) -> Self {
+        self.default.rule = rule;
+        self
+    }
+
+    /// Set the annotations of the rule at the given path (e.g.
+    /// `example/allow`), in the format of the `annotations` OPA lists for
+    /// each entrypoint of a bundle manifest, from the rule to the package
+    #[must_use]
+    pub fn with_rule_annotations(mut self, path: &str, annotations: &[Value]) -> Self {
+        let path = path.trim_matches('/');
+        Arc::make_mut(&mut self.rules).insert(
+            path.to_owned(),
+            RuleAnnotations::from_annotations(path, annotations),
+        );
+        self
+    }
+
+    /// Set the entrypoint being evaluated
+    pub(crate) fn enter(&self, entrypoint: &str) {
+        *self
+            .entrypoint
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(entrypoint.to_owned());
+    }
+
+    /// Get the annotations of the rule being evaluated
+    fn current(&self) -> &RuleAnnotations {
+        let entrypoint = self
+            .entrypoint
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        entrypoint
+            .as_deref()
+            .and_then(|entrypoint| self.rules.get(entrypoint.trim_matches('/')))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Returns the chain of metadata for the active rule.
+#[tracing::instrument(name = "rego.metadata.chain", skip(ctx))]
+pub fn metadata_chain<C: EvaluationContext>(ctx: &mut C) -> Vec<Value> {
+    ctx.rego_metadata()
+        .map(|metadata| metadata.current().chain.clone())
+        .unwrap_or_default()
+}
+
+/// Returns annotations declared for the active rule and using the rule as
+/// scope.
+#[tracing::instrument(name = "rego.metadata.rule", skip(ctx))]
+pub fn metadata_rule<C: EvaluationContext>(ctx: &mut C) -> Value {
+    ctx.rego_metadata().map_or_else(
+        || RuleAnnotations::default().rule,
+        |metadata| metadata.current().rule.clone(),
+    )
+}
 
 /// Parses the input Rego string and returns an object representation of the
 /// AST.
@@ -22,3 +166,49 @@ use anyhow::{bail, Result};
 pub fn parse_module(filename: String, rego: String) -> Result<serde_json::Value> {
     bail!("not implemented");
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn rule_annotations() {
+        let metadata = RegoMetadata::default()
+            .with_rule(json!({"title": "default"}))
+            .with_rule_annotations(
+                "example/allow",
+                &[
+                    json!({"scope": "rule", "title": "Allow"}),
+                    json!({"scope": "package", "title": "Example"}),
+                ],
+            );
+
+        // Outside of an evaluation, or for rules without annotations
+        assert_eq!(metadata.current().rule, json!({"title": "default"}));
+        metadata.enter("example/deny");
+        assert_eq!(metadata.current().rule, json!({"title": "default"}));
+        assert!(metadata.current().chain.is_empty());
+
+        // Clones share the evaluated entrypoint
+        metadata.clone().enter("example/allow");
+        assert_eq!(
+            metadata.current().rule,
+            json!({"scope": "rule", "title": "Allow"})
+        );
+        assert_eq!(
+            metadata.current().chain,
+            vec![
+                json!({
+                    "path": ["data", "example", "allow"],
+                    "annotations": {"scope": "rule", "title": "Allow"},
+                }),
+                json!({
+                    "path": ["data", "example"],
+                    "annotations": {"scope": "package", "title": "Example"},
+                }),
+            ]
+        );
+    }
+}
@@ -19,20 +19,57 @@ use std::cmp::Ordering;
 use anyhow::Result;
 use semver::Version;
 
-/// Compares valid SemVer formatted version strings.
+/// Compares valid `SemVer` formatted version strings.
+///
+/// Build metadata is ignored, as required by the `SemVer` precedence rules.
 #[tracing::instrument(name = "semver.compare", err)]
 pub fn compare(a: String, b: String) -> Result<i8> {
     let a = Version::parse(&a)?;
     let b = Version::parse(&b)?;
-    match a.cmp(&b) {
+    // `Version`'s ordering also compares the build metadata, which SemVer says
+    // must be ignored when determining precedence
+    let ordering = (a.major, a.minor, a.patch, &a.pre).cmp(&(b.major, b.minor, b.patch, &b.pre));
+    match ordering {
         Ordering::Less => Ok(-1),
         Ordering::Equal => Ok(0),
         Ordering::Greater => Ok(1),
     }
 }
 
-/// Validates that the input is a valid SemVer string.
+/// Validates that the input is a valid `SemVer` string. Any other value, like a
+/// number, is not valid.
 #[tracing::instrument(name = "semver.is_valid")]
-pub fn is_valid(vsn: String) -> bool {
-    Version::parse(&vsn).is_ok()
+pub fn is_valid(vsn: serde_json::Value) -> bool {
+    vsn.as_str().is_some_and(|vsn| Version::parse(vsn).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        assert_eq!(compare("1.0.0".into(), "1.0.1".into()).unwrap(), -1);
+        assert_eq!(compare("1.10.0".into(), "1.9.0".into()).unwrap(), 1);
+        assert_eq!(compare("1.0.0-alpha".into(), "1.0.0".into()).unwrap(), -1);
+        assert_eq!(
+            compare("1.0.0-alpha.10".into(), "1.0.0-alpha.9".into()).unwrap(),
+            1
+        );
+        assert_eq!(
+            compare("1.0.0+build.1".into(), "1.0.0+build.2".into()).unwrap(),
+            0
+        );
+        assert!(compare("1.0".into(), "1.0.0".into()).is_err());
+    }
+
+    #[test]
+    fn validity() {
+        assert!(is_valid(json!("1.0.0-rc.1+build.5")));
+        assert!(!is_valid(json!("v1.0.0")));
+        assert!(!is_valid(json!("1.0")));
+        assert!(!is_valid(json!(1)));
+    }
 }
@@ -0,0 +1,700 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implementation of `sprintf`, following the formatting rules of Go's `fmt`
+//! package the way OPA feeds it the arguments
+
+use std::fmt::Write;
+
+use serde_json::Value;
+
+/// An argument of `sprintf`, converted like OPA does before calling
+/// `fmt.Sprintf`: integers stay integers, other numbers become floats, and any
+/// other value is formatted as a Rego term string
+enum Arg {
+    Int(i128),
+    Float(f64),
+    Str(String),
+}
+
+impl Arg {
+    fn new(value: Value) -> Self {
+        match value {
+            Value::Number(n) => {
+                if let Some(n) = n.as_i64() {
+                    Self::Int(n.into())
+                } else if let Some(n) = n.as_u64() {
+                    Self::Int(n.into())
+                } else {
+                    Self::Float(n.as_f64().unwrap_or(f64::NAN))
+                }
+            }
+            Value::String(s) => Self::Str(s),
+            value => Self::Str(term_string(&value)),
+        }
+    }
+
+    /// The name of the Go type of the argument, as printed in errors
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::Int(n) if i64::try_from(*n).is_ok() => "int",
+            Self::Int(_) => "*big.Int",
+            Self::Float(_) => "float64",
+            Self::Str(_) => "string",
+        }
+    }
+
+    /// The argument formatted with `%v`
+    fn plain(&self) -> String {
+        match self {
+            Self::Int(n) => n.to_string(),
+            Self::Float(f) => format_float(*f, 'g', None),
+            Self::Str(s) => s.clone(),
+        }
+    }
+}
+
+/// Format a value like OPA's `Term.String`
+fn term_string(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_owned(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => quote(s, '"'),
+        Value::Array(values) => {
+            let values: Vec<_> = values.iter().map(term_string).collect();
+            format!("[{}]", values.join(", "))
+        }
+        Value::Object(map) => {
+            let entries: Vec<_> = map
+                .iter()
+                .map(|(key, value)| format!("{}: {}", quote(key, '"'), term_string(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+    }
+}
+
+/// Quote a string or a rune like Go's `strconv.Quote` and
+/// `strconv.QuoteRune`
+fn quote(s: &str, delimiter: char) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push(delimiter);
+    for c in s.chars() {
+        match c {
+            '\u{7}' => quoted.push_str("\\a"),
+            '\u{8}' => quoted.push_str("\\b"),
+            '\u{c}' => quoted.push_str("\\f"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\u{b}' => quoted.push_str("\\v"),
+            '\\' => quoted.push_str("\\\\"),
+            c if c == delimiter => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if !c.is_control() => quoted.push(c),
+            c if u32::from(c) < 0x80 => {
+                let _ = write!(quoted, "\\x{:02x}", u32::from(c));
+            }
+            c if u32::from(c) <= 0xFFFF => {
+                let _ = write!(quoted, "\\u{:04x}", u32::from(c));
+            }
+            c => {
+                let _ = write!(quoted, "\\U{:08x}", u32::from(c));
+            }
+        }
+    }
+    quoted.push(delimiter);
+    quoted
+}
+
+/// A flag of a verb
+#[derive(Clone, Copy)]
+enum Flag {
+    /// `-`, pad on the right
+    Minus,
+    /// `+`, always print a sign
+    Plus,
+    /// `#`, alternate format
+    Sharp,
+    /// ` `, leave a space for the sign, or between bytes
+    Space,
+    /// `0`, pad with leading zeros
+    Zero,
+}
+
+/// The flags, width and precision of a verb
+#[derive(Default)]
+struct Spec {
+    flags: u8,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn has(&self, flag: Flag) -> bool {
+        self.flags & (1 << flag as u8) != 0
+    }
+
+    fn set(&mut self, flag: Flag, enabled: bool) {
+        if enabled {
+            self.flags |= 1 << flag as u8;
+        } else {
+            self.flags &= !(1 << flag as u8);
+        }
+    }
+
+    /// Pad to the width with spaces, or zeros if the `0` flag is set
+    fn pad(&self, s: &str) -> String {
+        let zero = self.has(Flag::Zero) && !self.has(Flag::Minus);
+        self.pad_with(s, if zero { '0' } else { ' ' })
+    }
+
+    fn pad_with(&self, s: &str, padding: char) -> String {
+        let len = s.chars().count();
+        let Some(missing) = self.width.and_then(|width| width.checked_sub(len)) else {
+            return s.to_owned();
+        };
+        let padding: String = std::iter::repeat(padding).take(missing).collect();
+        if self.has(Flag::Minus) {
+            format!("{s}{padding}")
+        } else {
+            format!("{padding}{s}")
+        }
+    }
+
+    /// The sign of a number, depending on the `+` and space flags
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.has(Flag::Plus) {
+            "+"
+        } else if self.has(Flag::Space) {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    /// Pad a signed number, putting the zeros between the sign and the digits
+    fn pad_number(&self, sign: &str, digits: &str) -> String {
+        if self.has(Flag::Zero) && !self.has(Flag::Minus) {
+            let width = self.width.unwrap_or_default();
+            let len = sign.len() + digits.chars().count();
+            if width > len {
+                let zeros = "0".repeat(width - len);
+                return format!("{sign}{zeros}{digits}");
+            }
+        }
+        self.pad_with(&format!("{sign}{digits}"), ' ')
+    }
+}
+
+/// Widths and precisions larger than this are rejected, like in Go
+const MAX_WIDTH: usize = 1_000_000;
+
+/// Parse the digits of a width or precision, returning `None` if it is too
+/// large
+fn parse_number(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> Option<usize> {
+    let mut number: usize = 0;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        number = number
+            .saturating_mul(10)
+            .saturating_add(usize::try_from(digit).unwrap_or_default());
+        chars.next();
+    }
+    (number <= MAX_WIDTH).then_some(number)
+}
+
+/// Take a width or precision from the arguments
+fn number_arg(arg: Option<&Arg>) -> Option<i128> {
+    match arg {
+        Some(Arg::Int(n)) if n.unsigned_abs() <= MAX_WIDTH as u128 => Some(*n),
+        _ => None,
+    }
+}
+
+/// Format an integer in the given base, like Go's `fmt.fmtInteger`
+fn format_integer(n: i128, base: u32, verb: char, spec: &Spec) -> String {
+    let negative = n < 0;
+    let magnitude = n.unsigned_abs();
+    let mut digits = match base {
+        2 => format!("{magnitude:b}"),
+        8 => format!("{magnitude:o}"),
+        16 if verb == 'X' => format!("{magnitude:X}"),
+        16 => format!("{magnitude:x}"),
+        _ => magnitude.to_string(),
+    };
+
+    // A precision sets the minimum number of digits, the `0` flag pads to
+    // the width, but is ignored when a precision is set
+    let min_digits = match spec.precision {
+        Some(0) if n == 0 => return spec.pad_with("", ' '),
+        Some(precision) => precision,
+        None if spec.has(Flag::Zero) && !spec.has(Flag::Minus) => {
+            let width = spec.width.unwrap_or_default();
+            if negative || spec.has(Flag::Plus) || spec.has(Flag::Space) {
+                width.saturating_sub(1)
+            } else {
+                width
+            }
+        }
+        None => 0,
+    };
+    if digits.len() < min_digits {
+        digits = format!("{}{digits}", "0".repeat(min_digits - digits.len()));
+    }
+
+    let prefix = match (base, verb) {
+        (8, 'O') => "0o",
+        (8, _) if spec.has(Flag::Sharp) && !digits.starts_with('0') => "0",
+        (16, 'x') if spec.has(Flag::Sharp) => "0x",
+        (16, 'X') if spec.has(Flag::Sharp) => "0X",
+        (2, _) if spec.has(Flag::Sharp) => "0b",
+        _ => "",
+    };
+
+    let sign = spec.sign(negative);
+    spec.pad_with(&format!("{sign}{prefix}{digits}"), ' ')
+}
+
+/// The significant decimal digits of a positive float, without trailing
+/// zeros, and the position of the decimal point, like Go's `decimalSlice`
+fn decimal_digits(f: f64, significant: Option<usize>) -> (Vec<u8>, i32) {
+    let formatted = match significant {
+        Some(significant) => format!("{:.*e}", significant.saturating_sub(1), f),
+        None => format!("{f:e}"),
+    };
+    let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or_default();
+    let mut digits: Vec<u8> = mantissa.bytes().filter(u8::is_ascii_digit).collect();
+    while digits.len() > 1 && digits.last() == Some(&b'0') {
+        digits.pop();
+    }
+    if digits == [b'0'] {
+        return (Vec::new(), 0);
+    }
+    (digits, exponent + 1)
+}
+
+/// Format digits in scientific notation with `precision` decimals
+fn format_digits_e(digits: &[u8], point: i32, precision: usize, e: char) -> String {
+    let mut s = String::new();
+    s.push(char::from(digits.first().copied().unwrap_or(b'0')));
+    if precision > 0 {
+        s.push('.');
+        for i in 1..=precision {
+            s.push(char::from(digits.get(i).copied().unwrap_or(b'0')));
+        }
+    }
+    let exponent = if digits.is_empty() { 0 } else { point - 1 };
+    let sign = if exponent < 0 { '-' } else { '+' };
+    let _ = write!(s, "{e}{sign}{:02}", exponent.unsigned_abs());
+    s
+}
+
+/// Format digits in decimal notation with `precision` decimals
+fn format_digits_f(digits: &[u8], point: i32, precision: usize) -> String {
+    let digit = |i: i64| {
+        usize::try_from(i)
+            .ok()
+            .and_then(|i| digits.get(i))
+            .map_or('0', |digit| char::from(*digit))
+    };
+
+    let mut s = String::new();
+    if point > 0 {
+        s.extend((0..i64::from(point)).map(digit));
+    } else {
+        s.push('0');
+    }
+    if precision > 0 {
+        s.push('.');
+        for i in 0..precision {
+            s.push(digit(
+                i64::from(point) + i64::try_from(i).unwrap_or(i64::MAX),
+            ));
+        }
+    }
+    s
+}
+
+/// Format the magnitude of a float like Go's `strconv.FormatFloat`, `None`
+/// being the shortest representation for `g`
+fn format_float(f: f64, verb: char, precision: Option<usize>) -> String {
+    let f = f.abs();
+    if f.is_nan() {
+        return "NaN".to_owned();
+    }
+    if f.is_infinite() {
+        return "Inf".to_owned();
+    }
+
+    match verb {
+        'e' | 'E' => {
+            let (digits, point) = decimal_digits(f, Some(precision.unwrap_or(6) + 1));
+            format_digits_e(&digits, point, precision.unwrap_or(6), verb)
+        }
+        'f' | 'F' => format!("{:.*}", precision.unwrap_or(6), f),
+        _ => {
+            let e = if verb == 'G' { 'E' } else { 'e' };
+            let shortest = precision.is_none();
+            let precision = precision.map(|precision| precision.max(1));
+            let (digits, point) = decimal_digits(f, precision);
+            let nd = digits.len();
+            let mut precision = precision.unwrap_or(nd);
+
+            let mut eprec = precision;
+            if eprec > nd && i64::try_from(nd).unwrap_or(i64::MAX) >= i64::from(point) {
+                eprec = nd;
+            }
+            if shortest {
+                eprec = 6;
+            }
+            let exponent = i64::from(point) - 1;
+            if exponent < -4 || exponent >= i64::try_from(eprec).unwrap_or(i64::MAX) {
+                precision = precision.min(nd);
+                return format_digits_e(&digits, point, precision.saturating_sub(1), e);
+            }
+            if i64::try_from(precision).unwrap_or(i64::MAX) > i64::from(point) {
+                precision = nd;
+            }
+            let decimals = i64::try_from(precision).unwrap_or(i64::MAX) - i64::from(point);
+            format_digits_f(&digits, point, usize::try_from(decimals).unwrap_or(0))
+        }
+    }
+}
+
+/// Format a single argument, or return `None` if the verb does not apply to
+/// it
+fn format_arg(verb: char, spec: &Spec, arg: &Arg) -> Option<String> {
+    let formatted = match (verb, arg) {
+        ('v' | 'd', Arg::Int(n)) => format_integer(*n, 10, verb, spec),
+        ('b', Arg::Int(n)) => format_integer(*n, 2, verb, spec),
+        ('o' | 'O', Arg::Int(n)) => format_integer(*n, 8, verb, spec),
+        ('x' | 'X', Arg::Int(n)) => format_integer(*n, 16, verb, spec),
+        ('c', Arg::Int(n)) => {
+            let c = u32::try_from(*n)
+                .ok()
+                .and_then(char::from_u32)
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            spec.pad(&c.to_string())
+        }
+        ('q', Arg::Int(n)) => {
+            let c = u32::try_from(*n)
+                .ok()
+                .and_then(char::from_u32)
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            spec.pad(&quote(&c.to_string(), '\''))
+        }
+        ('U', Arg::Int(n)) => {
+            let mut s = format!("U+{n:04X}");
+            if spec.has(Flag::Sharp) {
+                if let Some(c) = u32::try_from(*n).ok().and_then(char::from_u32) {
+                    let _ = write!(s, " '{c}'");
+                }
+            }
+            spec.pad(&s)
+        }
+        ('v' | 'e' | 'E' | 'f' | 'F' | 'g' | 'G', Arg::Float(f)) => {
+            let verb = if verb == 'v' { 'g' } else { verb };
+            let digits = format_float(*f, verb, spec.precision);
+            spec.pad_number(spec.sign(f.is_sign_negative()), &digits)
+        }
+        ('v' | 's', Arg::Str(s)) => {
+            let s: String = match spec.precision {
+                Some(precision) => s.chars().take(precision).collect(),
+                None => s.clone(),
+            };
+            spec.pad(&s)
+        }
+        ('q', Arg::Str(s)) => {
+            if spec.has(Flag::Sharp) && !s.contains('`') && !s.chars().any(char::is_control) {
+                spec.pad(&format!("`{s}`"))
+            } else {
+                spec.pad(&quote(s, '"'))
+            }
+        }
+        ('x' | 'X', Arg::Str(s)) => {
+            let bytes = match spec.precision {
+                Some(precision) => &s.as_bytes()[..precision.min(s.len())],
+                None => s.as_bytes(),
+            };
+            let encoded: Vec<_> = bytes
+                .iter()
+                .map(|byte| {
+                    let prefix = if spec.has(Flag::Sharp) { "0x" } else { "" };
+                    if verb == 'X' {
+                        format!("{prefix}{byte:02X}")
+                    } else {
+                        format!("{prefix}{byte:02x}")
+                    }
+                })
+                .collect();
+            spec.pad(&encoded.join(if spec.has(Flag::Space) { " " } else { "" }))
+        }
+        ('T', arg) => spec.pad(arg.type_name()),
+        _ => return None,
+    };
+    Some(formatted)
+}
+
+/// Returns the given string, formatted.
+///
+/// The verbs and flags follow Go's `fmt` package. Integers are formatted as
+/// Go integers, other numbers as `float64`, strings as strings and any other
+/// value as its Rego representation, e.g. `[1, "a"]`. Errors in the format
+/// are reported inline, e.g. `%!d(string=a)` or `%!d(MISSING)`.
+#[tracing::instrument]
+pub fn sprintf(format: String, values: Vec<Value>) -> String {
+    let args: Vec<Arg> = values.into_iter().map(Arg::new).collect();
+    let mut args = args.iter();
+    let mut out = String::with_capacity(format.len());
+
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => {
+                    spec.set(Flag::Minus, true);
+                    spec.set(Flag::Zero, false);
+                }
+                '+' => spec.set(Flag::Plus, true),
+                '#' => spec.set(Flag::Sharp, true),
+                ' ' => spec.set(Flag::Space, true),
+                '0' => spec.set(Flag::Zero, !spec.has(Flag::Minus)),
+                _ => break,
+            }
+            chars.next();
+        }
+
+        // Width, either as digits or as an argument
+        if chars.peek() == Some(&'*') {
+            chars.next();
+            match number_arg(args.next()) {
+                Some(n) => {
+                    if n < 0 {
+                        spec.set(Flag::Minus, true);
+                        spec.set(Flag::Zero, false);
+                    }
+                    spec.width = usize::try_from(n.unsigned_abs()).ok();
+                }
+                None => out.push_str("%!(BADWIDTH)"),
+            }
+        } else if chars.peek().is_some_and(char::is_ascii_digit) {
+            spec.width = parse_number(&mut chars);
+        }
+
+        // Precision, either as digits or as an argument
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                match number_arg(args.next()) {
+                    Some(n) if n >= 0 => spec.precision = usize::try_from(n).ok(),
+                    _ => out.push_str("%!(BADPREC)"),
+                }
+            } else {
+                spec.precision = parse_number(&mut chars);
+            }
+        }
+
+        let Some(verb) = chars.next() else {
+            out.push_str("%!(NOVERB)");
+            break;
+        };
+        if verb == '%' {
+            out.push('%');
+            continue;
+        }
+
+        let Some(arg) = args.next() else {
+            let _ = write!(out, "%!{verb}(MISSING)");
+            continue;
+        };
+        match format_arg(verb, &spec, arg) {
+            Some(formatted) => out.push_str(&formatted),
+            None => {
+                let _ = write!(out, "%!{verb}({}={})", arg.type_name(), arg.plain());
+            }
+        }
+    }
+
+    let extra: Vec<_> = args
+        .map(|arg| format!("{}={}", arg.type_name(), arg.plain()))
+        .collect();
+    if !extra.is_empty() {
+        let _ = write!(out, "%!(EXTRA {})", extra.join(", "));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn check(format: &str, values: Value, expected: &str) {
+        let values = serde_json::from_value(values).unwrap();
+        assert_eq!(sprintf(format.to_owned(), values), expected, "{format}");
+    }
+
+    /// Outputs of Go's `fmt.Sprintf`, with the arguments converted by OPA
+    #[test]
+    fn go_compatibility() {
+        check("%d", json!([42]), "42");
+        check("%5d|%-5d|%05d", json!([42, 42, -42]), "   42|42   |-0042");
+        check("%.3d|%+d|% d", json!([7, 5, 5]), "007|+5| 5");
+        check(
+            "%x %X %o %O %b",
+            json!([255, 255, 8, 8, 5]),
+            "ff FF 10 0o10 101",
+        );
+        check("%#x %#o %#b", json!([255, 8, 5]), "0xff 010 0b101");
+        check(
+            "%c %q %U %#U",
+            json!([65, 65, 65, 65]),
+            "A 'A' U+0041 U+0041 'A'",
+        );
+        check("%v", json!([1.5]), "1.5");
+        check(
+            "%v %v %v",
+            json!([0.00001, 1e21, 123_456.5]),
+            "1e-05 1e+21 123456.5",
+        );
+        check(
+            "%.2f|%8.3f|%-8.2f|",
+            json!([3.14159, 3.14159, 2.5]),
+            "3.14|   3.142|2.50    |",
+        );
+        check("%08.3f|%+.1f", json!([-3.14159, 2.26]), "-003.142|+2.3");
+        check(
+            "%e|%.3E",
+            json!([1234.5678, 1234.56]),
+            "1.234568e+03|1.235E+03",
+        );
+        check(
+            "%g|%.3g|%.3g|%G",
+            json!([100_000_000.5, 1.0, 1234.5, 1e-7]),
+            "1.000000005e+08|1|1.23e+03|1E-07",
+        );
+        check(
+            "%s|%10s|%-4s|%.2s",
+            json!(["go", "right", "l", "trunc"]),
+            "go|     right|l   |tr",
+        );
+        check(
+            "%q|%#q|%x|% X",
+            json!(["hi\n", "raw", "hi", "hi"]),
+            "\"hi\\n\"|`raw`|6869|68 69",
+        );
+        check(
+            "%v|%v|%v",
+            json!([true, null, [1, "a"]]),
+            "true|null|[1, \"a\"]",
+        );
+        check(
+            "%v",
+            json!([{"b": [], "a": {"c": 1}}]),
+            "{\"a\": {\"c\": 1}, \"b\": []}",
+        );
+        check(
+            "%T %T %T %T",
+            json!([1, 1.5, "a", false]),
+            "int float64 string string",
+        );
+        check(
+            "%*d|%-*d|%.*f",
+            json!([5, 42, 4, 7, 1, 2.55]),
+            "   42|7   |2.5",
+        );
+        check("100%%", json!([]), "100%");
+    }
+
+    #[test]
+    fn go_errors() {
+        check("%s", json!([5]), "%!s(int=5)");
+        check("%d", json!(["a"]), "%!d(string=a)");
+        check("%f", json!([3]), "%!f(int=3)");
+        check("%t", json!([true]), "%!t(string=true)");
+        check("%z", json!([1.5]), "%!z(float64=1.5)");
+        check("%d %d", json!([1]), "1 %!d(MISSING)");
+        check(
+            "%d",
+            json!([1, "a", 2.5]),
+            "1%!(EXTRA string=a, float64=2.5)",
+        );
+        check("abc%", json!([]), "abc%!(NOVERB)");
+        check("%*d", json!(["a", 1]), "%!(BADWIDTH)1");
+        check("%d", json!([u64::MAX]), "18446744073709551615");
+        check(
+            "%s",
+            json!([u64::MAX]),
+            "%!s(*big.Int=18446744073709551615)",
+        );
+    }
+
+    /// Format random strings with random arguments, to make sure no input
+    /// can make the builtin panic
+    #[test]
+    fn fuzz() {
+        const ALPHABET: &[char] = &[
+            '%', '%', '%', 'd', 's', 'v', 'x', 'X', 'f', 'e', 'g', 'G', 'q', 'c', 'U', 'T', 'b',
+            'o', '.', '-', '+', ' ', '0', '#', '1', '9', '*', 'a', 'é',
+        ];
+
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            usize::try_from(state % u64::try_from(bound).unwrap()).unwrap()
+        };
+
+        let values = [
+            json!(0),
+            json!(-1),
+            json!(1_114_112),
+            json!(i64::MIN),
+            json!(u64::MAX),
+            json!(0.1),
+            json!(-1e300),
+            json!(5e-324),
+            json!("é\u{1}`"),
+            json!(""),
+            json!([1, {"a": null}]),
+            json!(false),
+        ];
+
+        for _ in 0..10_000 {
+            let format: String = (0..next(12))
+                .map(|_| ALPHABET[next(ALPHABET.len())])
+                .collect();
+            let args: Vec<Value> = (0..next(4))
+                .map(|_| values[next(values.len())].clone())
+                .collect();
+            sprintf(format, args);
+        }
+    }
+}
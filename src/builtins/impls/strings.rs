@@ -0,0 +1,507 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builtins related to strings, which OPA leaves to the SDK depending on its
+//! version
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use super::numbers::BigNumber;
+
+/// Returns the string representation of the number in the given base after
+/// truncating it to an integer value.
+///
+/// # Errors
+///
+/// Returns an error if the base is not one of 2, 8, 10 or 16.
+#[tracing::instrument(name = "format_int", err)]
+pub fn format_int(number: BigNumber, base: u32) -> Result<String> {
+    if !matches!(base, 2 | 8 | 10 | 16) {
+        bail!("operand 2 must be one of {{2, 8, 10, 16}}");
+    }
+
+    Ok(number.to_truncated_integer().to_str_radix(base))
+}
+
+/// Replaces a string from a list of old, new string pairs. Replacements are
+/// performed in the order they appear in the target string, without
+/// overlapping matches. The old string comparisons are done in argument
+/// order.
+#[tracing::instrument(name = "strings.replace_n")]
+pub fn replace_n(patterns: BTreeMap<String, String>, value: String) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value.as_str();
+    loop {
+        // Like Go's `strings.Replacer`, the first pattern in argument order
+        // wins when several match at the same position
+        let matched = patterns
+            .iter()
+            .find(|(old, _)| rest.starts_with(old.as_str()));
+        if let Some((old, new)) = matched {
+            out.push_str(new);
+            rest = &rest[old.len()..];
+            // An empty pattern matches between every character
+            if old.is_empty() {
+                let Some(c) = rest.chars().next() else {
+                    break;
+                };
+                out.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        } else {
+            let Some(c) = rest.chars().next() else {
+                break;
+            };
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+/// Renders a templated string with given template variables injected. For a
+/// given templated string and key/value mapping, values will be injected into
+/// the template where they are referenced by key.
+///
+/// Templates use the syntax of Go's `text/template`, and support printing
+/// fields like `{{.name}}` or `{{.user.name}}`, `{{if}}`, `{{else}}`,
+/// `{{range}}` and `{{with}}` blocks, comments and whitespace trimming.
+/// Referencing a missing key is an error.
+///
+/// # Errors
+///
+/// Returns an error if the template is malformed, or if it references a
+/// missing key.
+#[tracing::instrument(name = "strings.render_template", err)]
+pub fn render_template(template: String, vars: Value) -> Result<String> {
+    let tokens = template::tokenize(&template)?;
+    let mut parser = template::Parser {
+        tokens: &tokens,
+        position: 0,
+    };
+    let (nodes, terminator) = parser.parse_list()?;
+    if let Some(terminator) = terminator {
+        bail!("unexpected {{{{{terminator}}}}} in template");
+    }
+
+    let mut out = String::new();
+    template::render(&nodes, &vars, &vars, &mut out)?;
+    Ok(out)
+}
+
+/// A minimal implementation of Go's `text/template`
+mod template {
+    use anyhow::{bail, Result};
+    use serde_json::Value;
+
+    pub(super) enum Token {
+        Text(String),
+        Action(String),
+    }
+
+    /// Split the template into text and actions, applying the `{{-` and `-}}`
+    /// trim markers and dropping the comments
+    pub(super) fn tokenize(template: &str) -> Result<Vec<Token>> {
+        let mut tokens = Vec::new();
+        let mut rest = template;
+        let mut trim_next = false;
+
+        while let Some(start) = rest.find("{{") {
+            let mut text = &rest[..start];
+            if trim_next {
+                text = text.trim_start();
+            }
+            let mut action = &rest[start + 2..];
+            let Some(end) = action.find("}}") else {
+                bail!("unclosed action in template");
+            };
+            rest = &action[end + 2..];
+            action = &action[..end];
+
+            if let Some(trimmed) = action.strip_prefix("- ") {
+                text = text.trim_end();
+                action = trimmed;
+            }
+            trim_next = false;
+            if let Some(trimmed) = action.strip_suffix(" -") {
+                trim_next = true;
+                action = trimmed;
+            }
+
+            if !text.is_empty() {
+                tokens.push(Token::Text(text.to_owned()));
+            }
+            let action = action.trim();
+            if !(action.starts_with("/*") && action.ends_with("*/")) {
+                tokens.push(Token::Action(action.to_owned()));
+            }
+        }
+
+        let text = if trim_next { rest.trim_start() } else { rest };
+        if !text.is_empty() {
+            tokens.push(Token::Text(text.to_owned()));
+        }
+        Ok(tokens)
+    }
+
+    /// A reference to a value: `.` or `$` followed by field names
+    pub(super) struct Field {
+        root: bool,
+        path: Vec<String>,
+        source: String,
+    }
+
+    impl Field {
+        fn parse(source: &str) -> Result<Self> {
+            let (root, path) = if let Some(path) = source.strip_prefix('$') {
+                (true, path)
+            } else {
+                (false, source)
+            };
+            let path = match path {
+                "" if root => Vec::new(),
+                "." if !root => Vec::new(),
+                path if path.starts_with('.') && !path.ends_with('.') => {
+                    path[1..].split('.').map(ToOwned::to_owned).collect()
+                }
+                _ => bail!("unsupported template action {source:?}"),
+            };
+            if path.iter().any(|field| {
+                field.is_empty() || !field.chars().all(|c| c.is_alphanumeric() || c == '_')
+            }) {
+                bail!("unsupported template action {source:?}");
+            }
+            Ok(Self {
+                root,
+                path,
+                source: source.to_owned(),
+            })
+        }
+
+        fn eval<'a>(&self, dot: &'a Value, root: &'a Value) -> Result<&'a Value> {
+            let mut value = if self.root { root } else { dot };
+            for field in &self.path {
+                value = match value {
+                    Value::Object(map) => match map.get(field) {
+                        Some(value) => value,
+                        None => bail!(
+                            "executing template at <{}>: map has no entry for key {field:?}",
+                            self.source
+                        ),
+                    },
+                    _ => bail!(
+                        "executing template at <{}>: can't evaluate field {field} in {}",
+                        self.source,
+                        type_name(value)
+                    ),
+                };
+            }
+            Ok(value)
+        }
+    }
+
+    pub(super) enum Node {
+        Text(String),
+        Print(Field),
+        If(Field, Vec<Node>, Vec<Node>),
+        Range(Field, Vec<Node>, Vec<Node>),
+        With(Field, Vec<Node>, Vec<Node>),
+    }
+
+    pub(super) struct Parser<'a> {
+        pub(super) tokens: &'a [Token],
+        pub(super) position: usize,
+    }
+
+    impl Parser<'_> {
+        /// Parse nodes until the end of the template, or until an `else` or
+        /// `end` action, which is returned
+        pub(super) fn parse_list(&mut self) -> Result<(Vec<Node>, Option<String>)> {
+            let mut nodes = Vec::new();
+            while let Some(token) = self.tokens.get(self.position) {
+                self.position += 1;
+                let action = match token {
+                    Token::Text(text) => {
+                        nodes.push(Node::Text(text.clone()));
+                        continue;
+                    }
+                    Token::Action(action) => action.as_str(),
+                };
+
+                let (keyword, argument) = action.split_once(' ').unwrap_or((action, ""));
+                let argument = argument.trim();
+                match keyword {
+                    "end" | "else" => return Ok((nodes, Some(action.to_owned()))),
+                    "if" => nodes.push(self.parse_if(argument)?),
+                    "range" => {
+                        let (body, otherwise) = self.parse_block(keyword)?;
+                        nodes.push(Node::Range(Field::parse(argument)?, body, otherwise));
+                    }
+                    "with" => {
+                        let (body, otherwise) = self.parse_block(keyword)?;
+                        nodes.push(Node::With(Field::parse(argument)?, body, otherwise));
+                    }
+                    _ => nodes.push(Node::Print(Field::parse(action)?)),
+                }
+            }
+            Ok((nodes, None))
+        }
+
+        /// Parse the body of an `if`, where `else if` chains into another
+        /// `if` sharing the same `end`
+        fn parse_if(&mut self, condition: &str) -> Result<Node> {
+            let condition = Field::parse(condition)?;
+            let (body, terminator) = self.parse_list()?;
+            let otherwise = match terminator.as_deref() {
+                Some("end") => Vec::new(),
+                Some("else") => self.parse_end("if")?,
+                Some(terminator) => match terminator.strip_prefix("else if ") {
+                    Some(condition) => vec![self.parse_if(condition.trim())?],
+                    None => bail!("unexpected {{{{{terminator}}}}} in template"),
+                },
+                None => bail!("missing {{{{end}}}} for {{{{if}}}} in template"),
+            };
+            Ok(Node::If(condition, body, otherwise))
+        }
+
+        /// Parse the body and the optional `else` branch of a block
+        fn parse_block(&mut self, keyword: &str) -> Result<(Vec<Node>, Vec<Node>)> {
+            let (body, terminator) = self.parse_list()?;
+            match terminator.as_deref() {
+                Some("end") => Ok((body, Vec::new())),
+                Some("else") => Ok((body, self.parse_end(keyword)?)),
+                _ => bail!("missing {{{{end}}}} for {{{{{keyword}}}}} in template"),
+            }
+        }
+
+        /// Parse an `else` branch, which must be closed by an `end`
+        fn parse_end(&mut self, keyword: &str) -> Result<Vec<Node>> {
+            match self.parse_list()? {
+                (nodes, Some(terminator)) if terminator == "end" => Ok(nodes),
+                _ => bail!("missing {{{{end}}}} for {{{{{keyword}}}}} in template"),
+            }
+        }
+    }
+
+    /// The truth of a value in a condition, like Go's `template.IsTrue`
+    fn is_true(value: &Value) -> bool {
+        match value {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Number(n) => n.as_f64() != Some(0.0),
+            Value::String(s) => !s.is_empty(),
+            Value::Array(values) => !values.is_empty(),
+            Value::Object(map) => !map.is_empty(),
+        }
+    }
+
+    /// The name of the Go type a value is decoded to
+    fn type_name(value: &Value) -> &'static str {
+        match value {
+            Value::Null => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "json.Number",
+            Value::String(_) => "string",
+            Value::Array(_) => "[]interface {}",
+            Value::Object(_) => "map[string]interface {}",
+        }
+    }
+
+    /// Print a value like Go's `fmt.Print`
+    fn print(value: &Value, out: &mut String) {
+        match value {
+            Value::Null => out.push_str("<no value>"),
+            Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::Number(n) => out.push_str(&n.to_string()),
+            Value::String(s) => out.push_str(s),
+            Value::Array(values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    print(value, out);
+                }
+                out.push(']');
+            }
+            Value::Object(map) => {
+                out.push_str("map[");
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    out.push_str(key);
+                    out.push(':');
+                    print(value, out);
+                }
+                out.push(']');
+            }
+        }
+    }
+
+    pub(super) fn render(
+        nodes: &[Node],
+        dot: &Value,
+        root: &Value,
+        out: &mut String,
+    ) -> Result<()> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Print(field) => print(field.eval(dot, root)?, out),
+                Node::If(condition, body, otherwise) => {
+                    if is_true(condition.eval(dot, root)?) {
+                        render(body, dot, root, out)?;
+                    } else {
+                        render(otherwise, dot, root, out)?;
+                    }
+                }
+                Node::With(field, body, otherwise) => {
+                    let value = field.eval(dot, root)?;
+                    if is_true(value) {
+                        render(body, value, root, out)?;
+                    } else {
+                        render(otherwise, dot, root, out)?;
+                    }
+                }
+                Node::Range(field, body, otherwise) => {
+                    let values: Vec<&Value> = match field.eval(dot, root)? {
+                        Value::Array(values) => values.iter().collect(),
+                        Value::Object(map) => map.values().collect(),
+                        Value::Null => Vec::new(),
+                        value => bail!("range can't iterate over {}", type_name(value)),
+                    };
+                    if values.is_empty() {
+                        render(otherwise, dot, root, out)?;
+                    }
+                    for value in values {
+                        render(body, value, root, out)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn format_int_bases() {
+        let number = |n: Value| serde_json::from_str::<BigNumber>(&n.to_string()).unwrap();
+        assert_eq!(format_int(number(json!(255)), 16).unwrap(), "ff");
+        assert_eq!(format_int(number(json!(-255)), 16).unwrap(), "-ff");
+        assert_eq!(format_int(number(json!(5)), 2).unwrap(), "101");
+        assert_eq!(format_int(number(json!(8)), 8).unwrap(), "10");
+        assert_eq!(format_int(number(json!(-3.7)), 10).unwrap(), "-3");
+        assert_eq!(format_int(number(json!(3.7)), 10).unwrap(), "3");
+        assert_eq!(
+            format_int(number(json!(u64::MAX)), 16).unwrap(),
+            "ffffffffffffffff"
+        );
+        let big = serde_json::from_str("-340282366920938463463374607431768211457").unwrap();
+        assert_eq!(
+            format_int(big, 16).unwrap(),
+            "-100000000000000000000000000000001"
+        );
+        assert!(format_int(number(json!(1)), 3).is_err());
+    }
+
+    #[test]
+    fn replace_n_like_go() {
+        let patterns = |p: Value| serde_json::from_value(p).unwrap();
+        assert_eq!(
+            replace_n(patterns(json!({"<": "&lt;", ">": "&gt;"})), "<p>".into()),
+            "&lt;p&gt;"
+        );
+        // Replacements don't apply to the output of other replacements
+        assert_eq!(
+            replace_n(patterns(json!({"a": "b", "b": "a"})), "abba".into()),
+            "baab"
+        );
+        // The first pattern in argument order wins
+        assert_eq!(
+            replace_n(patterns(json!({"a": "1", "aa": "2"})), "aaa".into()),
+            "111"
+        );
+        assert_eq!(replace_n(patterns(json!({"": "-"})), "ab".into()), "-a-b-");
+    }
+
+    #[test]
+    fn render_template_like_go() {
+        let render = |template: &str, vars: Value| render_template(template.to_owned(), vars);
+
+        assert_eq!(render("{{.test}}", json!({"test": "foo"})).unwrap(), "foo");
+        assert_eq!(
+            render(
+                "Hello {{.user.name}}, you are {{.user.age}}",
+                json!({"user": {"name": "Ada", "age": 36}})
+            )
+            .unwrap(),
+            "Hello Ada, you are 36"
+        );
+        assert_eq!(
+            render(
+                "{{range .items}}<{{.}}>{{else}}none{{end}}",
+                json!({"items": [1, "a"]})
+            )
+            .unwrap(),
+            "<1><a>"
+        );
+        assert_eq!(
+            render(
+                "{{range .items}}<{{.}}>{{else}}none{{end}}",
+                json!({"items": []})
+            )
+            .unwrap(),
+            "none"
+        );
+        assert_eq!(
+            render(
+                "{{if .a}}a{{else if .b}}b{{else}}c{{end}}",
+                json!({"a": false, "b": 1})
+            )
+            .unwrap(),
+            "b"
+        );
+        assert_eq!(
+            render(
+                "{{with .user}}{{.name}}{{$.sep}}{{end}}",
+                json!({"user": {"name": "x"}, "sep": "!"})
+            )
+            .unwrap(),
+            "x!"
+        );
+        assert_eq!(
+            render(
+                "a  {{- /* comment */ -}}  b {{.list}} {{.map}}",
+                json!({"list": [1, 2], "map": {"k": true}})
+            )
+            .unwrap(),
+            "ab [1 2] map[k:true]"
+        );
+
+        assert!(render("{{.missing}}", json!({})).is_err());
+        assert!(render("{{if .a}}", json!({"a": true})).is_err());
+        assert!(render("{{printf \"%d\" 1}}", json!({})).is_err());
+        assert!(render("{{end}}", json!({})).is_err());
+    }
+}
@@ -14,8 +14,13 @@
 
 //! Builtins for date and time-related operations
 
+use std::fmt::Write;
+
 use anyhow::{anyhow, Context, Result};
-use chrono::{DateTime, Datelike, TimeZone, Timelike, Utc, Weekday};
+use chrono::{
+    format::{Fixed, Item, Numeric, Pad, Parsed},
+    DateTime, Datelike, NaiveDate, TimeZone, Timelike, Utc, Weekday,
+};
 use chrono_tz::Tz;
 use chronoutil::RelativeDuration;
 use serde::{Deserialize, Serialize};
@@ -38,6 +43,8 @@ impl TimestampWithOptionalTimezone {
     fn into_datetime(self) -> Result<DateTime<Tz>> {
         let (ts, tz) = match self {
             Self::Timestamp(ts) => (ts, Tz::UTC),
+            // An empty timezone means UTC, like in Go
+            Self::TimestampAndTimezone(ts, tz) if tz.is_empty() => (ts, Tz::UTC),
             Self::TimestampAndTimezone(ts, tz) => (
                 ts,
                 tz.parse()
@@ -49,6 +56,298 @@ impl TimestampWithOptionalTimezone {
     }
 }
 
+/// A type which holds a timestamp (in nanoseconds), with an optional timezone
+/// string and an optional layout
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum TimestampWithOptionalTimezoneAndLayout {
+    /// Holds a timestamp
+    Timestamp(i64),
+
+    /// Holds a timestamp and a timezone
+    TimestampAndTimezone(i64, String),
+
+    /// Holds a timestamp, a timezone and a layout
+    TimestampTimezoneAndLayout(i64, String, String),
+}
+
+/// Named layouts accepted in place of a Go reference layout, mirroring the
+/// constants of Go's `time` package
+const NAMED_LAYOUTS: &[(&str, &str)] = &[
+    ("ANSIC", "Mon Jan _2 15:04:05 2006"),
+    ("UnixDate", "Mon Jan _2 15:04:05 MST 2006"),
+    ("RubyDate", "Mon Jan 02 15:04:05 -0700 2006"),
+    ("RFC822", "02 Jan 06 15:04 MST"),
+    ("RFC822Z", "02 Jan 06 15:04 -0700"),
+    ("RFC850", "Monday, 02-Jan-06 15:04:05 MST"),
+    ("RFC1123", "Mon, 02 Jan 2006 15:04:05 MST"),
+    ("RFC1123Z", "Mon, 02 Jan 2006 15:04:05 -0700"),
+    ("RFC3339", "2006-01-02T15:04:05Z07:00"),
+    ("RFC3339Nano", "2006-01-02T15:04:05.999999999Z07:00"),
+    ("Kitchen", "3:04PM"),
+    ("Stamp", "Jan _2 15:04:05"),
+    ("StampMilli", "Jan _2 15:04:05.000"),
+    ("StampMicro", "Jan _2 15:04:05.000000"),
+    ("StampNano", "Jan _2 15:04:05.000000000"),
+    ("DateTime", "2006-01-02 15:04:05"),
+    ("DateOnly", "2006-01-02"),
+    ("TimeOnly", "15:04:05"),
+];
+
+/// Elements of a Go reference layout, by order of precedence
+const LAYOUT_CHUNKS: &[(&str, Item<'static>)] = &[
+    ("January", Item::Fixed(Fixed::LongMonthName)),
+    ("Jan", Item::Fixed(Fixed::ShortMonthName)),
+    ("Monday", Item::Fixed(Fixed::LongWeekdayName)),
+    ("Mon", Item::Fixed(Fixed::ShortWeekdayName)),
+    ("MST", Item::Fixed(Fixed::TimezoneName)),
+    ("2006", Item::Numeric(Numeric::Year, Pad::Zero)),
+    ("002", Item::Numeric(Numeric::Ordinal, Pad::Zero)),
+    ("01", Item::Numeric(Numeric::Month, Pad::Zero)),
+    ("02", Item::Numeric(Numeric::Day, Pad::Zero)),
+    ("03", Item::Numeric(Numeric::Hour12, Pad::Zero)),
+    ("04", Item::Numeric(Numeric::Minute, Pad::Zero)),
+    ("05", Item::Numeric(Numeric::Second, Pad::Zero)),
+    ("06", Item::Numeric(Numeric::YearMod100, Pad::Zero)),
+    ("_2", Item::Numeric(Numeric::Day, Pad::Space)),
+    ("15", Item::Numeric(Numeric::Hour, Pad::Zero)),
+    ("1", Item::Numeric(Numeric::Month, Pad::None)),
+    ("2", Item::Numeric(Numeric::Day, Pad::None)),
+    ("3", Item::Numeric(Numeric::Hour12, Pad::None)),
+    ("4", Item::Numeric(Numeric::Minute, Pad::None)),
+    ("5", Item::Numeric(Numeric::Second, Pad::None)),
+    ("PM", Item::Fixed(Fixed::UpperAmPm)),
+    ("pm", Item::Fixed(Fixed::LowerAmPm)),
+    ("-07:00", Item::Fixed(Fixed::TimezoneOffsetColon)),
+    ("-0700", Item::Fixed(Fixed::TimezoneOffset)),
+    ("-07", Item::Fixed(Fixed::TimezoneOffsetTripleColon)),
+    ("Z07:00", Item::Fixed(Fixed::TimezoneOffsetColonZ)),
+    ("Z0700", Item::Fixed(Fixed::TimezoneOffsetZ)),
+    ("Z07", Item::Fixed(Fixed::TimezoneOffsetTripleColon)),
+];
+
+/// An element of a Go reference layout
+enum LayoutItem<'a> {
+    /// An element `chrono` knows how to handle
+    Chrono(Item<'a>),
+
+    /// Fractional seconds, with their trailing zeros removed if `trim` is set
+    /// (`.999` instead of `.000`)
+    Fraction { digits: usize, trim: bool },
+}
+
+/// Split a Go reference layout (like `2006-01-02T15:04:05Z07:00`) or one of
+/// the named layouts into its elements
+fn go_layout(layout: &str) -> Vec<LayoutItem<'_>> {
+    let layout = NAMED_LAYOUTS
+        .iter()
+        .find(|(name, _)| *name == layout)
+        .map_or(layout, |(_, layout)| *layout);
+
+    let mut items = Vec::new();
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i < layout.len() {
+        let rest = &layout[i..];
+
+        let chunk = if let Some(digits) = fraction_len(rest) {
+            let trim = rest.as_bytes()[1] == b'9';
+            Some((digits + 1, LayoutItem::Fraction { digits, trim }))
+        } else {
+            LAYOUT_CHUNKS
+                .iter()
+                .find(|(chunk, _)| rest.starts_with(chunk))
+                .map(|(chunk, item)| (chunk.len(), LayoutItem::Chrono(item.clone())))
+        };
+
+        let Some((len, item)) = chunk else {
+            // Not a layout element, keep going until the end of the literal
+            i += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        };
+
+        if literal_start < i {
+            items.push(LayoutItem::Chrono(Item::Literal(&layout[literal_start..i])));
+        }
+
+        items.push(item);
+        i += len;
+        literal_start = i;
+    }
+
+    if literal_start < layout.len() {
+        items.push(LayoutItem::Chrono(Item::Literal(&layout[literal_start..])));
+    }
+
+    items
+}
+
+/// Translate a Go reference layout into `chrono` items used for parsing.
+///
+/// The items are relaxed the same way Go's `time.Parse` is: hour-only offsets
+/// also accept minutes and `Z`, and fractional seconds are accepted after the
+/// seconds even if the layout does not mention them.
+fn parse_items(layout: &str) -> Vec<Item<'_>> {
+    let layout = go_layout(layout);
+    let mut items = Vec::with_capacity(layout.len());
+    for (index, item) in layout.iter().enumerate() {
+        match item {
+            LayoutItem::Chrono(item @ Item::Numeric(Numeric::Second, _)) => {
+                items.push(item.clone());
+                if !matches!(layout.get(index + 1), Some(LayoutItem::Fraction { .. })) {
+                    items.push(Item::Fixed(Fixed::Nanosecond));
+                }
+            }
+            LayoutItem::Chrono(item) => items.push(item.clone()),
+            LayoutItem::Fraction { .. } => items.push(Item::Fixed(Fixed::Nanosecond)),
+        }
+    }
+
+    items
+}
+
+/// Parse the value with the items. Hour-only offsets are parsed by hand, as
+/// `chrono` has no public item accepting them with or without minutes.
+fn parse_value(parsed: &mut Parsed, value: &str, items: &[Item<'_>]) -> Result<()> {
+    let mut rest = value;
+    let mut chunks =
+        items.split(|item| matches!(item, Item::Fixed(Fixed::TimezoneOffsetTripleColon)));
+    let mut chunk = chunks.next().unwrap_or_default();
+    for next in chunks {
+        rest = chrono::format::parse_and_remainder(parsed, rest, chunk.iter())?;
+        let (offset, remainder) = parse_offset(rest).context("invalid time zone offset")?;
+        parsed.set_offset(offset)?;
+        rest = remainder;
+        chunk = next;
+    }
+
+    chrono::format::parse(parsed, rest, chunk.iter())?;
+    Ok(())
+}
+
+/// Parse a `Z`, `±hh`, `±hhmm` or `±hh:mm` offset at the start of the string,
+/// returning it in seconds with the rest of the string
+fn parse_offset(s: &str) -> Option<(i64, &str)> {
+    if let Some(rest) = s.strip_prefix('Z') {
+        return Some((0, rest));
+    }
+
+    let sign = match s.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let two_digits = |s: &str| -> Option<i64> {
+        let digits = s.get(..2)?;
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| digits.parse().ok())
+            .flatten()
+    };
+
+    let hours = two_digits(&s[1..])?;
+    let rest = &s[3..];
+    let after_colon = rest.strip_prefix(':').unwrap_or(rest);
+    let (minutes, rest) = match two_digits(after_colon) {
+        Some(minutes) => (minutes, &after_colon[2..]),
+        None => (0, rest),
+    };
+
+    Some((sign * (hours * 3600 + minutes * 60), rest))
+}
+
+/// Get the number of digits of a fractional second element (`.000` or
+/// `.999`) at the start of a layout
+fn fraction_len(layout: &str) -> Option<usize> {
+    let bytes = layout.as_bytes();
+    if bytes.len() < 2 || bytes[0] != b'.' || !matches!(bytes[1], b'0' | b'9') {
+        return None;
+    }
+
+    let digits = bytes[1..].iter().take_while(|b| **b == bytes[1]).count();
+    if bytes.get(digits + 1).map_or(false, u8::is_ascii_digit) {
+        return None;
+    }
+
+    Some(digits)
+}
+
+/// Parse a duration string the same way Go's `time.ParseDuration` does, like
+/// `1h30m` or `-1.5s`. Returns `None` if the string is invalid or overflows.
+fn parse_go_duration(duration: &str) -> Option<i64> {
+    let (negative, mut rest) = if let Some(rest) = duration.strip_prefix('-') {
+        (true, rest)
+    } else {
+        (false, duration.strip_prefix('+').unwrap_or(duration))
+    };
+
+    if rest == "0" {
+        return Some(0);
+    }
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut total: i128 = 0;
+    while !rest.is_empty() {
+        let (integer, after) = rest.split_at(rest.bytes().take_while(u8::is_ascii_digit).count());
+        let (fraction, after) = match after.strip_prefix('.') {
+            Some(after) => after.split_at(after.bytes().take_while(u8::is_ascii_digit).count()),
+            None => ("", after),
+        };
+
+        if integer.is_empty() && fraction.is_empty() {
+            return None;
+        }
+
+        let (unit, after) = after.split_at(
+            after
+                .find(|c: char| c.is_ascii_digit() || c == '.')
+                .unwrap_or(after.len()),
+        );
+        let unit: i128 = match unit {
+            "ns" => 1,
+            // Both the micro sign (U+00B5) and the Greek letter mu (U+03BC)
+            "us" | "\u{b5}s" | "\u{3bc}s" => 1_000,
+            "ms" => 1_000_000,
+            "s" => 1_000_000_000,
+            "m" => 60 * 1_000_000_000,
+            "h" => 60 * 60 * 1_000_000_000,
+            _ => return None,
+        };
+
+        if !integer.is_empty() {
+            total = total.checked_add(integer.parse::<i128>().ok()?.checked_mul(unit)?)?;
+        }
+
+        if !fraction.is_empty() {
+            // Digits past the nanosecond precision don't matter
+            let fraction = &fraction[..fraction.len().min(18)];
+            let scale = 10_i128.pow(u32::try_from(fraction.len()).ok()?);
+            total = total.checked_add(fraction.parse::<i128>().ok()? * unit / scale)?;
+        }
+
+        rest = after;
+    }
+
+    i64::try_from(if negative { -total } else { total }).ok()
+}
+
+/// Get the number of days in the given month
+fn days_in_month(year: i32, month: u32) -> i64 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .and_then(|next| next.pred_opt())
+        .map_or(31, |last| i64::from(last.day()))
+}
+
 /// Returns the nanoseconds since epoch after adding years, months and days to
 /// nanoseconds. `undefined` if the result would be outside the valid time range
 /// that can fit within an `int64`.
@@ -81,10 +380,48 @@ pub fn date(x: TimestampWithOptionalTimezone) -> Result<(i32, u32, u32)> {
 
 /// Returns the difference between two unix timestamps in nanoseconds (with
 /// optional timezone strings).
+/// The result is `[years, months, days, hours, minutes, seconds]`, always
+/// positive regardless of the order of the arguments.
 #[tracing::instrument(name = "time.diff", err)]
-// todo:: need to implement
-pub fn diff(ns1: serde_json::Value, ns2: serde_json::Value) -> Result<(u8, u8, u8, u8, u8, u8)> {
-    Err(anyhow!("not implemented"))
+pub fn diff(
+    ns1: TimestampWithOptionalTimezone,
+    ns2: TimestampWithOptionalTimezone,
+) -> Result<(i64, i64, i64, i64, i64, i64)> {
+    let t1 = ns1.into_datetime()?;
+    // Compare both dates in the timezone of the first one
+    let t2 = ns2.into_datetime()?.with_timezone(&t1.timezone());
+    let (t1, t2) = if t1 > t2 { (t2, t1) } else { (t1, t2) };
+
+    let mut year = i64::from(t2.year()) - i64::from(t1.year());
+    let mut month = i64::from(t2.month()) - i64::from(t1.month());
+    let mut day = i64::from(t2.day()) - i64::from(t1.day());
+    let mut hour = i64::from(t2.hour()) - i64::from(t1.hour());
+    let mut minute = i64::from(t2.minute()) - i64::from(t1.minute());
+    let mut second = i64::from(t2.second()) - i64::from(t1.second());
+
+    // Normalize negative values
+    if second < 0 {
+        second += 60;
+        minute -= 1;
+    }
+    if minute < 0 {
+        minute += 60;
+        hour -= 1;
+    }
+    if hour < 0 {
+        hour += 24;
+        day -= 1;
+    }
+    if day < 0 {
+        day += days_in_month(t1.year(), t1.month());
+        month -= 1;
+    }
+    if month < 0 {
+        month += 12;
+        year -= 1;
+    }
+
+    Ok((year, month, day, hour, minute, second))
 }
 
 /// Returns the current time since epoch in nanoseconds.
@@ -93,18 +430,140 @@ pub fn now_ns<C: EvaluationContext>(ctx: &mut C) -> i64 {
     ctx.now().timestamp_nanos_opt().unwrap()
 }
 
+/// Returns the formatted timestamp for the nanoseconds since epoch.
+///
+/// The layout uses Go's reference time (`Mon Jan 2 15:04:05 MST 2006`), or is
+/// the name of one of Go's predefined layouts. It defaults to `RFC3339Nano`.
+#[tracing::instrument(name = "time.format", err)]
+pub fn format(x: TimestampWithOptionalTimezoneAndLayout) -> Result<String> {
+    let (x, layout) = match x {
+        TimestampWithOptionalTimezoneAndLayout::Timestamp(ts) => {
+            (TimestampWithOptionalTimezone::Timestamp(ts), None)
+        }
+        TimestampWithOptionalTimezoneAndLayout::TimestampAndTimezone(ts, tz) => (
+            TimestampWithOptionalTimezone::TimestampAndTimezone(ts, tz),
+            None,
+        ),
+        TimestampWithOptionalTimezoneAndLayout::TimestampTimezoneAndLayout(ts, tz, layout) => (
+            TimestampWithOptionalTimezone::TimestampAndTimezone(ts, tz),
+            Some(layout),
+        ),
+    };
+
+    let date_time = x.into_datetime()?;
+    let layout = layout.unwrap_or_else(|| "RFC3339Nano".to_owned());
+
+    let mut output = String::new();
+    for item in go_layout(&layout) {
+        match item {
+            LayoutItem::Chrono(item) => {
+                write!(
+                    output,
+                    "{}",
+                    date_time.format_with_items(std::iter::once(item))
+                )
+                .map_err(|_| anyhow!("Could not format date with layout {layout:?}"))?;
+            }
+
+            LayoutItem::Fraction { digits, trim } => {
+                // The nanoseconds go past one second during leap seconds
+                let nanos = date_time.nanosecond() % 1_000_000_000;
+                let nanos = format!("{nanos:09}");
+                let mut fraction = &nanos[..digits.min(9)];
+                if trim {
+                    fraction = fraction.trim_end_matches('0');
+                }
+
+                if !fraction.is_empty() {
+                    output.push('.');
+                    output.push_str(fraction);
+                }
+            }
+        }
+    }
+
+    Ok(output)
+}
+
 /// Returns the duration in nanoseconds represented by a string.
+///
+/// The string uses Go's duration syntax, like `1h30m` or `-1.5s`. The more
+/// lenient syntax of the `duration_str` crate (like `1d`) is accepted as well.
 #[tracing::instrument(name = "time.parse_duration_ns", err)]
-pub fn parse_duration_ns(duration: String) -> Result<u128> {
-    Ok(duration_str::parse(duration.as_str())?.as_nanos())
+pub fn parse_duration_ns(duration: String) -> Result<i64> {
+    if let Some(ns) = parse_go_duration(&duration) {
+        return Ok(ns);
+    }
+
+    let ns = duration_str::parse(duration.as_str())?.as_nanos();
+    i64::try_from(ns).context("Duration out of range")
 }
 
 /// Returns the time in nanoseconds parsed from the string in the given format.
 /// `undefined` if the result would be outside the valid time range that can fit
 /// within an `int64`.
+///
+/// The layout uses Go's reference time (`Mon Jan 2 15:04:05 MST 2006`), or is
+/// the name of one of Go's predefined layouts, like `RFC3339`.
 #[tracing::instrument(name = "time.parse_ns", err)]
 pub fn parse_ns(layout: String, value: String) -> Result<i64> {
-    Err(anyhow!("not implemented"))
+    let items = parse_items(&layout);
+    let has = |pred: fn(&Item) -> bool| items.iter().any(pred);
+
+    let mut parsed = Parsed::new();
+    parse_value(&mut parsed, &value, &items)
+        .with_context(|| format!("Could not parse {value:?} with layout {layout:?}"))?;
+
+    // Fill the elements missing from the layout with the same defaults as Go
+    if !has(|i| matches!(i, Item::Numeric(Numeric::Year | Numeric::YearMod100, _))) {
+        parsed.set_year(0)?;
+    }
+    if !has(|i| matches!(i, Item::Numeric(Numeric::Ordinal, _))) {
+        if !has(|i| {
+            matches!(
+                i,
+                Item::Numeric(Numeric::Month, _)
+                    | Item::Fixed(Fixed::ShortMonthName | Fixed::LongMonthName)
+            )
+        }) {
+            parsed.set_month(1)?;
+        }
+        if !has(|i| matches!(i, Item::Numeric(Numeric::Day, _))) {
+            parsed.set_day(1)?;
+        }
+    }
+    if !has(|i| matches!(i, Item::Numeric(Numeric::Hour | Numeric::Hour12, _))) {
+        parsed.set_hour(0)?;
+    } else if !has(|i| matches!(i, Item::Numeric(Numeric::Hour, _)))
+        && !has(|i| matches!(i, Item::Fixed(Fixed::UpperAmPm | Fixed::LowerAmPm)))
+    {
+        // A 12-hour clock without AM/PM means AM
+        parsed.set_ampm(false)?;
+    }
+    if !has(|i| matches!(i, Item::Numeric(Numeric::Minute, _))) {
+        parsed.set_minute(0)?;
+    }
+
+    let date_time = if has(|i| {
+        matches!(
+            i,
+            Item::Fixed(
+                Fixed::TimezoneOffset
+                    | Fixed::TimezoneOffsetColon
+                    | Fixed::TimezoneOffsetZ
+                    | Fixed::TimezoneOffsetColonZ
+                    | Fixed::TimezoneOffsetDoubleColon
+                    | Fixed::TimezoneOffsetTripleColon
+            )
+        )
+    }) {
+        parsed.to_datetime()?.with_timezone(&Utc)
+    } else {
+        // Without an offset in the layout, the time is in UTC
+        Utc.from_utc_datetime(&parsed.to_naive_datetime_with_offset(0)?)
+    };
+
+    date_time.timestamp_nanos_opt().context("Invalid date")
 }
 
 /// Returns the time in nanoseconds parsed from the string in RFC3339 format.
@@ -132,3 +591,129 @@ pub fn weekday(x: TimestampWithOptionalTimezone) -> Result<&'static str> {
         Weekday::Sun => "Sunday",
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        // 2020-07-14T12:53:22Z
+        let expected = 1_594_731_202_000_000_000;
+
+        assert_eq!(
+            parse_ns("RFC3339".into(), "2020-07-14T12:53:22Z".into()).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_ns(
+                "2006-01-02T15:04:05Z07:00".into(),
+                "2020-07-14T14:53:22+02:00".into()
+            )
+            .unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_ns(
+                "Mon, 02 Jan 2006 15:04:05 -0700".into(),
+                "Tue, 14 Jul 2020 12:53:22.5 +0000".into()
+            )
+            .unwrap(),
+            expected + 500_000_000
+        );
+        assert_eq!(
+            parse_ns("Jan _2 2006 3:04PM".into(), "Jul 14 2020 12:53PM".into()).unwrap(),
+            expected - 22_000_000_000
+        );
+        assert_eq!(
+            parse_ns("2006-01-02".into(), "2020-07-14".into()).unwrap(),
+            1_594_684_800_000_000_000
+        );
+
+        // Hour-only offsets also accept minutes
+        for value in [
+            "2020-07-14 14:53:22 +02",
+            "2020-07-14 14:53:22 +0200",
+            "2020-07-14 11:23:22 -01:30",
+        ] {
+            assert_eq!(
+                parse_ns("2006-01-02 15:04:05 -07".into(), value.into()).unwrap(),
+                expected,
+                "{value}"
+            );
+        }
+        assert!(parse_ns(
+            "2006-01-02 15:04:05 -07".into(),
+            "2020-07-14 14:53:22 2".into()
+        )
+        .is_err());
+
+        assert!(parse_ns("2006-01-02".into(), "14/07/2020".into()).is_err());
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration_ns("0".into()).unwrap(), 0);
+        assert_eq!(
+            parse_duration_ns("1h30m".into()).unwrap(),
+            5_400_000_000_000
+        );
+        assert_eq!(parse_duration_ns("-1.5s".into()).unwrap(), -1_500_000_000);
+        assert_eq!(parse_duration_ns("1\u{b5}s".into()).unwrap(), 1_000);
+        assert_eq!(parse_duration_ns(".5ms".into()).unwrap(), 500_000);
+        assert_eq!(
+            parse_duration_ns("2h45m10.25s".into()).unwrap(),
+            9_910_250_000_000
+        );
+        assert!(parse_duration_ns("3000000h".into()).is_err());
+        assert!(parse_duration_ns("1x".into()).is_err());
+    }
+
+    #[test]
+    fn formatting() {
+        // 2020-07-14T12:53:22.5Z
+        let ts = 1_594_731_202_500_000_000;
+        let at = |tz: &str, layout: &str| {
+            format(
+                TimestampWithOptionalTimezoneAndLayout::TimestampTimezoneAndLayout(
+                    ts,
+                    tz.into(),
+                    layout.into(),
+                ),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            format(TimestampWithOptionalTimezoneAndLayout::Timestamp(ts)).unwrap(),
+            "2020-07-14T12:53:22.5Z"
+        );
+        assert_eq!(
+            at("Europe/Paris", "RFC3339Nano"),
+            "2020-07-14T14:53:22.5+02:00"
+        );
+        assert_eq!(
+            at("", "Mon, 02 Jan 2006 15:04:05 -0700"),
+            "Tue, 14 Jul 2020 12:53:22 +0000"
+        );
+        assert_eq!(at("", "Kitchen"), "12:53PM");
+        assert_eq!(at("", "Jan _2 15:04:05.000000"), "Jul 14 12:53:22.500000");
+        assert_eq!(at("", "January 2, 2006 at 3pm"), "July 14, 2020 at 12pm");
+    }
+
+    #[test]
+    fn difference() {
+        let t1 = TimestampWithOptionalTimezone::Timestamp(
+            parse_ns("RFC3339".into(), "2020-01-31T23:00:00Z".into()).unwrap(),
+        );
+        let t2 = TimestampWithOptionalTimezone::Timestamp(
+            parse_ns("RFC3339".into(), "2021-03-01T22:30:15Z".into()).unwrap(),
+        );
+        assert_eq!(diff(t2, t1).unwrap(), (1, 1, 0, 23, 30, 15));
+
+        // Both dates are compared in the timezone of the first one
+        let t1 = TimestampWithOptionalTimezone::TimestampAndTimezone(0, "Europe/Paris".into());
+        let t2 = TimestampWithOptionalTimezone::Timestamp(3_600_000_000_000);
+        assert_eq!(diff(t1, t2).unwrap(), (0, 0, 0, 1, 0, 0));
+    }
+}
@@ -12,15 +12,157 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Builtins to generate UUIDs
-use anyhow::{bail, Result};
+//! Builtins to generate and parse UUIDs
+
+use anyhow::{Context, Result};
+use rand::Rng;
+use serde_json::{Map, Value};
+use uuid::{Uuid, Variant};
+
+use crate::EvaluationContext;
+
+/// Offset between the start of the Gregorian calendar (1582-10-15), used by
+/// time-based UUIDs, and the Unix epoch, in 100ns intervals
+const GREGORIAN_OFFSET: i128 = 0x01B2_1DD2_1381_4000;
 
 /// Returns a new UUIDv4.
-#[tracing::instrument(name = "uuid.rfc4122", err)]
-pub fn rfc4122(k: String) -> Result<String> {
-    // note: the semantics required here is to generate a UUID that is similar *for
-    // the duration of the query for every k* the Go implementation uses a
-    // global builtin cache so that UUIDs per `k` are stored through a life of a
-    // query.
-    bail!("not implemented")
+///
+/// The same UUID is returned for a given `k` throughout a query evaluation.
+#[tracing::instrument(name = "uuid.rfc4122", skip(ctx), err)]
+pub fn rfc4122<C: EvaluationContext>(ctx: &mut C, k: String) -> Result<String> {
+    let cache_key = ("uuid.rfc4122", k);
+    if let Some(v) = ctx.cache_get(&cache_key)? {
+        return Ok(v);
+    };
+
+    let mut rng = ctx.get_rng();
+    let uuid = uuid::Builder::from_random_bytes(rng.gen())
+        .into_uuid()
+        .hyphenated()
+        .to_string();
+
+    ctx.cache_set(&cache_key, &uuid)?;
+    Ok(uuid)
+}
+
+/// Parses the string value as an UUID and returns an object with the
+/// well-defined fields of the UUID if valid.
+#[tracing::instrument(name = "uuid.parse", err)]
+pub fn parse(uuid: String) -> Result<Value> {
+    let uuid = Uuid::try_parse(&uuid).context("invalid UUID")?;
+    let bytes = uuid.as_bytes();
+
+    let variant = match uuid.get_variant() {
+        Variant::NCS => "Reserved",
+        Variant::RFC4122 => "RFC4122",
+        Variant::Microsoft => "Microsoft",
+        _ => "Future",
+    };
+
+    let mut result = Map::new();
+    result.insert("variant".into(), variant.into());
+    result.insert("version".into(), uuid.get_version_num().into());
+
+    if matches!(uuid.get_version_num(), 1 | 2) && uuid.get_variant() == Variant::RFC4122 {
+        let time_low = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let time_mid = u16::from_be_bytes([bytes[4], bytes[5]]);
+        let time_high = u16::from_be_bytes([bytes[6], bytes[7]]) & 0x0fff;
+        let timestamp =
+            (i128::from(time_high) << 48) | (i128::from(time_mid) << 32) | i128::from(time_low);
+
+        let node_id: Vec<_> = bytes[10..].iter().map(|b| format!("{b:02x}")).collect();
+        let mac_variables = match (bytes[10] & 0b10 != 0, bytes[10] & 0b01 != 0) {
+            (true, true) => "local:multicast",
+            (true, false) => "local:unicast",
+            (false, true) => "global:multicast",
+            (false, false) => "global:unicast",
+        };
+
+        // Times which don't fit in nanoseconds since epoch are left out
+        if let Ok(time) = i64::try_from((timestamp - GREGORIAN_OFFSET) * 100) {
+            result.insert("time".into(), time.into());
+        }
+        result.insert(
+            "clocksequence".into(),
+            (u16::from_be_bytes([bytes[8], bytes[9]]) & 0x3fff).into(),
+        );
+        result.insert("nodeid".into(), node_id.join("-").into());
+        result.insert("macvariables".into(), mac_variables.into());
+
+        if uuid.get_version_num() == 2 {
+            let domain = match bytes[9] {
+                0 => "Person",
+                1 => "Group",
+                2 => "Org",
+                _ => "Invalid",
+            };
+            result.insert("domain".into(), domain.into());
+            result.insert("id".into(), time_low.into());
+        }
+    }
+
+    Ok(Value::Object(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::DefaultContext;
+
+    #[test]
+    fn generate() {
+        let mut ctx = DefaultContext::default();
+        ctx.evaluation_start();
+
+        let a = rfc4122(&mut ctx, "a".into()).unwrap();
+        let b = rfc4122(&mut ctx, "b".into()).unwrap();
+        assert_eq!(rfc4122(&mut ctx, "a".into()).unwrap(), a);
+        assert_ne!(a, b);
+        assert_eq!(parse(a.clone()).unwrap()["version"], 4);
+
+        // A new evaluation generates new UUIDs
+        ctx.evaluation_start();
+        assert_ne!(rfc4122(&mut ctx, "a".into()).unwrap(), a);
+    }
+
+    #[test]
+    fn seeded() {
+        let mut ctx = DefaultContext::default().with_rng_seed(42);
+
+        ctx.evaluation_start();
+        let a = rfc4122(&mut ctx, "a".into()).unwrap();
+        let b = rfc4122(&mut ctx, "b".into()).unwrap();
+        assert_ne!(a, b);
+
+        // The same UUIDs are generated in the same order on every evaluation
+        ctx.evaluation_start();
+        assert_eq!(rfc4122(&mut ctx, "a".into()).unwrap(), a);
+        assert_eq!(rfc4122(&mut ctx, "b".into()).unwrap(), b);
+    }
+
+    #[test]
+    fn parse_time_based() {
+        assert_eq!(
+            parse("c232ab00-9414-11ec-b3c8-9f6bdeced846".into()).unwrap(),
+            json!({
+                "variant": "RFC4122",
+                "version": 1,
+                "time": 1_645_557_742_000_000_000_i64,
+                "clocksequence": 13256,
+                "nodeid": "9f-6b-de-ce-d8-46",
+                "macvariables": "local:multicast",
+            })
+        );
+
+        let parsed = parse("{000003e8-cbb9-21ea-b200-00045a86c8a1}".into()).unwrap();
+        assert_eq!(parsed["version"], 2);
+        assert_eq!(parsed["domain"], "Person");
+        assert_eq!(parsed["id"], 1000);
+        assert_eq!(parsed["clocksequence"], 12800);
+        assert_eq!(parsed["nodeid"], "00-04-5a-86-c8-a1");
+
+        assert!(parse("not-a-uuid".into()).is_err());
+    }
 }
@@ -14,26 +14,183 @@
 
 //! Builtins parse and serialize YAML documents
 
+use std::{cell::Cell, fmt};
+
 use anyhow::Result;
-use serde_yaml;
+use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Number, Value};
+
+/// Minimum number of nodes a document can expand to, see [`MAX_EXPANSION`]
+const MIN_NODES: usize = 10_000;
+
+/// Maximum number of nodes a document can expand to, relative to its size in
+/// bytes.
+///
+/// Aliases can make a small document expand to a huge value (the "billion
+/// laughs" attack). `serde_yaml` already bounds how many times aliases are
+/// repeated; this additionally rejects documents which expand way beyond what
+/// their size allows without aliases, like Go's `yaml` package does with its
+/// "excessive aliasing" check. Nodes are counted while the document is
+/// deserialized, so the expansion stops as soon as it goes over the limit.
+const MAX_EXPANSION: usize = 100;
+
+/// Deserializes a [`Value`], failing once more than the remaining number of
+/// nodes were visited
+#[derive(Clone, Copy)]
+struct BoundedValue<'a> {
+    remaining: &'a Cell<usize>,
+}
+
+impl BoundedValue<'_> {
+    fn visit<E: de::Error>(self) -> Result<(), E> {
+        let remaining = self
+            .remaining
+            .get()
+            .checked_sub(1)
+            .ok_or_else(|| E::custom("document contains excessive aliasing"))?;
+        self.remaining.set(remaining);
+        Ok(())
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for BoundedValue<'_> {
+    type Value = Value;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for BoundedValue<'_> {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any valid YAML value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Value, E> {
+        self.visit()?;
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value, E> {
+        self.visit()?;
+        Ok(Value::Number(v.into()))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+        self.visit()?;
+        Ok(Value::Number(v.into()))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Value, E> {
+        self.visit()?;
+        Ok(Number::from_f64(v).map_or(Value::Null, Value::Number))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+        self.visit_string(v.to_owned())
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Value, E> {
+        self.visit()?;
+        Ok(Value::String(v))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        self.visit()?;
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        self.visit_unit()
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        self.visit()?;
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element_seed(self)? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        self.visit()?;
+        let mut object = Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let value = map.next_value_seed(self)?;
+            object.insert(key, value);
+        }
+        Ok(Value::Object(object))
+    }
+}
+
+/// Parse a YAML document, rejecting the ones with excessive aliasing
+fn parse(x: &str) -> Result<Value> {
+    let remaining = Cell::new(MIN_NODES.max(x.len().saturating_mul(MAX_EXPANSION)));
+    let value = BoundedValue {
+        remaining: &remaining,
+    }
+    .deserialize(serde_yaml::Deserializer::from_str(x))?;
+    Ok(value)
+}
 
 /// Verifies the input string is a valid YAML document.
 #[tracing::instrument(name = "yaml.is_valid")]
 pub fn is_valid(x: String) -> bool {
-    let parse: Result<serde_yaml::Value, _> = serde_yaml::from_str(&x);
-    parse.is_ok()
+    parse(&x).is_ok()
 }
 
 /// Serializes the input term to YAML.
 #[tracing::instrument(name = "yaml.marshal", err)]
-pub fn marshal(x: serde_yaml::Value) -> Result<String> {
+pub fn marshal(x: Value) -> Result<String> {
     let parse: String = serde_yaml::to_string(&x)?;
     Ok(parse)
 }
 
 /// Deserializes the input string.
 #[tracing::instrument(name = "yaml.unmarshal", err)]
-pub fn unmarshal(x: String) -> Result<serde_json::Value> {
-    let parse: serde_json::Value = serde_yaml::from_str(&x)?;
-    Ok(parse)
+pub fn unmarshal(x: String) -> Result<Value> {
+    parse(&x)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn aliases() {
+        assert_eq!(
+            unmarshal("a: &x [1, 2]\nb: *x\n".into()).unwrap(),
+            json!({"a": [1, 2], "b": [1, 2]})
+        );
+
+        let mut laughs = String::from("a: &a [lol, lol, lol, lol, lol, lol, lol, lol, lol]\n");
+        for (name, previous) in ["b", "c", "d", "e", "f", "g"]
+            .iter()
+            .zip(["a", "b", "c", "d", "e", "f"])
+        {
+            let aliases = vec![format!("*{previous}"); 9].join(", ");
+            laughs.push_str(&format!("{name}: &{name} [{aliases}]\n"));
+        }
+
+        let err = unmarshal(laughs.clone()).unwrap_err();
+        assert!(err.to_string().contains("excessive aliasing"), "{err}");
+        assert!(!is_valid(laughs));
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = json!({"kind": "Pod", "spec": {"containers": [{"name": "app"}]}});
+        let yaml = marshal(value.clone()).unwrap();
+        assert_eq!(unmarshal(yaml).unwrap(), value);
+        assert!(!is_valid("a: [".into()));
+    }
 }
@@ -30,9 +30,22 @@ pub mod traits;
 #[allow(clippy::too_many_lines)]
 pub fn resolve<C: EvaluationContext>(name: &str) -> Result<Box<dyn Builtin<C>>> {
     match name {
+        #[cfg(feature = "base64url-builtins")]
+        "base64url.decode" => Ok(self::impls::base64url::decode.wrap()),
+
         #[cfg(feature = "base64url-builtins")]
         "base64url.encode_no_pad" => Ok(self::impls::base64url::encode_no_pad.wrap()),
 
+        #[cfg(all(
+            feature = "crypto-hmac-builtins",
+            any(
+                feature = "crypto-md5-builtins",
+                feature = "crypto-sha1-builtins",
+                feature = "crypto-sha2-builtins"
+            )
+        ))]
+        "crypto.hmac.equal" => Ok(self::impls::crypto::hmac::equal.wrap()),
+
         #[cfg(all(feature = "crypto-md5-builtins", feature = "crypto-hmac-builtins"))]
         "crypto.hmac.md5" => Ok(self::impls::crypto::hmac::md5.wrap()),
 
@@ -54,28 +67,53 @@ pub fn resolve<C: EvaluationContext>(name: &str) -> Result<Box<dyn Builtin<C>>>
         #[cfg(all(feature = "crypto-sha2-builtins", feature = "crypto-digest-builtins"))]
         "crypto.sha256" => Ok(self::impls::crypto::digest::sha256.wrap()),
 
+        #[cfg(feature = "crypto-x509-builtins")]
         "crypto.x509.parse_and_verify_certificates" => {
-            Ok(self::impls::crypto::x509::parse_and_verify_certificates.wrap())
+            Ok(self::impls::crypto_x509::parse_and_verify_certificates.wrap())
         }
+
+        #[cfg(feature = "crypto-x509-builtins")]
         "crypto.x509.parse_certificate_request" => {
-            Ok(self::impls::crypto::x509::parse_certificate_request.wrap())
-        }
-        "crypto.x509.parse_certificates" => {
-            Ok(self::impls::crypto::x509::parse_certificates.wrap())
+            Ok(self::impls::crypto_x509::parse_certificate_request.wrap())
         }
+
+        #[cfg(feature = "crypto-x509-builtins")]
+        "crypto.x509.parse_certificates" => Ok(self::impls::crypto_x509::parse_certificates.wrap()),
+
+        #[cfg(feature = "crypto-x509-builtins")]
         "crypto.x509.parse_rsa_private_key" => {
-            Ok(self::impls::crypto::x509::parse_rsa_private_key.wrap())
+            Ok(self::impls::crypto_x509::parse_rsa_private_key.wrap())
         }
+
+        #[cfg(feature = "strings-builtins")]
+        "format_int" => Ok(self::impls::strings::format_int.wrap()),
+
+        #[cfg(feature = "glob-builtins")]
+        "glob.match" => Ok(self::impls::glob::glob_match.wrap()),
+
         #[cfg(feature = "glob-builtins")]
         "glob.quote_meta" => Ok(self::impls::glob::quote_meta.wrap()),
 
         "graph.reachable_paths" => Ok(self::impls::graph::reachable_paths.wrap()),
+
+        #[cfg(feature = "graphql-builtins")]
         "graphql.is_valid" => Ok(self::impls::graphql::is_valid.wrap()),
+
+        #[cfg(feature = "graphql-builtins")]
         "graphql.parse" => Ok(self::impls::graphql::parse.wrap()),
+
+        #[cfg(feature = "graphql-builtins")]
         "graphql.parse_and_verify" => Ok(self::impls::graphql::parse_and_verify.wrap()),
+
+        #[cfg(feature = "graphql-builtins")]
         "graphql.parse_query" => Ok(self::impls::graphql::parse_query.wrap()),
+
+        #[cfg(feature = "graphql-builtins")]
         "graphql.parse_schema" => Ok(self::impls::graphql::parse_schema.wrap()),
 
+        #[cfg(feature = "graphql-builtins")]
+        "graphql.schema_is_valid" => Ok(self::impls::graphql::schema_is_valid.wrap()),
+
         #[cfg(feature = "hex-builtins")]
         "hex.decode" => Ok(self::impls::hex::decode.wrap()),
 
@@ -86,36 +124,101 @@ pub fn resolve<C: EvaluationContext>(name: &str) -> Result<Box<dyn Builtin<C>>>
         "http.send" => Ok(self::impls::http::send.wrap()),
 
         "indexof_n" => Ok(self::impls::indexof_n.wrap()),
+
+        "internal.print" => Ok(self::impls::print::print.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.decode" => Ok(self::impls::io::jwt::decode.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.decode_verify" => Ok(self::impls::io::jwt::decode_verify.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.encode_sign" => Ok(self::impls::io::jwt::encode_sign.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.encode_sign_raw" => Ok(self::impls::io::jwt::encode_sign_raw.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_es256" => Ok(self::impls::io::jwt::verify_es256.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_es384" => Ok(self::impls::io::jwt::verify_es384.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_es512" => Ok(self::impls::io::jwt::verify_es512.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_hs256" => Ok(self::impls::io::jwt::verify_hs256.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_hs384" => Ok(self::impls::io::jwt::verify_hs384.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_hs512" => Ok(self::impls::io::jwt::verify_hs512.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_ps256" => Ok(self::impls::io::jwt::verify_ps256.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_ps384" => Ok(self::impls::io::jwt::verify_ps384.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_ps512" => Ok(self::impls::io::jwt::verify_ps512.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_rs256" => Ok(self::impls::io::jwt::verify_rs256.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_rs384" => Ok(self::impls::io::jwt::verify_rs384.wrap()),
+
+        #[cfg(feature = "jwt-builtins")]
         "io.jwt.verify_rs512" => Ok(self::impls::io::jwt::verify_rs512.wrap()),
 
+        #[cfg(feature = "json-builtins")]
+        "json.filter" => Ok(self::impls::json::filter.wrap()),
+
         #[cfg(feature = "json-builtins")]
         "json.patch" => Ok(self::impls::json::patch.wrap()),
 
+        #[cfg(feature = "json-builtins")]
+        "json.remove" => Ok(self::impls::json::remove.wrap()),
+
+        #[cfg(feature = "net-builtins")]
+        "net.cidr_contains" => Ok(self::impls::net::cidr_contains.wrap()),
+
+        #[cfg(feature = "net-builtins")]
         "net.cidr_contains_matches" => Ok(self::impls::net::cidr_contains_matches.wrap()),
+
+        #[cfg(feature = "net-builtins")]
         "net.cidr_expand" => Ok(self::impls::net::cidr_expand.wrap()),
+
+        #[cfg(feature = "net-builtins")]
+        "net.cidr_intersects" => Ok(self::impls::net::cidr_intersects.wrap()),
+
+        #[cfg(feature = "net-builtins")]
+        "net.cidr_is_valid" => Ok(self::impls::net::cidr_is_valid.wrap()),
+
+        #[cfg(feature = "net-builtins")]
         "net.cidr_merge" => Ok(self::impls::net::cidr_merge.wrap()),
+
+        #[cfg(feature = "net-builtins")]
         "net.lookup_ip_addr" => Ok(self::impls::net::lookup_ip_addr.wrap()),
 
+        #[cfg(feature = "numbers-builtins")]
+        "numbers.range_step" => Ok(self::impls::numbers::range_step.wrap()),
+
+        #[cfg(feature = "object-builtins")]
+        "object.subset" => Ok(self::impls::object::subset.wrap()),
+
         #[cfg(feature = "object-builtins")]
         "object.union_n" => Ok(self::impls::object::union_n.wrap()),
 
         "opa.runtime" => Ok(self::impls::opa::runtime.wrap()),
 
+        #[cfg(feature = "http-aws-sigv4")]
+        "providers.aws.sign_req" => Ok(self::impls::providers::aws::sign_req.wrap()),
+
         #[cfg(feature = "rng")]
         "rand.intn" => Ok(self::impls::rand::intn.wrap()),
 
@@ -138,6 +241,8 @@ pub fn resolve<C: EvaluationContext>(name: &str) -> Result<Box<dyn Builtin<C>>>
             Ok(self::impls::regex::find_all_string_submatch_n.wrap())
         }
 
+        "rego.metadata.chain" => Ok(self::impls::rego::metadata_chain.wrap()),
+        "rego.metadata.rule" => Ok(self::impls::rego::metadata_rule.wrap()),
         "rego.parse_module" => Ok(self::impls::rego::parse_module.wrap()),
 
         #[cfg(feature = "semver-builtins")]
@@ -149,6 +254,12 @@ pub fn resolve<C: EvaluationContext>(name: &str) -> Result<Box<dyn Builtin<C>>>
         #[cfg(feature = "sprintf-builtins")]
         "sprintf" => Ok(self::impls::sprintf.wrap()),
 
+        #[cfg(feature = "strings-builtins")]
+        "strings.render_template" => Ok(self::impls::strings::render_template.wrap()),
+
+        #[cfg(feature = "strings-builtins")]
+        "strings.replace_n" => Ok(self::impls::strings::replace_n.wrap()),
+
         #[cfg(feature = "time-builtins")]
         "time.add_date" => Ok(self::impls::time::add_date.wrap()),
 
@@ -161,6 +272,9 @@ pub fn resolve<C: EvaluationContext>(name: &str) -> Result<Box<dyn Builtin<C>>>
         #[cfg(feature = "time-builtins")]
         "time.diff" => Ok(self::impls::time::diff.wrap()),
 
+        #[cfg(feature = "time-builtins")]
+        "time.format" => Ok(self::impls::time::format.wrap()),
+
         #[cfg(feature = "time-builtins")]
         "time.now_ns" => Ok(self::impls::time::now_ns.wrap()),
 
@@ -196,6 +310,10 @@ pub fn resolve<C: EvaluationContext>(name: &str) -> Result<Box<dyn Builtin<C>>>
         #[cfg(feature = "urlquery-builtins")]
         "urlquery.encode_object" => Ok(self::impls::urlquery::encode_object.wrap()),
 
+        #[cfg(feature = "uuid-builtins")]
+        "uuid.parse" => Ok(self::impls::uuid::parse.wrap()),
+
+        #[cfg(feature = "uuid-builtins")]
         "uuid.rfc4122" => Ok(self::impls::uuid::rfc4122.wrap()),
 
         #[cfg(feature = "yaml-builtins")]
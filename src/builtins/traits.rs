@@ -55,7 +55,11 @@ where
 /// A utility trait used to help constructing [`Builtin`]s out of a regular
 /// function, abstracting away the parameters deserialization, the return value
 /// serialization, for async/non-async variants, and Result/non-Result variants
-pub(crate) trait BuiltinFunc<
+///
+/// It is implemented for functions and closures taking up to four
+/// deserializable parameters, optionally preceded by a `&mut C` evaluation
+/// context.
+pub trait BuiltinFunc<
     C: 'static,
     const ASYNC: bool,
     const RESULT: bool,
@@ -63,12 +67,14 @@ pub(crate) trait BuiltinFunc<
     P: 'static,
 >: Sized + Send + Sync + 'static
 {
+    /// Call the function with JSON-serialized arguments
     fn call<'a>(
         &'a self,
         context: &'a mut C,
         args: &'a [&'a [u8]],
     ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, anyhow::Error>> + Send + 'a>>;
 
+    /// Box the function into a [`Builtin`]
     fn wrap(self) -> Box<dyn Builtin<C>> {
         Box::new(WrappedBuiltin {
             func: self,
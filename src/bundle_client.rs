@@ -0,0 +1,458 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A client periodically downloading bundles from a bundle server
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use reqwest::{
+    header::{ETAG, IF_NONE_MATCH},
+    StatusCode,
+};
+use tokio::{sync::watch, task::JoinHandle};
+
+#[cfg(feature = "bundle-signatures")]
+use crate::BundleVerification;
+use crate::{Bundle, Error, EvaluationContext, ReloadablePolicy};
+
+/// Default delay between two polls of the bundle server
+const DEFAULT_POLLING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Default maximum delay between two attempts when the server fails
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Extra time given to the server to answer a long-polling request
+const LONG_POLLING_GRACE: Duration = Duration::from_secs(10);
+
+/// Default maximum size of a downloaded bundle, in bytes
+const DEFAULT_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// Configuration of a [`BundleClient`]
+#[derive(Debug, Clone)]
+pub struct BundleClientConfig {
+    url: String,
+    polling_interval: Duration,
+    max_backoff: Duration,
+    long_polling_timeout: Option<Duration>,
+    max_size: usize,
+    persistence_path: Option<PathBuf>,
+    bearer_token: Option<String>,
+    #[cfg(feature = "bundle-signatures")]
+    verification: Option<BundleVerification>,
+}
+
+impl BundleClientConfig {
+    /// Download the bundle from the given URL
+    #[must_use]
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            polling_interval: DEFAULT_POLLING_INTERVAL,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            long_polling_timeout: None,
+            max_size: DEFAULT_MAX_SIZE,
+            persistence_path: None,
+            bearer_token: None,
+            #[cfg(feature = "bundle-signatures")]
+            verification: None,
+        }
+    }
+
+    /// Set the delay between two polls of the bundle server
+    #[must_use]
+    pub fn with_polling_interval(mut self, interval: Duration) -> Self {
+        self.polling_interval = interval;
+        self
+    }
+
+    /// Set the maximum delay between two attempts when downloading the bundle
+    /// fails. The delay doubles after each failure, starting from the polling
+    /// interval.
+    #[must_use]
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Ask the server to hold the request until the bundle changes, for at
+    /// most the given duration, instead of polling periodically
+    #[must_use]
+    pub fn with_long_polling(mut self, timeout: Duration) -> Self {
+        self.long_polling_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the maximum size of a downloaded bundle, in bytes. Larger bundles
+    /// fail to download.
+    #[must_use]
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Save the downloaded bundles to the given path, and load it on startup
+    /// before the first download succeeds
+    #[must_use]
+    pub fn with_persistence_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.persistence_path = Some(path.into());
+        self
+    }
+
+    /// Authenticate to the bundle server with the given bearer token
+    #[must_use]
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    /// Only accept bundles signed by the given keys
+    #[cfg(feature = "bundle-signatures")]
+    #[must_use]
+    pub fn with_verification(mut self, verification: BundleVerification) -> Self {
+        self.verification = Some(verification);
+        self
+    }
+
+    async fn load(&self, bytes: &[u8]) -> Result<Bundle, Error> {
+        #[cfg(feature = "bundle-signatures")]
+        if let Some(verification) = &self.verification {
+            return Bundle::load_verified(bytes, verification).await;
+        }
+
+        Bundle::load(bytes).await
+    }
+}
+
+/// A background task downloading bundles from a bundle server, publishing
+/// each new version to its subscribers. Policies can be hot-swapped with each
+/// new version with [`BundleClient::reload`].
+///
+/// The task is stopped when the client is dropped.
+#[derive(Debug)]
+pub struct BundleClient {
+    receiver: watch::Receiver<Option<Arc<Bundle>>>,
+    task: JoinHandle<()>,
+}
+
+impl BundleClient {
+    /// Start downloading bundles in the background. This must be called from
+    /// within a Tokio runtime.
+    #[must_use]
+    pub fn spawn(config: BundleClientConfig) -> Self {
+        let (sender, receiver) = watch::channel(None);
+        let task = tokio::spawn(run(config, sender));
+        Self { receiver, task }
+    }
+
+    /// Get a receiver notified each time a new bundle is activated
+    #[must_use]
+    pub fn subscribe(&self) -> watch::Receiver<Option<Arc<Bundle>>> {
+        self.receiver.clone()
+    }
+
+    /// The current bundle, if one was loaded already
+    #[must_use]
+    pub fn current(&self) -> Option<Arc<Bundle>> {
+        self.receiver.borrow().clone()
+    }
+
+    /// Wait for the first bundle to be loaded
+    ///
+    /// # Errors
+    ///
+    /// If the background task stopped
+    pub async fn ready(&self) -> Result<Arc<Bundle>, Error> {
+        let mut receiver = self.receiver.clone();
+        let bundle = receiver
+            .wait_for(Option::is_some)
+            .await
+            .context("bundle client stopped")?;
+        Ok(bundle.clone().context("bundle client stopped")?)
+    }
+
+    /// Reload the policy with the current bundle, if any, and then with each
+    /// new bundle, in the background until the client is dropped. The
+    /// previous version of the policy stays in use when a bundle fails to
+    /// load.
+    pub fn reload<C: EvaluationContext>(&self, policy: Arc<ReloadablePolicy<C>>) {
+        let mut receiver = self.receiver.clone();
+        tokio::spawn(async move {
+            loop {
+                let bundle = receiver.borrow_and_update().clone();
+                if let Some(bundle) = bundle {
+                    match policy.reload_bundle(&bundle).await {
+                        Ok(()) => {
+                            tracing::info!(revision = %bundle.manifest().revision, "reloaded policy");
+                        }
+                        Err(e) => tracing::warn!(error = ?e, "failed to reload policy"),
+                    }
+                }
+
+                // The sender is dropped when the client stops
+                if receiver.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+impl Drop for BundleClient {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The result of a single download attempt
+enum Download {
+    Modified {
+        bytes: Vec<u8>,
+        etag: Option<String>,
+    },
+    NotModified,
+}
+
+#[tracing::instrument(skip_all, fields(url = %config.url))]
+async fn run(config: BundleClientConfig, sender: watch::Sender<Option<Arc<Bundle>>>) {
+    if let Some(path) = &config.persistence_path {
+        match restore(&config, path).await {
+            Ok(Some(bundle)) => {
+                sender.send_replace(Some(Arc::new(bundle)));
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!(error = ?e, "failed to load persisted bundle"),
+        }
+    }
+
+    let client = reqwest::Client::new();
+    let mut etag = None;
+    let mut backoff = config.polling_interval;
+
+    loop {
+        let delay = match poll(&config, &client, etag.as_deref()).await {
+            Ok(Download::Modified {
+                bytes,
+                etag: new_etag,
+            }) => match config.load(&bytes).await {
+                Ok(bundle) => {
+                    tracing::info!(revision = %bundle.manifest().revision, "activating bundle");
+                    sender.send_replace(Some(Arc::new(bundle)));
+                    etag = new_etag;
+                    backoff = config.polling_interval;
+
+                    if let Some(path) = &config.persistence_path {
+                        if let Err(e) = persist(path, &bytes).await {
+                            tracing::warn!(error = ?e, "failed to persist bundle");
+                        }
+                    }
+
+                    next_delay(&config)
+                }
+                Err(e) => {
+                    tracing::warn!(error = ?e, "downloaded an invalid bundle");
+                    failure_delay(&config, &mut backoff)
+                }
+            },
+            Ok(Download::NotModified) => next_delay(&config),
+            Err(e) => {
+                tracing::warn!(error = ?e, "failed to download bundle");
+                failure_delay(&config, &mut backoff)
+            }
+        };
+
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Delay before the next poll after a successful one. With long-polling,
+/// the server already waited for changes.
+fn next_delay(config: &BundleClientConfig) -> Duration {
+    if config.long_polling_timeout.is_some() {
+        Duration::ZERO
+    } else {
+        config.polling_interval
+    }
+}
+
+/// Delay before the next attempt after a failure, with exponential backoff
+fn failure_delay(config: &BundleClientConfig, backoff: &mut Duration) -> Duration {
+    let delay = *backoff;
+    *backoff = (*backoff * 2).min(config.max_backoff);
+    delay
+}
+
+async fn poll(
+    config: &BundleClientConfig,
+    client: &reqwest::Client,
+    etag: Option<&str>,
+) -> Result<Download> {
+    let mut request = client.get(&config.url);
+    if let Some(etag) = etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(timeout) = config.long_polling_timeout {
+        request = request
+            .header("Prefer", format!("wait={}", timeout.as_secs()))
+            .timeout(timeout + LONG_POLLING_GRACE);
+    }
+    if let Some(token) = &config.bearer_token {
+        request = request.bearer_auth(token);
+    }
+
+    let response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Download::NotModified);
+    }
+
+    let mut response = response.error_for_status()?;
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned);
+
+    let too_large = || anyhow::anyhow!("bundle is larger than {} bytes", config.max_size);
+    if response
+        .content_length()
+        .is_some_and(|length| length > u64::try_from(config.max_size).unwrap_or(u64::MAX))
+    {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > config.max_size {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(Download::Modified { bytes, etag })
+}
+
+/// Load the bundle saved by a previous run, if any
+async fn restore(config: &BundleClientConfig, path: &Path) -> Result<Option<Bundle>> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(Some(config.load(&bytes).await?))
+}
+
+/// Atomically replace the persisted bundle
+async fn persist(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    tokio::fs::write(&tmp, bytes).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::tokio::write::GzipEncoder;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// Build a bundle with the given policy module
+    async fn tarball(module: &[u8]) -> Vec<u8> {
+        let mut builder = tokio_tar::Builder::new(GzipEncoder::new(Vec::new()));
+        let mut header = tokio_tar::Header::new_gnu();
+        header.set_size(module.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "policy.wasm", module)
+            .await
+            .unwrap();
+        let mut encoder = builder.into_inner().await.unwrap();
+        encoder.shutdown().await.unwrap();
+        encoder.into_inner()
+    }
+
+    /// Serve the bundle with the `"v1"` entity tag, counting the requests
+    /// which were answered with it
+    async fn bundle_server(bundle: Vec<u8>) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/bundle.tar.gz", listener.local_addr().unwrap());
+        let downloads = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = downloads.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buffer = [0; 4096];
+                let read = stream.read(&mut buffer).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buffer[..read]).to_lowercase();
+                let response = if request.contains("if-none-match: \"v1\"") {
+                    b"HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".to_vec()
+                } else {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        bundle.len()
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(&bundle);
+                    response
+                };
+                let _ = stream.write_all(&response).await;
+            }
+        });
+        (url, downloads)
+    }
+
+    #[tokio::test]
+    async fn download() {
+        let (url, downloads) = bundle_server(tarball(b"\0asm").await).await;
+        let client = BundleClient::spawn(
+            BundleClientConfig::new(&url).with_polling_interval(Duration::from_millis(10)),
+        );
+        let bundle = tokio::time::timeout(Duration::from_secs(10), client.ready())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bundle.module(), b"\0asm");
+
+        // Unchanged bundles are not downloaded again
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(downloads.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn max_size() {
+        let bundle = tarball(&[0; 1024]).await;
+        let (url, _) = bundle_server(bundle.clone()).await;
+        let client = reqwest::Client::new();
+
+        let config = BundleClientConfig::new(&url).with_max_size(bundle.len() - 1);
+        let error = poll(&config, &client, None).await.err().unwrap();
+        assert!(error.to_string().contains("larger than"));
+
+        let config = config.with_max_size(bundle.len());
+        assert!(matches!(
+            poll(&config, &client, None).await.unwrap(),
+            Download::Modified { etag: Some(etag), .. } if etag == "\"v1\""
+        ));
+        assert!(matches!(
+            poll(&config, &client, Some("\"v1\"")).await.unwrap(),
+            Download::NotModified
+        ));
+    }
+}
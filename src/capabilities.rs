@@ -0,0 +1,102 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Restriction of the builtins a policy is allowed to call
+
+use std::{collections::HashSet, future::Future, pin::Pin};
+
+use crate::builtins::traits::Builtin;
+
+/// Builtins which perform network I/O
+const NETWORK_BUILTINS: &[&str] = &["http.send", "net.lookup_ip_addr"];
+
+/// Error returned when a policy calls a builtin disabled by the
+/// [`Capabilities`] of the runtime
+#[derive(Debug, thiserror::Error)]
+#[error("builtin {name:?} not allowed by capabilities")]
+pub struct BuiltinNotAllowed {
+    name: String,
+}
+
+impl BuiltinNotAllowed {
+    pub(crate) fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+        }
+    }
+
+    /// The name of the disabled builtin
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Set of builtins a policy is allowed to call. All builtins are allowed by
+/// default.
+///
+/// Policies calling disabled builtins can still be loaded, but their
+/// evaluation fails with a [`BuiltinNotAllowed`] error when the builtin is
+/// called.
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    denied: HashSet<String>,
+}
+
+impl Capabilities {
+    /// Disable the builtin with the given name
+    #[must_use]
+    pub fn deny_builtin(mut self, name: impl Into<String>) -> Self {
+        self.denied.insert(name.into());
+        self
+    }
+
+    /// Disable all the builtins performing network I/O, like `http.send`
+    #[must_use]
+    pub fn deny_network(self) -> Self {
+        NETWORK_BUILTINS
+            .iter()
+            .fold(self, |capabilities, name| capabilities.deny_builtin(*name))
+    }
+
+    /// Check if the builtin with the given name is allowed
+    #[must_use]
+    pub fn allows(&self, name: &str) -> bool {
+        !self.denied.contains(name)
+    }
+}
+
+/// A builtin which fails when called, used in place of disabled builtins
+pub(crate) struct DisallowedBuiltin {
+    name: String,
+}
+
+impl DisallowedBuiltin {
+    pub(crate) fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+        }
+    }
+}
+
+impl<C> Builtin<C> for DisallowedBuiltin {
+    fn call<'a>(
+        &'a self,
+        _context: &'a mut C,
+        _args: &'a [&'a [u8]],
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, anyhow::Error>> + Send + 'a>> {
+        let err = BuiltinNotAllowed::new(&self.name);
+        Box::pin(async move { Err(err.into()) })
+    }
+}
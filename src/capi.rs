@@ -0,0 +1,250 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! C bindings, to embed the runtime in non-Rust hosts
+//!
+//! The C header is in `include/opa_wasm.h`. The library can be built as a
+//! shared or static library with:
+//!
+//! ```text
+//! cargo rustc --release --features capi --crate-type cdylib
+//! cargo rustc --release --features capi --crate-type staticlib
+//! ```
+//!
+//! Functions returning a pointer return `NULL` on error, in which case
+//! [`opa_wasm_last_error`] describes what went wrong.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::AssertUnwindSafe,
+    ptr,
+};
+
+use anyhow::Context;
+use wasmtime::{Config, Engine, Module, Store};
+
+use crate::{DefaultContext, Policy, Runtime};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A loaded policy, with its own store.
+///
+/// A policy must not be evaluated from multiple threads at the same time.
+pub struct OpaWasmPolicy {
+    store: Store<()>,
+    policy: Policy<DefaultContext>,
+}
+
+fn set_last_error(message: &str) {
+    // Interior NUL bytes would truncate the message
+    let message = CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Run the function, recording its error or panic as the last error
+fn guard<T>(f: impl FnOnce() -> anyhow::Result<T>) -> Option<T> {
+    match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(e)) => {
+            set_last_error(&format!("{e:#}"));
+            None
+        }
+        Err(_) => {
+            set_last_error("opa-wasm panicked");
+            None
+        }
+    }
+}
+
+/// Read a C string argument
+///
+/// # Safety
+///
+/// The pointer must be null or point to a NUL-terminated string
+unsafe fn str_arg<'a>(ptr: *const c_char, name: &str) -> anyhow::Result<&'a str> {
+    anyhow::ensure!(!ptr.is_null(), "{name} is null");
+    CStr::from_ptr(ptr)
+        .to_str()
+        .with_context(|| format!("{name} is not valid UTF-8"))
+}
+
+/// Read an optional JSON document argument, defaulting to an empty object
+///
+/// # Safety
+///
+/// The pointer must be null or point to a NUL-terminated string
+unsafe fn json_arg(ptr: *const c_char, name: &str) -> anyhow::Result<serde_json::Value> {
+    if ptr.is_null() {
+        return Ok(serde_json::Value::Object(serde_json::Map::default()));
+    }
+
+    let json = str_arg(ptr, name)?;
+    serde_json::from_str(json).with_context(|| format!("{name} is not valid JSON"))
+}
+
+/// Load a policy from a compiled WASM module, with a JSON data document.
+///
+/// `data_json` may be `NULL` to load the policy without data. The returned
+/// policy must be freed with [`opa_wasm_free`].
+///
+/// # Safety
+///
+/// `wasm` must point to `wasm_len` readable bytes, and `data_json` must be
+/// `NULL` or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn opa_wasm_load(
+    wasm: *const u8,
+    wasm_len: usize,
+    data_json: *const c_char,
+) -> *mut OpaWasmPolicy {
+    guard(|| {
+        anyhow::ensure!(!wasm.is_null(), "wasm is null");
+        let wasm = std::slice::from_raw_parts(wasm, wasm_len);
+        let data = json_arg(data_json, "data_json")?;
+
+        let mut config = Config::new();
+        config.async_support(true);
+        let engine = Engine::new(&config)?;
+        let module = Module::new(&engine, wasm)?;
+
+        let mut store = Store::new(&engine, ());
+        let policy =
+            Runtime::new_blocking(&mut store, &module)?.with_data_blocking(&mut store, &data)?;
+
+        Ok(Box::into_raw(Box::new(OpaWasmPolicy { store, policy })))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Evaluate an entrypoint of the policy with a JSON input document, returning
+/// the JSON-encoded result set.
+///
+/// `input_json` may be `NULL` to evaluate without input. The returned string
+/// must be freed with [`opa_wasm_string_free`].
+///
+/// # Safety
+///
+/// `policy` must have been returned by [`opa_wasm_load`] and not freed yet,
+/// and not be used by another thread concurrently. `entrypoint` must point to
+/// a NUL-terminated string, and `input_json` must be `NULL` or point to a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn opa_wasm_eval_json(
+    policy: *mut OpaWasmPolicy,
+    entrypoint: *const c_char,
+    input_json: *const c_char,
+) -> *mut c_char {
+    guard(|| {
+        let policy = policy.as_mut().context("policy is null")?;
+        let entrypoint = str_arg(entrypoint, "entrypoint")?;
+        let input = json_arg(input_json, "input_json")?;
+
+        let result: serde_json::Value =
+            policy
+                .policy
+                .evaluate_blocking(&mut policy.store, entrypoint, &input)?;
+
+        // JSON strings escape NUL characters, so this cannot fail
+        let result = CString::new(serde_json::to_string(&result)?)?;
+        Ok(result.into_raw())
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Free a policy returned by [`opa_wasm_load`]. Does nothing if `policy` is
+/// `NULL`.
+///
+/// # Safety
+///
+/// `policy` must be `NULL` or have been returned by [`opa_wasm_load`], and
+/// not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn opa_wasm_free(policy: *mut OpaWasmPolicy) {
+    if !policy.is_null() {
+        drop(Box::from_raw(policy));
+    }
+}
+
+/// Free a string returned by [`opa_wasm_eval_json`]. Does nothing if `string`
+/// is `NULL`.
+///
+/// # Safety
+///
+/// `string` must be `NULL` or have been returned by [`opa_wasm_eval_json`],
+/// and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn opa_wasm_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// The message of the last error which happened on this thread, or `NULL` if
+/// there was none.
+///
+/// The message is owned by the library, and is valid until the next call to
+/// a function of this library on the same thread.
+#[no_mangle]
+pub extern "C" fn opa_wasm_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        let error = opa_wasm_last_error();
+        assert!(!error.is_null());
+        unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn load_errors() {
+        let policy = unsafe { opa_wasm_load(ptr::null(), 0, ptr::null()) };
+        assert!(policy.is_null());
+        assert_eq!(last_error(), "wasm is null");
+
+        // A valid WASM module, but not an OPA policy
+        let wasm = b"\0asm\x01\0\0\0";
+        let policy = unsafe { opa_wasm_load(wasm.as_ptr(), wasm.len(), ptr::null()) };
+        assert!(policy.is_null());
+        assert!(last_error().starts_with("failed to load the policy"));
+
+        let data = CString::new("{").unwrap();
+        let policy = unsafe { opa_wasm_load(wasm.as_ptr(), wasm.len(), data.as_ptr()) };
+        assert!(policy.is_null());
+        assert!(last_error().starts_with("data_json is not valid JSON"));
+    }
+
+    #[test]
+    fn eval_errors() {
+        let entrypoint = CString::new("hello").unwrap();
+        let result =
+            unsafe { opa_wasm_eval_json(ptr::null_mut(), entrypoint.as_ptr(), ptr::null()) };
+        assert!(result.is_null());
+        assert_eq!(last_error(), "policy is null");
+
+        // Freeing null pointers is a no-op
+        unsafe {
+            opa_wasm_free(ptr::null_mut());
+            opa_wasm_string_free(ptr::null_mut());
+        }
+    }
+}
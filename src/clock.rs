@@ -0,0 +1,66 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sources of the current time used during evaluation
+
+use chrono::{DateTime, Utc};
+
+/// A source of the current time, used to set the evaluation time returned by
+/// `time.now_ns`
+///
+/// Replacing the [`SystemClock`] lets tests and replay scenarios freeze or
+/// fake the current time.
+pub trait Clock: Send + Sync + 'static {
+    /// Get the current date and time
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// A [`Clock`] reading the system time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A [`Clock`] always returning the same instant
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock {
+    time: DateTime<Utc>,
+}
+
+impl FixedClock {
+    /// Create a clock frozen at the given instant
+    #[must_use]
+    pub fn new(time: DateTime<Utc>) -> Self {
+        Self { time }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.time
+    }
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> DateTime<Utc> + Send + Sync + 'static,
+{
+    fn now(&self) -> DateTime<Utc> {
+        self()
+    }
+}
@@ -0,0 +1,296 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Caches of compiled policy modules
+
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use sha2::{Digest, Sha256};
+use wasmtime::{Engine, Module};
+
+use crate::Error;
+
+/// A cache of compiled modules, used by [`compile_module`] to skip the
+/// compilation of modules which were already compiled once.
+///
+/// Entries are the serialized modules, keyed by a hash of the WASM module and
+/// of the [`wasmtime::Engine`] configuration.
+///
+/// # Safety
+///
+/// Cached modules are loaded as native code and executed as-is, see
+/// [`wasmtime::Module::deserialize`]. Implementations must only return bytes
+/// which were previously given to [`CompilationCache::put`] for the same key,
+/// and must not let anyone else tamper with them.
+pub unsafe trait CompilationCache: Send + Sync {
+    /// Get the serialized module for a key, if it is in the cache
+    fn get(&self, key: &str) -> Option<Vec<u8>>;
+
+    /// Store the serialized module for a key
+    fn put(&self, key: &str, module: &[u8]);
+}
+
+// SAFETY: this only forwards to the inner cache
+unsafe impl<T: CompilationCache + ?Sized> CompilationCache for Arc<T> {
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        (**self).get(key)
+    }
+
+    fn put(&self, key: &str, module: &[u8]) {
+        (**self).put(key, module);
+    }
+}
+
+/// A [`CompilationCache`] keeping the compiled modules in memory
+#[derive(Debug, Default, Clone)]
+pub struct InMemoryCompilationCache {
+    entries: Arc<Mutex<HashMap<String, Arc<[u8]>>>>,
+}
+
+impl InMemoryCompilationCache {
+    /// Create an empty cache
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of modules in the cache
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Whether the cache is empty
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all the modules from the cache
+    pub fn clear(&self) {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+// SAFETY: entries can only be inserted through `put`
+unsafe impl CompilationCache for InMemoryCompilationCache {
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.get(key).map(|module| module.to_vec())
+    }
+
+    fn put(&self, key: &str, module: &[u8]) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.insert(key.to_owned(), module.into());
+    }
+}
+
+/// A [`CompilationCache`] storing the compiled modules as files in a
+/// directory, so that they persist across restarts.
+#[derive(Debug, Clone)]
+pub struct FsCompilationCache {
+    directory: PathBuf,
+}
+
+impl FsCompilationCache {
+    /// Create a cache storing modules in the given directory. The directory
+    /// is created when the first module is stored.
+    ///
+    /// # Safety
+    ///
+    /// The files in the directory are loaded as native code and executed
+    /// as-is, see [`wasmtime::Module::deserialize`]. The directory must only
+    /// be writable by trusted users, and its files must only be written by
+    /// this cache.
+    #[must_use]
+    pub unsafe fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{key}.cwasm"))
+    }
+}
+
+// SAFETY: the caller of `FsCompilationCache::new` guarantees that the
+// directory is only written by this cache
+unsafe impl CompilationCache for FsCompilationCache {
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        match std::fs::read(self.path(key)) {
+            Ok(module) => Some(module),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    key,
+                    "could not read compiled module"
+                );
+                None
+            }
+        }
+    }
+
+    fn put(&self, key: &str, module: &[u8]) {
+        // Write to a temporary file first, so that concurrent readers never
+        // see a partially written module
+        let path = self.path(key);
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let res = std::fs::create_dir_all(&self.directory)
+            .and_then(|()| std::fs::write(&tmp, module))
+            .and_then(|()| std::fs::rename(&tmp, &path));
+
+        if let Err(e) = res {
+            tracing::warn!(
+                error = &e as &dyn std::error::Error,
+                key,
+                "could not store compiled module"
+            );
+            let _ = std::fs::remove_file(&tmp);
+        }
+    }
+}
+
+/// Feeds [`Hash`] implementations into a SHA-256 digest
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn finish(&self) -> u64 {
+        unreachable!("only the digest is used")
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+}
+
+/// Compute the cache key of a module for a given engine
+fn cache_key(engine: &Engine, wasm: &[u8]) -> String {
+    let mut hasher = Sha256Hasher(Sha256::new());
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    hasher.0.update(wasm);
+    hex::encode(hasher.0.finalize())
+}
+
+/// Compile a WASM module, reusing the compiled module from the cache if the
+/// same module was already compiled by a compatible [`wasmtime::Engine`].
+///
+/// # Errors
+///
+/// If the module could not be compiled
+pub fn compile_module(
+    engine: &Engine,
+    wasm: &[u8],
+    cache: &dyn CompilationCache,
+) -> Result<Module, Error> {
+    let key = cache_key(engine, wasm);
+
+    let cached = cache.get(&key);
+    #[cfg(feature = "metrics")]
+    crate::metrics::observe_cache("compilation", cached.is_some());
+
+    if let Some(serialized) = cached {
+        // SAFETY: the `CompilationCache` contract guarantees these bytes come
+        // from `Module::serialize`
+        match unsafe { Module::deserialize(engine, serialized) } {
+            Ok(module) => return Ok(module),
+            Err(e) => {
+                tracing::warn!(error = ?e, key, "could not load cached module, compiling it again");
+            }
+        }
+    }
+
+    let module = Module::new(engine, wasm).map_err(Error::Load)?;
+    match module.serialize() {
+        Ok(serialized) => cache.put(&key, &serialized),
+        Err(e) => tracing::warn!(error = ?e, key, "could not serialize compiled module"),
+    }
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The smallest valid WASM module
+    const EMPTY_MODULE: &[u8] = b"\0asm\x01\0\0\0";
+
+    #[test]
+    fn in_memory() {
+        let engine = Engine::default();
+        let cache = InMemoryCompilationCache::new();
+
+        compile_module(&engine, EMPTY_MODULE, &cache).unwrap();
+        assert_eq!(cache.len(), 1);
+
+        // The second compilation hits the cache
+        compile_module(&engine, EMPTY_MODULE, &cache).unwrap();
+        assert_eq!(cache.len(), 1);
+
+        // A corrupted entry is replaced
+        let key = cache_key(&engine, EMPTY_MODULE);
+        cache.put(&key, b"garbage");
+        compile_module(&engine, EMPTY_MODULE, &cache).unwrap();
+        assert_ne!(cache.get(&key).unwrap(), b"garbage");
+    }
+
+    #[test]
+    fn key_depends_on_engine() {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let fuel = Engine::new(&config).unwrap();
+        let default = Engine::default();
+
+        assert_ne!(
+            cache_key(&fuel, EMPTY_MODULE),
+            cache_key(&default, EMPTY_MODULE)
+        );
+        assert_eq!(
+            cache_key(&default, EMPTY_MODULE),
+            cache_key(&Engine::default(), EMPTY_MODULE)
+        );
+    }
+
+    #[test]
+    fn filesystem() {
+        let directory =
+            std::env::temp_dir().join(format!("opa-wasm-compilation-cache-{}", std::process::id()));
+        let engine = Engine::default();
+        // SAFETY: the directory is private to this test
+        let cache = unsafe { FsCompilationCache::new(&directory) };
+
+        let key = cache_key(&engine, EMPTY_MODULE);
+        assert!(cache.get(&key).is_none());
+
+        compile_module(&engine, EMPTY_MODULE, &cache).unwrap();
+        assert!(cache.get(&key).is_some());
+        compile_module(&engine, EMPTY_MODULE, &cache).unwrap();
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
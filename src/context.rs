@@ -22,6 +22,18 @@ use chrono::TimeZone;
 use serde::{de::DeserializeOwned, Serialize};
 
 /// Context passed through builtin evaluation
+///
+/// Builtins get the clock, random number generator, caches, environment,
+/// files, capabilities and host configuration from the context rather than
+/// from the process, so that evaluations are reproducible and each runtime
+/// can be configured separately. The exceptions are the randomness of cryptographic signatures
+/// and the time used to sign outgoing AWS requests, which must come from the
+/// operating system.
+///
+/// Only the clock, the random number generator, and the evaluation cache must
+/// be implemented. Without the other getters, the builtins use their default
+/// configuration and don't cache anything, so that nothing is shared between
+/// contexts by accident.
 pub trait EvaluationContext: Send + 'static {
     /// The type of random number generator used by this context
     #[cfg(feature = "rng")]
@@ -35,6 +47,116 @@ pub trait EvaluationContext: Send + 'static {
     #[cfg(feature = "time")]
     fn now(&self) -> chrono::DateTime<chrono::Utc>;
 
+    /// Replace the current date and time until the next evaluation starts.
+    /// This is used to replay a recorded evaluation; contexts which don't
+    /// support it keep their own clock.
+    #[cfg(feature = "time")]
+    fn set_now(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        let _ = now;
+    }
+
+    /// Get the cache shared by `http.send` calls across evaluations. Without
+    /// it, responses are not cached across evaluations.
+    #[cfg(feature = "http-builtins")]
+    fn http_cache(&self) -> Option<&crate::builtins::impls::http::InterQueryCache> {
+        None
+    }
+
+    /// Get the cache of `http.send` calls made during the current evaluation,
+    /// which must be cleared by [`EvaluationContext::evaluation_start`].
+    /// Without it, every call sends its request.
+    #[cfg(feature = "http-builtins")]
+    fn http_intra_query_cache(&self) -> Option<&crate::builtins::impls::http::IntraQueryCache> {
+        None
+    }
+
+    /// Get the policy restricting the destinations `http.send` can reach.
+    /// Without it, every destination is allowed.
+    #[cfg(feature = "http-builtins")]
+    fn http_egress_policy(&self) -> Option<&crate::builtins::impls::http::EgressPolicy> {
+        None
+    }
+
+    /// Get the runtime-level configuration of `http.send`. Without it, the
+    /// default configuration is used.
+    #[cfg(feature = "http-builtins")]
+    fn http_config(&self) -> Option<&crate::builtins::impls::http::HttpConfig> {
+        None
+    }
+
+    /// Get the pool of HTTP clients reused across `http.send` calls. Without
+    /// it, every call builds its own client.
+    #[cfg(feature = "http-builtins")]
+    fn http_client_pool(&self) -> Option<&crate::builtins::impls::http::ClientPool> {
+        None
+    }
+
+    /// Get the requests discovered and prefetched by
+    /// [`crate::Policy::evaluate_with_prefetch`]. Without it, the requests
+    /// are sent one after the other as the policy makes them.
+    #[cfg(feature = "http-builtins")]
+    fn http_prefetch(&self) -> Option<&crate::builtins::impls::http::HttpPrefetch> {
+        None
+    }
+
+    /// Get the configuration of the network builtins. Without it, the default
+    /// configuration is used.
+    #[cfg(feature = "net-builtins")]
+    fn net_config(&self) -> Option<&crate::builtins::impls::net::NetConfig> {
+        None
+    }
+
+    /// Get the tries built by `net.cidr_contains_matches` during the current
+    /// evaluation, which must be cleared by
+    /// [`EvaluationContext::evaluation_start`]. Without it, the tries are
+    /// built on every call.
+    #[cfg(feature = "net-builtins")]
+    fn net_cidr_trie_cache(&self) -> Option<&crate::builtins::impls::net::CidrTrieCache> {
+        None
+    }
+
+    /// Get the resolver of the JSON Web Key Sets used by
+    /// `io.jwt.decode_verify`. Without it, the keys must be given in the
+    /// constraints.
+    #[cfg(feature = "jwt-jwks")]
+    fn jwks_resolver(&self) -> Option<&crate::builtins::impls::io::jwt::jwks::JwksResolver> {
+        None
+    }
+
+    /// Get the metadata returned by `opa.runtime`. Without it, the default
+    /// metadata is returned, with the environment of the process.
+    fn runtime_info(&self) -> Option<&crate::builtins::impls::opa::RuntimeInfo> {
+        None
+    }
+
+    /// Get the annotations returned by the `rego.metadata.*` builtins.
+    /// Without it, they return no annotation.
+    fn rego_metadata(&self) -> Option<&crate::builtins::impls::rego::RegoMetadata> {
+        None
+    }
+
+    /// Get the cache of compiled regular expressions shared across
+    /// evaluations. Without it, the expressions are compiled on every call.
+    #[cfg(feature = "regex-builtins")]
+    fn regex_cache(&self) -> Option<&crate::builtins::impls::regex::RegexCache> {
+        None
+    }
+
+    /// Get the cache of compiled globs shared across evaluations. Without it,
+    /// the globs are compiled on every call.
+    #[cfg(feature = "glob-builtins")]
+    fn glob_cache(&self) -> Option<&crate::builtins::impls::glob::GlobCache> {
+        None
+    }
+
+    /// Get the builtins this context is allowed to call, on top of the
+    /// [`crate::Capabilities`] of the runtime. This lets a runtime shared by
+    /// several tenants restrict each of them separately. Without it, only the
+    /// runtime capabilities apply.
+    fn capabilities(&self) -> Option<&crate::Capabilities> {
+        None
+    }
+
     /// Notify the context on evaluation start, so it can clean itself up
     fn evaluation_start(&mut self);
 
@@ -57,8 +179,56 @@ pub trait EvaluationContext: Send + 'static {
 pub struct DefaultContext {
     cache: HashMap<String, serde_json::Value>,
 
+    #[cfg(feature = "rng")]
+    rng_seed: Option<u64>,
+
+    #[cfg(feature = "rng")]
+    rng: Option<rand::rngs::StdRng>,
+
     #[cfg(feature = "time")]
     evaluation_time: chrono::DateTime<chrono::Utc>,
+
+    #[cfg(feature = "time")]
+    clock: Box<dyn crate::Clock>,
+
+    #[cfg(feature = "http-builtins")]
+    http_cache: crate::builtins::impls::http::InterQueryCache,
+
+    #[cfg(feature = "http-builtins")]
+    http_intra_query_cache: crate::builtins::impls::http::IntraQueryCache,
+
+    #[cfg(feature = "http-builtins")]
+    http_egress_policy: crate::builtins::impls::http::EgressPolicy,
+
+    #[cfg(feature = "http-builtins")]
+    http_config: crate::builtins::impls::http::HttpConfig,
+
+    #[cfg(feature = "http-builtins")]
+    http_client_pool: crate::builtins::impls::http::ClientPool,
+
+    #[cfg(feature = "http-builtins")]
+    http_prefetch: crate::builtins::impls::http::HttpPrefetch,
+
+    #[cfg(feature = "net-builtins")]
+    net_config: crate::builtins::impls::net::NetConfig,
+
+    #[cfg(feature = "net-builtins")]
+    net_cidr_trie_cache: crate::builtins::impls::net::CidrTrieCache,
+
+    #[cfg(feature = "jwt-jwks")]
+    jwks_resolver: crate::builtins::impls::io::jwt::jwks::JwksResolver,
+
+    runtime_info: crate::builtins::impls::opa::RuntimeInfo,
+
+    rego_metadata: crate::builtins::impls::rego::RegoMetadata,
+
+    #[cfg(feature = "regex-builtins")]
+    regex_cache: crate::builtins::impls::regex::RegexCache,
+
+    #[cfg(feature = "glob-builtins")]
+    glob_cache: crate::builtins::impls::glob::GlobCache,
+
+    capabilities: Option<crate::Capabilities>,
 }
 
 #[allow(clippy::derivable_impls)]
@@ -67,19 +237,187 @@ impl Default for DefaultContext {
         Self {
             cache: HashMap::new(),
 
+            #[cfg(feature = "rng")]
+            rng_seed: None,
+
+            #[cfg(feature = "rng")]
+            rng: None,
+
             #[cfg(feature = "time")]
             evaluation_time: chrono::Utc.timestamp_nanos(0),
+
+            #[cfg(feature = "time")]
+            clock: Box::new(crate::SystemClock),
+
+            #[cfg(feature = "http-builtins")]
+            http_cache: crate::builtins::impls::http::InterQueryCache::default(),
+
+            #[cfg(feature = "http-builtins")]
+            http_intra_query_cache: crate::builtins::impls::http::IntraQueryCache::default(),
+
+            #[cfg(feature = "http-builtins")]
+            http_egress_policy: crate::builtins::impls::http::EgressPolicy::default(),
+
+            #[cfg(feature = "http-builtins")]
+            http_config: crate::builtins::impls::http::HttpConfig::default(),
+
+            #[cfg(feature = "http-builtins")]
+            http_client_pool: crate::builtins::impls::http::ClientPool::default(),
+
+            #[cfg(feature = "http-builtins")]
+            http_prefetch: crate::builtins::impls::http::HttpPrefetch::default(),
+
+            #[cfg(feature = "net-builtins")]
+            net_config: crate::builtins::impls::net::NetConfig::default(),
+
+            #[cfg(feature = "net-builtins")]
+            net_cidr_trie_cache: crate::builtins::impls::net::CidrTrieCache::default(),
+
+            #[cfg(feature = "jwt-jwks")]
+            jwks_resolver: crate::builtins::impls::io::jwt::jwks::JwksResolver::default(),
+
+            runtime_info: crate::builtins::impls::opa::RuntimeInfo::default(),
+
+            rego_metadata: crate::builtins::impls::rego::RegoMetadata::default(),
+
+            #[cfg(feature = "regex-builtins")]
+            regex_cache: crate::builtins::impls::regex::RegexCache::default(),
+
+            #[cfg(feature = "glob-builtins")]
+            glob_cache: crate::builtins::impls::glob::GlobCache::default(),
+
+            capabilities: None,
         }
     }
 }
 
+impl DefaultContext {
+    /// Seed the random number generator used by builtins like `rand.intn` and
+    /// `uuid.rfc4122`. Each evaluation then generates the same values, which
+    /// makes tests reproducible.
+    #[cfg(feature = "rng")]
+    #[must_use]
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    /// Use the given clock to set the evaluation time, instead of the system
+    /// time
+    #[cfg(feature = "time")]
+    #[must_use]
+    pub fn with_clock(mut self, clock: impl crate::Clock) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Use the given cache for `http.send` calls. The same cache can be shared
+    /// by multiple contexts.
+    #[cfg(feature = "http-builtins")]
+    #[must_use]
+    pub fn with_http_cache(mut self, cache: crate::builtins::impls::http::InterQueryCache) -> Self {
+        self.http_cache = cache;
+        self
+    }
+
+    /// Restrict the destinations `http.send` calls can reach
+    #[cfg(feature = "http-builtins")]
+    #[must_use]
+    pub fn with_http_egress_policy(
+        mut self,
+        policy: crate::builtins::impls::http::EgressPolicy,
+    ) -> Self {
+        self.http_egress_policy = policy;
+        self
+    }
+
+    /// Use the given runtime-level configuration for `http.send` calls
+    #[cfg(feature = "http-builtins")]
+    #[must_use]
+    pub fn with_http_config(mut self, config: crate::builtins::impls::http::HttpConfig) -> Self {
+        self.http_config = config;
+        self
+    }
+
+    /// Set the configuration of the network builtins, like DNS lookups
+    #[cfg(feature = "net-builtins")]
+    #[must_use]
+    pub fn with_net_config(mut self, config: crate::builtins::impls::net::NetConfig) -> Self {
+        self.net_config = config;
+        self
+    }
+
+    /// Fetch the keys used by `io.jwt.decode_verify` from JSON Web Key Sets,
+    /// when its constraints contain neither `cert` nor `secret`. The same
+    /// resolver can be shared by multiple contexts.
+    #[cfg(feature = "jwt-jwks")]
+    #[must_use]
+    pub fn with_jwks_resolver(
+        mut self,
+        resolver: crate::builtins::impls::io::jwt::jwks::JwksResolver,
+    ) -> Self {
+        self.jwks_resolver = resolver;
+        self
+    }
+
+    /// Set the environment, version and configuration returned by `opa.runtime`
+    #[must_use]
+    pub fn with_runtime_info(mut self, info: crate::builtins::impls::opa::RuntimeInfo) -> Self {
+        self.runtime_info = info;
+        self
+    }
+
+    /// Set the annotations returned by the `rego.metadata.*` builtins
+    #[must_use]
+    pub fn with_rego_metadata(
+        mut self,
+        metadata: crate::builtins::impls::rego::RegoMetadata,
+    ) -> Self {
+        self.rego_metadata = metadata;
+        self
+    }
+
+    /// Use the given cache of compiled regular expressions. The same cache can be
+    /// shared by multiple contexts.
+    #[cfg(feature = "regex-builtins")]
+    #[must_use]
+    pub fn with_regex_cache(mut self, cache: crate::builtins::impls::regex::RegexCache) -> Self {
+        self.regex_cache = cache;
+        self
+    }
+
+    /// Use the given cache of compiled globs. The same cache can be shared by
+    /// multiple contexts.
+    #[cfg(feature = "glob-builtins")]
+    #[must_use]
+    pub fn with_glob_cache(mut self, cache: crate::builtins::impls::glob::GlobCache) -> Self {
+        self.glob_cache = cache;
+        self
+    }
+
+    /// Restrict the builtins evaluations with this context can call, on top
+    /// of the capabilities of the runtime
+    #[must_use]
+    pub fn with_capabilities(mut self, capabilities: crate::Capabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+}
+
 impl EvaluationContext for DefaultContext {
     #[cfg(feature = "rng")]
-    type Rng = rand::rngs::ThreadRng;
+    type Rng = rand::rngs::StdRng;
 
     #[cfg(feature = "rng")]
     fn get_rng(&mut self) -> Self::Rng {
-        rand::thread_rng()
+        use rand::{Rng, SeedableRng};
+
+        match &mut self.rng {
+            // Derive a new generator from the seeded one, so that successive
+            // calls return different values
+            Some(rng) => rand::rngs::StdRng::seed_from_u64(rng.gen()),
+            None => rand::rngs::StdRng::from_entropy(),
+        }
     }
 
     #[cfg(feature = "time")]
@@ -87,14 +425,102 @@ impl EvaluationContext for DefaultContext {
         self.evaluation_time
     }
 
+    #[cfg(feature = "time")]
+    fn set_now(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        self.evaluation_time = now;
+    }
+
+    #[cfg(feature = "http-builtins")]
+    fn http_cache(&self) -> Option<&crate::builtins::impls::http::InterQueryCache> {
+        Some(&self.http_cache)
+    }
+
+    #[cfg(feature = "http-builtins")]
+    fn http_intra_query_cache(&self) -> Option<&crate::builtins::impls::http::IntraQueryCache> {
+        Some(&self.http_intra_query_cache)
+    }
+
+    #[cfg(feature = "http-builtins")]
+    fn http_egress_policy(&self) -> Option<&crate::builtins::impls::http::EgressPolicy> {
+        Some(&self.http_egress_policy)
+    }
+
+    #[cfg(feature = "http-builtins")]
+    fn http_config(&self) -> Option<&crate::builtins::impls::http::HttpConfig> {
+        Some(&self.http_config)
+    }
+
+    #[cfg(feature = "http-builtins")]
+    fn http_client_pool(&self) -> Option<&crate::builtins::impls::http::ClientPool> {
+        Some(&self.http_client_pool)
+    }
+
+    #[cfg(feature = "http-builtins")]
+    fn http_prefetch(&self) -> Option<&crate::builtins::impls::http::HttpPrefetch> {
+        Some(&self.http_prefetch)
+    }
+
+    #[cfg(feature = "net-builtins")]
+    fn net_config(&self) -> Option<&crate::builtins::impls::net::NetConfig> {
+        Some(&self.net_config)
+    }
+
+    #[cfg(feature = "net-builtins")]
+    fn net_cidr_trie_cache(&self) -> Option<&crate::builtins::impls::net::CidrTrieCache> {
+        Some(&self.net_cidr_trie_cache)
+    }
+
+    #[cfg(feature = "jwt-jwks")]
+    fn jwks_resolver(&self) -> Option<&crate::builtins::impls::io::jwt::jwks::JwksResolver> {
+        Some(&self.jwks_resolver)
+    }
+
+    fn runtime_info(&self) -> Option<&crate::builtins::impls::opa::RuntimeInfo> {
+        Some(&self.runtime_info)
+    }
+
+    fn rego_metadata(&self) -> Option<&crate::builtins::impls::rego::RegoMetadata> {
+        Some(&self.rego_metadata)
+    }
+
+    #[cfg(feature = "regex-builtins")]
+    fn regex_cache(&self) -> Option<&crate::builtins::impls::regex::RegexCache> {
+        Some(&self.regex_cache)
+    }
+
+    #[cfg(feature = "glob-builtins")]
+    fn glob_cache(&self) -> Option<&crate::builtins::impls::glob::GlobCache> {
+        Some(&self.glob_cache)
+    }
+
+    fn capabilities(&self) -> Option<&crate::Capabilities> {
+        self.capabilities.as_ref()
+    }
+
     fn evaluation_start(&mut self) {
         // Clear the cache
         self.cache = HashMap::new();
 
+        #[cfg(feature = "rng")]
+        {
+            // Restart the seeded random number generator
+            self.rng = self.rng_seed.map(rand::SeedableRng::seed_from_u64);
+        }
+
+        #[cfg(feature = "http-builtins")]
+        {
+            self.http_intra_query_cache = crate::builtins::impls::http::IntraQueryCache::default();
+        }
+
+        #[cfg(feature = "net-builtins")]
+        {
+            self.net_cidr_trie_cache = crate::builtins::impls::net::CidrTrieCache::default();
+        }
+
         #[cfg(feature = "time")]
         {
-            // Set the evaluation time to now
-            self.evaluation_time = chrono::Utc::now();
+            // Set the evaluation time from the clock
+            self.evaluation_time = self.clock.now();
         }
     }
 
@@ -131,6 +557,9 @@ pub mod tests {
         #[cfg(feature = "time")]
         clock: chrono::DateTime<chrono::Utc>,
 
+        #[cfg(feature = "time")]
+        evaluation_time: chrono::DateTime<chrono::Utc>,
+
         #[cfg(feature = "rng")]
         seed: u64,
     }
@@ -138,15 +567,21 @@ pub mod tests {
     #[allow(clippy::derivable_impls)]
     impl Default for TestContext {
         fn default() -> Self {
+            #[cfg(feature = "time")]
+            let clock = chrono::Utc
+                // Corresponds to 2020-07-14T12:53:22Z
+                // We're using this method because it's available on old versions of chrono
+                .timestamp_opt(1_594_731_202, 0)
+                .unwrap();
+
             Self {
                 inner: DefaultContext::default(),
 
                 #[cfg(feature = "time")]
-                clock: chrono::Utc
-                    // Corresponds to 2020-07-14T12:53:22Z
-                    // We're using this method because it's available on old versions of chrono
-                    .timestamp_opt(1_594_731_202, 0)
-                    .unwrap(),
+                clock,
+
+                #[cfg(feature = "time")]
+                evaluation_time: clock,
 
                 #[cfg(feature = "rng")]
                 seed: 0,
@@ -160,11 +595,90 @@ pub mod tests {
 
         fn evaluation_start(&mut self) {
             self.inner.evaluation_start();
+
+            #[cfg(feature = "time")]
+            {
+                // Restore the fixed time, in case the previous evaluation
+                // replayed another one
+                self.evaluation_time = self.clock;
+            }
+        }
+
+        #[cfg(feature = "http-builtins")]
+        fn http_cache(&self) -> Option<&crate::builtins::impls::http::InterQueryCache> {
+            self.inner.http_cache()
+        }
+
+        #[cfg(feature = "http-builtins")]
+        fn http_intra_query_cache(&self) -> Option<&crate::builtins::impls::http::IntraQueryCache> {
+            self.inner.http_intra_query_cache()
+        }
+
+        #[cfg(feature = "http-builtins")]
+        fn http_egress_policy(&self) -> Option<&crate::builtins::impls::http::EgressPolicy> {
+            self.inner.http_egress_policy()
+        }
+
+        #[cfg(feature = "http-builtins")]
+        fn http_config(&self) -> Option<&crate::builtins::impls::http::HttpConfig> {
+            self.inner.http_config()
+        }
+
+        #[cfg(feature = "http-builtins")]
+        fn http_client_pool(&self) -> Option<&crate::builtins::impls::http::ClientPool> {
+            self.inner.http_client_pool()
+        }
+
+        #[cfg(feature = "http-builtins")]
+        fn http_prefetch(&self) -> Option<&crate::builtins::impls::http::HttpPrefetch> {
+            self.inner.http_prefetch()
+        }
+
+        #[cfg(feature = "net-builtins")]
+        fn net_config(&self) -> Option<&crate::builtins::impls::net::NetConfig> {
+            self.inner.net_config()
+        }
+
+        #[cfg(feature = "net-builtins")]
+        fn net_cidr_trie_cache(&self) -> Option<&crate::builtins::impls::net::CidrTrieCache> {
+            self.inner.net_cidr_trie_cache()
+        }
+
+        #[cfg(feature = "jwt-jwks")]
+        fn jwks_resolver(&self) -> Option<&crate::builtins::impls::io::jwt::jwks::JwksResolver> {
+            self.inner.jwks_resolver()
+        }
+
+        fn runtime_info(&self) -> Option<&crate::builtins::impls::opa::RuntimeInfo> {
+            self.inner.runtime_info()
+        }
+
+        fn rego_metadata(&self) -> Option<&crate::builtins::impls::rego::RegoMetadata> {
+            self.inner.rego_metadata()
+        }
+
+        #[cfg(feature = "regex-builtins")]
+        fn regex_cache(&self) -> Option<&crate::builtins::impls::regex::RegexCache> {
+            self.inner.regex_cache()
+        }
+
+        #[cfg(feature = "glob-builtins")]
+        fn glob_cache(&self) -> Option<&crate::builtins::impls::glob::GlobCache> {
+            self.inner.glob_cache()
+        }
+
+        fn capabilities(&self) -> Option<&crate::Capabilities> {
+            self.inner.capabilities()
         }
 
         #[cfg(feature = "time")]
         fn now(&self) -> chrono::DateTime<chrono::Utc> {
-            self.clock
+            self.evaluation_time
+        }
+
+        #[cfg(feature = "time")]
+        fn set_now(&mut self, now: chrono::DateTime<chrono::Utc>) {
+            self.evaluation_time = now;
         }
 
         #[cfg(feature = "rng")]
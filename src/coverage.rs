@@ -0,0 +1,158 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Coverage of the policy entrypoints by evaluations
+//!
+//! OPA's WASM target does not expose which rules or expressions were
+//! evaluated, so coverage is tracked at the entrypoint level: an entrypoint
+//! is covered once an evaluation of it produced a value.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// How many times an entrypoint was evaluated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntrypointCoverage {
+    /// Number of successful evaluations
+    pub evaluations: u64,
+
+    /// Number of evaluations which produced a value
+    pub defined: u64,
+}
+
+impl EntrypointCoverage {
+    /// Whether an evaluation of the entrypoint produced a value
+    #[must_use]
+    pub fn is_covered(&self) -> bool {
+        self.defined > 0
+    }
+}
+
+/// A coverage report, serialized with the same top-level `coverage`
+/// percentage as `opa test --coverage` reports
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoverageReport {
+    /// The coverage of each entrypoint
+    pub entrypoints: BTreeMap<String, EntrypointCoverage>,
+
+    /// Number of covered entrypoints
+    pub covered: usize,
+
+    /// Number of entrypoints which were never covered
+    pub not_covered: usize,
+
+    /// Percentage of covered entrypoints
+    pub coverage: f64,
+}
+
+/// Collects the coverage of the entrypoints of the policies it is set on
+/// with [`RuntimeBuilder::coverage`](crate::RuntimeBuilder::coverage).
+///
+/// This is cheap to clone and clones share the same counters, so one
+/// collector can be shared by multiple runtimes, e.g. in a test suite.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    entrypoints: Arc<Mutex<BTreeMap<String, EntrypointCoverage>>>,
+}
+
+impl Coverage {
+    /// Create an empty coverage collector
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entrypoints(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, EntrypointCoverage>> {
+        self.entrypoints
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Track entrypoints, so that they are reported even if never evaluated
+    pub(crate) fn register<'a>(&self, entrypoints: impl IntoIterator<Item = &'a str>) {
+        let mut tracked = self.entrypoints();
+        for entrypoint in entrypoints {
+            tracked.entry(entrypoint.to_owned()).or_default();
+        }
+    }
+
+    /// Record a successful evaluation of an entrypoint
+    pub(crate) fn record(&self, entrypoint: &str, result: &serde_json::Value) {
+        // Evaluations return a result set, which is empty when undefined
+        let defined = result
+            .as_array()
+            .map_or(true, |results| !results.is_empty());
+
+        let mut tracked = self.entrypoints();
+        let coverage = tracked.entry(entrypoint.to_owned()).or_default();
+        coverage.evaluations += 1;
+        if defined {
+            coverage.defined += 1;
+        }
+    }
+
+    /// Build a report of the coverage so far
+    #[must_use]
+    pub fn report(&self) -> CoverageReport {
+        let entrypoints = self.entrypoints().clone();
+        let covered = entrypoints.values().filter(|c| c.is_covered()).count();
+        let not_covered = entrypoints.len() - covered;
+        #[allow(clippy::cast_precision_loss)]
+        let coverage = if entrypoints.is_empty() {
+            0.0
+        } else {
+            covered as f64 * 100.0 / entrypoints.len() as f64
+        };
+
+        CoverageReport {
+            entrypoints,
+            covered,
+            not_covered,
+            coverage,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn report() {
+        let coverage = Coverage::new();
+        coverage.register(["example/allow", "example/deny", "example/reason"]);
+        coverage.record("example/allow", &json!([{"result": true}]));
+        coverage.record("example/allow", &json!([]));
+        coverage.record("example/deny", &json!([]));
+
+        let report = coverage.report();
+        assert_eq!(report.covered, 1);
+        assert_eq!(report.not_covered, 2);
+        assert!((report.coverage - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(
+            report.entrypoints["example/allow"],
+            EntrypointCoverage {
+                evaluations: 2,
+                defined: 1
+            }
+        );
+        assert_eq!(report.entrypoints["example/reason"].evaluations, 0);
+    }
+}
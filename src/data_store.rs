@@ -0,0 +1,444 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Storage of the `data` document outside of the policy memory
+//!
+//! Large data sets don't have to be loaded entirely in the linear memory of
+//! the policy. A [`LazyData`] loads the subtrees each entrypoint reads from a
+//! [`DataStore`] right before it is evaluated, and keeps track of what was
+//! already loaded.
+
+#![allow(clippy::module_name_repetitions)]
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+use wasmtime::AsContextMut;
+
+use crate::{Error, Policy};
+
+#[cfg(feature = "data-store-http")]
+mod http_store;
+#[cfg(feature = "data-store-redis")]
+mod redis_store;
+
+#[cfg(feature = "data-store-http")]
+pub use self::http_store::HttpDataStore;
+#[cfg(feature = "data-store-redis")]
+pub use self::redis_store::RedisDataStore;
+
+/// Storage of the `data` document, read and written one path at a time
+pub trait DataStore: Send + Sync {
+    /// Get the value at the given path, or [`None`] if there is none
+    ///
+    /// # Errors
+    ///
+    /// If the value could not be fetched
+    fn get<'a>(
+        &'a self,
+        path: &'a [String],
+    ) -> Pin<Box<dyn Future<Output = Result<Option<Value>>> + Send + 'a>>;
+
+    /// Set the value at the given path
+    ///
+    /// # Errors
+    ///
+    /// If the value could not be stored
+    fn set<'a>(
+        &'a self,
+        path: &'a [String],
+        value: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+    /// Apply a JSON Merge Patch (RFC 7396) to the value at the given path
+    ///
+    /// # Errors
+    ///
+    /// If the patch could not be applied
+    fn patch<'a>(
+        &'a self,
+        path: &'a [String],
+        changes: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+}
+
+/// A [`DataStore`] keeping the whole document in the host memory.
+///
+/// Cloning it is cheap, and clones share the same document.
+#[derive(Debug, Clone)]
+pub struct MemoryDataStore {
+    document: Arc<RwLock<Value>>,
+}
+
+impl Default for MemoryDataStore {
+    fn default() -> Self {
+        Self::new(Value::Object(serde_json::Map::new()))
+    }
+}
+
+impl MemoryDataStore {
+    /// Create a store holding the given document
+    #[must_use]
+    pub fn new(document: Value) -> Self {
+        Self {
+            document: Arc::new(RwLock::new(document)),
+        }
+    }
+
+    fn get_sync(&self, path: &[String]) -> Option<Value> {
+        let document = self.document.read().unwrap_or_else(PoisonError::into_inner);
+        path.iter()
+            .try_fold(&*document, |value, key| value.get(key))
+            .cloned()
+    }
+
+    fn update(&self, path: &[String], update: impl FnOnce(&mut Value)) -> Result<()> {
+        let mut document = self
+            .document
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        update(entry(&mut document, path)?);
+        Ok(())
+    }
+}
+
+impl DataStore for MemoryDataStore {
+    fn get<'a>(
+        &'a self,
+        path: &'a [String],
+    ) -> Pin<Box<dyn Future<Output = Result<Option<Value>>> + Send + 'a>> {
+        let value = self.get_sync(path);
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn set<'a>(
+        &'a self,
+        path: &'a [String],
+        value: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        let res = self.update(path, |target| *target = value);
+        Box::pin(async move { res })
+    }
+
+    fn patch<'a>(
+        &'a self,
+        path: &'a [String],
+        changes: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        let res = self.update(path, |target| merge(target, changes));
+        Box::pin(async move { res })
+    }
+}
+
+/// Get the value at the given path, creating the missing objects on the way
+fn entry<'a>(document: &'a mut Value, path: &[String]) -> Result<&'a mut Value> {
+    let mut value = document;
+    for key in path {
+        if value.is_null() {
+            *value = Value::Object(serde_json::Map::new());
+        }
+        let Value::Object(object) = value else {
+            bail!("can't set {key:?} in a value which is not an object");
+        };
+        value = object.entry(key.clone()).or_insert(Value::Null);
+    }
+    Ok(value)
+}
+
+/// Apply a JSON Merge Patch (RFC 7396) to a value
+pub(crate) fn merge(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(serde_json::Map::new());
+    }
+    if let Value::Object(object) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                object.remove(&key);
+            } else {
+                merge(object.entry(key).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// A segment of a path declared with [`LazyData::with_paths`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// A literal key
+    Key(String),
+    /// A key taken from the input, from a `{input.some.field}` segment
+    Input(Vec<String>),
+}
+
+impl Segment {
+    fn parse(segment: &str) -> Self {
+        let field = segment
+            .strip_prefix("{input")
+            .and_then(|rest| rest.strip_suffix('}'));
+        match field.map(|field| (field, field.strip_prefix('.'))) {
+            Some(("", _)) => Self::Input(Vec::new()),
+            Some((_, Some(field))) => {
+                Self::Input(field.split('.').map(ToOwned::to_owned).collect())
+            }
+            _ => Self::Key(segment.to_owned()),
+        }
+    }
+
+    /// Resolve the segment against the input, returning [`None`] if the
+    /// input does not have a string or number at that location
+    fn resolve(&self, input: &Value) -> Option<String> {
+        match self {
+            Self::Key(key) => Some(key.clone()),
+            Self::Input(path) => match path.iter().try_fold(input, |value, key| value.get(key))? {
+                Value::String(key) => Some(key.clone()),
+                Value::Number(key) => Some(key.to_string()),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Loads the subtrees of the `data` document each entrypoint reads from a
+/// [`DataStore`], as they are needed.
+///
+/// Paths are declared per entrypoint with [`LazyData::with_paths`], and
+/// loaded by [`LazyData::load`] before evaluating the entrypoint, unless they
+/// or one of their parents were already loaded.
+pub struct LazyData {
+    store: Arc<dyn DataStore>,
+    paths: HashMap<String, Vec<Vec<Segment>>>,
+    loaded: HashSet<Vec<String>>,
+}
+
+impl std::fmt::Debug for LazyData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazyData")
+            .field("paths", &self.paths)
+            .field("loaded", &self.loaded)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LazyData {
+    /// Load the data from the given store
+    #[must_use]
+    pub fn new(store: impl DataStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            paths: HashMap::new(),
+            loaded: HashSet::new(),
+        }
+    }
+
+    /// Declare the paths the entrypoint reads, as slash-separated keys.
+    ///
+    /// A `{input.some.field}` segment is replaced by the value of that field
+    /// of the input, e.g. `users/{input.user}` only loads the data of the user
+    /// the policy is evaluated for.
+    #[must_use]
+    pub fn with_paths<'a>(
+        mut self,
+        entrypoint: &str,
+        paths: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let paths = paths.into_iter().map(|path| {
+            path.split('/')
+                .filter(|s| !s.is_empty())
+                .map(Segment::parse)
+                .collect()
+        });
+        self.paths
+            .entry(entrypoint.to_owned())
+            .or_default()
+            .extend(paths);
+        self
+    }
+
+    /// Whether the given path, or one of its parents, was loaded
+    fn is_loaded(&self, path: &[String]) -> bool {
+        (0..=path.len()).any(|len| self.loaded.contains(&path[..len]))
+    }
+
+    /// Load the paths the entrypoint reads for this input in the policy, if
+    /// they were not loaded yet. Paths missing from the store are left
+    /// undefined.
+    ///
+    /// # Errors
+    ///
+    /// If a value could not be fetched from the store or set in the policy
+    pub async fn load<C, T: Send>(
+        &mut self,
+        policy: &mut Policy<C>,
+        mut store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &Value,
+    ) -> Result<(), Error> {
+        let paths: Vec<Vec<String>> = self
+            .paths
+            .get(entrypoint)
+            .into_iter()
+            .flatten()
+            .filter_map(|path| path.iter().map(|s| s.resolve(input)).collect())
+            .collect();
+
+        for path in paths {
+            if self.is_loaded(&path) {
+                continue;
+            }
+            if let Some(value) = self.store.get(&path).await? {
+                let keys: Vec<&str> = path.iter().map(String::as_str).collect();
+                policy.set_data_path(&mut store, &keys, &value).await?;
+            }
+            self.loaded.insert(path);
+        }
+        Ok(())
+    }
+
+    /// Set the value at the given path in the store, and in the policy if
+    /// that path was loaded
+    ///
+    /// # Errors
+    ///
+    /// If the value could not be stored or set in the policy
+    pub async fn set<C, T: Send>(
+        &mut self,
+        policy: &mut Policy<C>,
+        store: impl AsContextMut<Data = T>,
+        path: &[&str],
+        value: Value,
+    ) -> Result<(), Error> {
+        let owned: Vec<String> = path.iter().map(ToString::to_string).collect();
+        self.store.set(&owned, value.clone()).await?;
+        if self.is_loaded(&owned) {
+            policy.set_data_path(store, path, &value).await?;
+        }
+        Ok(())
+    }
+
+    /// Apply a JSON Merge Patch (RFC 7396) to the value at the given path in
+    /// the store, and reload it in the policy if that path was loaded
+    ///
+    /// # Errors
+    ///
+    /// If the patch could not be applied, or the patched value could not be
+    /// set in the policy
+    pub async fn patch<C, T: Send>(
+        &mut self,
+        policy: &mut Policy<C>,
+        store: impl AsContextMut<Data = T>,
+        path: &[&str],
+        changes: Value,
+    ) -> Result<(), Error> {
+        let owned: Vec<String> = path.iter().map(ToString::to_string).collect();
+        self.store.patch(&owned, changes).await?;
+        if self.is_loaded(&owned) {
+            let value = self.store.get(&owned).await?.unwrap_or(Value::Null);
+            policy.set_data_path(store, path, &value).await?;
+        }
+        Ok(())
+    }
+
+    /// Forget that the given path and its children were loaded, so that they
+    /// are fetched again the next time an entrypoint reads them
+    pub fn invalidate(&mut self, path: &[&str]) {
+        self.loaded.retain(|loaded| {
+            loaded.len() < path.len() || loaded.iter().zip(path).any(|(a, b)| a != b)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn path(path: &[&str]) -> Vec<String> {
+        path.iter().map(ToString::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn memory_store() {
+        let store = MemoryDataStore::new(json!({"users": {"alice": {"admin": true}}}));
+        assert_eq!(
+            store.get(&path(&["users", "alice"])).await.unwrap(),
+            Some(json!({"admin": true}))
+        );
+        assert_eq!(store.get(&path(&["users", "bob"])).await.unwrap(), None);
+
+        store
+            .set(&path(&["users", "bob", "admin"]), json!(false))
+            .await
+            .unwrap();
+        store
+            .patch(
+                &path(&["users", "alice"]),
+                json!({"admin": null, "roles": ["dev"]}),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get(&[]).await.unwrap(),
+            Some(json!({"users": {"alice": {"roles": ["dev"]}, "bob": {"admin": false}}}))
+        );
+
+        assert!(store
+            .set(&path(&["users", "bob", "admin", "nested"]), json!(1))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn path_templates() {
+        let lazy = LazyData::new(MemoryDataStore::default()).with_paths(
+            "authz/allow",
+            ["users/{input.user.id}", "/roles/", "{input}"],
+        );
+        let paths = &lazy.paths["authz/allow"];
+        assert_eq!(
+            paths[0],
+            [
+                Segment::Key("users".to_owned()),
+                Segment::Input(path(&["user", "id"]))
+            ]
+        );
+        assert_eq!(paths[1], [Segment::Key("roles".to_owned())]);
+        assert_eq!(paths[2], [Segment::Input(Vec::new())]);
+
+        let input = json!({"user": {"id": 42}});
+        assert_eq!(paths[0][1].resolve(&input).as_deref(), Some("42"));
+        assert_eq!(paths[2][0].resolve(&input), None);
+    }
+
+    #[test]
+    fn loaded_paths() {
+        let mut lazy = LazyData::new(MemoryDataStore::default());
+        lazy.loaded.insert(path(&["users", "alice"]));
+        assert!(lazy.is_loaded(&path(&["users", "alice", "roles"])));
+        assert!(!lazy.is_loaded(&path(&["users"])));
+
+        lazy.invalidate(&["users"]);
+        assert!(!lazy.is_loaded(&path(&["users", "alice"])));
+    }
+}
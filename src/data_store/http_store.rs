@@ -0,0 +1,137 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`DataStore`] backed by an HTTP data service
+
+use std::{future::Future, pin::Pin};
+
+use anyhow::{bail, Context, Result};
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode, Url};
+use serde_json::Value;
+
+use super::DataStore;
+
+/// A [`DataStore`] backed by an HTTP data service.
+///
+/// The value at a path is read with a `GET` request on that path, relative to
+/// the base URL, written with a `PUT` request, and patched with a `PATCH`
+/// request with a JSON Merge Patch body. A `404` response means there is no
+/// value at that path.
+#[derive(Debug, Clone)]
+pub struct HttpDataStore {
+    client: Client,
+    base_url: Url,
+}
+
+impl HttpDataStore {
+    /// Use the data service at the given base URL
+    #[must_use]
+    pub fn new(base_url: Url) -> Self {
+        Self {
+            client: Client::new(),
+            base_url,
+        }
+    }
+
+    /// Send the requests with the given client, e.g. to set default
+    /// authentication headers
+    #[must_use]
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    fn url(&self, path: &[String]) -> Result<Url> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .ok()
+            .context("the data service URL can't be a base")?
+            .pop_if_empty()
+            .extend(path);
+        Ok(url)
+    }
+}
+
+impl DataStore for HttpDataStore {
+    fn get<'a>(
+        &'a self,
+        path: &'a [String],
+    ) -> Pin<Box<dyn Future<Output = Result<Option<Value>>> + Send + 'a>> {
+        Box::pin(async move {
+            let response = self.client.get(self.url(path)?).send().await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let value = response.error_for_status()?.json().await?;
+            Ok(Some(value))
+        })
+    }
+
+    fn set<'a>(
+        &'a self,
+        path: &'a [String],
+        value: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            self.client
+                .put(self.url(path)?)
+                .json(&value)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+
+    fn patch<'a>(
+        &'a self,
+        path: &'a [String],
+        changes: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .patch(self.url(path)?)
+                .header(CONTENT_TYPE, "application/merge-patch+json")
+                .body(serde_json::to_vec(&changes)?)
+                .send()
+                .await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                bail!("no value to patch at {path:?}");
+            }
+            response.error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        let path = ["users".to_owned(), "alice/bob".to_owned()];
+        let store = HttpDataStore::new(Url::parse("https://data.example.com/v1/").unwrap());
+        assert_eq!(
+            store.url(&path).unwrap().as_str(),
+            "https://data.example.com/v1/users/alice%2Fbob"
+        );
+        let store = HttpDataStore::new(Url::parse("https://data.example.com").unwrap());
+        assert_eq!(
+            store.url(&[]).unwrap().as_str(),
+            "https://data.example.com/"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`DataStore`] backed by Redis
+
+use std::{future::Future, pin::Pin};
+
+use anyhow::Result;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+use serde_json::Value;
+
+use super::{merge, DataStore};
+
+/// A [`DataStore`] backed by Redis.
+///
+/// Each path is stored as a separate key holding the JSON value, so values
+/// have to be read at the paths they were written at. Patches are applied by
+/// reading the value, merging and writing it back, which is not atomic.
+#[derive(Clone)]
+pub struct RedisDataStore {
+    connection: MultiplexedConnection,
+    prefix: String,
+}
+
+impl std::fmt::Debug for RedisDataStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisDataStore")
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisDataStore {
+    /// Connect to the Redis server at the given URL, e.g.
+    /// `redis://127.0.0.1/`
+    ///
+    /// # Errors
+    ///
+    /// If the URL is invalid or the connection failed
+    pub async fn connect(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_multiplexed_tokio_connection().await?;
+        Ok(Self {
+            connection,
+            prefix: String::new(),
+        })
+    }
+
+    /// Prefix the keys with the given string, e.g. to share the database with
+    /// other applications
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn key(&self, path: &[String]) -> String {
+        format!("{}{}", self.prefix, path.join("/"))
+    }
+
+    async fn get_value(&self, path: &[String]) -> Result<Option<Value>> {
+        let mut connection = self.connection.clone();
+        let value: Option<String> = connection.get(self.key(path)).await?;
+        Ok(value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()?)
+    }
+
+    async fn set_value(&self, path: &[String], value: &Value) -> Result<()> {
+        let mut connection = self.connection.clone();
+        connection
+            .set::<_, _, ()>(self.key(path), serde_json::to_string(value)?)
+            .await?;
+        Ok(())
+    }
+}
+
+impl DataStore for RedisDataStore {
+    fn get<'a>(
+        &'a self,
+        path: &'a [String],
+    ) -> Pin<Box<dyn Future<Output = Result<Option<Value>>> + Send + 'a>> {
+        Box::pin(self.get_value(path))
+    }
+
+    fn set<'a>(
+        &'a self,
+        path: &'a [String],
+        value: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move { self.set_value(path, &value).await })
+    }
+
+    fn patch<'a>(
+        &'a self,
+        path: &'a [String],
+        changes: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut value = self.get_value(path).await?.unwrap_or(Value::Null);
+            merge(&mut value, changes);
+            self.set_value(path, &value).await
+        })
+    }
+}
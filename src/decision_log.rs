@@ -0,0 +1,269 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structured logging of policy decisions
+//!
+//! When a [`DecisionLogger`] is set on a runtime with
+//! [`RuntimeBuilder::decision_logger`](crate::RuntimeBuilder::decision_logger),
+//! each evaluation emits one [`DecisionLog`] to a [`DecisionLogSink`].
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A builtin call which failed during an evaluation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuiltinError {
+    /// The name of the builtin
+    pub name: String,
+
+    /// Why the builtin failed
+    pub message: String,
+}
+
+/// A record of a single policy evaluation, using the same fields as OPA's
+/// decision logs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DecisionLog {
+    /// A unique identifier for this decision
+    pub decision_id: String,
+
+    /// The labels of the [`DecisionLogger`] which emitted this decision
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+
+    /// The entrypoint which was evaluated
+    pub path: String,
+
+    /// The input of the evaluation, when [`InputMode::Full`] was set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<serde_json::Value>,
+
+    /// The hex-encoded SHA-256 hash of the JSON input, when
+    /// [`InputMode::Hash`] was set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_sha256: Option<String>,
+
+    /// The result of the evaluation, if it succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+
+    /// Why the evaluation failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// The builtin calls which failed during the evaluation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub builtin_errors: Vec<BuiltinError>,
+
+    /// Evaluation metrics, like `timer_rego_query_eval_ns`
+    #[serde(default)]
+    pub metrics: BTreeMap<String, u64>,
+
+    /// When the evaluation happened
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Receives the decisions emitted by a [`DecisionLogger`].
+///
+/// Sinks are called synchronously on the evaluation path, so they should
+/// not block, e.g. by buffering the decisions and shipping them in the
+/// background.
+pub trait DecisionLogSink: Send + Sync {
+    /// Called once per evaluation
+    fn log(&self, decision: DecisionLog);
+}
+
+impl<T: DecisionLogSink + ?Sized> DecisionLogSink for Arc<T> {
+    fn log(&self, decision: DecisionLog) {
+        T::log(self, decision);
+    }
+}
+
+impl<F: Fn(DecisionLog) + Send + Sync> DecisionLogSink for F {
+    fn log(&self, decision: DecisionLog) {
+        self(decision);
+    }
+}
+
+/// How the input of an evaluation is recorded in its [`DecisionLog`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InputMode {
+    /// Record the whole input
+    #[default]
+    Full,
+
+    /// Only record a SHA-256 hash of the input, e.g. when it contains
+    /// sensitive data
+    Hash,
+
+    /// Don't record the input
+    Omit,
+}
+
+/// Emits a [`DecisionLog`] for each evaluation of a policy.
+///
+/// This is cheap to clone, and clones share the same sink.
+#[derive(Clone)]
+pub struct DecisionLogger {
+    sink: Arc<dyn DecisionLogSink>,
+    input_mode: InputMode,
+    labels: BTreeMap<String, String>,
+}
+
+impl std::fmt::Debug for DecisionLogger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DecisionLogger")
+            .field("input_mode", &self.input_mode)
+            .field("labels", &self.labels)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DecisionLogger {
+    /// Send the decisions to the given sink
+    #[must_use]
+    pub fn new(sink: impl DecisionLogSink + 'static) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            input_mode: InputMode::default(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// Set how the input is recorded in the decisions
+    #[must_use]
+    pub fn with_input_mode(mut self, mode: InputMode) -> Self {
+        self.input_mode = mode;
+        self
+    }
+
+    /// Add a label to every decision, e.g. to identify the instance which
+    /// made it
+    #[must_use]
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.insert(key.into(), value.into());
+        self
+    }
+
+    /// Build and emit the decision of an evaluation
+    pub(crate) fn log<V: Serialize>(
+        &self,
+        entrypoint: &str,
+        input: &V,
+        result: Result<&serde_json::Value, &anyhow::Error>,
+        eval_time: Duration,
+        builtin_errors: Vec<BuiltinError>,
+        timestamp: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let (input, input_sha256) = match self.input_mode {
+            InputMode::Full => (Some(serde_json::to_value(input)?), None),
+            InputMode::Hash => {
+                let digest = Sha256::digest(serde_json::to_vec(input)?);
+                (None, Some(hex::encode(digest)))
+            }
+            InputMode::Omit => (None, None),
+        };
+
+        let (result, error) = match result {
+            Ok(result) => (Some(result.clone()), None),
+            Err(e) => (None, Some(format!("{e:#}"))),
+        };
+
+        let eval_ns = u64::try_from(eval_time.as_nanos()).unwrap_or(u64::MAX);
+        let metrics = BTreeMap::from([("timer_rego_query_eval_ns".to_owned(), eval_ns)]);
+
+        let decision_id = uuid::Builder::from_random_bytes(rand::random())
+            .into_uuid()
+            .to_string();
+
+        self.sink.log(DecisionLog {
+            decision_id,
+            labels: self.labels.clone(),
+            path: entrypoint.to_owned(),
+            input,
+            input_sha256,
+            result,
+            error,
+            builtin_errors,
+            metrics,
+            timestamp,
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn logged(logger: impl FnOnce(DecisionLogger) -> DecisionLogger) -> DecisionLog {
+        let decisions = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let decisions = decisions.clone();
+            move |decision| decisions.lock().unwrap().push(decision)
+        };
+
+        logger(DecisionLogger::new(sink))
+            .log(
+                "example/allow",
+                &json!({"user": "alice"}),
+                Ok(&json!([{"result": true}])),
+                Duration::from_millis(1),
+                Vec::new(),
+                DateTime::from_timestamp(0, 0).unwrap(),
+            )
+            .unwrap();
+
+        let mut decisions = decisions.lock().unwrap();
+        assert_eq!(decisions.len(), 1);
+        decisions.pop().unwrap()
+    }
+
+    #[test]
+    fn full_input() {
+        let decision = logged(|logger| logger.with_label("id", "test"));
+        assert_eq!(decision.path, "example/allow");
+        assert_eq!(decision.input, Some(json!({"user": "alice"})));
+        assert_eq!(decision.input_sha256, None);
+        assert_eq!(decision.result, Some(json!([{"result": true}])));
+        assert_eq!(decision.metrics["timer_rego_query_eval_ns"], 1_000_000);
+        assert_eq!(decision.labels["id"], "test");
+
+        let value = serde_json::to_value(&decision).unwrap();
+        assert_eq!(value["timestamp"], "1970-01-01T00:00:00Z");
+        assert!(value.get("error").is_none());
+    }
+
+    #[test]
+    fn hashed_input() {
+        let decision = logged(|logger| logger.with_input_mode(InputMode::Hash));
+        assert_eq!(decision.input, None);
+        assert_eq!(
+            decision.input_sha256.as_deref(),
+            Some("a5cd97f8496e61268797de605913bd8a29ac3af68ec6af1bea67fdb50c2c0ebf"),
+        );
+
+        let decision = logged(|logger| logger.with_input_mode(InputMode::Omit));
+        assert_eq!(decision.input, None);
+        assert_eq!(decision.input_sha256, None);
+    }
+}
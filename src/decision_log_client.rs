@@ -0,0 +1,385 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A decision log sink uploading decisions to a remote OPA decision log
+//! service
+
+use std::{collections::VecDeque, ops::Range, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_compression::tokio::write::GzipEncoder;
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use tokio::{io::AsyncWriteExt, task::JoinHandle};
+
+use crate::{DecisionLog, DecisionLogSink};
+
+/// Default delay between two uploads
+const DEFAULT_UPLOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default maximum size of a single gzipped upload, same as OPA
+const DEFAULT_UPLOAD_SIZE_LIMIT: usize = 32_768;
+
+/// Default maximum number of decisions waiting to be uploaded
+const DEFAULT_BUFFER_SIZE_LIMIT: usize = 10_000;
+
+/// Configuration of a [`DecisionLogClient`]
+#[derive(Debug, Clone)]
+pub struct DecisionLogClientConfig {
+    url: String,
+    upload_interval: Duration,
+    upload_size_limit: usize,
+    buffer_size_limit: usize,
+    bearer_token: Option<String>,
+}
+
+impl DecisionLogClientConfig {
+    /// Upload the decisions to the given URL, usually the `/logs` endpoint of
+    /// the decision log service
+    #[must_use]
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            upload_interval: DEFAULT_UPLOAD_INTERVAL,
+            upload_size_limit: DEFAULT_UPLOAD_SIZE_LIMIT,
+            buffer_size_limit: DEFAULT_BUFFER_SIZE_LIMIT,
+            bearer_token: None,
+        }
+    }
+
+    /// Set the delay between two uploads
+    #[must_use]
+    pub fn with_upload_interval(mut self, interval: Duration) -> Self {
+        self.upload_interval = interval;
+        self
+    }
+
+    /// Set the maximum size in bytes of a single gzipped upload. Buffered
+    /// decisions are split in as many uploads as needed, and a decision
+    /// bigger than this limit on its own is dropped.
+    #[must_use]
+    pub fn with_upload_size_limit(mut self, limit: usize) -> Self {
+        self.upload_size_limit = limit;
+        self
+    }
+
+    /// Set the maximum number of decisions waiting to be uploaded. When the
+    /// buffer is full, the oldest decisions are dropped.
+    #[must_use]
+    pub fn with_buffer_size_limit(mut self, limit: usize) -> Self {
+        self.buffer_size_limit = limit;
+        self
+    }
+
+    /// Authenticate to the decision log service with the given bearer token
+    #[must_use]
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+}
+
+/// State shared between the client handle and its background task
+#[derive(Debug)]
+struct Shared {
+    config: DecisionLogClientConfig,
+    client: reqwest::Client,
+    buffer: std::sync::Mutex<VecDeque<DecisionLog>>,
+
+    /// Makes sure only one upload happens at a time, so that decisions are
+    /// uploaded in order
+    upload: tokio::sync::Mutex<()>,
+}
+
+impl Shared {
+    fn buffer(&self) -> std::sync::MutexGuard<'_, VecDeque<DecisionLog>> {
+        self.buffer
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Add decisions to the buffer, dropping the oldest ones if it is full
+    fn push(&self, decisions: impl IntoIterator<Item = DecisionLog>, front: bool) {
+        let mut buffer = self.buffer();
+        let mut dropped = 0;
+        for decision in decisions {
+            if front {
+                buffer.push_front(decision);
+            } else {
+                buffer.push_back(decision);
+            }
+        }
+
+        while buffer.len() > self.config.buffer_size_limit {
+            buffer.pop_front();
+            dropped += 1;
+        }
+
+        if dropped > 0 {
+            tracing::warn!(dropped, "decision log buffer is full, dropping decisions");
+        }
+    }
+
+    /// Upload all the buffered decisions. Decisions which could not be
+    /// uploaded are put back in the buffer.
+    async fn upload(&self) -> Result<()> {
+        let _guard = self.upload.lock().await;
+        let decisions: Vec<DecisionLog> = self.buffer().drain(..).collect();
+        if decisions.is_empty() {
+            return Ok(());
+        }
+
+        let chunks = match encode_chunks(&decisions, self.config.upload_size_limit).await {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                self.push(decisions.into_iter().rev(), true);
+                return Err(e);
+            }
+        };
+
+        for (range, body) in chunks {
+            if let Err(e) = self.send(body).await {
+                // Put back what was not uploaded yet, before what was logged
+                // in the meantime
+                self.push(decisions[range.start..].iter().rev().cloned(), true);
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send(&self, body: Vec<u8>) -> Result<()> {
+        let mut request = self
+            .client
+            .post(&self.config.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_ENCODING, "gzip")
+            .body(body);
+        if let Some(token) = &self.config.bearer_token {
+            request = request.bearer_auth(token);
+        }
+
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// A [`DecisionLogSink`] buffering the decisions and uploading them
+/// periodically to a remote service implementing OPA's decision log API.
+///
+/// Decisions are uploaded as gzipped JSON arrays, split so that each upload
+/// stays under the configured size limit. Decisions which failed to upload
+/// are retried on the next upload.
+///
+/// The background task is stopped when the client is dropped. Call
+/// [`DecisionLogClient::flush`] before to upload the remaining decisions.
+#[derive(Debug)]
+pub struct DecisionLogClient {
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+impl DecisionLogClient {
+    /// Start uploading decisions in the background. This must be called from
+    /// within a Tokio runtime.
+    #[must_use]
+    pub fn spawn(config: DecisionLogClientConfig) -> Self {
+        let shared = Arc::new(Shared {
+            config,
+            client: reqwest::Client::new(),
+            buffer: std::sync::Mutex::default(),
+            upload: tokio::sync::Mutex::default(),
+        });
+        let task = tokio::spawn(run(shared.clone()));
+        Self { shared, task }
+    }
+
+    /// Number of decisions waiting to be uploaded
+    #[must_use]
+    pub fn pending(&self) -> usize {
+        self.shared.buffer().len()
+    }
+
+    /// Upload the buffered decisions now
+    ///
+    /// # Errors
+    ///
+    /// If an upload failed. The decisions which were not uploaded stay
+    /// buffered.
+    pub async fn flush(&self) -> Result<(), crate::Error> {
+        Ok(self.shared.upload().await?)
+    }
+}
+
+impl DecisionLogSink for DecisionLogClient {
+    fn log(&self, decision: DecisionLog) {
+        self.shared.push([decision], false);
+    }
+}
+
+impl Drop for DecisionLogClient {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[tracing::instrument(skip_all, fields(url = %shared.config.url))]
+async fn run(shared: Arc<Shared>) {
+    let period = shared.config.upload_interval;
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = shared.upload().await {
+            tracing::warn!(error = ?e, "failed to upload decision logs");
+        }
+    }
+}
+
+async fn gzip(decisions: &[DecisionLog]) -> Result<Vec<u8>> {
+    let json = serde_json::to_vec(decisions)?;
+    let mut encoder = GzipEncoder::new(Vec::new());
+    encoder.write_all(&json).await?;
+    encoder.shutdown().await?;
+    Ok(encoder.into_inner())
+}
+
+/// Split the decisions in gzipped JSON arrays of at most `limit` bytes,
+/// returning each chunk along with the range of decisions it contains.
+///
+/// Chunks which are too big are split in half until they fit. Single
+/// decisions which don't fit are dropped.
+async fn encode_chunks(
+    decisions: &[DecisionLog],
+    limit: usize,
+) -> Result<Vec<(Range<usize>, Vec<u8>)>> {
+    let mut chunks = Vec::new();
+    // Ranges of decisions left to encode, starting with all of them
+    let mut pending: Vec<Range<usize>> = Vec::new();
+    pending.push(0..decisions.len());
+
+    while let Some(range) = pending.pop() {
+        let body = gzip(&decisions[range.clone()]).await?;
+        if body.len() <= limit {
+            chunks.push((range, body));
+        } else if range.len() > 1 {
+            let middle = range.start + range.len() / 2;
+            // The first half is processed first to keep the decisions in order
+            pending.push(middle..range.end);
+            pending.push(range.start..middle);
+        } else {
+            tracing::warn!(
+                decision_id = %decisions[range.start].decision_id,
+                size = body.len(),
+                "decision is bigger than the upload size limit, dropping it"
+            );
+        }
+    }
+
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::tokio::bufread::GzipDecoder;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    fn decision(id: usize, padding: usize) -> DecisionLog {
+        DecisionLog {
+            decision_id: id.to_string(),
+            labels: std::collections::BTreeMap::new(),
+            path: "example/allow".to_owned(),
+            input: Some(serde_json::Value::String(
+                // Pseudo-random data from a linear congruential generator,
+                // which does not compress well
+                (0..padding)
+                    .scan(u64::try_from(id).unwrap(), |state, _| {
+                        *state = state
+                            .wrapping_mul(6_364_136_223_846_793_005)
+                            .wrapping_add(1_442_695_040_888_963_407);
+                        Some(char::from(
+                            b'a' + u8::try_from((*state >> 33) % 26).unwrap(),
+                        ))
+                    })
+                    .collect(),
+            )),
+            input_sha256: None,
+            result: Some(serde_json::Value::Bool(true)),
+            error: None,
+            builtin_errors: Vec::new(),
+            metrics: std::collections::BTreeMap::new(),
+            timestamp: chrono::DateTime::from_timestamp(0, 0).unwrap(),
+        }
+    }
+
+    async fn gunzip(body: &[u8]) -> Vec<DecisionLog> {
+        let mut json = Vec::new();
+        GzipDecoder::new(body).read_to_end(&mut json).await.unwrap();
+        serde_json::from_slice(&json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn single_chunk() {
+        let decisions: Vec<_> = (0..10).map(|id| decision(id, 10)).collect();
+        let chunks = encode_chunks(&decisions, DEFAULT_UPLOAD_SIZE_LIMIT)
+            .await
+            .unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].0, 0..10);
+        assert_eq!(gunzip(&chunks[0].1).await, decisions);
+    }
+
+    #[tokio::test]
+    async fn split_chunks() {
+        let decisions: Vec<_> = (0..20).map(|id| decision(id, 500)).collect();
+        let chunks = encode_chunks(&decisions, 2048).await.unwrap();
+        assert!(chunks.len() > 1);
+
+        // Chunks are in order, contiguous, and fit in the limit
+        let mut next = 0;
+        let mut uploaded = Vec::new();
+        for (range, body) in chunks {
+            assert_eq!(range.start, next);
+            assert!(body.len() <= 2048);
+            next = range.end;
+            uploaded.extend(gunzip(&body).await);
+        }
+        assert_eq!(uploaded, decisions);
+    }
+
+    #[tokio::test]
+    async fn oversized_decision() {
+        let decisions = vec![decision(0, 10), decision(1, 10_000), decision(2, 10)];
+        let chunks = encode_chunks(&decisions, 1024).await.unwrap();
+        let ranges: Vec<_> = chunks.into_iter().map(|(range, _)| range).collect();
+        assert_eq!(ranges, vec![0..1, 2..3]);
+    }
+
+    #[tokio::test]
+    async fn buffer_limit() {
+        let client = DecisionLogClient::spawn(
+            DecisionLogClientConfig::new("http://localhost:0/logs")
+                .with_upload_interval(Duration::from_secs(3600))
+                .with_buffer_size_limit(3),
+        );
+        for id in 0..5 {
+            client.log(decision(id, 0));
+        }
+        assert_eq!(client.pending(), 3);
+        assert_eq!(client.shared.buffer()[0].decision_id, "2");
+    }
+}
@@ -0,0 +1,354 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An Envoy `ext_authz` v3 gRPC service backed by a policy, compatible with
+//! the inputs and decisions of `opa-envoy-plugin`
+
+use std::{borrow::Cow, collections::BTreeMap, sync::Arc};
+
+use envoy_types::pb::{
+    envoy::{
+        config::core::v3::{
+            address, socket_address::PortSpecifier, Address, HeaderValue, HeaderValueOption,
+        },
+        r#type::v3::HttpStatus,
+        service::auth::v3::{
+            attribute_context::Peer, authorization_server::Authorization,
+            check_response::HttpResponse, CheckRequest, CheckResponse, DeniedHttpResponse,
+            OkHttpResponse,
+        },
+    },
+    google::rpc,
+};
+use serde_json::{json, Map, Value};
+
+use crate::{EvaluationContext, PolicyPool, ResultSet};
+
+pub use envoy_types::pb::envoy::service::auth::v3::authorization_server::AuthorizationServer;
+
+/// gRPC status codes used in the responses
+const CODE_OK: i32 = 0;
+const CODE_PERMISSION_DENIED: i32 = 7;
+
+fn address_input(address: Option<&Address>) -> Value {
+    let socket = address
+        .and_then(|address| address.address.as_ref())
+        .and_then(|address| match address {
+            address::Address::SocketAddress(socket) => Some(socket),
+            _ => None,
+        });
+
+    match socket {
+        Some(socket) => {
+            let port = match &socket.port_specifier {
+                Some(PortSpecifier::PortValue(port)) => json!(port),
+                _ => Value::Null,
+            };
+            json!({"address": {"socketAddress": {"address": socket.address, "portValue": port}}})
+        }
+        None => json!({}),
+    }
+}
+
+fn peer_input(peer: Option<&Peer>) -> Value {
+    let Some(peer) = peer else {
+        return json!({});
+    };
+
+    let mut value = address_input(peer.address.as_ref());
+    if let Value::Object(object) = &mut value {
+        if !peer.principal.is_empty() {
+            object.insert("principal".to_owned(), json!(peer.principal));
+        }
+    }
+    value
+}
+
+/// Split the path in decoded segments, like `input.parsed_path`
+fn parsed_path(path: &str) -> Vec<String> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    path.trim_start_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            urlencoding::decode(segment).map_or_else(|_| segment.to_owned(), Cow::into_owned)
+        })
+        .collect()
+}
+
+/// Parse the query string, like `input.parsed_query`
+fn parsed_query(path: &str) -> BTreeMap<String, Vec<String>> {
+    let mut parsed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if let Some((_, query)) = path.split_once('?') {
+        let query = query.split('#').next().unwrap_or_default();
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            parsed
+                .entry(key.into_owned())
+                .or_default()
+                .push(value.into_owned());
+        }
+    }
+    parsed
+}
+
+/// Build the policy input from the check request, with the same shape as
+/// `opa-envoy-plugin`
+fn input(request: &CheckRequest) -> Value {
+    let attributes = request.attributes.as_ref();
+    let http = attributes
+        .and_then(|a| a.request.as_ref())
+        .and_then(|r| r.http.as_ref());
+
+    let mut input = json!({
+        "attributes": {
+            "source": peer_input(attributes.and_then(|a| a.source.as_ref())),
+            "destination": peer_input(attributes.and_then(|a| a.destination.as_ref())),
+            "contextExtensions": attributes.map(|a| &a.context_extensions),
+        },
+    });
+
+    if let Some(http) = http {
+        input["attributes"]["request"] = json!({
+            "http": {
+                "id": http.id,
+                "method": http.method,
+                "headers": http.headers,
+                "path": http.path,
+                "host": http.host,
+                "scheme": http.scheme,
+                "protocol": http.protocol,
+                "size": http.size,
+                "body": http.body,
+            },
+        });
+        input["parsed_path"] = json!(parsed_path(&http.path));
+        input["parsed_query"] = json!(parsed_query(&http.path));
+
+        let is_json = http
+            .headers
+            .get("content-type")
+            .is_some_and(|content_type| content_type.starts_with("application/json"));
+        let body = if is_json && !http.body.is_empty() {
+            serde_json::from_str(&http.body).unwrap_or(Value::Null)
+        } else {
+            Value::Null
+        };
+        input["parsed_body"] = body;
+    }
+
+    input
+}
+
+fn headers(value: Option<&Value>) -> Vec<HeaderValueOption> {
+    let Some(Value::Object(headers)) = value else {
+        return Vec::new();
+    };
+
+    headers
+        .iter()
+        .map(|(key, value)| HeaderValueOption {
+            header: Some(HeaderValue {
+                key: key.clone(),
+                value: value
+                    .as_str()
+                    .map_or_else(|| value.to_string(), ToOwned::to_owned),
+                ..HeaderValue::default()
+            }),
+            ..HeaderValueOption::default()
+        })
+        .collect()
+}
+
+/// Build the check response from the policy result, which is either a
+/// boolean, or an object with an `allowed` field and optional `headers`,
+/// `request_headers_to_remove`, `response_headers_to_add`, `http_status` and
+/// `body` fields
+fn response(result: &Value) -> CheckResponse {
+    let empty = Map::new();
+    let (allowed, decision) = match result {
+        Value::Bool(allowed) => (*allowed, &empty),
+        Value::Object(decision) => (
+            decision.get("allowed") == Some(&Value::Bool(true)),
+            decision,
+        ),
+        _ => (false, &empty),
+    };
+
+    if allowed {
+        let headers_to_remove = decision
+            .get("request_headers_to_remove")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(ToOwned::to_owned)
+            .collect();
+
+        CheckResponse {
+            status: Some(rpc::Status {
+                code: CODE_OK,
+                ..rpc::Status::default()
+            }),
+            http_response: Some(HttpResponse::OkResponse(OkHttpResponse {
+                headers: headers(decision.get("headers")),
+                headers_to_remove,
+                response_headers_to_add: headers(decision.get("response_headers_to_add")),
+                ..OkHttpResponse::default()
+            })),
+            ..CheckResponse::default()
+        }
+    } else {
+        let code = decision
+            .get("http_status")
+            .and_then(Value::as_i64)
+            .and_then(|code| i32::try_from(code).ok())
+            .unwrap_or(403);
+        let body = match decision.get("body") {
+            Some(Value::String(body)) => body.clone(),
+            Some(body) => body.to_string(),
+            None => String::new(),
+        };
+
+        CheckResponse {
+            status: Some(rpc::Status {
+                code: CODE_PERMISSION_DENIED,
+                ..rpc::Status::default()
+            }),
+            http_response: Some(HttpResponse::DeniedResponse(DeniedHttpResponse {
+                status: Some(HttpStatus { code }),
+                headers: headers(decision.get("headers")),
+                body,
+            })),
+            ..CheckResponse::default()
+        }
+    }
+}
+
+/// An Envoy external authorization service evaluating a policy entrypoint
+/// for each check request.
+///
+/// Serve it with `tonic`, wrapped in an [`AuthorizationServer`].
+pub struct ExtAuthz<C> {
+    pool: Arc<PolicyPool<C>>,
+    entrypoint: String,
+}
+
+impl<C> std::fmt::Debug for ExtAuthz<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExtAuthz")
+            .field("entrypoint", &self.entrypoint)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C> ExtAuthz<C> {
+    /// Authorize requests with the given entrypoint, evaluated on the
+    /// instances of the pool
+    #[must_use]
+    pub fn new(pool: Arc<PolicyPool<C>>, entrypoint: impl Into<String>) -> Self {
+        Self {
+            pool,
+            entrypoint: entrypoint.into(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<C: EvaluationContext> Authorization for ExtAuthz<C> {
+    async fn check(
+        &self,
+        request: tonic::Request<CheckRequest>,
+    ) -> Result<tonic::Response<CheckResponse>, tonic::Status> {
+        let input = input(request.get_ref());
+        let result = self
+            .pool
+            .evaluate(&self.entrypoint, &input)
+            .await
+            .and_then(|results: ResultSet| results.into_optional().map_err(crate::Error::from))
+            .map_err(|e| {
+                tracing::error!(
+                    error = &e as &dyn std::error::Error,
+                    "failed to evaluate the policy"
+                );
+                tonic::Status::internal("failed to evaluate the policy")
+            })?;
+
+        // An undefined result denies the request
+        let result = result.unwrap_or(Value::Null);
+
+        Ok(tonic::Response::new(response(&result)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use envoy_types::pb::envoy::service::auth::v3::{
+        attribute_context::{HttpRequest, Request},
+        AttributeContext,
+    };
+
+    use super::*;
+
+    #[test]
+    fn check_request_input() {
+        let request = CheckRequest {
+            attributes: Some(AttributeContext {
+                request: Some(Request {
+                    http: Some(HttpRequest {
+                        method: "POST".to_owned(),
+                        path: "/api/items%2Fx/42?tag=a&tag=b".to_owned(),
+                        headers: [("content-type".to_owned(), "application/json".to_owned())]
+                            .into(),
+                        body: r#"{"name": "foo"}"#.to_owned(),
+                        ..HttpRequest::default()
+                    }),
+                    ..Request::default()
+                }),
+                ..AttributeContext::default()
+            }),
+        };
+
+        let input = input(&request);
+        assert_eq!(input["attributes"]["request"]["http"]["method"], "POST");
+        assert_eq!(input["parsed_path"], json!(["api", "items/x", "42"]));
+        assert_eq!(input["parsed_query"], json!({"tag": ["a", "b"]}));
+        assert_eq!(input["parsed_body"], json!({"name": "foo"}));
+    }
+
+    #[test]
+    fn check_response() {
+        let res = response(&json!({
+            "allowed": true,
+            "headers": {"x-user": "alice"},
+            "request_headers_to_remove": ["authorization"],
+        }));
+        assert_eq!(res.status.unwrap().code, CODE_OK);
+        let Some(HttpResponse::OkResponse(ok)) = res.http_response else {
+            panic!("expected an OK response");
+        };
+        assert_eq!(ok.headers[0].header.as_ref().unwrap().key, "x-user");
+        assert_eq!(ok.headers_to_remove, ["authorization"]);
+
+        let res = response(&json!({"allowed": false, "http_status": 401, "body": "nope"}));
+        assert_eq!(res.status.unwrap().code, CODE_PERMISSION_DENIED);
+        let Some(HttpResponse::DeniedResponse(denied)) = res.http_response else {
+            panic!("expected a denied response");
+        };
+        assert_eq!(denied.status.unwrap().code, 401);
+        assert_eq!(denied.body, "nope");
+
+        let res = response(&Value::Null);
+        assert_eq!(res.status.unwrap().code, CODE_PERMISSION_DENIED);
+    }
+}
@@ -0,0 +1,494 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Errors returned by the policy runtime
+
+use crate::types::{AbiFeature, AbiVersion};
+
+/// A specialized [`Result`](std::result::Result) type for this crate
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors returned when loading or evaluating a policy
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The policy module could not be compiled or instantiated
+    #[error("failed to load the policy")]
+    Load(#[source] anyhow::Error),
+
+    /// A builtin called by the policy failed
+    #[error("builtin {name:?} failed{}", describe_call(.location.as_deref(), .arguments))]
+    Builtin {
+        /// The name of the builtin
+        name: String,
+
+        /// A summary of the arguments the builtin was called with, with the
+        /// values looking like secrets redacted
+        arguments: String,
+
+        /// Where the builtin was called from, if known: the evaluated
+        /// entrypoint, and the last note of the `trace` builtin when the
+        /// evaluation is traced
+        location: Option<String>,
+
+        /// Why the builtin failed
+        #[source]
+        source: anyhow::Error,
+    },
+
+    /// The policy aborted its execution, e.g. because of a runtime error in
+    /// the policy like a conflict between rule values
+    #[error("policy aborted: {message}")]
+    PolicyAbort {
+        /// The message given by the policy
+        message: String,
+    },
+
+    /// The policy execution trapped
+    #[error("policy execution trapped")]
+    Trap(#[source] anyhow::Error),
+
+    /// The evaluation was interrupted
+    #[error(transparent)]
+    Cancelled(#[from] EvaluationCancelled),
+
+    /// The evaluation consumed all its fuel
+    #[error(transparent)]
+    OutOfFuel(#[from] OutOfFuel),
+
+    /// The policy needed more memory than allowed
+    #[error(transparent)]
+    MemoryLimitExceeded(#[from] MemoryLimitExceeded),
+
+    /// The policy was built with an ABI version lacking a requested feature
+    #[error(transparent)]
+    UnsupportedAbi(#[from] UnsupportedAbi),
+
+    /// The evaluation result does not have the expected shape, e.g. because
+    /// it is undefined
+    #[error(transparent)]
+    Result(#[from] ResultError),
+
+    /// A `data` document was mounted at a path conflicting with another one
+    #[error(transparent)]
+    DataMountConflict(#[from] DataMountConflict),
+
+    /// The input does not match the schema set for the entrypoint
+    #[error(transparent)]
+    InvalidInput(#[from] InvalidInput),
+
+    /// An I/O error on the host, e.g. while reading a bundle
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// Any other error
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// Wrap an error which happened while loading a policy
+    pub(crate) fn load(e: anyhow::Error) -> Self {
+        Self::classify(e).unwrap_or_else(Self::Load)
+    }
+
+    /// The trap which caused this error, if any
+    #[must_use]
+    pub fn trap(&self) -> Option<&wasmtime::Trap> {
+        match self {
+            Self::Trap(e) => e.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// Find the typed error an [`anyhow::Error`] was created from
+    fn classify(e: anyhow::Error) -> std::result::Result<Self, anyhow::Error> {
+        let e = match e.downcast::<MemoryLimitExceeded>() {
+            Ok(e) => return Ok(e.into()),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<OutOfFuel>() {
+            Ok(e) => return Ok(e.into()),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<EvaluationCancelled>() {
+            Ok(e) => return Ok(e.into()),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<UnsupportedAbi>() {
+            Ok(e) => return Ok(e.into()),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<ResultError>() {
+            Ok(e) => return Ok(e.into()),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<InvalidInput>() {
+            Ok(e) => return Ok(e.into()),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<BuiltinFailed>() {
+            Ok(BuiltinFailed {
+                name,
+                arguments,
+                location,
+                source,
+            }) => {
+                return Ok(Self::Builtin {
+                    name,
+                    arguments,
+                    location,
+                    source,
+                })
+            }
+            Err(e) => e,
+        };
+        let e = match e.downcast::<PolicyAborted>() {
+            Ok(PolicyAborted { message }) => return Ok(Self::PolicyAbort { message }),
+            Err(e) => e,
+        };
+        if e.downcast_ref::<wasmtime::Trap>().is_some() {
+            return Ok(Self::Trap(e));
+        }
+        match e.downcast::<std::io::Error>() {
+            Ok(e) => Ok(e.into()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        Self::classify(e).unwrap_or_else(Self::Other)
+    }
+}
+
+/// Marks the errors returned by builtins as they go through the WASM module
+#[derive(Debug, thiserror::Error)]
+#[error("builtin {name:?} failed{}", describe_call(.location.as_deref(), .arguments))]
+pub(crate) struct BuiltinFailed {
+    pub(crate) name: String,
+    pub(crate) arguments: String,
+    pub(crate) location: Option<String>,
+    #[source]
+    pub(crate) source: anyhow::Error,
+}
+
+fn describe_call(location: Option<&str>, arguments: &str) -> String {
+    match location {
+        Some(location) => format!(" in {location} with arguments ({arguments})"),
+        None => format!(" with arguments ({arguments})"),
+    }
+}
+
+/// Object keys whose values are redacted from the builtin arguments summary
+const SECRET_KEYS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "password",
+    "secret",
+    "token",
+    "api_key",
+    "api-key",
+    "apikey",
+    "private_key",
+    "client_key",
+    "credential",
+];
+
+/// Strings longer than this are truncated in the builtin arguments summary
+const MAX_SUMMARY_STRING: usize = 64;
+
+/// Summarize the JSON-encoded arguments of a builtin call for error
+/// messages, redacting the values of secret-looking keys, bearer and basic
+/// credentials, and truncating long strings
+pub(crate) fn summarize_arguments(args: &[&[u8]]) -> String {
+    fn redact(value: serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(map) => map
+                .into_iter()
+                .map(|(key, value)| {
+                    let lowercase = key.to_lowercase();
+                    let value = if SECRET_KEYS.iter().any(|secret| lowercase.contains(secret)) {
+                        serde_json::Value::String("<redacted>".to_owned())
+                    } else {
+                        redact(value)
+                    };
+                    (key, value)
+                })
+                .collect(),
+            serde_json::Value::Array(values) => values.into_iter().map(redact).collect(),
+            serde_json::Value::String(s) => {
+                let lowercase = s.to_lowercase();
+                if lowercase.starts_with("bearer ") || lowercase.starts_with("basic ") {
+                    serde_json::Value::String("<redacted>".to_owned())
+                } else if s.chars().count() > MAX_SUMMARY_STRING {
+                    let truncated: String = s.chars().take(MAX_SUMMARY_STRING).collect();
+                    serde_json::Value::String(format!("{truncated}..."))
+                } else {
+                    serde_json::Value::String(s)
+                }
+            }
+            value => value,
+        }
+    }
+
+    args.iter()
+        .map(|arg| match serde_json::from_slice(arg) {
+            Ok(value) => redact(value).to_string(),
+            Err(_) => format!("<{} bytes>", arg.len()),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Raised by the `opa_abort` import when the policy aborts its execution
+#[derive(Debug, thiserror::Error)]
+#[error("policy aborted: {message}")]
+pub(crate) struct PolicyAborted {
+    pub(crate) message: String,
+}
+
+/// Error returned when a policy evaluation was interrupted before completing,
+/// see [`Policy::evaluate_until`](crate::Policy::evaluate_until)
+#[derive(Debug, thiserror::Error)]
+#[error("policy evaluation was cancelled")]
+pub struct EvaluationCancelled;
+
+/// Error returned when the result of a policy evaluation does not have the
+/// expected shape, see [`Policy::evaluate_typed`](crate::Policy::evaluate_typed)
+#[derive(Debug, thiserror::Error)]
+pub enum ResultError {
+    /// The policy returned no result
+    #[error("policy result is undefined")]
+    Undefined,
+
+    /// The policy returned more than one result
+    #[error("policy returned {0} results, expected one")]
+    MultipleResults(usize),
+
+    /// The policy result could not be deserialized to the expected type
+    #[error("policy result does not have the expected type")]
+    InvalidType(#[source] serde_json::Error),
+}
+
+/// Error returned when a policy needed more memory than allowed by
+/// [`RuntimeBuilder::max_memory_pages`](crate::RuntimeBuilder::max_memory_pages)
+#[derive(Debug, thiserror::Error)]
+#[error("policy exceeded its memory limit of {max_pages} pages")]
+pub struct MemoryLimitExceeded {
+    pub(crate) max_pages: u32,
+}
+
+impl MemoryLimitExceeded {
+    /// The maximum number of 64 KiB pages the policy was allowed to use
+    #[must_use]
+    pub fn max_pages(&self) -> u32 {
+        self.max_pages
+    }
+}
+
+/// Error returned when mounting a `data` document at, above or below the path
+/// of another one, or over a document of the base `data` document, see
+/// [`Runtime::add_data_at`](crate::Runtime::add_data_at)
+#[derive(Debug, thiserror::Error)]
+#[error("cannot mount data at data.{path}: conflicts with the document at data.{existing}")]
+pub struct DataMountConflict {
+    pub(crate) path: String,
+    pub(crate) existing: String,
+}
+
+impl DataMountConflict {
+    /// The path of the document which could not be mounted, in dotted
+    /// notation
+    #[must_use]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The path of the document already mounted or set in the base `data`
+    /// document, in dotted notation
+    #[must_use]
+    pub fn existing(&self) -> &str {
+        &self.existing
+    }
+}
+
+/// Error returned when the input of an evaluation does not match the JSON
+/// Schema of its entrypoint, see
+/// [`Runtime::set_input_schema`](crate::Runtime::set_input_schema)
+#[derive(Debug, thiserror::Error)]
+#[error("input does not match the schema of entrypoint {entrypoint:?}: {}", describe_violations(.violations))]
+pub struct InvalidInput {
+    pub(crate) entrypoint: String,
+    pub(crate) violations: Vec<SchemaViolation>,
+}
+
+impl InvalidInput {
+    /// The entrypoint which was evaluated
+    #[must_use]
+    pub fn entrypoint(&self) -> &str {
+        &self.entrypoint
+    }
+
+    /// The ways the input does not match the schema
+    #[must_use]
+    pub fn violations(&self) -> &[SchemaViolation] {
+        &self.violations
+    }
+}
+
+fn describe_violations(violations: &[SchemaViolation]) -> String {
+    violations
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// A way the input of an evaluation does not match the schema of its
+/// entrypoint
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SchemaViolation {
+    pub(crate) instance_path: String,
+    pub(crate) schema_path: String,
+    pub(crate) message: String,
+}
+
+impl SchemaViolation {
+    /// The JSON pointer to the offending value in the input, empty for the
+    /// whole input
+    #[must_use]
+    pub fn instance_path(&self) -> &str {
+        &self.instance_path
+    }
+
+    /// The JSON pointer to the keyword of the schema which rejected the value
+    #[must_use]
+    pub fn schema_path(&self) -> &str {
+        &self.schema_path
+    }
+
+    /// A description of the violation
+    #[must_use]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.instance_path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{} at {}", self.message, self.instance_path)
+        }
+    }
+}
+
+/// Error returned when a policy evaluation consumed all the fuel it was given,
+/// see [`Policy::evaluate_with_fuel`](crate::Policy::evaluate_with_fuel)
+#[derive(Debug, thiserror::Error)]
+#[error("policy evaluation ran out of fuel (limit: {limit})")]
+pub struct OutOfFuel {
+    pub(crate) limit: u64,
+}
+
+impl OutOfFuel {
+    /// The amount of fuel the evaluation was given
+    #[must_use]
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+
+/// Error returned when using a feature the ABI version of the policy lacks,
+/// see [`AbiVersion::supports`](crate::AbiVersion::supports)
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "this policy was built with ABI {version} which lacks {feature} (requires ABI {})",
+    feature.min_version()
+)]
+pub struct UnsupportedAbi {
+    pub(crate) version: AbiVersion,
+    pub(crate) feature: AbiFeature,
+}
+
+impl UnsupportedAbi {
+    /// The ABI version the policy was built with
+    #[must_use]
+    pub fn version(&self) -> AbiVersion {
+        self.version
+    }
+
+    /// The feature which was requested
+    #[must_use]
+    pub fn feature(&self) -> AbiFeature {
+        self.feature
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_policy_abort() {
+        let e = anyhow::Error::from(PolicyAborted {
+            message: "var assignment conflict".to_owned(),
+        })
+        .context("error while executing WebAssembly function");
+
+        match Error::from(e) {
+            Error::PolicyAbort { message } => assert_eq!(message, "var assignment conflict"),
+            e => panic!("unexpected error {e:?}"),
+        }
+    }
+
+    #[test]
+    fn builtin_error_context() {
+        let arguments = summarize_arguments(&[
+            br#"{"url": "https://example.com", "headers": {"Authorization": "abc"}}"#,
+            br#"["Bearer xyz", 42]"#,
+            b"not json",
+        ]);
+        assert_eq!(
+            arguments,
+            r#"{"headers":{"Authorization":"<redacted>"},"url":"https://example.com"}, ["<redacted>",42], <8 bytes>"#
+        );
+
+        let long = serde_json::to_vec(&"a".repeat(100)).unwrap();
+        let arguments = summarize_arguments(&[&long]);
+        assert_eq!(arguments, format!("\"{}...\"", "a".repeat(64)));
+
+        let e = anyhow::Error::from(BuiltinFailed {
+            name: "http.send".to_owned(),
+            arguments: r#"{"url":"https://example.com"}"#.to_owned(),
+            location: Some("data.test.allow".to_owned()),
+            source: anyhow::anyhow!("connection refused"),
+        });
+        let e = Error::from(e);
+        assert_eq!(
+            e.to_string(),
+            r#"builtin "http.send" failed in data.test.allow with arguments ({"url":"https://example.com"})"#
+        );
+        assert!(matches!(
+            e,
+            Error::Builtin {
+                location: Some(_),
+                ..
+            }
+        ));
+    }
+}
@@ -468,7 +468,6 @@ impl Func for OpaValueAddPath {
 }
 
 impl OpaValueAddPath {
-    #[allow(dead_code)]
     #[tracing::instrument(name = "opa_value_add_path", skip_all, err)]
     pub async fn call<T: Send>(
         &self,
@@ -496,7 +495,6 @@ impl Func for OpaValueRemovePath {
 }
 
 impl OpaValueRemovePath {
-    #[allow(dead_code)]
     #[tracing::instrument(name = "opa_value_remove_path", skip_all, err)]
     pub async fn call<T: Send>(
         &self,
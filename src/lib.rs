@@ -15,18 +15,95 @@
 #![doc = include_str!("../README.md")]
 #![deny(missing_docs, clippy::pedantic)]
 
+#[cfg(feature = "admission")]
+mod admission;
+#[cfg(feature = "blocking")]
+mod blocking;
 pub mod builtins;
+#[cfg(feature = "bundle-client")]
+mod bundle_client;
+mod capabilities;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "time")]
+mod clock;
+#[cfg(feature = "compilation-cache")]
+mod compilation_cache;
 mod context;
+mod coverage;
+pub mod data_store;
+#[cfg(feature = "decision-logs")]
+mod decision_log;
+#[cfg(feature = "decision-log-client")]
+mod decision_log_client;
+#[cfg(feature = "envoy")]
+pub mod envoy;
+mod error;
 mod funcs;
 #[cfg(feature = "loader")]
 mod loader;
+mod memo;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "axum")]
+mod middleware;
+mod observer;
+mod patch;
 mod policy;
+mod pool;
+mod print;
+mod reload;
+mod replay;
+mod result_set;
+mod shared;
+mod snapshot;
+mod trace;
 mod types;
 
+#[cfg(feature = "admission")]
+pub use self::admission::{AdmissionResponse, AdmissionReview, AdmissionStatus, AdmissionWebhook};
+#[cfg(feature = "bundle-client")]
+pub use self::bundle_client::{BundleClient, BundleClientConfig};
+#[cfg(feature = "time")]
+pub use self::clock::{Clock, FixedClock, SystemClock};
+#[cfg(feature = "compilation-cache")]
+pub use self::compilation_cache::{
+    compile_module, CompilationCache, FsCompilationCache, InMemoryCompilationCache,
+};
+#[cfg(feature = "decision-logs")]
+pub use self::decision_log::{
+    BuiltinError, DecisionLog, DecisionLogSink, DecisionLogger, InputMode,
+};
+#[cfg(feature = "decision-log-client")]
+pub use self::decision_log_client::{DecisionLogClient, DecisionLogClientConfig};
 #[cfg(feature = "loader")]
-pub use self::loader::{load_bundle, read_bundle};
+pub use self::loader::{load_bundle, read_bundle, Bundle, BundleManifest, WasmResolver};
+#[cfg(feature = "bundle-signatures")]
+pub use self::loader::{BundleVerification, BundleVerificationKey};
+#[cfg(feature = "axum")]
+pub use self::middleware::{Authorize, AuthorizeLayer, Decision};
 pub use self::{
+    capabilities::{BuiltinNotAllowed, Capabilities},
     context::{tests::TestContext, DefaultContext, EvaluationContext},
-    policy::{Policy, Runtime},
-    types::AbiVersion,
+    coverage::{Coverage, CoverageReport, EntrypointCoverage},
+    data_store::{DataStore, LazyData, MemoryDataStore},
+    error::{
+        DataMountConflict, Error, EvaluationCancelled, InvalidInput, MemoryLimitExceeded,
+        OutOfFuel, Result, ResultError, SchemaViolation, UnsupportedAbi,
+    },
+    memo::{BuiltinMemoization, MemoizationScope, MEMOIZABLE_BUILTINS},
+    observer::{BuiltinObserver, BuiltinOutcome},
+    policy::{Policy, Runtime, RuntimeBuilder},
+    pool::PolicyPool,
+    print::{PrintBuffer, PrintHook},
+    reload::ReloadablePolicy,
+    replay::{
+        is_non_deterministic, BuiltinCall, BuiltinRecorder, BuiltinReplayer, Recording,
+        EVALUATION_TIME, JWKS_KEYS, NON_DETERMINISTIC_BUILTINS,
+    },
+    result_set::ResultSet,
+    shared::SharedPolicy,
+    snapshot::PolicySnapshot,
+    trace::{Trace, TraceEvent},
+    types::{AbiFeature, AbiVersion},
 };
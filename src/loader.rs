@@ -17,13 +17,17 @@ use std::path::Path;
 use anyhow::Context;
 use async_compression::tokio::bufread::GzipDecoder;
 use futures_util::TryStreamExt;
+use serde::Deserialize;
 use tokio::io::{AsyncBufRead, AsyncReadExt, BufReader};
 use tokio_tar::Archive;
 use tracing::{info_span, Instrument};
+use wasmtime::{AsContextMut, Module};
+
+use crate::{builtins::impls::rego::RegoMetadata, Error, EvaluationContext, Policy, Runtime};
 
 /// Read an OPA compiled bundle from disk
 #[tracing::instrument(err)]
-pub async fn read_bundle(path: impl AsRef<Path> + std::fmt::Debug) -> anyhow::Result<Vec<u8>> {
+pub async fn read_bundle(path: impl AsRef<Path> + std::fmt::Debug) -> Result<Vec<u8>, Error> {
     let file = tokio::fs::File::open(path).await?;
     let reader = BufReader::new(file);
     load_bundle(reader).await
@@ -33,7 +37,7 @@ pub async fn read_bundle(path: impl AsRef<Path> + std::fmt::Debug) -> anyhow::Re
 #[tracing::instrument(skip_all, err)]
 pub async fn load_bundle(
     reader: impl AsyncBufRead + Unpin + Send + Sync,
-) -> anyhow::Result<Vec<u8>> {
+) -> Result<Vec<u8>, Error> {
     // Wrap the reader in a gzip decoder, then in a tar unarchiver
     let reader = GzipDecoder::new(reader);
     let mut archive = Archive::new(reader);
@@ -42,11 +46,10 @@ pub async fn load_bundle(
     let entries = archive.entries()?;
     let mut entry = entries
         .try_filter(|e| {
-            std::future::ready(
-                e.path()
-                    .map(|p| p.as_os_str() == "/policy.wasm")
-                    .unwrap_or(false),
-            )
+            std::future::ready(e.path().is_ok_and(|p| {
+                p.to_str()
+                    .is_some_and(|p| p.eq_ignore_ascii_case("/policy.wasm"))
+            }))
         })
         .try_next()
         .instrument(info_span!("find_bundle_entry"))
@@ -62,3 +65,619 @@ pub async fn load_bundle(
 
     Ok(buf)
 }
+
+/// The `.manifest` file of an OPA bundle
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BundleManifest {
+    /// The revision of the bundle
+    pub revision: String,
+
+    /// The paths of the data tree owned by the bundle. [`None`] means the
+    /// bundle owns the whole tree.
+    pub roots: Option<Vec<String>>,
+
+    /// Arbitrary metadata attached to the bundle
+    pub metadata: serde_json::Map<String, serde_json::Value>,
+
+    /// The entrypoints of the compiled policy
+    pub wasm: Vec<WasmResolver>,
+}
+
+/// An entrypoint of the compiled policy, listed in the bundle manifest
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WasmResolver {
+    /// The path of the entrypoint, e.g. `example/allow`
+    pub entrypoint: String,
+
+    /// The path of the module in the bundle
+    pub module: String,
+
+    /// The annotations of the rule, from the rule to the package
+    pub annotations: Vec<serde_json::Value>,
+}
+
+impl BundleManifest {
+    /// The roots of the bundle, split into path segments
+    fn root_paths(&self) -> impl Iterator<Item = Vec<&str>> {
+        self.roots
+            .iter()
+            .flatten()
+            .map(|root| root.split('/').filter(|s| !s.is_empty()).collect())
+    }
+
+    /// Check if the given data path is owned by the bundle, i.e. is one of
+    /// its roots or under one of them
+    fn owns(&self, path: &[&str]) -> bool {
+        self.roots.is_none() || self.root_paths().any(|root| path.starts_with(&root))
+    }
+
+    /// Check if one of the bundle roots is under the given data path
+    fn is_root_ancestor(&self, path: &[&str]) -> bool {
+        self.root_paths()
+            .any(|root| root.len() > path.len() && root.starts_with(path))
+    }
+
+    /// Check that a data document loaded at the given path only sets values
+    /// owned by the bundle. Documents at a parent of a root may only have keys
+    /// leading to the roots.
+    fn check_data<'a>(&self, path: &[&'a str], value: &'a serde_json::Value) -> anyhow::Result<()> {
+        if self.owns(path) {
+            return Ok(());
+        }
+
+        match value {
+            serde_json::Value::Object(object) if self.is_root_ancestor(path) => {
+                for (key, value) in object {
+                    let path: Vec<&str> = path.iter().copied().chain([key.as_str()]).collect();
+                    self.check_data(&path, value)?;
+                }
+                Ok(())
+            }
+            _ => anyhow::bail!("{:?} is outside of the bundle roots", path.join("/")),
+        }
+    }
+}
+
+/// An OPA bundle, with its compiled policy, data and manifest
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    module: Vec<u8>,
+    data: serde_json::Value,
+    manifest: BundleManifest,
+}
+
+impl Bundle {
+    /// Read an OPA compiled bundle from disk
+    ///
+    /// # Errors
+    ///
+    /// See [`Bundle::load`]
+    #[tracing::instrument(name = "Bundle::read", err)]
+    pub async fn read(path: impl AsRef<Path> + std::fmt::Debug) -> Result<Self, Error> {
+        let file = tokio::fs::File::open(path).await?;
+        let reader = BufReader::new(file);
+        Self::load(reader).await
+    }
+
+    /// Load an OPA compiled bundle from a gzipped tarball, as produced by
+    /// `opa build -t wasm`
+    ///
+    /// # Errors
+    ///
+    /// If the archive is invalid, has no `/policy.wasm` module, or has
+    /// invalid data files
+    #[tracing::instrument(name = "Bundle::load", skip_all, err)]
+    pub async fn load(reader: impl AsyncBufRead + Unpin + Send + Sync) -> Result<Self, Error> {
+        let files = read_files(reader).await?;
+        Ok(Self::from_files(&files)?)
+    }
+
+    /// Load an OPA compiled bundle from a gzipped tarball, checking its
+    /// `.signatures.json` file against the given trusted keys first
+    ///
+    /// # Errors
+    ///
+    /// If the bundle signature is missing or invalid, if the signed file
+    /// hashes don't match the bundle files, or see [`Bundle::load`]
+    #[cfg(feature = "bundle-signatures")]
+    #[tracing::instrument(name = "Bundle::load_verified", skip_all, err)]
+    pub async fn load_verified(
+        reader: impl AsyncBufRead + Unpin + Send + Sync,
+        verification: &BundleVerification,
+    ) -> Result<Self, Error> {
+        let mut files = read_files(reader).await?;
+        let signatures = files
+            .iter()
+            .position(|(path, _)| path == SIGNATURES_FILE)
+            .map(|index| files.remove(index).1)
+            .context("bundle is not signed")?;
+        verification.verify(&signatures, &files)?;
+        Ok(Self::from_files(&files)?)
+    }
+
+    /// Build the bundle from the files of the archive
+    fn from_files(files: &[(String, Vec<u8>)]) -> anyhow::Result<Self> {
+        let mut module = None;
+        let mut manifest = None;
+        let mut data_files = Vec::new();
+
+        for (path, content) in files {
+            let (dir, file) = path.rsplit_once('/').unwrap_or(("", path.as_str()));
+
+            match file {
+                _ if dir.is_empty() && file.eq_ignore_ascii_case("policy.wasm") => {
+                    module = Some(content.clone());
+                }
+
+                ".manifest" if dir.is_empty() => {
+                    let value: BundleManifest =
+                        serde_json::from_slice(content).context("invalid bundle manifest")?;
+                    manifest = Some(value);
+                }
+
+                "data.json" => {
+                    let value: serde_json::Value = serde_json::from_slice(content)
+                        .with_context(|| format!("invalid JSON data file {path}"))?;
+                    data_files.push((dir, value));
+                }
+
+                "data.yaml" | "data.yml" => {
+                    let value: serde_json::Value = serde_yaml::from_slice(content)
+                        .with_context(|| format!("invalid YAML data file {path}"))?;
+                    data_files.push((dir, value));
+                }
+
+                _ => {}
+            }
+        }
+
+        let module = module.context("could not find WASM policy in tar archive")?;
+        let manifest = manifest.unwrap_or_default();
+
+        let mut data = serde_json::Value::Object(serde_json::Map::new());
+        for (dir, value) in data_files {
+            let path: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+            manifest
+                .check_data(&path, &value)
+                .with_context(|| format!("invalid data file in {dir:?}"))?;
+            insert_data(&mut data, &path, value)?;
+        }
+
+        Ok(Self {
+            module,
+            data,
+            manifest,
+        })
+    }
+
+    /// The compiled WASM policy
+    #[must_use]
+    pub fn module(&self) -> &[u8] {
+        &self.module
+    }
+
+    /// The data tree built from the bundle data files
+    #[must_use]
+    pub fn data(&self) -> &serde_json::Value {
+        &self.data
+    }
+
+    /// The bundle manifest, or an empty one if the bundle had none
+    #[must_use]
+    pub fn manifest(&self) -> &BundleManifest {
+        &self.manifest
+    }
+
+    /// The annotations of the entrypoints listed in the manifest, to be
+    /// returned by the `rego.metadata.*` builtins, see
+    /// [`crate::DefaultContext::with_rego_metadata`]
+    #[must_use]
+    pub fn rego_metadata(&self) -> RegoMetadata {
+        self.manifest
+            .wasm
+            .iter()
+            .fold(RegoMetadata::default(), |metadata, resolver| {
+                metadata.with_rule_annotations(&resolver.entrypoint, &resolver.annotations)
+            })
+    }
+
+    /// Compile and instantiate the bundled policy in the given store, with the
+    /// bundled data loaded
+    ///
+    /// # Errors
+    ///
+    /// If the module failed to compile, see also
+    /// [`Runtime::new_with_evaluation_context`] and [`Runtime::with_data`]
+    pub async fn instantiate<C: EvaluationContext, T: Send>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        context: C,
+    ) -> Result<Policy<C>, Error> {
+        let module =
+            Module::new(store.as_context_mut().engine(), &self.module).map_err(Error::load)?;
+        let runtime = Runtime::new_with_evaluation_context(&mut store, &module, context).await?;
+        runtime.with_data(&mut store, &self.data).await
+    }
+}
+
+/// Read all the files of a gzipped tarball, with their path relative to the
+/// root of the archive
+async fn read_files(
+    reader: impl AsyncBufRead + Unpin + Send + Sync,
+) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let reader = GzipDecoder::new(reader);
+    let mut archive = Archive::new(reader);
+
+    let mut files = Vec::new();
+    let mut entries = archive.entries()?;
+    while let Some(mut entry) = entries.try_next().await? {
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let path = path.trim_start_matches('/').to_owned();
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await?;
+        files.push((path, buf));
+    }
+
+    Ok(files)
+}
+
+/// Merge a value in the data tree at the given path
+fn insert_data(
+    data: &mut serde_json::Value,
+    path: &[&str],
+    value: serde_json::Value,
+) -> anyhow::Result<()> {
+    let Some((first, rest)) = path.split_first() else {
+        return merge_data(data, value);
+    };
+
+    let object = data
+        .as_object_mut()
+        .context("data files overlap with a non-object value")?;
+    let child = object
+        .entry(*first)
+        .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+    insert_data(child, rest, value)
+}
+
+/// Deep-merge two objects, failing if a key is defined twice with non-object
+/// values
+fn merge_data(data: &mut serde_json::Value, value: serde_json::Value) -> anyhow::Result<()> {
+    match (data, value) {
+        (serde_json::Value::Object(data), serde_json::Value::Object(value)) => {
+            for (key, value) in value {
+                if let Some(existing) = data.get_mut(&key) {
+                    merge_data(existing, value)
+                        .with_context(|| format!("conflicting data for key {key:?}"))?;
+                } else {
+                    data.insert(key, value);
+                }
+            }
+            Ok(())
+        }
+        (data, value) if data.as_object().is_some_and(serde_json::Map::is_empty) => {
+            *data = value;
+            Ok(())
+        }
+        _ => anyhow::bail!("data files define the same value twice"),
+    }
+}
+
+/// Name of the file holding the bundle signatures
+#[cfg(feature = "bundle-signatures")]
+const SIGNATURES_FILE: &str = ".signatures.json";
+
+/// A trusted key used to verify bundle signatures
+#[cfg(feature = "bundle-signatures")]
+#[derive(Debug, Clone)]
+pub struct BundleVerificationKey {
+    key: String,
+    algorithm: String,
+    scope: Option<String>,
+}
+
+#[cfg(feature = "bundle-signatures")]
+impl BundleVerificationKey {
+    /// A key using the `RS256` algorithm. The key is either a PEM public key,
+    /// a PEM certificate, a JWK, or the shared secret for HMAC algorithms.
+    #[must_use]
+    pub fn new(key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            algorithm: "RS256".to_owned(),
+            scope: None,
+        }
+    }
+
+    /// Set the JWT algorithm used to sign the bundles, like `ES256` or `HS256`
+    #[must_use]
+    pub fn with_algorithm(mut self, algorithm: impl Into<String>) -> Self {
+        self.algorithm = algorithm.into();
+        self
+    }
+
+    /// Require the signatures made with this key to have the given scope
+    #[must_use]
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+}
+
+/// The trusted keys used to verify signed bundles, see
+/// [`Bundle::load_verified`]
+#[cfg(feature = "bundle-signatures")]
+#[derive(Debug, Clone, Default)]
+pub struct BundleVerification {
+    keys: std::collections::HashMap<String, BundleVerificationKey>,
+    keyid: Option<String>,
+    scope: Option<String>,
+}
+
+/// The payload of a bundle signature
+#[cfg(feature = "bundle-signatures")]
+#[derive(Debug, Deserialize)]
+struct SignedFiles {
+    files: Vec<SignedFile>,
+    keyid: Option<String>,
+    scope: Option<String>,
+}
+
+/// The hash of a file in a bundle signature
+#[cfg(feature = "bundle-signatures")]
+#[derive(Debug, Deserialize)]
+struct SignedFile {
+    name: String,
+    hash: String,
+    algorithm: String,
+}
+
+#[cfg(feature = "bundle-signatures")]
+impl BundleVerification {
+    /// Trust the given key, identified by its key ID
+    #[must_use]
+    pub fn with_key(mut self, keyid: impl Into<String>, key: BundleVerificationKey) -> Self {
+        self.keys.insert(keyid.into(), key);
+        self
+    }
+
+    /// Key ID to use when the signature does not specify one
+    #[must_use]
+    pub fn with_keyid(mut self, keyid: impl Into<String>) -> Self {
+        self.keyid = Some(keyid.into());
+        self
+    }
+
+    /// Require the signatures to have the given scope, unless the key
+    /// specifies its own
+    #[must_use]
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Check the `.signatures.json` file against the other bundle files
+    fn verify(&self, signatures: &[u8], files: &[(String, Vec<u8>)]) -> anyhow::Result<()> {
+        #[derive(Deserialize)]
+        struct Signatures {
+            signatures: Vec<String>,
+        }
+
+        let Signatures { signatures } =
+            serde_json::from_slice(signatures).context("invalid bundle signatures file")?;
+        let [jwt] = &signatures[..] else {
+            anyhow::bail!("bundle must have exactly one signature");
+        };
+
+        // Find which key was used, from the JWT header or payload
+        let (header, payload) = crate::builtins::impls::io::jwt::decode(jwt.clone())
+            .map(|(header, payload, _)| (header, payload))
+            .context("invalid bundle signature")?;
+        let keyid = header
+            .get("kid")
+            .and_then(serde_json::Value::as_str)
+            .or_else(|| payload.get("keyid").and_then(serde_json::Value::as_str))
+            .or(self.keyid.as_deref())
+            .context("bundle signature has no key ID")?;
+        let key = self
+            .keys
+            .get(keyid)
+            .with_context(|| format!("unknown bundle signing key {keyid:?}"))?;
+
+        let (_, payload) =
+            crate::builtins::impls::io::jwt::verify_with_key(jwt, &key.algorithm, &key.key)
+                .context("invalid bundle signature")?;
+        let signed: SignedFiles =
+            serde_json::from_value(payload).context("invalid bundle signature payload")?;
+
+        if let Some(signed_keyid) = &signed.keyid {
+            anyhow::ensure!(
+                signed_keyid == keyid,
+                "bundle signature key ID {signed_keyid:?} does not match {keyid:?}"
+            );
+        }
+
+        if let Some(scope) = key.scope.as_ref().or(self.scope.as_ref()) {
+            anyhow::ensure!(
+                signed.scope.as_ref() == Some(scope),
+                "bundle signature scope does not match {scope:?}"
+            );
+        }
+
+        // Every file must be signed, and every signed file must be present
+        let mut hashes: std::collections::HashMap<&str, &SignedFile> = signed
+            .files
+            .iter()
+            .map(|file| (file.name.trim_start_matches('/'), file))
+            .collect();
+        for (path, content) in files {
+            let signed = hashes
+                .remove(path.as_str())
+                .with_context(|| format!("bundle file {path:?} is not signed"))?;
+            let hash = hash_file(path, content, &signed.algorithm)?;
+            anyhow::ensure!(
+                hash.eq_ignore_ascii_case(&signed.hash),
+                "hash mismatch for bundle file {path:?}"
+            );
+        }
+        if let Some(name) = hashes.keys().next() {
+            anyhow::bail!("signed bundle file {name:?} is missing");
+        }
+
+        Ok(())
+    }
+}
+
+/// Hash a bundle file as OPA does. JSON files are hashed in their compact
+/// form, with sorted keys.
+#[cfg(feature = "bundle-signatures")]
+fn hash_file(path: &str, content: &[u8], algorithm: &str) -> anyhow::Result<String> {
+    use sha2::{Digest, Sha256, Sha384, Sha512};
+
+    let normalized;
+    let is_json = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let content = if is_json || path == ".manifest" {
+        let value: serde_json::Value = serde_json::from_slice(content)
+            .with_context(|| format!("invalid JSON file {path:?}"))?;
+        normalized = serde_json::to_vec(&value)?;
+        &normalized[..]
+    } else {
+        content
+    };
+
+    let hash = match algorithm {
+        "SHA-256" => Sha256::digest(content).to_vec(),
+        "SHA-384" => Sha384::digest(content).to_vec(),
+        "SHA-512" => Sha512::digest(content).to_vec(),
+        algorithm => anyhow::bail!("unsupported bundle hash algorithm {algorithm:?}"),
+    };
+    Ok(hex::encode(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn data_tree() {
+        let mut data = json!({});
+        insert_data(&mut data, &[], json!({"a": {"b": 1}})).unwrap();
+        insert_data(&mut data, &["a", "c"], json!({"d": 2})).unwrap();
+        insert_data(&mut data, &["x"], json!([1, 2])).unwrap();
+        assert_eq!(data, json!({"a": {"b": 1, "c": {"d": 2}}, "x": [1, 2]}));
+
+        assert!(insert_data(&mut data, &["a", "b"], json!({"e": 3})).is_err());
+        assert!(insert_data(&mut data, &["x"], json!([3])).is_err());
+    }
+
+    #[test]
+    fn manifest_annotations() {
+        let manifest: BundleManifest = serde_json::from_value(json!({
+            "wasm": [{
+                "entrypoint": "example/allow",
+                "module": "/policy.wasm",
+                "annotations": [{"scope": "rule", "title": "Allow"}],
+            }],
+        }))
+        .unwrap();
+        assert_eq!(manifest.wasm[0].entrypoint, "example/allow");
+        assert_eq!(manifest.wasm[0].annotations.len(), 1);
+    }
+
+    #[test]
+    fn manifest_roots() {
+        let manifest = BundleManifest {
+            roots: Some(vec!["a/b".to_owned()]),
+            ..BundleManifest::default()
+        };
+        assert!(manifest.owns(&["a", "b", "c"]));
+        assert!(manifest.owns(&["a", "b"]));
+        assert!(!manifest.owns(&["a"]));
+        assert!(!manifest.owns(&["x"]));
+        assert!(BundleManifest::default().owns(&["x"]));
+
+        // Documents at a parent of a root can only set data under the root
+        assert!(manifest.check_data(&["a"], &json!({"b": {"c": 1}})).is_ok());
+        assert!(manifest.check_data(&[], &json!({"a": {"b": 1}})).is_ok());
+        assert!(manifest.check_data(&["a"], &json!({"x": 1})).is_err());
+        assert!(manifest
+            .check_data(&[], &json!({"a": {"b": 1, "c": 2}}))
+            .is_err());
+        assert!(manifest.check_data(&["a"], &json!([1])).is_err());
+        assert!(manifest.check_data(&["x"], &json!({})).is_err());
+    }
+
+    #[cfg(feature = "bundle-signatures")]
+    #[test]
+    fn signatures() {
+        let files = vec![
+            ("policy.wasm".to_owned(), b"\0asm".to_vec()),
+            ("data.json".to_owned(), br#"{"b": 1, "a": 2}"#.to_vec()),
+        ];
+        let signed_files: Vec<_> = files
+            .iter()
+            .map(|(name, content)| {
+                json!({
+                    "name": name,
+                    "hash": hash_file(name, content, "SHA-256").unwrap(),
+                    "algorithm": "SHA-256",
+                })
+            })
+            .collect();
+
+        // "secret", base64url-encoded
+        let key = json!({"kty": "oct", "k": "c2VjcmV0"});
+        let jwt = crate::builtins::impls::io::jwt::encode_sign(
+            json!({"alg": "HS256", "kid": "main"}),
+            json!({"files": signed_files, "scope": "write"}),
+            key,
+        )
+        .unwrap();
+        let signatures = serde_json::to_vec(&json!({ "signatures": [jwt] })).unwrap();
+
+        let verification = BundleVerification::default().with_key(
+            "main",
+            BundleVerificationKey::new("secret")
+                .with_algorithm("HS256")
+                .with_scope("write"),
+        );
+        verification.verify(&signatures, &files).unwrap();
+
+        // Tampered file
+        let mut tampered = files.clone();
+        tampered[1].1 = br#"{"a": 3}"#.to_vec();
+        assert!(verification.verify(&signatures, &tampered).is_err());
+
+        // Unsigned file
+        let mut extra = files.clone();
+        extra.push(("other.json".to_owned(), b"{}".to_vec()));
+        assert!(verification.verify(&signatures, &extra).is_err());
+
+        // Wrong key
+        let verification = BundleVerification::default().with_key(
+            "main",
+            BundleVerificationKey::new("other").with_algorithm("HS256"),
+        );
+        assert!(verification.verify(&signatures, &files).is_err());
+    }
+
+    #[cfg(feature = "bundle-signatures")]
+    #[test]
+    fn json_hash_ignores_extension_case() {
+        let content = br#"{"b": 1, "a": 2}"#;
+        assert_eq!(
+            hash_file("data.JSON", content, "SHA-256").unwrap(),
+            hash_file("data.json", content, "SHA-256").unwrap(),
+        );
+    }
+}
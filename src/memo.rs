@@ -0,0 +1,194 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Memoization of the results of pure builtins
+
+use std::{
+    collections::HashSet,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{builtins::impls::lru::LruCache, replay::is_non_deterministic};
+
+/// Builtins memoized by default: they always return the same result for the
+/// same arguments, and are expensive enough for memoization to pay off
+pub const MEMOIZABLE_BUILTINS: &[&str] = &[
+    "glob.match",
+    "graphql.is_valid",
+    "graphql.parse",
+    "graphql.parse_query",
+    "graphql.parse_schema",
+    "graphql.schema_is_valid",
+    "regex.find_all_string_submatch_n",
+    "regex.find_n",
+    "regex.globs_match",
+    "regex.is_valid",
+    "regex.match",
+    "regex.replace",
+    "regex.split",
+    "regex.template_match",
+    "semver.compare",
+    "semver.is_valid",
+    "time.parse_duration_ns",
+    "time.parse_rfc3339_ns",
+    "units.parse",
+    "units.parse_bytes",
+    "yaml.is_valid",
+    "yaml.unmarshal",
+];
+
+/// How long memoized builtin results are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemoizationScope {
+    /// Results are forgotten at the start of each evaluation
+    #[default]
+    Evaluation,
+
+    /// Results are kept across evaluations, for the lifetime of the runtime
+    Runtime,
+}
+
+/// Configuration of the memoization of builtin results, set with
+/// [`RuntimeBuilder::memoize_builtins`](crate::RuntimeBuilder::memoize_builtins).
+///
+/// Only successful calls are memoized. Non-deterministic builtins, like
+/// `http.send` or `time.now_ns`, are never memoized.
+#[derive(Debug, Clone)]
+pub struct BuiltinMemoization {
+    scope: MemoizationScope,
+    max_entries: usize,
+    builtins: HashSet<String>,
+}
+
+impl BuiltinMemoization {
+    /// Memoize up to `max_entries` results of the [`MEMOIZABLE_BUILTINS`],
+    /// for the duration of an evaluation
+    #[must_use]
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            scope: MemoizationScope::default(),
+            max_entries,
+            builtins: MEMOIZABLE_BUILTINS.iter().map(|&b| b.to_owned()).collect(),
+        }
+    }
+
+    /// Set how long the results are kept
+    #[must_use]
+    pub fn with_scope(mut self, scope: MemoizationScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Set which builtins are memoized, replacing the default list
+    #[must_use]
+    pub fn with_builtins<I, S>(mut self, builtins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.builtins = builtins.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+/// The memoized results of a runtime
+pub(crate) struct BuiltinMemo {
+    config: BuiltinMemoization,
+    cache: Mutex<LruCache<Vec<u8>>>,
+}
+
+impl BuiltinMemo {
+    pub(crate) fn new(config: BuiltinMemoization) -> Self {
+        let cache = LruCache::new("memo", config.max_entries);
+        Self {
+            config,
+            cache: Mutex::new(cache),
+        }
+    }
+
+    /// The key of a builtin call in the cache, or [`None`] if the builtin is
+    /// not memoized
+    pub(crate) fn key(&self, name: &str, args: &[&[u8]]) -> Option<String> {
+        if !self.config.builtins.contains(name) || is_non_deterministic(name) {
+            return None;
+        }
+
+        // The arguments are JSON documents, which cannot contain NUL bytes
+        let mut key = name.to_owned();
+        for arg in args {
+            key.push('\0');
+            key.push_str(std::str::from_utf8(arg).ok()?);
+        }
+        Some(key)
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(key)
+    }
+
+    pub(crate) fn insert(&self, key: String, result: Vec<u8>) {
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(key, result);
+    }
+
+    /// Forget the results of the previous evaluation, if they are scoped to
+    /// the evaluation
+    pub(crate) fn evaluation_start(&self) {
+        if self.config.scope == MemoizationScope::Evaluation {
+            self.cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        let memo = BuiltinMemo::new(BuiltinMemoization::new(16));
+        assert_eq!(
+            memo.key("regex.match", &[b"\"^a\"", b"\"abc\""]).as_deref(),
+            Some("regex.match\0\"^a\"\0\"abc\"")
+        );
+        assert!(memo.key("sprintf", &[b"\"%d\"", b"[1]"]).is_none());
+
+        // Non-deterministic builtins are never memoized
+        let memo = BuiltinMemo::new(BuiltinMemoization::new(16).with_builtins(["time.now_ns"]));
+        assert!(memo.key("time.now_ns", &[]).is_none());
+    }
+
+    #[test]
+    fn scopes() {
+        let memo = BuiltinMemo::new(BuiltinMemoization::new(16));
+        memo.insert("a".to_owned(), b"true".to_vec());
+        assert_eq!(memo.get("a").as_deref(), Some(&b"true"[..]));
+        memo.evaluation_start();
+        assert!(memo.get("a").is_none());
+
+        let memo =
+            BuiltinMemo::new(BuiltinMemoization::new(16).with_scope(MemoizationScope::Runtime));
+        memo.insert("a".to_owned(), b"true".to_vec());
+        memo.evaluation_start();
+        assert!(memo.get("a").is_some());
+    }
+}
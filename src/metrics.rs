@@ -0,0 +1,255 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prometheus metrics about the policy runtime
+//!
+//! The metrics are shared by all the runtimes of the process, and can be
+//! scraped with [`gather`], e.g. to be encoded with a
+//! [`prometheus::TextEncoder`] alongside the service's own metrics.
+
+use std::{sync::OnceLock, time::Duration};
+
+use prometheus::{
+    proto::MetricFamily, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts,
+    Registry,
+};
+
+/// The metrics tracked by the runtime
+pub(crate) struct Metrics {
+    registry: Registry,
+    evaluations: IntCounterVec,
+    evaluation_duration: HistogramVec,
+    builtin_calls: IntCounterVec,
+    builtin_duration: HistogramVec,
+    cache_requests: IntCounterVec,
+    cache_evictions: IntCounterVec,
+    memory_grown_pages: IntCounter,
+    http_rate_limited: IntCounterVec,
+    http_circuit_state: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("opa_wasm".to_owned()), None)?;
+
+        let evaluations = IntCounterVec::new(
+            Opts::new("evaluations_total", "Number of policy evaluations"),
+            &["entrypoint", "outcome"],
+        )?;
+        registry.register(Box::new(evaluations.clone()))?;
+
+        let evaluation_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "evaluation_duration_seconds",
+                "Time spent evaluating policies",
+            ),
+            &["entrypoint"],
+        )?;
+        registry.register(Box::new(evaluation_duration.clone()))?;
+
+        let builtin_calls = IntCounterVec::new(
+            Opts::new("builtin_calls_total", "Number of builtin calls"),
+            &["builtin", "outcome"],
+        )?;
+        registry.register(Box::new(builtin_calls.clone()))?;
+
+        let builtin_duration = HistogramVec::new(
+            HistogramOpts::new("builtin_duration_seconds", "Time spent in builtin calls"),
+            &["builtin"],
+        )?;
+        registry.register(Box::new(builtin_duration.clone()))?;
+
+        let cache_requests = IntCounterVec::new(
+            Opts::new(
+                "cache_requests_total",
+                "Number of lookups in the builtin caches",
+            ),
+            &["cache", "result"],
+        )?;
+        registry.register(Box::new(cache_requests.clone()))?;
+
+        let cache_evictions = IntCounterVec::new(
+            Opts::new(
+                "cache_evictions_total",
+                "Number of entries evicted from the builtin caches",
+            ),
+            &["cache", "cause"],
+        )?;
+        registry.register(Box::new(cache_evictions.clone()))?;
+
+        let memory_grown_pages = IntCounter::new(
+            "memory_grown_pages_total",
+            "Number of 64 KiB pages the policies grew their memory by",
+        )?;
+        registry.register(Box::new(memory_grown_pages.clone()))?;
+
+        let http_rate_limited = IntCounterVec::new(
+            Opts::new(
+                "http_rate_limited_total",
+                "Number of http.send requests rejected by the rate limit",
+            ),
+            &["host"],
+        )?;
+        registry.register(Box::new(http_rate_limited.clone()))?;
+
+        let http_circuit_state = IntGaugeVec::new(
+            Opts::new(
+                "http_circuit_state",
+                "State of the http.send circuit breakers (0: closed, 1: open, 2: half open)",
+            ),
+            &["host"],
+        )?;
+        registry.register(Box::new(http_circuit_state.clone()))?;
+
+        Ok(Self {
+            registry,
+            evaluations,
+            evaluation_duration,
+            builtin_calls,
+            builtin_duration,
+            cache_requests,
+            cache_evictions,
+            memory_grown_pages,
+            http_rate_limited,
+            http_circuit_state,
+        })
+    }
+}
+
+fn outcome(success: bool) -> &'static str {
+    if success {
+        "success"
+    } else {
+        "error"
+    }
+}
+
+pub(crate) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().expect("invalid metric definitions"))
+}
+
+/// The registry holding the runtime metrics, e.g. to register it as a
+/// collector of another registry
+#[must_use]
+pub fn registry() -> &'static Registry {
+    &metrics().registry
+}
+
+/// Collect the current value of all the runtime metrics
+#[must_use]
+pub fn gather() -> Vec<MetricFamily> {
+    registry().gather()
+}
+
+pub(crate) fn observe_evaluation(entrypoint: &str, duration: Duration, success: bool) {
+    let metrics = metrics();
+    metrics
+        .evaluations
+        .with_label_values(&[entrypoint, outcome(success)])
+        .inc();
+    metrics
+        .evaluation_duration
+        .with_label_values(&[entrypoint])
+        .observe(duration.as_secs_f64());
+}
+
+pub(crate) fn observe_builtin(name: &str, duration: Duration, success: bool) {
+    let metrics = metrics();
+    metrics
+        .builtin_calls
+        .with_label_values(&[name, outcome(success)])
+        .inc();
+    metrics
+        .builtin_duration
+        .with_label_values(&[name])
+        .observe(duration.as_secs_f64());
+}
+
+/// Count a lookup in one of the builtin caches
+pub(crate) fn observe_cache(cache: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    metrics()
+        .cache_requests
+        .with_label_values(&[cache, result])
+        .inc();
+}
+
+/// Count an entry evicted from one of the builtin caches, either because it
+/// `expired` or to keep the cache within its `size`
+#[cfg_attr(not(feature = "http-builtins"), allow(dead_code))]
+pub(crate) fn observe_cache_eviction(cache: &str, cause: &str) {
+    metrics()
+        .cache_evictions
+        .with_label_values(&[cache, cause])
+        .inc();
+}
+
+pub(crate) fn observe_memory_growth(pages: u64) {
+    if pages > 0 {
+        metrics().memory_grown_pages.inc_by(pages);
+    }
+}
+
+/// Count a request rejected by the rate limit of its host
+#[cfg_attr(not(feature = "http-builtins"), allow(dead_code))]
+pub(crate) fn observe_rate_limited(host: &str) {
+    metrics().http_rate_limited.with_label_values(&[host]).inc();
+}
+
+/// Set the state of the circuit breaker of a host
+#[cfg_attr(not(feature = "http-builtins"), allow(dead_code))]
+pub(crate) fn set_circuit_state(host: &str, state: i64) {
+    metrics()
+        .http_circuit_state
+        .with_label_values(&[host])
+        .set(state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter(name: &str, labels: &[(&str, &str)]) -> f64 {
+        gather()
+            .iter()
+            .filter(|family| family.get_name() == name)
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| {
+                labels.iter().all(|(name, value)| {
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.get_name() == *name && label.get_value() == *value)
+                })
+            })
+            .map(|metric| metric.get_counter().get_value())
+            .sum()
+    }
+
+    #[test]
+    fn gather_metrics() {
+        let labels = [("cache", "metrics-test"), ("result", "hit")];
+        let before = counter("opa_wasm_cache_requests_total", &labels);
+        observe_cache("metrics-test", true);
+        observe_cache("metrics-test", true);
+        observe_cache("metrics-test", false);
+        let after = counter("opa_wasm_cache_requests_total", &labels);
+        assert!((after - before - 2.0).abs() < f64::EPSILON);
+
+        observe_evaluation("metrics-test/allow", Duration::from_millis(5), true);
+        let labels = [("entrypoint", "metrics-test/allow"), ("outcome", "success")];
+        assert!(counter("opa_wasm_evaluations_total", &labels) >= 1.0);
+    }
+}
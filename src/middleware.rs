@@ -0,0 +1,310 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A [`tower`](tower_layer) middleware authorizing HTTP requests with a
+//! policy, for use with `axum`
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    async_trait,
+    body::Body,
+    extract::FromRequestParts,
+    http::{request::Parts, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+use serde::Serialize;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{EvaluationContext, PolicyPool, ResultSet};
+
+/// The input passed to the policy for each request
+#[derive(Debug, Serialize)]
+struct RequestInput {
+    method: String,
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    query: Option<String>,
+    headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<serde_json::Value>,
+}
+
+impl RequestInput {
+    fn new(parts: &Parts, body: Option<&[u8]>) -> Self {
+        let mut headers = BTreeMap::new();
+        for (name, value) in &parts.headers {
+            let value = String::from_utf8_lossy(value.as_bytes());
+            headers
+                .entry(name.as_str().to_owned())
+                .and_modify(|existing: &mut String| {
+                    existing.push_str(", ");
+                    existing.push_str(&value);
+                })
+                .or_insert_with(|| value.into_owned());
+        }
+
+        // Pass the body as JSON when possible, as a string otherwise
+        let body = body.map(|body| {
+            serde_json::from_slice(body).unwrap_or_else(|_| {
+                serde_json::Value::String(String::from_utf8_lossy(body).into_owned())
+            })
+        });
+
+        Self {
+            method: parts.method.to_string(),
+            path: parts.uri.path().to_owned(),
+            query: parts.uri.query().map(ToOwned::to_owned),
+            headers,
+            body,
+        }
+    }
+}
+
+/// The result of the policy for the current request, added to the request
+/// extensions by the [`AuthorizeLayer`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision(pub serde_json::Value);
+
+impl Decision {
+    /// Whether the request is allowed: the policy must return either `true`,
+    /// or an object with an `allow` field set to `true`
+    #[must_use]
+    pub fn is_allowed(&self) -> bool {
+        match &self.0 {
+            serde_json::Value::Bool(allow) => *allow,
+            serde_json::Value::Object(object) => {
+                object.get("allow") == Some(&serde_json::Value::Bool(true))
+            }
+            _ => false,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Decision {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<Self>().cloned().ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "the route is not authorized by a policy",
+        ))
+    }
+}
+
+/// A [`Layer`] evaluating a policy entrypoint for each request, rejecting
+/// the denied requests with a `403 Forbidden` response.
+///
+/// The policy gets the `method`, `path`, `query` and `headers` of the
+/// request as input, and optionally its `body`. Allowed requests get the
+/// [`Decision`] in their extensions, so that handlers can extract it.
+pub struct AuthorizeLayer<C> {
+    pool: Arc<PolicyPool<C>>,
+    entrypoint: Arc<str>,
+    body_limit: Option<usize>,
+}
+
+impl<C> Clone for AuthorizeLayer<C> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            entrypoint: self.entrypoint.clone(),
+            body_limit: self.body_limit,
+        }
+    }
+}
+
+impl<C> std::fmt::Debug for AuthorizeLayer<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthorizeLayer")
+            .field("entrypoint", &self.entrypoint)
+            .field("body_limit", &self.body_limit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C> AuthorizeLayer<C> {
+    /// Authorize requests with the given entrypoint, evaluated on the
+    /// instances of the pool
+    #[must_use]
+    pub fn new(pool: Arc<PolicyPool<C>>, entrypoint: &str) -> Self {
+        Self {
+            pool,
+            entrypoint: entrypoint.into(),
+            body_limit: None,
+        }
+    }
+
+    /// Pass the request body to the policy. Requests with a body bigger than
+    /// `limit` bytes are rejected with a `413 Payload Too Large` response.
+    #[must_use]
+    pub fn with_body(mut self, limit: usize) -> Self {
+        self.body_limit = Some(limit);
+        self
+    }
+}
+
+impl<S, C> Layer<S> for AuthorizeLayer<C> {
+    type Service = Authorize<S, C>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Authorize {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The [`Service`] created by an [`AuthorizeLayer`]
+pub struct Authorize<S, C> {
+    inner: S,
+    layer: AuthorizeLayer<C>,
+}
+
+impl<S: Clone, C> Clone for Authorize<S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S: std::fmt::Debug, C> std::fmt::Debug for Authorize<S, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Authorize")
+            .field("inner", &self.inner)
+            .field("layer", &self.layer)
+            .finish()
+    }
+}
+
+impl<S, C> Service<Request<Body>> for Authorize<S, C>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    C: EvaluationContext,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        // Use the service which was driven to readiness, and leave a fresh
+        // clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+
+            let (input, body) = if let Some(limit) = layer.body_limit {
+                let Ok(bytes) = hyper::body::to_bytes(http_body::Limited::new(body, limit)).await
+                else {
+                    return Ok(StatusCode::PAYLOAD_TOO_LARGE.into_response());
+                };
+                let input = RequestInput::new(&parts, Some(&bytes));
+                (input, Body::from(bytes))
+            } else {
+                (RequestInput::new(&parts, None), body)
+            };
+
+            let decision = match evaluate(&layer, &input).await {
+                Ok(decision) => decision,
+                Err(e) => {
+                    tracing::error!(
+                        error = &e as &dyn std::error::Error,
+                        "failed to evaluate the policy"
+                    );
+                    return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+                }
+            };
+
+            if !decision.is_allowed() {
+                return Ok(StatusCode::FORBIDDEN.into_response());
+            }
+
+            parts.extensions.insert(decision);
+            inner.call(Request::from_parts(parts, body)).await
+        })
+    }
+}
+
+async fn evaluate<C: EvaluationContext>(
+    layer: &AuthorizeLayer<C>,
+    input: &RequestInput,
+) -> Result<Decision, crate::Error> {
+    let results: ResultSet = layer.pool.evaluate(&layer.entrypoint, input).await?;
+
+    // An undefined result denies the request
+    let result = results.into_optional()?.unwrap_or(serde_json::Value::Null);
+    Ok(Decision(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn request_input() {
+        let request = Request::post("/api/items?page=2")
+            .header("Content-Type", "application/json")
+            .header("X-Forwarded-For", "10.0.0.1")
+            .header("X-Forwarded-For", "10.0.0.2")
+            .body(())
+            .unwrap();
+        let (parts, ()) = request.into_parts();
+
+        let input = RequestInput::new(&parts, Some(br#"{"name": "foo"}"#));
+        assert_eq!(
+            serde_json::to_value(&input).unwrap(),
+            json!({
+                "method": "POST",
+                "path": "/api/items",
+                "query": "page=2",
+                "headers": {
+                    "content-type": "application/json",
+                    "x-forwarded-for": "10.0.0.1, 10.0.0.2",
+                },
+                "body": {"name": "foo"},
+            })
+        );
+
+        let input = RequestInput::new(&parts, Some(b"not json"));
+        assert_eq!(input.body, Some(json!("not json")));
+    }
+
+    #[test]
+    fn decision() {
+        assert!(Decision(json!(true)).is_allowed());
+        assert!(Decision(json!({"allow": true, "reason": "admin"})).is_allowed());
+        assert!(!Decision(json!(false)).is_allowed());
+        assert!(!Decision(json!({"allow": "yes"})).is_allowed());
+        assert!(!Decision(serde_json::Value::Null).is_allowed());
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Instrumentation hooks for builtin calls
+
+use std::time::Duration;
+
+/// The outcome of a builtin call, passed to [`BuiltinObserver::on_end`]
+#[derive(Debug)]
+pub enum BuiltinOutcome<'a> {
+    /// The builtin returned successfully
+    Success {
+        /// Size in bytes of the JSON-serialized result
+        result_size: usize,
+    },
+
+    /// The builtin failed
+    Error(&'a anyhow::Error),
+}
+
+impl BuiltinOutcome<'_> {
+    /// Whether the builtin call succeeded
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        matches!(self, Self::Success { .. })
+    }
+}
+
+/// Receives events for every builtin call made by a policy, e.g. to record
+/// per-builtin latency metrics.
+///
+/// Observers are called synchronously on the evaluation path, so they should
+/// not block.
+pub trait BuiltinObserver: Send + Sync {
+    /// Called before the builtin is invoked, with the total size in bytes of
+    /// its JSON-serialized arguments
+    fn on_start(&self, name: &str, args_size: usize) {
+        let _ = (name, args_size);
+    }
+
+    /// Called after the builtin returned, with the time spent in the call
+    fn on_end(
+        &self,
+        name: &str,
+        args_size: usize,
+        duration: Duration,
+        outcome: BuiltinOutcome<'_>,
+    ) {
+        let _ = (name, args_size, duration, outcome);
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversion of JSON Patch and JSON Merge Patch documents to path updates
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// A single update of the data document
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum PathUpdate {
+    /// Set the value at the given path
+    Set(Vec<String>, Value),
+
+    /// Remove the value at the given path
+    Remove(Vec<String>),
+}
+
+/// An operation of a JSON Patch (RFC 6902) document. Only the operations
+/// which can be applied to objects without reading the document are
+/// supported.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Operation {
+    Add { path: String, value: Value },
+    Replace { path: String, value: Value },
+    Remove { path: String },
+}
+
+/// Parse a JSON Pointer (RFC 6901) in its segments
+fn parse_pointer(pointer: &str) -> Result<Vec<String>> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let Some(pointer) = pointer.strip_prefix('/') else {
+        bail!("JSON pointer {pointer:?} must start with a slash");
+    };
+
+    Ok(pointer
+        .split('/')
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+/// Convert a JSON Patch document to a list of updates
+pub(crate) fn json_patch(patch: Value) -> Result<Vec<PathUpdate>> {
+    let operations: Vec<Operation> =
+        serde_json::from_value(patch).context("invalid or unsupported JSON Patch")?;
+
+    operations
+        .into_iter()
+        .map(|operation| {
+            Ok(match operation {
+                Operation::Add { path, value } | Operation::Replace { path, value } => {
+                    PathUpdate::Set(parse_pointer(&path)?, value)
+                }
+                Operation::Remove { path } => PathUpdate::Remove(parse_pointer(&path)?),
+            })
+        })
+        .collect()
+}
+
+/// Convert a JSON Merge Patch (RFC 7396) document to a list of updates.
+///
+/// Nested objects are merged key by key, which assumes the patched document
+/// has objects at the same locations.
+pub(crate) fn merge_patch(patch: Value) -> Vec<PathUpdate> {
+    let mut updates = Vec::new();
+    match patch {
+        Value::Object(object) => merge_object(&mut Vec::new(), object, &mut updates),
+        value => updates.push(PathUpdate::Set(Vec::new(), value)),
+    }
+    updates
+}
+
+fn merge_object(
+    path: &mut Vec<String>,
+    object: serde_json::Map<String, Value>,
+    updates: &mut Vec<PathUpdate>,
+) {
+    for (key, value) in object {
+        path.push(key);
+        match value {
+            Value::Null => updates.push(PathUpdate::Remove(path.clone())),
+            Value::Object(object) => merge_object(path, object, updates),
+            value => updates.push(PathUpdate::Set(path.clone(), value)),
+        }
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn path(segments: &[&str]) -> Vec<String> {
+        segments.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn json_patch_updates() {
+        let updates = json_patch(json!([
+            {"op": "add", "path": "/a/b", "value": 1},
+            {"op": "replace", "path": "/a~1b/c~0d", "value": [2]},
+            {"op": "remove", "path": "/x"},
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            updates,
+            vec![
+                PathUpdate::Set(path(&["a", "b"]), json!(1)),
+                PathUpdate::Set(path(&["a/b", "c~d"]), json!([2])),
+                PathUpdate::Remove(path(&["x"])),
+            ]
+        );
+
+        assert!(json_patch(json!([{"op": "move", "from": "/a", "path": "/b"}])).is_err());
+        assert!(json_patch(json!([{"op": "remove", "path": "a"}])).is_err());
+    }
+
+    #[test]
+    fn merge_patch_updates() {
+        let updates = merge_patch(json!({"a": {"b": 1, "c": null}, "d": [1]}));
+        assert_eq!(
+            updates,
+            vec![
+                PathUpdate::Set(path(&["a", "b"]), json!(1)),
+                PathUpdate::Remove(path(&["a", "c"])),
+                PathUpdate::Set(path(&["d"]), json!([1])),
+            ]
+        );
+    }
+}
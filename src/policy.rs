@@ -16,19 +16,46 @@ use std::{
     collections::{HashMap, HashSet},
     ffi::CString,
     fmt::Debug,
+    future::Future,
+    io::Write,
     ops::Deref,
-    sync::Arc,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use anyhow::{Context, Result};
-use tokio::sync::{Mutex, OnceCell};
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::{Mutex, OnceCell},
+};
 use tracing::Instrument;
-use wasmtime::{AsContextMut, Caller, Linker, Memory, MemoryType, Module};
+use wasmtime::{AsContext, AsContextMut, Caller, Engine, Linker, Memory, MemoryType, Module, Trap};
 
+#[cfg(feature = "decision-logs")]
+use crate::decision_log::{BuiltinError, DecisionLogger};
+#[cfg(feature = "input-schema")]
+use crate::error::{InvalidInput, SchemaViolation};
 use crate::{
-    builtins::traits::Builtin,
+    builtins::traits::{Builtin, BuiltinFunc},
+    capabilities::{BuiltinNotAllowed, Capabilities, DisallowedBuiltin},
+    coverage::Coverage,
+    error::{
+        summarize_arguments, BuiltinFailed, DataMountConflict, Error, EvaluationCancelled,
+        MemoryLimitExceeded, OutOfFuel, PolicyAborted,
+    },
     funcs::{self, Func},
-    types::{AbiVersion, Addr, BuiltinId, EntrypointId, Heap, NulStr, Value},
+    memo::{BuiltinMemo, BuiltinMemoization},
+    observer::{BuiltinObserver, BuiltinOutcome},
+    patch::PathUpdate,
+    print::PrintHook,
+    replay::{is_non_deterministic, BuiltinRecorder, BuiltinReplayer, ReplayMode},
+    result_set::ResultSet,
+    trace::{Trace, TraceEvent},
+    types::{AbiFeature, AbiVersion, Addr, BuiltinId, EntrypointId, Heap, NulStr, Value},
     DefaultContext, EvaluationContext,
 };
 
@@ -53,6 +80,23 @@ async fn alloc_str<V: Into<Vec<u8>>, T: Send>(
     Ok(heap)
 }
 
+/// Size of a WebAssembly page, in bytes
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// Counts the bytes written to it
+struct ByteCount(usize);
+
+impl Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 async fn load_json<V: serde::Serialize, T: Send>(
     opa_malloc: &funcs::OpaMalloc,
     opa_free: &funcs::OpaFree,
@@ -68,9 +112,189 @@ async fn load_json<V: serde::Serialize, T: Send>(
     Ok(data)
 }
 
+/// Bytes of heap the updates of the `data` document can leave behind before
+/// it gets compacted, when the document itself is smaller than that
+const DATA_COMPACTION_THRESHOLD: i32 = 1024 * 1024;
+
+/// Interval at which [`Policy::evaluate_until`] increments the engine epoch
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Epoch tickers of the engines with evaluations in [`Policy::evaluate_until`]
+static EPOCH_TICKERS: std::sync::Mutex<Vec<EpochTicker>> = std::sync::Mutex::new(Vec::new());
+
+/// Increments the epoch of an engine from a background thread, until dropped
+struct EpochTicker {
+    engine: Engine,
+    users: usize,
+    _stop: mpsc::Sender<()>,
+}
+
+impl EpochTicker {
+    fn start(engine: Engine) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let ticked = engine.clone();
+        std::thread::spawn(move || {
+            // Dropping the sender disconnects the channel and stops the loop
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(EPOCH_TICK) {
+                ticked.increment_epoch();
+            }
+        });
+        Self {
+            engine,
+            users: 1,
+            _stop: stop,
+        }
+    }
+
+    fn tickers() -> std::sync::MutexGuard<'static, Vec<EpochTicker>> {
+        EPOCH_TICKERS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Keeps the shared epoch ticker of an engine running, which stops once its
+/// last lease is dropped
+struct EpochTickerLease {
+    engine: Engine,
+}
+
+impl EpochTickerLease {
+    fn acquire(engine: &Engine) -> Self {
+        let mut tickers = EpochTicker::tickers();
+        match tickers.iter_mut().find(|t| Engine::same(&t.engine, engine)) {
+            Some(ticker) => ticker.users += 1,
+            None => tickers.push(EpochTicker::start(engine.clone())),
+        }
+        Self {
+            engine: engine.clone(),
+        }
+    }
+}
+
+impl Drop for EpochTickerLease {
+    fn drop(&mut self) {
+        let mut tickers = EpochTicker::tickers();
+        if let Some(idx) = tickers
+            .iter()
+            .position(|t| Engine::same(&t.engine, &self.engine))
+        {
+            tickers[idx].users -= 1;
+            if tickers[idx].users == 0 {
+                tickers.swap_remove(idx);
+            }
+        }
+    }
+}
+
+/// Makes a store yield back on every epoch change for the duration of an
+/// evaluation, and sets it back to trapping on its epoch deadline once
+/// dropped, even if the evaluation future was dropped before completing
+struct EpochYield<S: AsContextMut> {
+    store: S,
+    _ticker: EpochTickerLease,
+}
+
+impl<S: AsContextMut> EpochYield<S> {
+    fn new(mut store: S) -> Self {
+        let mut ctx = store.as_context_mut();
+        ctx.set_epoch_deadline(1);
+        ctx.epoch_deadline_async_yield_and_update(1);
+        let ticker = EpochTickerLease::acquire(ctx.engine());
+        Self {
+            store,
+            _ticker: ticker,
+        }
+    }
+}
+
+impl<S: AsContextMut> Drop for EpochYield<S> {
+    fn drop(&mut self) {
+        self.store.as_context_mut().epoch_deadline_trap();
+    }
+}
+
+/// Find the document of `data` a document mounted at `path` would replace,
+/// either the value already at that path or a non-object value above it
+fn mount_conflict(data: &serde_json::Value, path: &[String]) -> Option<String> {
+    if path.is_empty() {
+        // Mounting at the root only replaces an empty document
+        return match data {
+            serde_json::Value::Object(object) if object.is_empty() => None,
+            _ => Some(String::new()),
+        };
+    }
+
+    let mut current = data;
+    for (depth, key) in path.iter().enumerate() {
+        let serde_json::Value::Object(object) = current else {
+            return Some(path[..depth].join("."));
+        };
+        current = object.get(key)?;
+    }
+    Some(path.join("."))
+}
+
+/// Size of the chunks a streamed `data` document is copied to the linear
+/// memory in
+const DATA_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Copy a JSON document of `len` bytes from the reader to the linear memory
+/// chunk by chunk, and parse it there
+async fn load_json_reader<R: AsyncRead + Unpin, T: Send>(
+    opa_malloc: &funcs::OpaMalloc,
+    opa_free: &funcs::OpaFree,
+    opa_json_parse: &funcs::OpaJsonParse,
+    mut store: impl AsContextMut<Data = T>,
+    memory: &Memory,
+    mut reader: R,
+    len: usize,
+) -> Result<Value> {
+    let heap = opa_malloc.call(&mut store, len).await?;
+    let base: usize = heap
+        .ptr
+        .try_into()
+        .context("opa_malloc returned an invalid pointer value")?;
+
+    let mut chunk = vec![0; DATA_CHUNK_SIZE.min(len)];
+    let mut offset = 0;
+    while offset < len {
+        let size = chunk.len().min(len - offset);
+        let read = reader.read(&mut chunk[..size]).await?;
+        if read == 0 {
+            bail!("the data document is shorter than the expected {len} bytes");
+        }
+        memory.write(&mut store, base + offset, &chunk[..read])?;
+        offset += read;
+    }
+
+    let data = opa_json_parse.call(&mut store, &heap).await?;
+    opa_free.call(&mut store, heap).await?;
+    if data.0 == 0 {
+        bail!("the data document is not valid JSON");
+    }
+    Ok(data)
+}
+
 struct LoadedBuiltins<C> {
     builtins: HashMap<i32, (String, Box<dyn Builtin<C>>)>,
     context: Mutex<C>,
+    observer: Option<Arc<dyn BuiltinObserver>>,
+    replay: Option<ReplayMode>,
+    memo: Option<BuiltinMemo>,
+    strict_errors: bool,
+
+    /// The entrypoint of the current evaluation
+    entrypoint: std::sync::Mutex<Option<String>>,
+    #[cfg(feature = "decision-logs")]
+    builtin_errors: std::sync::Mutex<Vec<BuiltinError>>,
+
+    /// The trace of the current evaluation, if it is traced
+    trace: std::sync::Mutex<Option<Trace>>,
+
+    /// Whether the current evaluation is the discovery pass of
+    /// [`Policy::evaluate_with_prefetch`], which must not have side effects
+    discovering: AtomicBool,
 }
 
 impl<C> std::fmt::Debug for LoadedBuiltins<C> {
@@ -81,21 +305,92 @@ impl<C> std::fmt::Debug for LoadedBuiltins<C> {
     }
 }
 
+impl<C> LoadedBuiltins<C> {
+    /// Add an event to the trace of the current evaluation, if it is traced
+    fn trace_event(&self, event: impl FnOnce() -> Option<TraceEvent>) {
+        let mut trace = self
+            .trace
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(trace) = trace.as_mut() {
+            if let Some(event) = event() {
+                trace.push(event);
+            }
+        }
+    }
+
+    /// Start or stop tracing the evaluations, returning the trace collected
+    /// so far
+    fn set_tracing(&self, enabled: bool) -> Option<Trace> {
+        let mut trace = self
+            .trace
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        std::mem::replace(&mut *trace, enabled.then(Trace::default))
+    }
+
+    fn is_discovering(&self) -> bool {
+        self.discovering.load(Ordering::Relaxed)
+    }
+
+    /// Describe where a builtin is called from, with the current entrypoint
+    /// and the last note of the trace, if the evaluation is traced
+    fn call_location(&self) -> Option<String> {
+        let entrypoint = self
+            .entrypoint
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .as_ref()
+            .map(|entrypoint| format!("data.{}", entrypoint.replace('/', ".")))?;
+        let trace = self
+            .trace
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        match trace.as_ref().and_then(|trace| trace.notes().last()) {
+            Some(note) => Some(format!("{entrypoint}, after the note {note:?}")),
+            None => Some(entrypoint),
+        }
+    }
+}
+
 impl<C> LoadedBuiltins<C>
 where
     C: EvaluationContext,
 {
-    fn from_map(map: HashMap<String, BuiltinId>, context: C) -> Result<Self> {
+    fn from_map(
+        map: HashMap<String, BuiltinId>,
+        context: C,
+        capabilities: &Capabilities,
+        mut custom: HashMap<String, Box<dyn Builtin<C>>>,
+        observer: Option<Arc<dyn BuiltinObserver>>,
+        replay: Option<ReplayMode>,
+        memo: Option<BuiltinMemo>,
+    ) -> Result<Self> {
         let res: Result<_> = map
             .into_iter()
             .map(|(k, v)| {
-                let builtin = crate::builtins::resolve(&k)?;
+                let builtin: Box<dyn Builtin<C>> = if !capabilities.allows(&k) {
+                    Box::new(DisallowedBuiltin::new(&k))
+                } else if let Some(builtin) = custom.remove(&k) {
+                    builtin
+                } else {
+                    crate::builtins::resolve(&k)?
+                };
                 Ok((v.0, (k, builtin)))
             })
             .collect();
         Ok(Self {
             builtins: res?,
             context: Mutex::new(context),
+            observer,
+            replay,
+            memo,
+            strict_errors: true,
+            entrypoint: std::sync::Mutex::default(),
+            #[cfg(feature = "decision-logs")]
+            builtin_errors: std::sync::Mutex::default(),
+            trace: std::sync::Mutex::default(),
+            discovering: AtomicBool::new(false),
         })
     }
 
@@ -111,43 +406,275 @@ where
             .get(&builtin_id)
             .with_context(|| format!("unknown builtin id {builtin_id}"))?;
 
-        let span = tracing::info_span!("builtin", %name);
-        let _enter = span.enter();
+        // The span is attached to the future instead of being entered, as
+        // holding an entered span across await points would leak it to
+        // unrelated tasks
+        self.call_builtin(&mut caller, memory, name, builtin.as_ref(), args)
+            .instrument(tracing::info_span!("builtin", %name))
+            .await
+    }
 
-        let opa_json_dump = funcs::OpaJsonDump::from_caller(&mut caller)?;
-        let opa_json_parse = funcs::OpaJsonParse::from_caller(&mut caller)?;
-        let opa_malloc = funcs::OpaMalloc::from_caller(&mut caller)?;
-        let opa_free = funcs::OpaFree::from_caller(&mut caller)?;
+    async fn call_builtin<T: Send, const N: usize>(
+        &self,
+        caller: &mut Caller<'_, T>,
+        memory: &Memory,
+        name: &str,
+        builtin: &dyn Builtin<C>,
+        args: [i32; N],
+    ) -> Result<i32, anyhow::Error> {
+        let opa_json_dump = funcs::OpaJsonDump::from_caller(caller)?;
+        let opa_json_parse = funcs::OpaJsonParse::from_caller(caller)?;
+        let opa_malloc = funcs::OpaMalloc::from_caller(caller)?;
+        let opa_free = funcs::OpaFree::from_caller(caller)?;
 
         // Call opa_json_dump on each argument
         let mut args_json = Vec::with_capacity(N);
         for arg in args {
-            args_json.push(opa_json_dump.call(&mut caller, &Value(arg)).await?);
+            args_json.push(opa_json_dump.call(&mut *caller, &Value(arg)).await?);
         }
 
         // Extract the JSON value of each argument
         let mut mapped_args = Vec::with_capacity(N);
         for arg_json in args_json {
-            let arg = arg_json.read(&caller, memory)?;
+            let arg = arg_json.read(&*caller, memory)?;
             mapped_args.push(arg.to_bytes());
         }
 
-        let mut ctx = self.context.lock().await;
+        // The discovery pass sees fake `http.send` responses, which must not
+        // be memoized
+        let memo = self.memo.as_ref().filter(|_| !self.is_discovering());
+        let memo_key = memo.and_then(|memo| memo.key(name, &mapped_args));
+        let memoized = memo
+            .zip(memo_key.as_deref())
+            .and_then(|(memo, key)| memo.get(key));
 
-        // Actually call the function
-        let ret = (async move { builtin.call(&mut ctx, &mapped_args).await })
-            .instrument(tracing::info_span!("builtin.call"))
-            .await?;
+        let ret = if let Some(ret) = memoized {
+            ret
+        } else {
+            let ret = match self.invoke(name, builtin, &mapped_args).await {
+                Ok(ret) => ret,
+                // Outside of the strict mode, a failing builtin is undefined,
+                // which the policy sees as a NULL value. Calling a builtin
+                // which is not allowed stays fatal.
+                Err(e)
+                    if !self.strict_errors
+                        && !e
+                            .chain()
+                            .any(<dyn std::error::Error>::is::<BuiltinNotAllowed>) =>
+                {
+                    tracing::debug!(
+                        error = %format!("{e:#}"),
+                        "builtin failed, treating it as undefined"
+                    );
+                    return Ok(0);
+                }
+                Err(e) => return Err(e),
+            };
+            if let Some((memo, key)) = memo.zip(memo_key) {
+                memo.insert(key, ret.clone());
+            }
+            ret
+        };
 
-        let json = alloc_str(&opa_malloc, &mut caller, memory, ret).await?;
-        let data = opa_json_parse.call(&mut caller, &json).await?;
-        opa_free.call(&mut caller, json).await?;
+        let json = alloc_str(&opa_malloc, &mut *caller, memory, ret).await?;
+        let data = opa_json_parse.call(&mut *caller, &json).await?;
+        opa_free.call(&mut *caller, json).await?;
 
         Ok(data.0)
     }
 
-    async fn evaluation_start(&self) {
-        self.context.lock().await.evaluation_start();
+    /// Call the builtin, with the observer, replay, metrics, tracing and
+    /// decision logs bookkeeping
+    async fn invoke(
+        &self,
+        name: &str,
+        builtin: &dyn Builtin<C>,
+        mapped_args: &[&[u8]],
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let mut ctx = self.context.lock().await;
+
+        // The discovery pass is not observed, recorded nor logged, as the
+        // evaluation is run again afterwards
+        let discovering = self.is_discovering();
+        let observer = self.observer.as_ref().filter(|_| !discovering);
+
+        let args_size = mapped_args.iter().map(|arg| arg.len()).sum();
+        if let Some(observer) = observer {
+            observer.on_start(name, args_size);
+        }
+        let start = Instant::now();
+
+        let replay = self
+            .replay
+            .as_ref()
+            .filter(|_| !discovering && is_non_deterministic(name));
+
+        // Actually call the function, unless its result is replayed
+        let ret = if ctx
+            .capabilities()
+            .is_some_and(|capabilities| !capabilities.allows(name))
+        {
+            Err(BuiltinNotAllowed::new(name).into())
+        } else if let Some(ReplayMode::Replay(replayer)) = replay {
+            replayer.replay(name, mapped_args)
+        } else if discovering && name == "internal.print" {
+            // Like `opa_println`, prints are left to the actual evaluation
+            Ok(b"true".to_vec())
+        } else {
+            (async { builtin.call(&mut ctx, mapped_args).await })
+                .instrument(tracing::info_span!("builtin.call"))
+                .await
+        };
+
+        let ret = match (replay, ret) {
+            (Some(ReplayMode::Record(recorder)), Ok(ret)) => {
+                recorder.record(name, mapped_args, &ret).map(|()| ret)
+            }
+            (_, ret) => ret,
+        };
+
+        if let Some(observer) = observer {
+            let outcome = match &ret {
+                Ok(ret) => BuiltinOutcome::Success {
+                    result_size: ret.len(),
+                },
+                Err(e) => BuiltinOutcome::Error(e),
+            };
+            observer.on_end(name, args_size, start.elapsed(), outcome);
+        }
+        #[cfg(feature = "metrics")]
+        if !discovering {
+            crate::metrics::observe_builtin(name, start.elapsed(), ret.is_ok());
+        }
+
+        if name == "trace" && ret.is_ok() && !discovering {
+            self.trace_event(|| {
+                let message = serde_json::from_slice(mapped_args.first()?).ok()?;
+                Some(TraceEvent::Note { message })
+            });
+        }
+
+        #[cfg(feature = "decision-logs")]
+        if let (Err(e), false) = (&ret, discovering) {
+            self.builtin_errors
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .push(BuiltinError {
+                    name: name.to_owned(),
+                    message: format!("{e:#}"),
+                });
+        }
+
+        let ret = ret.map_err(|source| BuiltinFailed {
+            name: name.to_owned(),
+            arguments: summarize_arguments(mapped_args),
+            location: self.call_location(),
+            source,
+        })?;
+
+        Ok(ret)
+    }
+
+    async fn evaluation_start(&self, entrypoint: &str) {
+        *self
+            .entrypoint
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(entrypoint.to_owned());
+
+        #[cfg(feature = "decision-logs")]
+        self.builtin_errors
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clear();
+
+        if let Some(memo) = &self.memo {
+            memo.evaluation_start();
+        }
+
+        let mut context = self.context.lock().await;
+        context.evaluation_start();
+        if let Some(metadata) = context.rego_metadata() {
+            metadata.enter(entrypoint);
+        }
+
+        #[cfg(feature = "time")]
+        if self.is_discovering() {
+            // The evaluation time is recorded by the evaluation following the
+            // discovery pass
+        } else if let Err(e) = self.replay_evaluation_time(&mut *context) {
+            tracing::warn!(error = ?e, "could not record or replay the evaluation time");
+        }
+
+        #[cfg(feature = "jwt-jwks")]
+        if let Some(resolver) = context.jwks_resolver() {
+            resolver.set_replay(self.replay.clone());
+        }
+    }
+
+    /// Record the time the evaluation started, or replace it with the
+    /// recorded one, as builtins like `io.jwt.decode_verify` depend on it
+    #[cfg(feature = "time")]
+    fn replay_evaluation_time(&self, context: &mut C) -> Result<()> {
+        use chrono::TimeZone;
+
+        use crate::replay::EVALUATION_TIME;
+
+        match &self.replay {
+            Some(ReplayMode::Record(recorder)) => {
+                let now = context
+                    .now()
+                    .timestamp_nanos_opt()
+                    .context("evaluation time out of range")?;
+                recorder.record(EVALUATION_TIME, &[], &serde_json::to_vec(&now)?)?;
+            }
+            Some(ReplayMode::Replay(replayer)) => {
+                let now = replayer.replay(EVALUATION_TIME, &[])?;
+                let now: i64 = serde_json::from_slice(&now)?;
+                context.set_now(chrono::Utc.timestamp_nanos(now));
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    /// The builtin calls which failed since the evaluation started
+    #[cfg(feature = "decision-logs")]
+    fn take_builtin_errors(&self) -> Vec<BuiltinError> {
+        std::mem::take(
+            &mut self
+                .builtin_errors
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+        )
+    }
+}
+
+/// Ends the discovery pass of [`Policy::evaluate_with_prefetch`] when dropped
+#[cfg(feature = "http-builtins")]
+struct DiscoveryGuard<'a, C>(&'a LoadedBuiltins<C>);
+
+#[cfg(feature = "http-builtins")]
+impl<'a, C> DiscoveryGuard<'a, C> {
+    fn start(builtins: &'a LoadedBuiltins<C>) -> Self {
+        builtins.discovering.store(true, Ordering::Relaxed);
+        Self(builtins)
+    }
+}
+
+#[cfg(feature = "http-builtins")]
+impl<C> Drop for DiscoveryGuard<'_, C> {
+    fn drop(&mut self) {
+        self.0.discovering.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Stops tracing the evaluations when dropped
+struct TracingGuard<'a, C>(&'a LoadedBuiltins<C>);
+
+impl<C> Drop for TracingGuard<'_, C> {
+    fn drop(&mut self) {
+        self.0.set_tracing(false);
     }
 }
 
@@ -156,8 +683,15 @@ where
 pub struct Runtime<C> {
     version: AbiVersion,
     memory: Memory,
+    max_memory_pages: Option<u32>,
     entrypoints: HashMap<String, EntrypointId>,
     loaded_builtins: Arc<OnceCell<LoadedBuiltins<C>>>,
+    #[cfg(feature = "decision-logs")]
+    decision_logger: Option<DecisionLogger>,
+    coverage: Option<Coverage>,
+    data_mounts: Vec<(Vec<String>, serde_json::Value)>,
+    #[cfg(feature = "input-schema")]
+    input_schemas: HashMap<String, jsonschema::JSONSchema>,
 
     eval_func: funcs::Eval,
     opa_eval_ctx_new_func: funcs::OpaEvalCtxNew,
@@ -171,7 +705,10 @@ pub struct Runtime<C> {
     opa_json_dump_func: funcs::OpaJsonDump,
     opa_heap_ptr_set_func: funcs::OpaHeapPtrSet,
     opa_heap_ptr_get_func: funcs::OpaHeapPtrGet,
+    base_heap_ptr: Addr,
     opa_eval_func: Option<funcs::OpaEval>,
+    opa_value_add_path_func: Option<funcs::OpaValueAddPath>,
+    opa_value_remove_path_func: Option<funcs::OpaValueRemovePath>,
 }
 
 impl<C> Debug for Runtime<C> {
@@ -199,13 +736,213 @@ impl Runtime<DefaultContext> {
     ///    some of the exported functions
     ///  - it failed to load the entrypoints or the builtins list
     #[allow(clippy::too_many_lines)]
-    pub async fn new<T: Send>(store: impl AsContextMut<Data = T>, module: &Module) -> Result<Self> {
+    pub async fn new<T: Send>(
+        store: impl AsContextMut<Data = T>,
+        module: &Module,
+    ) -> Result<Self, Error> {
         let context = DefaultContext::default();
         Self::new_with_evaluation_context(store, module, context).await
     }
 }
 
+/// A builder to customize a [`Runtime`] before loading a policy module
+pub struct RuntimeBuilder<C> {
+    context: C,
+    capabilities: Capabilities,
+    builtins: HashMap<String, Box<dyn Builtin<C>>>,
+    observer: Option<Arc<dyn BuiltinObserver>>,
+    replay: Option<ReplayMode>,
+    #[cfg(feature = "decision-logs")]
+    decision_logger: Option<DecisionLogger>,
+    coverage: Option<Coverage>,
+    max_memory_pages: Option<u32>,
+    eval_fastpath: bool,
+    print_hook: Option<Arc<dyn PrintHook>>,
+    memoization: Option<BuiltinMemoization>,
+    strict_builtin_errors: bool,
+}
+
+impl<C> RuntimeBuilder<C> {
+    /// Restrict the builtins the policy is allowed to call
+    #[must_use]
+    pub fn capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Limit the linear memory the policy can grow to, in 64 KiB pages.
+    ///
+    /// Evaluations needing more memory fail with a [`MemoryLimitExceeded`]
+    /// error.
+    #[must_use]
+    pub fn max_memory_pages(mut self, pages: u32) -> Self {
+        self.max_memory_pages = Some(pages);
+        self
+    }
+
+    /// Whether to use the one-shot `opa_eval` export when the module has it,
+    /// which is the case for modules targeting ABI 1.2 or later. It is
+    /// enabled by default; disabling it falls back to the legacy
+    /// `opa_eval_ctx_*` flow, e.g. to compare both.
+    #[must_use]
+    pub fn eval_fastpath(mut self, enabled: bool) -> Self {
+        self.eval_fastpath = enabled;
+        self
+    }
+
+    /// Whether a failing builtin aborts the evaluation, like OPA's
+    /// `strict-builtin-errors` option. It is enabled by default, and the
+    /// evaluation fails with an [`Error::Builtin`] carrying the builtin name
+    /// and its error. When disabled, the failing call is undefined and the
+    /// evaluation continues, which is the default behavior of OPA: the
+    /// builtin hands a NULL value address back to the module, which the
+    /// compiled policy treats as an undefined result. The error is still
+    /// logged at the debug level, and reported in the decision logs. Calls to
+    /// builtins not allowed by the capabilities always abort the evaluation.
+    #[must_use]
+    pub fn strict_builtin_errors(mut self, enabled: bool) -> Self {
+        self.strict_builtin_errors = enabled;
+        self
+    }
+
+    /// Memoize the results of pure builtins, so that calls with the same
+    /// arguments skip the builtin. Memoized calls are not reported to the
+    /// observer.
+    #[must_use]
+    pub fn memoize_builtins(mut self, memoization: BuiltinMemoization) -> Self {
+        self.memoization = Some(memoization);
+        self
+    }
+
+    /// Set an observer notified of every builtin call
+    #[must_use]
+    pub fn observer(mut self, observer: impl BuiltinObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Record the results of the non-deterministic builtins, like
+    /// `http.send` or `time.now_ns`, so that decisions can be replayed later
+    /// with [`RuntimeBuilder::replay`].
+    ///
+    /// This replaces any replayer previously set.
+    #[must_use]
+    pub fn record(mut self, recorder: BuiltinRecorder) -> Self {
+        self.replay = Some(ReplayMode::Record(recorder));
+        self
+    }
+
+    /// Feed back recorded results instead of calling the non-deterministic
+    /// builtins, to reproduce a previous decision.
+    ///
+    /// This replaces any recorder previously set.
+    #[must_use]
+    pub fn replay(mut self, replayer: BuiltinReplayer) -> Self {
+        self.replay = Some(ReplayMode::Replay(replayer));
+        self
+    }
+
+    /// Emit a [`DecisionLog`](crate::DecisionLog) for each evaluation of the
+    /// policy
+    #[cfg(feature = "decision-logs")]
+    #[must_use]
+    pub fn decision_logger(mut self, logger: DecisionLogger) -> Self {
+        self.decision_logger = Some(logger);
+        self
+    }
+
+    /// Track which entrypoints were covered by the evaluations of the policy
+    #[must_use]
+    pub fn coverage(mut self, coverage: Coverage) -> Self {
+        self.coverage = Some(coverage);
+        self
+    }
+
+    /// Send the messages printed by the policy with `print` statements, or
+    /// through the `opa_println` import, to the given hook, instead of
+    /// emitting them as `tracing` events
+    #[must_use]
+    pub fn print_hook(mut self, hook: impl PrintHook + 'static) -> Self
+    where
+        C: 'static,
+    {
+        let hook: Arc<dyn PrintHook> = Arc::new(hook);
+        self.print_hook = Some(hook.clone());
+        self.builtins.insert(
+            "internal.print".to_owned(),
+            Box::new(crate::builtins::impls::print::HookedPrint::new(hook)),
+        );
+        self
+    }
+
+    /// Register a custom builtin function, resolved by name when the policy
+    /// is loaded.
+    ///
+    /// The function can be sync or async, return a [`Result`] or not, and
+    /// optionally take the evaluation context as first parameter. Its
+    /// parameters and return value are converted from and to JSON.
+    /// Custom builtins take precedence over the ones provided by this crate,
+    /// but are still subject to the [`Capabilities`] restrictions.
+    #[must_use]
+    pub fn register_builtin<
+        F,
+        const ASYNC: bool,
+        const RESULT: bool,
+        const CONTEXT: bool,
+        P: 'static,
+    >(
+        mut self,
+        name: impl Into<String>,
+        func: F,
+    ) -> Self
+    where
+        C: 'static,
+        F: BuiltinFunc<C, ASYNC, RESULT, CONTEXT, P>,
+    {
+        self.builtins.insert(name.into(), func.wrap());
+        self
+    }
+
+    /// Load a new WASM policy module into the given store
+    ///
+    /// # Errors
+    ///
+    /// See [`Runtime::new_with_evaluation_context`]
+    pub async fn build<T: Send>(
+        self,
+        store: impl AsContextMut<Data = T>,
+        module: &Module,
+    ) -> Result<Runtime<C>, Error>
+    where
+        C: EvaluationContext,
+    {
+        Runtime::instantiate(store, module, self)
+            .await
+            .map_err(Error::load)
+    }
+}
+
 impl<C> Runtime<C> {
+    /// Start building a runtime with the given evaluation context
+    #[must_use]
+    pub fn builder(context: C) -> RuntimeBuilder<C> {
+        RuntimeBuilder {
+            context,
+            capabilities: Capabilities::default(),
+            builtins: HashMap::new(),
+            observer: None,
+            replay: None,
+            #[cfg(feature = "decision-logs")]
+            decision_logger: None,
+            coverage: None,
+            max_memory_pages: None,
+            eval_fastpath: true,
+            print_hook: None,
+            memoization: None,
+            strict_builtin_errors: true,
+        }
+    }
+
     /// Load a new WASM policy module into the given store, with a given
     /// evaluation context.
     ///
@@ -219,17 +956,44 @@ impl<C> Runtime<C> {
     ///  - the WASM module is not a valid OPA WASM compiled policy, and lacks
     ///    some of the exported functions
     ///  - it failed to load the entrypoints or the builtins list
-    #[allow(clippy::too_many_lines)]
     pub async fn new_with_evaluation_context<T: Send>(
-        mut store: impl AsContextMut<Data = T>,
+        store: impl AsContextMut<Data = T>,
         module: &Module,
         context: C,
+    ) -> Result<Self, Error>
+    where
+        C: EvaluationContext,
+    {
+        Self::builder(context).build(store, module).await
+    }
+
+    #[allow(clippy::too_many_lines)]
+    async fn instantiate<T: Send>(
+        mut store: impl AsContextMut<Data = T>,
+        module: &Module,
+        builder: RuntimeBuilder<C>,
     ) -> Result<Self>
     where
         C: EvaluationContext,
     {
-        // TODO:: min/max values should be extended from outside the function
-        let ty = MemoryType::new(8, None);
+        let RuntimeBuilder {
+            context,
+            capabilities,
+            builtins: custom_builtins,
+            observer,
+            replay,
+            #[cfg(feature = "decision-logs")]
+            decision_logger,
+            coverage,
+            max_memory_pages,
+            eval_fastpath,
+            print_hook,
+            memoization,
+            strict_builtin_errors,
+        } = builder;
+
+        let min_pages = max_memory_pages.map_or(8, |max| max.min(8));
+        let ty = MemoryType::new(min_pages, max_memory_pages);
         let memory = Memory::new_async(&mut store, ty).await?;
 
         // TODO: make the context configurable and reset it on evaluation
@@ -244,19 +1008,33 @@ impl<C> Runtime<C> {
             move |caller: Caller<'_, _>, addr: i32| -> Result<(), anyhow::Error> {
                 let addr = NulStr(addr);
                 let msg = addr.read(&caller, &memory)?;
-                let msg = msg.to_string_lossy().into_owned();
-                tracing::error!("opa_abort: {}", msg);
-                anyhow::bail!(msg)
+                let message = msg.to_string_lossy().into_owned();
+                tracing::error!("opa_abort: {}", message);
+                Err(PolicyAborted { message }.into())
             },
         )?;
 
+        let printing_builtins = eventually_builtins.clone();
         linker.func_wrap(
             "env",
             "opa_println",
             move |caller: Caller<'_, _>, addr: i32| {
+                // Prints are only shown once, by the evaluation following the
+                // discovery pass of `evaluate_with_prefetch`
+                if printing_builtins
+                    .get()
+                    .is_some_and(LoadedBuiltins::is_discovering)
+                {
+                    return Ok(());
+                }
                 let addr = NulStr(addr);
                 let msg = addr.read(&caller, &memory)?;
-                tracing::info!("opa_print: {}", msg.to_string_lossy());
+                let msg = msg.to_string_lossy();
+                if let Some(hook) = &print_hook {
+                    hook.print(&msg);
+                } else {
+                    tracing::info!("opa_print: {}", msg);
+                }
                 Ok(())
             },
         )?;
@@ -385,27 +1163,54 @@ impl<C> Runtime<C> {
         let builtins = opa_json_dump_func
             .decode(&mut store, &memory, &builtins)
             .await?;
-        let builtins = LoadedBuiltins::from_map(builtins, context)?;
+        let mut builtins = LoadedBuiltins::from_map(
+            builtins,
+            context,
+            &capabilities,
+            custom_builtins,
+            observer,
+            replay,
+            memoization.map(BuiltinMemo::new),
+        )?;
+        builtins.strict_errors = strict_builtin_errors;
         eventually_builtins.set(builtins)?;
 
         // Load the entrypoints map
         let entrypoints = funcs::Entrypoints::from_instance(&mut store, &instance)?
             .call(&mut store)
             .await?;
-        let entrypoints = opa_json_dump_func
+        let entrypoints: HashMap<String, EntrypointId> = opa_json_dump_func
             .decode(&mut store, &memory, &entrypoints)
             .await?;
+        if let Some(coverage) = &coverage {
+            coverage.register(entrypoints.keys().map(String::as_str));
+        }
 
-        let opa_eval_func = version
-            .has_eval_fastpath()
-            .then(|| funcs::OpaEval::from_instance(&mut store, &instance))
-            .transpose()?;
+        // Modules built for older ABIs, or with the export stripped, use the
+        // legacy evaluation flow
+        let opa_eval_func = (eval_fastpath && version.supports(AbiFeature::EvalFastpath))
+            .then(|| funcs::OpaEval::from_instance(&mut store, &instance).ok())
+            .flatten();
+        if eval_fastpath && opa_eval_func.is_none() {
+            tracing::debug!(%version, "eval fastpath not available, using the legacy flow");
+        }
+
+        // The data documents are allocated from there
+        let opa_heap_ptr_get_func = funcs::OpaHeapPtrGet::from_instance(&mut store, &instance)?;
+        let base_heap_ptr = opa_heap_ptr_get_func.call(&mut store).await?;
 
         Ok(Self {
             version,
             memory,
+            max_memory_pages,
             entrypoints,
             loaded_builtins: eventually_builtins,
+            #[cfg(feature = "decision-logs")]
+            decision_logger,
+            coverage,
+            data_mounts: Vec::new(),
+            #[cfg(feature = "input-schema")]
+            input_schemas: HashMap::new(),
 
             eval_func: funcs::Eval::from_instance(&mut store, &instance)?,
             opa_eval_ctx_new_func: funcs::OpaEvalCtxNew::from_instance(&mut store, &instance)?,
@@ -426,8 +1231,15 @@ impl<C> Runtime<C> {
             opa_json_parse_func: funcs::OpaJsonParse::from_instance(&mut store, &instance)?,
             opa_json_dump_func,
             opa_heap_ptr_set_func: funcs::OpaHeapPtrSet::from_instance(&mut store, &instance)?,
-            opa_heap_ptr_get_func: funcs::OpaHeapPtrGet::from_instance(&mut store, &instance)?,
+            opa_heap_ptr_get_func,
+            base_heap_ptr,
             opa_eval_func,
+            opa_value_add_path_func: funcs::OpaValueAddPath::from_instance(&mut store, &instance)
+                .ok(),
+            opa_value_remove_path_func: funcs::OpaValueRemovePath::from_instance(
+                &mut store, &instance,
+            )
+            .ok(),
         })
     }
 
@@ -447,6 +1259,97 @@ impl<C> Runtime<C> {
         .await
     }
 
+    /// Mount a `data` document at the given path, e.g. `["teams"]` for
+    /// `data.teams`, so that independent documents don't have to be merged
+    /// beforehand.
+    ///
+    /// Mounted documents are set once the document given when instantiating
+    /// the policy is loaded, which must not already hold anything at their
+    /// path. Mounting below the root requires the
+    /// [`AbiFeature::DataPathUpdates`] feature.
+    ///
+    /// # Errors
+    ///
+    /// If the value could not be serialized, or with a
+    /// [`DataMountConflict`](crate::DataMountConflict) if a document is
+    /// already mounted at, above or below that path. Instantiating the policy
+    /// also fails with a [`DataMountConflict`](crate::DataMountConflict) if
+    /// the `data` document already holds a value at that path.
+    pub fn add_data_at<V: serde::Serialize>(
+        &mut self,
+        path: &[&str],
+        value: &V,
+    ) -> Result<(), Error> {
+        let path: Vec<String> = path.iter().map(ToString::to_string).collect();
+        if let Some((existing, _)) = self
+            .data_mounts
+            .iter()
+            .find(|(existing, _)| existing.iter().zip(&path).all(|(a, b)| a == b))
+        {
+            return Err(DataMountConflict {
+                path: path.join("."),
+                existing: existing.join("."),
+            }
+            .into());
+        }
+
+        let value = serde_json::to_value(value).context("could not serialize the value")?;
+        self.data_mounts.push((path, value));
+        Ok(())
+    }
+
+    /// Validate the input of an entrypoint against a JSON Schema before each
+    /// evaluation, before it is written into the module memory. Malformed
+    /// inputs are then rejected with an [`InvalidInput`](crate::InvalidInput)
+    /// error listing the violations, instead of leading to confusing
+    /// undefined decisions.
+    ///
+    /// # Errors
+    ///
+    /// If the entrypoint does not exist, or if the schema is invalid
+    #[cfg(feature = "input-schema")]
+    pub fn set_input_schema(
+        &mut self,
+        entrypoint: &str,
+        schema: &serde_json::Value,
+    ) -> Result<(), Error> {
+        if !self.entrypoints.contains_key(entrypoint) {
+            return Err(anyhow::anyhow!("could not find entrypoint {entrypoint}").into());
+        }
+
+        let schema = jsonschema::JSONSchema::compile(schema).map_err(|e| {
+            anyhow::anyhow!("invalid input schema for entrypoint {entrypoint}: {e}")
+        })?;
+        self.input_schemas.insert(entrypoint.to_owned(), schema);
+        Ok(())
+    }
+
+    /// Check the input against the schema of the entrypoint, if any
+    #[cfg(feature = "input-schema")]
+    fn validate_input<V: serde::Serialize>(&self, entrypoint: &str, input: &V) -> Result<()> {
+        let Some(schema) = self.input_schemas.get(entrypoint) else {
+            return Ok(());
+        };
+
+        let input = serde_json::to_value(input).context("could not serialize the input")?;
+        let violations: Vec<SchemaViolation> = match schema.validate(&input) {
+            Ok(()) => return Ok(()),
+            Err(errors) => errors
+                .map(|e| SchemaViolation {
+                    instance_path: e.instance_path.to_string(),
+                    schema_path: e.schema_path.to_string(),
+                    message: e.to_string(),
+                })
+                .collect(),
+        };
+
+        Err(InvalidInput {
+            entrypoint: entrypoint.to_owned(),
+            violations,
+        }
+        .into())
+    }
+
     /// Instanciate the policy with an empty `data` object
     ///
     /// # Errors
@@ -455,7 +1358,7 @@ impl<C> Runtime<C> {
     pub async fn without_data<T: Send>(
         self,
         store: impl AsContextMut<Data = T>,
-    ) -> Result<Policy<C>> {
+    ) -> Result<Policy<C>, Error> {
         let data = serde_json::Value::Object(serde_json::Map::default());
         self.with_data(store, &data).await
     }
@@ -469,24 +1372,125 @@ impl<C> Runtime<C> {
         self,
         mut store: impl AsContextMut<Data = T>,
         data: &V,
-    ) -> Result<Policy<C>> {
-        let data = self.load_json(&mut store, data).await?;
+    ) -> Result<Policy<C>, Error> {
+        let data = self
+            .load_json(&mut store, data)
+            .await
+            .map_err(|e| self.check_memory_limit(&store, e))?;
+        self.into_policy(store, data).await
+    }
+
+    /// Create the policy once its `data` document is loaded, and mount the
+    /// documents added with [`Runtime::add_data_at`]
+    async fn into_policy<T: Send>(
+        mut self,
+        mut store: impl AsContextMut<Data = T>,
+        data: Value,
+    ) -> Result<Policy<C>, Error> {
         let heap_ptr = self.opa_heap_ptr_get_func.call(&mut store).await?;
-        Ok(Policy {
+        let mounts = std::mem::take(&mut self.data_mounts);
+        if !mounts.is_empty() {
+            // The dumped document is allocated after the heap pointer, and
+            // discarded by the updates setting the mounted documents
+            let json = self.opa_json_dump_func.call(&mut store, &data).await?;
+            let json = json.read(&store, &self.memory)?;
+            let base: serde_json::Value = serde_json::from_slice(json.to_bytes())
+                .context("could not parse the data document")?;
+            for (path, _) in &mounts {
+                if let Some(existing) = mount_conflict(&base, path) {
+                    return Err(DataMountConflict {
+                        path: path.join("."),
+                        existing,
+                    }
+                    .into());
+                }
+            }
+        }
+        let mut policy = Policy {
             runtime: self,
             data,
             heap_ptr,
-        })
+            data_heap_size: 0,
+        };
+        policy.data_heap_size = policy.heap_used();
+
+        if !mounts.is_empty() {
+            let updates = mounts
+                .into_iter()
+                .map(|(path, value)| PathUpdate::Set(path, value))
+                .collect();
+            policy
+                .update_data(&mut store, updates)
+                .await
+                .map_err(|e| policy.runtime.check_memory_limit(&store, e))?;
+        }
+        Ok(policy)
     }
 
-    /// Get the default entrypoint of this module. May return [`None`] if no
-    /// entrypoint with ID 0 was found
-    #[must_use]
-    pub fn default_entrypoint(&self) -> Option<&str> {
-        self.entrypoints
-            .iter()
-            .find_map(|(k, v)| (v.0 == 0).then_some(k.as_str()))
-    }
+    /// Instanciate the policy with a JSON `data` document of `len` bytes read
+    /// from `reader`.
+    ///
+    /// The document is copied to the policy memory chunk by chunk and parsed
+    /// there, instead of being deserialized and serialized again in the host
+    /// memory first, which keeps the memory usage low for large documents.
+    ///
+    /// # Errors
+    ///
+    /// If the reader failed or yielded less than `len` bytes, or if the
+    /// document is not valid JSON
+    pub async fn with_data_reader<R: AsyncRead + Unpin + Send, T: Send>(
+        self,
+        mut store: impl AsContextMut<Data = T>,
+        reader: R,
+        len: usize,
+    ) -> Result<Policy<C>, Error> {
+        let data = load_json_reader(
+            &self.opa_malloc_func,
+            &self.opa_free_func,
+            &self.opa_json_parse_func,
+            &mut store,
+            &self.memory,
+            reader,
+            len,
+        )
+        .await
+        .map_err(|e| self.check_memory_limit(&store, e))?;
+        self.into_policy(store, data).await
+    }
+
+    /// Instanciate the policy with the JSON `data` document in the given file,
+    /// see [`Runtime::with_data_reader`]
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be read, or if it is not valid JSON
+    pub async fn with_data_file<T: Send>(
+        self,
+        store: impl AsContextMut<Data = T>,
+        path: impl AsRef<Path>,
+    ) -> Result<Policy<C>, Error> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path)
+            .await
+            .with_context(|| format!("could not open {}", path.display()))?;
+        let len = file
+            .metadata()
+            .await
+            .context("could not stat the file")?
+            .len();
+        let len = len.try_into().context("the data file is too large")?;
+        self.with_data_reader(store, tokio::io::BufReader::new(file), len)
+            .await
+    }
+
+    /// Get the default entrypoint of this module. May return [`None`] if no
+    /// entrypoint with ID 0 was found
+    #[must_use]
+    pub fn default_entrypoint(&self) -> Option<&str> {
+        self.entrypoints
+            .iter()
+            .find_map(|(k, v)| (v.0 == 0).then_some(k.as_str()))
+    }
 
     /// Get the list of entrypoints found in this module.
     #[must_use]
@@ -499,6 +1503,30 @@ impl<C> Runtime<C> {
     pub fn abi_version(&self) -> AbiVersion {
         self.version
     }
+
+    /// Whether evaluations use the one-shot `opa_eval` export instead of the
+    /// legacy `opa_eval_ctx_*` flow, see [`RuntimeBuilder::eval_fastpath`]
+    #[must_use]
+    pub fn uses_eval_fastpath(&self) -> bool {
+        self.opa_eval_func.is_some()
+    }
+
+    /// Get the current size of the module's linear memory, in WebAssembly
+    /// pages of 64 KiB
+    #[must_use]
+    pub fn memory_pages(&self, store: impl AsContext) -> u64 {
+        self.memory.size(&store)
+    }
+
+    /// Mark the error as caused by the memory limit if the memory is full
+    fn check_memory_limit(&self, store: impl AsContext, e: anyhow::Error) -> anyhow::Error {
+        match self.max_memory_pages {
+            Some(max_pages) if self.memory.size(&store) >= u64::from(max_pages) => {
+                e.context(MemoryLimitExceeded { max_pages })
+            }
+            _ => e,
+        }
+    }
 }
 
 /// An instance of a policy, ready to be executed
@@ -507,6 +1535,9 @@ pub struct Policy<C> {
     runtime: Runtime<C>,
     data: Value,
     heap_ptr: Addr,
+    /// Bytes of heap used by the `data` document when it was last loaded or
+    /// compacted
+    data_heap_size: i32,
 }
 
 impl<C> Policy<C> {
@@ -521,50 +1552,158 @@ impl<C> Policy<C> {
         mut store: impl AsContextMut<Data = T>,
         entrypoint: &str,
         input: &V,
+    ) -> Result<R, Error>
+    where
+        C: EvaluationContext,
+    {
+        self.evaluate_inner(&mut store, entrypoint, input)
+            .await
+            .map_err(|e| self.runtime.check_memory_limit(&store, e).into())
+    }
+
+    async fn evaluate_inner<V: serde::Serialize, R: for<'de> serde::Deserialize<'de>, T: Send>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<R>
+    where
+        C: EvaluationContext,
+    {
+        #[cfg(feature = "metrics")]
+        let (start, pages) = (Instant::now(), self.runtime.memory.size(&store));
+
+        let res = self.evaluate_observed(&mut store, entrypoint, input).await;
+
+        #[cfg(feature = "metrics")]
+        {
+            crate::metrics::observe_evaluation(entrypoint, start.elapsed(), res.is_ok());
+            let grown = self.runtime.memory.size(&store).saturating_sub(pages);
+            crate::metrics::observe_memory_growth(grown);
+        }
+
+        res
+    }
+
+    async fn evaluate_observed<V: serde::Serialize, R: for<'de> serde::Deserialize<'de>, T: Send>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<R>
+    where
+        C: EvaluationContext,
+    {
+        #[cfg(feature = "decision-logs")]
+        let logging = self.runtime.decision_logger.is_some();
+        #[cfg(not(feature = "decision-logs"))]
+        let logging = false;
+
+        if !logging && self.runtime.coverage.is_none() {
+            return self.evaluate_raw(store, entrypoint, input).await;
+        }
+
+        // Evaluate to a JSON value first, to inspect the result
+        #[cfg(feature = "decision-logs")]
+        let start = Instant::now();
+        let res: Result<serde_json::Value> = self.evaluate_raw(&mut store, entrypoint, input).await;
+        #[cfg(feature = "decision-logs")]
+        let eval_time = start.elapsed();
+
+        if let (Some(coverage), Ok(result)) = (&self.runtime.coverage, &res) {
+            coverage.record(entrypoint, result);
+        }
+
+        #[cfg(feature = "decision-logs")]
+        if let Some(logger) = &self.runtime.decision_logger {
+            let (builtin_errors, timestamp) = match self.loaded_builtins.get() {
+                Some(builtins) => (
+                    builtins.take_builtin_errors(),
+                    builtins.context.lock().await.now(),
+                ),
+                None => (Vec::new(), chrono::Utc::now()),
+            };
+            // A decision which could not be logged does not change the result
+            // of the evaluation
+            if let Err(e) = logger.log(
+                entrypoint,
+                input,
+                res.as_ref(),
+                eval_time,
+                builtin_errors,
+                timestamp,
+            ) {
+                tracing::warn!(error = ?e, entrypoint, "could not log the decision");
+            }
+        }
+
+        Ok(serde_json::from_value(res?)?)
+    }
+
+    async fn evaluate_raw<V: serde::Serialize, R: for<'de> serde::Deserialize<'de>, T: Send>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
     ) -> Result<R>
     where
         C: EvaluationContext,
     {
         // Lookup the entrypoint
+        let entrypoint_name = entrypoint;
         let entrypoint = self
             .runtime
             .entrypoints
             .get(entrypoint)
             .with_context(|| format!("could not find entrypoint {entrypoint}"))?;
 
+        #[cfg(feature = "input-schema")]
+        self.runtime.validate_input(entrypoint_name, input)?;
+
         self.loaded_builtins
             .get()
             .context("builtins where never initialized")?
-            .evaluation_start()
+            .evaluation_start(entrypoint_name)
             .await;
 
         // Take the fast path if it is awailable
         if let Some(opa_eval) = &self.runtime.opa_eval_func {
-            // Write the input
-            let input = serde_json::to_vec(&input)?;
-            let input_heap = Heap {
-                ptr: self.heap_ptr.0,
-                len: input.len().try_into().context("input too long")?,
-                // Not managed by a malloc
-                freed: true,
-            };
+            // Serialize the JSON input directly in the linear memory, right
+            // after the data. Building the value through the OPA value ABI
+            // instead would need a call into the module for every node, which
+            // is slower than letting the module parse the JSON.
+            // The input is serialized a first time only to measure it, so
+            // that the memory can be grown before writing to it.
+            let start = usize::try_from(self.heap_ptr.0).context("invalid heap pointer")?;
+            let mut len = ByteCount(0);
+            serde_json::to_writer(&mut len, input)?;
+            let end = start + len.0;
+
+            let size = self.runtime.memory.data_size(&store);
+            if end > size {
+                let needed_pages = ((end - size + PAGE_SIZE - 1) / PAGE_SIZE) as u64;
+                if let Some(max_pages) = self.runtime.max_memory_pages {
+                    let pages = self.runtime.memory.size(&store) + needed_pages;
+                    if pages > u64::from(max_pages) {
+                        return Err(MemoryLimitExceeded { max_pages }.into());
+                    }
+                }
 
-            // Check if we need to grow the memory first
-            let current_pages = self.runtime.memory.size(&store);
-            let needed_pages = input_heap.pages();
-            if current_pages < needed_pages {
                 self.runtime
                     .memory
-                    .grow_async(&mut store, needed_pages - current_pages)
+                    .grow_async(&mut store, needed_pages)
                     .await?;
             }
 
-            // Write the JSON input to memory
-            self.runtime.memory.write(
-                &mut store,
-                input_heap.ptr.try_into().context("invalid heap pointer")?,
-                &input[..],
-            )?;
+            let mut buffer = &mut self.runtime.memory.data_mut(&mut store)[start..end];
+            serde_json::to_writer(&mut buffer, input)?;
+
+            let input_heap = Heap {
+                ptr: self.heap_ptr.0,
+                len: len.0.try_into().context("input too long")?,
+                // Not managed by a malloc
+                freed: true,
+            };
 
             let heap_ptr = Addr(input_heap.end());
 
@@ -626,6 +1765,510 @@ impl<C> Policy<C> {
             Ok(result)
         }
     }
+
+    /// Evaluate a policy and deserialize its single result, unwrapping it from
+    /// the result set.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ResultError`](crate::ResultError) if the result set is empty, has more than one
+    /// result, or if the result does not match the expected type, or any
+    /// error [`Policy::evaluate`] can return.
+    pub async fn evaluate_typed<V: serde::Serialize, R: serde::de::DeserializeOwned, T: Send>(
+        &self,
+        store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<R, Error>
+    where
+        C: EvaluationContext,
+    {
+        let results = self.evaluate_result_set(store, entrypoint, input).await?;
+        Ok(results.into_single_typed()?)
+    }
+
+    /// Evaluate a policy and return its [`ResultSet`], which tells apart an
+    /// undefined result from a defined but empty one
+    ///
+    /// # Errors
+    ///
+    /// See [`Policy::evaluate`]
+    pub async fn evaluate_result_set<V: serde::Serialize, T: Send>(
+        &self,
+        store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<ResultSet, Error>
+    where
+        C: EvaluationContext,
+    {
+        self.evaluate(store, entrypoint, input).await
+    }
+
+    /// Evaluate a policy returning a boolean, like an `allow` rule
+    ///
+    /// # Errors
+    ///
+    /// See [`Policy::evaluate_typed`]
+    pub async fn evaluate_bool<V: serde::Serialize, T: Send>(
+        &self,
+        store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<bool, Error>
+    where
+        C: EvaluationContext,
+    {
+        self.evaluate_typed(store, entrypoint, input).await
+    }
+
+    /// Evaluate a policy, returning its result along with a coarse [`Trace`]
+    /// of the evaluation.
+    ///
+    /// Unlike `opa eval --explain`, the trace does not have rule-level
+    /// events, as the WASM target has no hooks for them: it only records
+    /// whether the entrypoint was defined, and the notes emitted by the
+    /// policy with the `trace` builtin.
+    ///
+    /// # Errors
+    ///
+    /// See [`Policy::evaluate`]
+    pub async fn evaluate_with_trace<
+        V: serde::Serialize,
+        R: for<'de> serde::Deserialize<'de>,
+        T: Send,
+    >(
+        &self,
+        store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<(R, Trace), Error>
+    where
+        C: EvaluationContext,
+    {
+        let builtins = self
+            .loaded_builtins
+            .get()
+            .context("builtins where never initialized")?;
+
+        // Stop tracing even if the evaluation gets cancelled
+        let tracing = TracingGuard(builtins);
+        builtins.set_tracing(true);
+        builtins.trace_event(|| {
+            Some(TraceEvent::Enter {
+                query: entrypoint.to_owned(),
+            })
+        });
+
+        let result: serde_json::Value = self.evaluate(store, entrypoint, input).await?;
+        let mut trace = builtins.set_tracing(false).unwrap_or_default();
+        drop(tracing);
+
+        let query = entrypoint.to_owned();
+        if result.as_array().is_some_and(Vec::is_empty) {
+            trace.push(TraceEvent::Fail { query });
+        } else {
+            trace.push(TraceEvent::Exit { query });
+        }
+
+        let result = serde_json::from_value(result).context("could not decode the result")?;
+        Ok((result, trace))
+    }
+
+    /// Evaluate every entrypoint of the policy with the same input, returning
+    /// the results keyed by entrypoint name
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the evaluation of one of the entrypoints failed
+    pub async fn evaluate_all<V: serde::Serialize, T: Send>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        input: &V,
+    ) -> Result<HashMap<String, serde_json::Value>, Error>
+    where
+        C: EvaluationContext,
+    {
+        let mut results = HashMap::with_capacity(self.runtime.entrypoints.len());
+        for entrypoint in self.runtime.entrypoints.keys() {
+            let result = self.evaluate(&mut store, entrypoint, input).await?;
+            results.insert(entrypoint.clone(), result);
+        }
+
+        Ok(results)
+    }
+
+    /// Set the value at the given path of the `data` document, without
+    /// reloading the whole document
+    ///
+    /// # Errors
+    ///
+    /// If the path does not point inside an object, or if the module does not
+    /// support updating its data, e.g. with an [`UnsupportedAbi`](crate::UnsupportedAbi) error
+    pub async fn set_data_path<V: serde::Serialize, T: Send>(
+        &mut self,
+        store: impl AsContextMut<Data = T>,
+        path: &[&str],
+        value: &V,
+    ) -> Result<(), Error> {
+        let path = path.iter().map(ToString::to_string).collect();
+        let value = serde_json::to_value(value).context("could not serialize the value")?;
+        Ok(self
+            .update_data(store, vec![PathUpdate::Set(path, value)])
+            .await?)
+    }
+
+    /// Remove the value at the given path of the `data` document, without
+    /// reloading the whole document
+    ///
+    /// # Errors
+    ///
+    /// If the path does not exist, or if the module does not support updating
+    /// its data, e.g. with an [`UnsupportedAbi`](crate::UnsupportedAbi) error
+    pub async fn remove_data_path<T: Send>(
+        &mut self,
+        store: impl AsContextMut<Data = T>,
+        path: &[&str],
+    ) -> Result<(), Error> {
+        let path = path.iter().map(ToString::to_string).collect();
+        Ok(self
+            .update_data(store, vec![PathUpdate::Remove(path)])
+            .await?)
+    }
+
+    /// Apply a JSON Patch (RFC 6902) to the `data` document. Only the `add`,
+    /// `replace` and `remove` operations on objects are supported.
+    ///
+    /// # Errors
+    ///
+    /// If the patch is invalid or could not be applied. Operations before
+    /// the failing one stay applied.
+    pub async fn apply_json_patch<T: Send>(
+        &mut self,
+        store: impl AsContextMut<Data = T>,
+        patch: serde_json::Value,
+    ) -> Result<(), Error> {
+        let updates = crate::patch::json_patch(patch)?;
+        Ok(self.update_data(store, updates).await?)
+    }
+
+    /// Apply a JSON Merge Patch (RFC 7396) to the `data` document
+    ///
+    /// # Errors
+    ///
+    /// If the patch could not be applied. Updates before the failing one stay
+    /// applied.
+    pub async fn apply_merge_patch<T: Send>(
+        &mut self,
+        store: impl AsContextMut<Data = T>,
+        patch: serde_json::Value,
+    ) -> Result<(), Error> {
+        let updates = crate::patch::merge_patch(patch);
+        Ok(self.update_data(store, updates).await?)
+    }
+
+    async fn update_data<T: Send>(
+        &mut self,
+        mut store: impl AsContextMut<Data = T>,
+        updates: Vec<PathUpdate>,
+    ) -> Result<()> {
+        // Discard what the previous evaluations allocated, and make sure the
+        // new values are allocated after the existing data
+        self.runtime
+            .opa_heap_ptr_set_func
+            .call(&mut store, &self.heap_ptr)
+            .await?;
+
+        let res = self.apply_updates(&mut store, updates).await;
+
+        // Keep what was allocated so far, even if an update failed
+        self.heap_ptr = self.runtime.opa_heap_ptr_get_func.call(&mut store).await?;
+        res?;
+
+        // The values replaced or removed by the updates stay allocated, so
+        // reload the document at the start of the heap once they add up
+        let threshold = self
+            .data_heap_size
+            .saturating_mul(2)
+            .max(DATA_COMPACTION_THRESHOLD);
+        if self.heap_used() > threshold {
+            self.compact_data(&mut store).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Bytes of heap used since the module was instantiated
+    fn heap_used(&self) -> i32 {
+        self.heap_ptr.0 - self.runtime.base_heap_ptr.0
+    }
+
+    /// Reload the `data` document at the start of the heap, dropping what
+    /// the previous updates left behind
+    async fn compact_data<T: Send>(
+        &mut self,
+        mut store: impl AsContextMut<Data = T>,
+    ) -> Result<()> {
+        let json = self
+            .runtime
+            .opa_json_dump_func
+            .call(&mut store, &self.data)
+            .await?;
+        let json = json.read(&store, &self.runtime.memory)?.to_bytes().to_vec();
+
+        self.runtime
+            .opa_heap_ptr_set_func
+            .call(&mut store, &self.runtime.base_heap_ptr)
+            .await?;
+        let json = alloc_str(
+            &self.runtime.opa_malloc_func,
+            &mut store,
+            &self.runtime.memory,
+            json,
+        )
+        .await?;
+        self.data = self
+            .runtime
+            .opa_json_parse_func
+            .call(&mut store, &json)
+            .await?;
+        self.runtime.opa_free_func.call(&mut store, json).await?;
+
+        self.heap_ptr = self.runtime.opa_heap_ptr_get_func.call(&mut store).await?;
+        self.data_heap_size = self.heap_used();
+        Ok(())
+    }
+
+    async fn apply_updates<T: Send>(
+        &mut self,
+        mut store: impl AsContextMut<Data = T>,
+        updates: Vec<PathUpdate>,
+    ) -> Result<()> {
+        for update in updates {
+            match update {
+                PathUpdate::Set(path, value) if path.is_empty() => {
+                    self.data = self.runtime.load_json(&mut store, &value).await?;
+                }
+
+                PathUpdate::Remove(path) if path.is_empty() => {
+                    let empty = serde_json::Value::Object(serde_json::Map::default());
+                    self.data = self.runtime.load_json(&mut store, &empty).await?;
+                }
+
+                PathUpdate::Set(path, value) => {
+                    self.runtime.version.require(AbiFeature::DataPathUpdates)?;
+                    let add_path = self
+                        .runtime
+                        .opa_value_add_path_func
+                        .as_ref()
+                        .context("module does not support updating its data")?;
+                    let path_value = self.runtime.load_json(&mut store, &path).await?;
+                    let value = self.runtime.load_json(&mut store, &value).await?;
+                    add_path
+                        .call(&mut store, &self.data, &path_value, &value)
+                        .await
+                        .with_context(|| format!("could not set data at {path:?}"))?;
+                }
+
+                PathUpdate::Remove(path) => {
+                    self.runtime.version.require(AbiFeature::DataPathUpdates)?;
+                    let remove_path = self
+                        .runtime
+                        .opa_value_remove_path_func
+                        .as_ref()
+                        .context("module does not support updating its data")?;
+                    let path_value = self.runtime.load_json(&mut store, &path).await?;
+                    remove_path
+                        .call(&mut store, &self.data, &path_value)
+                        .await
+                        .with_context(|| format!("could not remove data at {path:?}"))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate a policy, sending its independent `http.send` requests
+    /// concurrently.
+    ///
+    /// Builtins are resolved synchronously by the module, so a single
+    /// evaluation waits on its requests one after the other. This first runs a
+    /// discovery pass, in which `GET`, `HEAD` and `OPTIONS` requests are
+    /// recorded instead of sent, and answered with an error response. The
+    /// recorded requests are then sent concurrently, at most
+    /// [`HttpConfig::with_max_parallel_requests`] at a time, before the
+    /// actual evaluation picks up their responses.
+    ///
+    /// Requests built from the response of another one, and all requests
+    /// following one with another method, are still sent during the actual
+    /// evaluation.
+    ///
+    /// The discovery pass is invisible outside of the prefetched requests:
+    /// `print` calls, the builtin observer, the recorder and the decision
+    /// logs only see the actual evaluation. Other builtins still run during
+    /// both passes.
+    ///
+    /// If the context has no [`EvaluationContext::http_prefetch`], or if the
+    /// builtins are replayed, this is the same as [`Policy::evaluate`].
+    ///
+    /// [`HttpConfig::with_max_parallel_requests`]: crate::builtins::impls::http::HttpConfig::with_max_parallel_requests
+    ///
+    /// # Errors
+    ///
+    /// Returns any error [`Policy::evaluate`] can return. Errors of the
+    /// discovery pass are ignored.
+    #[cfg(feature = "http-builtins")]
+    pub async fn evaluate_with_prefetch<
+        V: serde::Serialize,
+        R: for<'de> serde::Deserialize<'de>,
+        T: Send,
+    >(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<R, Error>
+    where
+        C: EvaluationContext,
+    {
+        let Some(builtins) = self.loaded_builtins.get() else {
+            return self.evaluate(store, entrypoint, input).await;
+        };
+
+        let Some(prefetch) = builtins.context.lock().await.http_prefetch().cloned() else {
+            return self.evaluate(store, entrypoint, input).await;
+        };
+        // Replayed evaluations get their `http.send` responses from the
+        // recording
+        if matches!(builtins.replay, Some(ReplayMode::Replay(_))) {
+            return self.evaluate(store, entrypoint, input).await;
+        }
+
+        prefetch.start_discovery().await;
+        let discovery: Result<serde_json::Value> = {
+            let _guard = DiscoveryGuard::start(builtins);
+            self.evaluate_raw(&mut store, entrypoint, input).await
+        };
+        if let Err(e) = discovery {
+            tracing::debug!(error = %e, "http.send prefetch discovery stopped early");
+        }
+
+        let requests = crate::builtins::impls::http::prefetch(&mut *builtins.context.lock().await);
+        requests.await;
+
+        let res = self.evaluate(&mut store, entrypoint, input).await;
+        prefetch.clear().await;
+        res
+    }
+
+    /// Evaluate a policy with a limited amount of fuel, returning the result
+    /// along with the amount of fuel consumed.
+    ///
+    /// This requires the [`wasmtime::Engine`] to be configured with
+    /// [`wasmtime::Config::consume_fuel`]. Note that the store also consumes
+    /// fuel when instantiating the policy, so enough fuel must be set on the
+    /// store beforehand.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`OutOfFuel`] error if the evaluation consumed all its fuel,
+    /// an error if fuel consumption is not enabled on the engine, or any error
+    /// [`Policy::evaluate`] can return.
+    pub async fn evaluate_with_fuel<
+        V: serde::Serialize,
+        R: for<'de> serde::Deserialize<'de>,
+        T: Send,
+    >(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+        fuel: u64,
+    ) -> Result<(R, u64), Error>
+    where
+        C: EvaluationContext,
+    {
+        store.as_context_mut().set_fuel(fuel)?;
+
+        let res = self.evaluate_inner(&mut store, entrypoint, input).await;
+        let consumed = fuel - store.as_context().get_fuel()?;
+
+        match res {
+            Ok(res) => Ok((res, consumed)),
+            Err(e) if e.downcast_ref::<Trap>() == Some(&Trap::OutOfFuel) => {
+                Err(OutOfFuel { limit: fuel }.into())
+            }
+            Err(e) => Err(self.runtime.check_memory_limit(&store, e).into()),
+        }
+    }
+
+    /// Evaluate a policy, aborting the evaluation if it takes longer than the
+    /// given timeout.
+    ///
+    /// See [`Policy::evaluate_until`] for how the evaluation gets interrupted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`EvaluationCancelled`] error if the timeout elapsed, or
+    /// any error [`Policy::evaluate`] can return.
+    pub async fn evaluate_with_deadline<
+        V: serde::Serialize,
+        R: for<'de> serde::Deserialize<'de>,
+        T: Send,
+    >(
+        &self,
+        store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+        timeout: Duration,
+    ) -> Result<R, Error>
+    where
+        C: EvaluationContext,
+    {
+        self.evaluate_until(store, entrypoint, input, tokio::time::sleep(timeout))
+            .await
+    }
+
+    /// Evaluate a policy, aborting the evaluation as soon as the `cancel`
+    /// future resolves, e.g. a `CancellationToken::cancelled()` future.
+    ///
+    /// The evaluation can only be interrupted while it is suspended, which
+    /// happens when an async builtin is pending. To also stop a policy stuck
+    /// in a loop, the [`wasmtime::Engine`] must be configured with
+    /// [`wasmtime::Config::epoch_interruption`]: while such evaluations are
+    /// running, a single background thread per engine increments its epoch
+    /// every 10ms, and the store yields back on every epoch change.
+    /// Once the call returns or is dropped, the store is set back to
+    /// trapping when it reaches its epoch deadline, which must be set again
+    /// before evaluating with [`Policy::evaluate`], see
+    /// [`wasmtime::Store::set_epoch_deadline`]. Other stores evaluating
+    /// policies with the same engine meanwhile must set their own deadline.
+    ///
+    /// The policy can still be evaluated after an interrupted evaluation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`EvaluationCancelled`] error if the evaluation was
+    /// interrupted, or any error [`Policy::evaluate`] can return.
+    pub async fn evaluate_until<V: serde::Serialize, R: for<'de> serde::Deserialize<'de>, T: Send>(
+        &self,
+        store: impl AsContextMut<Data = T>,
+        entrypoint: &str,
+        input: &V,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<R, Error>
+    where
+        C: EvaluationContext,
+    {
+        let mut guard = EpochYield::new(store);
+
+        tokio::select! {
+            biased;
+            res = self.evaluate(&mut guard.store, entrypoint, input) => res,
+            () = cancel => Err(EvaluationCancelled.into()),
+        }
+    }
 }
 
 impl<C> Deref for Policy<C> {
@@ -0,0 +1,194 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A pool of pre-instantiated policies
+
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::Context;
+use tokio::sync::Semaphore;
+use wasmtime::{Engine, Module, Store};
+
+use crate::{Error, EvaluationContext, Policy, Runtime};
+
+/// A policy instance with its own store
+struct Instance<C> {
+    store: Store<()>,
+    policy: Policy<C>,
+}
+
+/// A pool of policy instances, each with its own [`wasmtime::Store`], which
+/// can be evaluated concurrently.
+///
+/// All instances are created upfront with the same module and data, so that
+/// evaluations don't pay the cost of instantiating the module. Evaluations
+/// wait for an instance to be available when all of them are in use.
+pub struct PolicyPool<C> {
+    instances: Mutex<Vec<Instance<C>>>,
+    permits: Semaphore,
+    size: usize,
+}
+
+impl<C> std::fmt::Debug for PolicyPool<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PolicyPool")
+            .field("size", &self.size)
+            .field("available", &self.permits.available_permits())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Puts the instance back in the pool when dropped, even if the evaluation
+/// was cancelled
+struct Checkout<'a, T> {
+    instances: &'a Mutex<Vec<T>>,
+    instance: Option<T>,
+}
+
+impl<'a, T> Checkout<'a, T> {
+    /// Take an instance out of the pool
+    fn take(instances: &'a Mutex<Vec<T>>) -> Option<Self> {
+        let instance = instances
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop()?;
+        Some(Self {
+            instances,
+            instance: Some(instance),
+        })
+    }
+}
+
+impl<T> Drop for Checkout<'_, T> {
+    fn drop(&mut self) {
+        // The instances are only pushed and popped while the lock is held, so
+        // the list is still consistent after a panic elsewhere
+        if let Some(instance) = self.instance.take() {
+            self.instances
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(instance);
+        }
+    }
+}
+
+impl<C: EvaluationContext> PolicyPool<C> {
+    /// Instantiate `size` instances of the module with the given data, each
+    /// with an evaluation context created by the `context` function.
+    ///
+    /// The [`wasmtime::Engine`] must have async support enabled.
+    ///
+    /// # Errors
+    ///
+    /// If one of the instances failed to load, see
+    /// [`Runtime::new_with_evaluation_context`] and [`Runtime::with_data`]
+    pub async fn new<V: serde::Serialize>(
+        engine: &Engine,
+        module: &Module,
+        data: &V,
+        size: usize,
+        mut context: impl FnMut() -> C,
+    ) -> Result<Self, Error> {
+        let mut instances = Vec::with_capacity(size);
+        for _ in 0..size {
+            let mut store = Store::new(engine, ());
+            let runtime =
+                Runtime::new_with_evaluation_context(&mut store, module, context()).await?;
+            let policy = runtime.with_data(&mut store, data).await?;
+            instances.push(Instance { store, policy });
+        }
+
+        Ok(Self {
+            instances: Mutex::new(instances),
+            permits: Semaphore::new(size),
+            size,
+        })
+    }
+
+    /// The number of instances in the pool
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Evaluate the policy with the given entrypoint and input on the next
+    /// available instance.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy evaluation failed
+    pub async fn evaluate<V: serde::Serialize, R: for<'de> serde::Deserialize<'de>>(
+        &self,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<R, Error> {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .context("policy pool is closed")?;
+        let mut checkout =
+            Checkout::take(&self.instances).context("no policy instance available")?;
+        let Instance { store, policy } = checkout
+            .instance
+            .as_mut()
+            .context("no policy instance available")?;
+
+        policy.evaluate(store, entrypoint, input).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkout_returns_instances() {
+        let instances = Mutex::new(vec![1, 2]);
+
+        let first = Checkout::take(&instances).unwrap();
+        let second = Checkout::take(&instances).unwrap();
+        assert_eq!(first.instance, Some(2));
+        assert_eq!(second.instance, Some(1));
+        assert!(Checkout::take(&instances).is_none());
+
+        drop(first);
+        assert_eq!(*instances.lock().unwrap(), vec![2]);
+        drop(second);
+        assert_eq!(*instances.lock().unwrap(), vec![2, 1]);
+    }
+
+    #[test]
+    fn checkout_survives_poisoned_lock() {
+        let instances = Mutex::new(vec![1]);
+        let checkout = Checkout::take(&instances).unwrap();
+
+        // Poison the lock while the instance is checked out
+        std::thread::scope(|scope| {
+            let poisoned = scope
+                .spawn(|| {
+                    let _guard = instances.lock().unwrap();
+                    panic!("poison the lock");
+                })
+                .join();
+            assert!(poisoned.is_err());
+        });
+        assert!(instances.is_poisoned());
+
+        // The instance still goes back to the pool, and can be taken again
+        drop(checkout);
+        let checkout = Checkout::take(&instances).unwrap();
+        assert_eq!(checkout.instance, Some(1));
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Destinations for the output of `print` statements
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Receives the messages printed by the policies with `print` statements,
+/// set with [`RuntimeBuilder::print_hook`](crate::RuntimeBuilder::print_hook).
+///
+/// By default, messages are emitted as `tracing` events.
+pub trait PrintHook: Send + Sync {
+    /// Called for each printed message
+    fn print(&self, message: &str);
+}
+
+impl<T: PrintHook + ?Sized> PrintHook for Arc<T> {
+    fn print(&self, message: &str) {
+        T::print(self, message);
+    }
+}
+
+impl<F: Fn(&str) + Send + Sync> PrintHook for F {
+    fn print(&self, message: &str) {
+        self(message);
+    }
+}
+
+/// A [`PrintHook`] keeping the printed messages, to be retrieved after the
+/// evaluation with [`PrintBuffer::take`].
+///
+/// This is cheap to clone and clones share the same messages.
+#[derive(Debug, Clone, Default)]
+pub struct PrintBuffer {
+    messages: Arc<Mutex<Vec<String>>>,
+}
+
+impl PrintBuffer {
+    /// Create an empty buffer
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the messages printed so far, leaving the buffer empty
+    #[must_use]
+    pub fn take(&self) -> Vec<String> {
+        let mut messages = self.messages.lock().unwrap_or_else(PoisonError::into_inner);
+        std::mem::take(&mut *messages)
+    }
+}
+
+impl PrintHook for PrintBuffer {
+    fn print(&self, message: &str) {
+        self.messages
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(message.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer() {
+        let buffer = PrintBuffer::new();
+        let hook: Arc<dyn PrintHook> = Arc::new(buffer.clone());
+        hook.print("hello");
+        hook.print("world");
+
+        assert_eq!(buffer.take(), ["hello", "world"]);
+        assert!(buffer.take().is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Policies which can be replaced without interrupting evaluations
+
+use std::sync::{Arc, RwLock};
+
+use wasmtime::{Engine, Module};
+
+use crate::{Error, EvaluationContext, PolicyPool};
+
+/// Callback notified of the outcome of each reload
+type ReloadCallback = Box<dyn Fn(Result<(), &Error>) + Send + Sync>;
+
+/// A [`PolicyPool`] which can be swapped with a new version of the policy.
+///
+/// New evaluations use the latest loaded version, while evaluations started
+/// before a reload finish against the previous one.
+pub struct ReloadablePolicy<C> {
+    engine: Engine,
+    size: usize,
+    context: Box<dyn Fn() -> C + Send + Sync>,
+    current: RwLock<Arc<PolicyPool<C>>>,
+    on_reload: Option<ReloadCallback>,
+}
+
+impl<C> std::fmt::Debug for ReloadablePolicy<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReloadablePolicy")
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: EvaluationContext> ReloadablePolicy<C> {
+    /// Load the initial version of the policy, in a pool of `size` instances,
+    /// see [`PolicyPool::new`]
+    ///
+    /// # Errors
+    ///
+    /// If the policy failed to load
+    pub async fn new<V: serde::Serialize>(
+        engine: &Engine,
+        module: &Module,
+        data: &V,
+        size: usize,
+        context: impl Fn() -> C + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        let pool = PolicyPool::new(engine, module, data, size, &context).await?;
+        Ok(Self {
+            engine: engine.clone(),
+            size,
+            context: Box::new(context),
+            current: RwLock::new(Arc::new(pool)),
+            on_reload: None,
+        })
+    }
+
+    /// Set a callback notified each time a reload succeeded or failed
+    #[must_use]
+    pub fn on_reload(
+        mut self,
+        callback: impl Fn(Result<(), &Error>) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_reload = Some(Box::new(callback));
+        self
+    }
+
+    /// The version of the policy new evaluations currently use
+    ///
+    /// # Panics
+    ///
+    /// If the lock was poisoned
+    #[must_use]
+    pub fn current(&self) -> Arc<PolicyPool<C>> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Load a new version of the policy, and make new evaluations use it once
+    /// loaded. The previous version stays in use if loading fails.
+    ///
+    /// # Errors
+    ///
+    /// If the new version failed to load
+    ///
+    /// # Panics
+    ///
+    /// If the lock was poisoned
+    pub async fn reload<V: serde::Serialize>(
+        &self,
+        module: &Module,
+        data: &V,
+    ) -> Result<(), Error> {
+        let res = PolicyPool::new(&self.engine, module, data, self.size, &self.context).await;
+
+        if let Some(callback) = &self.on_reload {
+            callback(res.as_ref().map(|_| ()));
+        }
+
+        let pool = res?;
+        *self.current.write().unwrap() = Arc::new(pool);
+        Ok(())
+    }
+
+    /// Load a new version of the policy from a bundle, with the bundled data
+    ///
+    /// # Errors
+    ///
+    /// If the bundled module failed to compile, or see
+    /// [`ReloadablePolicy::reload`]
+    #[cfg(feature = "loader")]
+    pub async fn reload_bundle(&self, bundle: &crate::Bundle) -> Result<(), Error> {
+        let module = match Module::new(&self.engine, bundle.module()).map_err(Error::load) {
+            Ok(module) => module,
+            Err(e) => {
+                if let Some(callback) = &self.on_reload {
+                    callback(Err(&e));
+                }
+                return Err(e);
+            }
+        };
+
+        self.reload(&module, bundle.data()).await
+    }
+
+    /// Evaluate the latest version of the policy, see
+    /// [`PolicyPool::evaluate`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the policy evaluation failed
+    pub async fn evaluate<V: serde::Serialize, R: for<'de> serde::Deserialize<'de>>(
+        &self,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<R, Error> {
+        self.current().evaluate(entrypoint, input).await
+    }
+}
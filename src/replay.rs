@@ -0,0 +1,256 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capture and replay of non-deterministic builtin results
+//!
+//! Some builtins (`http.send`, `time.now_ns`, `rand.intn`…) return a
+//! different result each time they are called. To reproduce a decision
+//! later, their results can be recorded with a [`BuiltinRecorder`] during
+//! the original evaluation, and fed back with a [`BuiltinReplayer`] instead
+//! of calling them again.
+//!
+//! Builtins which depend on the evaluation time (`io.jwt.decode_verify`,
+//! `crypto.x509.parse_and_verify_certificates`…) or on keys fetched from a
+//! JSON Web Key Set are reproduced by recording the time the evaluation
+//! started under [`EVALUATION_TIME`], and the fetched keys under
+//! [`JWKS_KEYS`].
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// The builtins whose result depend on something else than their arguments,
+/// and are therefore recorded and replayed
+pub const NON_DETERMINISTIC_BUILTINS: &[&str] = &[
+    "http.send",
+    "net.lookup_ip_addr",
+    "opa.runtime",
+    "rand.intn",
+    "time.now_ns",
+    "uuid.rfc4122",
+];
+
+/// Name under which the time an evaluation started is recorded, in
+/// nanoseconds since the Unix epoch
+pub const EVALUATION_TIME: &str = "$evaluation_time";
+
+/// Name under which the JSON Web Keys used by `io.jwt.decode_verify` are
+/// recorded, with the key ID of the token as argument
+pub const JWKS_KEYS: &str = "$jwks_keys";
+
+/// Whether the result of the given builtin is recorded and replayed
+#[must_use]
+pub fn is_non_deterministic(name: &str) -> bool {
+    NON_DETERMINISTIC_BUILTINS.contains(&name)
+}
+
+/// A single recorded builtin call
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuiltinCall {
+    /// The name of the builtin
+    pub name: String,
+
+    /// The arguments the builtin was called with
+    pub args: Vec<serde_json::Value>,
+
+    /// The value returned by the builtin
+    pub result: serde_json::Value,
+}
+
+/// The non-deterministic builtin calls made during one or more evaluations,
+/// in call order
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Recording {
+    calls: Vec<BuiltinCall>,
+}
+
+impl Recording {
+    /// The recorded calls, in call order
+    #[must_use]
+    pub fn calls(&self) -> &[BuiltinCall] {
+        &self.calls
+    }
+
+    /// Number of recorded calls
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// Whether no call was recorded
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+}
+
+impl From<Vec<BuiltinCall>> for Recording {
+    fn from(calls: Vec<BuiltinCall>) -> Self {
+        Self { calls }
+    }
+}
+
+/// Records the results of non-deterministic builtins.
+///
+/// This is cheap to clone and clones share the same recording, so one can
+/// be handed to [`crate::RuntimeBuilder::record`] while keeping a copy to
+/// [`BuiltinRecorder::take`] the calls after each evaluation.
+#[derive(Debug, Clone, Default)]
+pub struct BuiltinRecorder {
+    calls: Arc<Mutex<Vec<BuiltinCall>>>,
+}
+
+impl BuiltinRecorder {
+    /// Create an empty recorder
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the calls recorded so far, leaving the recorder empty
+    #[must_use]
+    pub fn take(&self) -> Recording {
+        let mut calls = self.calls.lock().unwrap_or_else(PoisonError::into_inner);
+        Recording {
+            calls: std::mem::take(&mut *calls),
+        }
+    }
+
+    pub(crate) fn record(&self, name: &str, args: &[&[u8]], result: &[u8]) -> anyhow::Result<()> {
+        let args = args
+            .iter()
+            .map(|arg| serde_json::from_slice(arg))
+            .collect::<Result<_, _>>()?;
+        let result = serde_json::from_slice(result)?;
+        self.calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(BuiltinCall {
+                name: name.to_owned(),
+                args,
+                result,
+            });
+        Ok(())
+    }
+}
+
+type CallKey = (String, Vec<serde_json::Value>);
+
+/// Feeds back the results of a [`Recording`] instead of calling the
+/// non-deterministic builtins.
+///
+/// Calls are matched by builtin name and arguments. When the same call was
+/// recorded multiple times, the results are returned in the recorded order.
+/// A call which was not recorded fails the evaluation.
+#[derive(Debug, Clone, Default)]
+pub struct BuiltinReplayer {
+    calls: Arc<Mutex<HashMap<CallKey, VecDeque<serde_json::Value>>>>,
+}
+
+impl BuiltinReplayer {
+    /// Create a replayer from a recording
+    #[must_use]
+    pub fn new(recording: Recording) -> Self {
+        let mut calls: HashMap<CallKey, VecDeque<serde_json::Value>> = HashMap::new();
+        for call in recording.calls {
+            calls
+                .entry((call.name, call.args))
+                .or_default()
+                .push_back(call.result);
+        }
+
+        Self {
+            calls: Arc::new(Mutex::new(calls)),
+        }
+    }
+
+    /// Whether all the recorded calls were replayed
+    #[must_use]
+    pub fn is_exhausted(&self) -> bool {
+        self.calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .all(VecDeque::is_empty)
+    }
+
+    pub(crate) fn replay(&self, name: &str, args: &[&[u8]]) -> anyhow::Result<Vec<u8>> {
+        let args = args
+            .iter()
+            .map(|arg| serde_json::from_slice(arg))
+            .collect::<Result<_, _>>()?;
+        let result = self
+            .calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(&(name.to_owned(), args))
+            .and_then(VecDeque::pop_front)
+            .with_context(|| format!("no recorded result left for call to {name}"))?;
+        Ok(serde_json::to_vec(&result)?)
+    }
+}
+
+/// Whether builtin results are recorded or replayed
+#[derive(Debug, Clone)]
+pub(crate) enum ReplayMode {
+    Record(BuiltinRecorder),
+    Replay(BuiltinReplayer),
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn record_and_replay() {
+        let recorder = BuiltinRecorder::new();
+        recorder.record("time.now_ns", &[], b"1").unwrap();
+        recorder
+            .record("rand.intn", &[b"\"a\"".as_slice(), b"10"], b"4")
+            .unwrap();
+        recorder.record("time.now_ns", &[], b"2").unwrap();
+
+        let recording = recorder.take();
+        assert_eq!(recording.len(), 3);
+        assert!(recorder.take().is_empty());
+
+        // Recordings survive a round-trip through JSON
+        let recording: Recording =
+            serde_json::from_value(serde_json::to_value(&recording).unwrap()).unwrap();
+        assert_eq!(recording.calls()[1].args, vec![json!("a"), json!(10)]);
+
+        let replayer = BuiltinReplayer::new(recording);
+        assert_eq!(replayer.replay("time.now_ns", &[]).unwrap(), b"1");
+        assert_eq!(
+            replayer
+                .replay("rand.intn", &[b"\"a\"".as_slice(), b"10"])
+                .unwrap(),
+            b"4"
+        );
+        assert!(replayer
+            .replay("rand.intn", &[b"\"b\"".as_slice(), b"10"])
+            .is_err());
+        assert!(!replayer.is_exhausted());
+        assert_eq!(replayer.replay("time.now_ns", &[]).unwrap(), b"2");
+        assert!(replayer.is_exhausted());
+        assert!(replayer.replay("time.now_ns", &[]).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The result set of a policy evaluation
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::ResultError;
+
+/// The result set returned by a policy evaluation, telling apart an
+/// undefined result from a defined but empty one.
+///
+/// Errors during the evaluation are returned as an [`Error`](crate::Error)
+/// instead, so that they are never mistaken for a result.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ResultSet {
+    entries: Vec<Map<String, Value>>,
+}
+
+impl ResultSet {
+    /// Whether the entrypoint is defined for the input, i.e. whether at least
+    /// one result was returned
+    #[must_use]
+    pub fn is_defined(&self) -> bool {
+        self.results().next().is_some()
+    }
+
+    /// Whether the entrypoint is defined, but evaluated to a single empty
+    /// set, array or object, e.g. a partial set rule without any element
+    #[must_use]
+    pub fn is_empty_set(&self) -> bool {
+        let mut results = self.results();
+        match (results.next(), results.next()) {
+            (Some(Value::Array(values)), None) => values.is_empty(),
+            (Some(Value::Object(values)), None) => values.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// The number of results
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.results().count()
+    }
+
+    /// Whether there are no results, which means the entrypoint is
+    /// undefined, see [`ResultSet::is_defined`]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        !self.is_defined()
+    }
+
+    /// The results of the evaluation
+    pub fn results(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().filter_map(|entry| entry.get("result"))
+    }
+
+    /// Take the single result of the evaluation
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ResultError::Undefined`] if there is no result, or
+    /// [`ResultError::MultipleResults`] if there is more than one
+    pub fn into_single(self) -> Result<Value, ResultError> {
+        let mut results: Vec<Value> = self
+            .entries
+            .into_iter()
+            .filter_map(|mut entry| entry.remove("result"))
+            .collect();
+        match results.len() {
+            0 => Err(ResultError::Undefined),
+            1 => Ok(results.remove(0)),
+            n => Err(ResultError::MultipleResults(n)),
+        }
+    }
+
+    /// Take the single result of the evaluation, or [`None`] if it is
+    /// undefined
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ResultError::MultipleResults`] if there is more than one
+    /// result
+    pub fn into_optional(self) -> Result<Option<Value>, ResultError> {
+        match self.into_single() {
+            Ok(result) => Ok(Some(result)),
+            Err(ResultError::Undefined) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Take the single result of the evaluation, and deserialize it
+    ///
+    /// # Errors
+    ///
+    /// See [`ResultSet::into_single`], or [`ResultError::InvalidType`] if the
+    /// result could not be deserialized
+    pub fn into_single_typed<R: DeserializeOwned>(self) -> Result<R, ResultError> {
+        serde_json::from_value(self.into_single()?).map_err(ResultError::InvalidType)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn result_set(value: Value) -> ResultSet {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn undefined() {
+        let results = result_set(json!([]));
+        assert!(!results.is_defined());
+        assert!(!results.is_empty_set());
+        assert!(matches!(results.clone().into_optional(), Ok(None)));
+        assert!(matches!(results.into_single(), Err(ResultError::Undefined)));
+
+        // Entries without a result are undefined too
+        let results = result_set(json!([{}]));
+        assert!(results.is_empty());
+        assert!(matches!(results.into_single(), Err(ResultError::Undefined)));
+    }
+
+    #[test]
+    fn empty_set() {
+        let results = result_set(json!([{"result": []}]));
+        assert!(results.is_defined());
+        assert!(results.is_empty_set());
+        assert_eq!(results.into_single().unwrap(), json!([]));
+
+        let results = result_set(json!([{"result": false}]));
+        assert!(results.is_defined());
+        assert!(!results.is_empty_set());
+        assert!(!results.into_single_typed::<bool>().unwrap());
+    }
+
+    #[test]
+    fn multiple_results() {
+        let results = result_set(json!([{"result": 1}, {"result": 2}]));
+        assert_eq!(results.len(), 2);
+        assert!(!results.is_empty_set());
+        assert!(matches!(
+            results.clone().into_single(),
+            Err(ResultError::MultipleResults(2))
+        ));
+        assert!(results.clone().into_optional().is_err());
+        assert!(matches!(
+            result_set(json!([{"result": "a"}])).into_single_typed::<bool>(),
+            Err(ResultError::InvalidType(_))
+        ));
+        assert_eq!(
+            serde_json::to_value(results).unwrap(),
+            json!([{"result": 1}, {"result": 2}])
+        );
+    }
+}
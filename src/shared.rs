@@ -0,0 +1,185 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A policy which can be shared across threads and tasks
+
+use std::{
+    num::NonZeroUsize,
+    sync::{Mutex, PoisonError},
+};
+
+use wasmtime::{Engine, Module, Store};
+
+use crate::{Error, EvaluationContext, Policy, Runtime};
+
+/// A policy instance with its own store
+struct Instance<C> {
+    store: Store<()>,
+    policy: Policy<C>,
+}
+
+/// A policy which can be evaluated concurrently from multiple threads or
+/// tasks, e.g. behind an [`Arc`](std::sync::Arc).
+///
+/// Each evaluation runs on its own [`wasmtime::Store`]. Instances are
+/// recycled once an evaluation completes, so new stores are only created when
+/// all the existing ones are busy. Unlike a [`PolicyPool`](crate::PolicyPool),
+/// evaluations never wait for an instance to be available.
+pub struct SharedPolicy<C> {
+    engine: Engine,
+    module: Module,
+    data: serde_json::Value,
+    context: Box<dyn Fn() -> C + Send + Sync>,
+    idle: Mutex<Vec<Instance<C>>>,
+    max_idle: usize,
+}
+
+impl<C> std::fmt::Debug for SharedPolicy<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("SharedPolicy")
+            .field("idle", &idle.len())
+            .field("max_idle", &self.max_idle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: EvaluationContext> SharedPolicy<C> {
+    /// Create a shared policy from a compiled module and its data, with an
+    /// evaluation context for each instance created by the `context`
+    /// function.
+    ///
+    /// The [`wasmtime::Engine`] must have async support enabled. A first
+    /// instance is created immediately, so that invalid modules are reported
+    /// early.
+    ///
+    /// # Errors
+    ///
+    /// If the data could not be serialized, or if the first instance failed
+    /// to load, see [`Runtime::new_with_evaluation_context`] and
+    /// [`Runtime::with_data`]
+    pub async fn new<V: serde::Serialize>(
+        engine: &Engine,
+        module: &Module,
+        data: &V,
+        context: impl Fn() -> C + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        let data = serde_json::to_value(data).map_err(|e| Error::Load(e.into()))?;
+        let shared = Self {
+            engine: engine.clone(),
+            module: module.clone(),
+            data,
+            context: Box::new(context),
+            idle: Mutex::new(Vec::new()),
+            max_idle: std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        };
+
+        let instance = shared.instantiate().await?;
+        shared.release(instance);
+        Ok(shared)
+    }
+
+    /// Set how many idle instances are kept around for later evaluations.
+    /// Defaults to the available parallelism of the host.
+    #[must_use]
+    pub fn with_max_idle_instances(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self.idle
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .truncate(max_idle);
+        self
+    }
+
+    /// The number of idle instances ready for evaluation
+    #[must_use]
+    pub fn idle_instances(&self) -> usize {
+        self.idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    async fn instantiate(&self) -> Result<Instance<C>, Error> {
+        let mut store = Store::new(&self.engine, ());
+        let runtime =
+            Runtime::new_with_evaluation_context(&mut store, &self.module, (self.context)())
+                .await?;
+        let policy = runtime.with_data(&mut store, &self.data).await?;
+        Ok(Instance { store, policy })
+    }
+
+    fn release(&self, instance: Instance<C>) {
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        if idle.len() < self.max_idle {
+            idle.push(instance);
+        }
+    }
+
+    /// Evaluate the policy with the given entrypoint and input, on an idle
+    /// instance or on a new one if none is available.
+    ///
+    /// Instances are only reused after successful evaluations: an instance
+    /// whose evaluation failed or was cancelled is discarded, as its state is
+    /// unknown.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a new instance failed to load, or if the policy
+    /// evaluation failed
+    pub async fn evaluate<V: serde::Serialize, R: for<'de> serde::Deserialize<'de>>(
+        &self,
+        entrypoint: &str,
+        input: &V,
+    ) -> Result<R, Error> {
+        let idle = self
+            .idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop();
+        let mut instance = match idle {
+            Some(instance) => instance,
+            None => self.instantiate().await?,
+        };
+
+        let res = instance
+            .policy
+            .evaluate(&mut instance.store, entrypoint, input)
+            .await;
+
+        // The result not having the expected shape does not affect the
+        // instance
+        if matches!(res, Ok(_) | Err(Error::Result(_))) {
+            self.release(instance);
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DefaultContext, PolicyPool};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn handles_are_send_sync() {
+        assert_send_sync::<Runtime<DefaultContext>>();
+        assert_send_sync::<Policy<DefaultContext>>();
+        assert_send_sync::<PolicyPool<DefaultContext>>();
+        assert_send_sync::<SharedPolicy<DefaultContext>>();
+    }
+}
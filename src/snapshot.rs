@@ -0,0 +1,209 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Snapshots of compiled policies, to skip compilation on cold starts
+
+use std::path::Path;
+
+use anyhow::Context;
+use wasmtime::{AsContextMut, Engine, Module};
+
+use crate::{DefaultContext, Error, Policy, Runtime};
+
+/// Magic bytes at the start of a serialized snapshot
+const MAGIC: &[u8; 8] = b"OPAWSNAP";
+
+/// Version of the snapshot format
+const VERSION: u32 = 1;
+
+/// A compiled policy module along with the data document to load it with.
+///
+/// Compiling a policy module typically takes hundreds of milliseconds, which
+/// dominates the cold-start latency of short-lived processes. A snapshot can be
+/// written to disk ahead of time, e.g. when building a container image, and
+/// restored without compiling the module again.
+///
+/// The snapshot can only be restored with a [`wasmtime::Engine`] configured
+/// the same way as the one used to compile the module, and running on the
+/// same version of `wasmtime`.
+#[derive(Debug, Clone)]
+pub struct PolicySnapshot {
+    module: Vec<u8>,
+    data: serde_json::Value,
+}
+
+impl PolicySnapshot {
+    /// Create a snapshot of a compiled module and the data document to load
+    /// it with
+    ///
+    /// # Errors
+    ///
+    /// If the module could not be serialized
+    pub fn new(module: &Module, data: serde_json::Value) -> Result<Self, Error> {
+        let module = module.serialize().map_err(Error::Load)?;
+        Ok(Self { module, data })
+    }
+
+    /// The data document stored in this snapshot
+    #[must_use]
+    pub fn data(&self) -> &serde_json::Value {
+        &self.data
+    }
+
+    /// Serialize the snapshot
+    ///
+    /// # Errors
+    ///
+    /// If the data document could not be serialized
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let data = serde_json::to_vec(&self.data).map_err(|e| Error::Other(e.into()))?;
+
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 12 + data.len() + self.module.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&data);
+        bytes.extend_from_slice(&self.module);
+        Ok(bytes)
+    }
+
+    /// Parse a snapshot serialized with [`PolicySnapshot::to_bytes`]
+    ///
+    /// # Errors
+    ///
+    /// If the bytes are not a valid snapshot
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Self::parse(bytes).map_err(Error::Load)
+    }
+
+    fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let rest = bytes
+            .strip_prefix(MAGIC.as_slice())
+            .context("not a policy snapshot")?;
+
+        let (version, rest) = rest.split_at(rest.len().min(4));
+        let version = u32::from_le_bytes(version.try_into().context("truncated snapshot")?);
+        anyhow::ensure!(version == VERSION, "unsupported snapshot version {version}");
+
+        let (length, rest) = rest.split_at(rest.len().min(8));
+        let length = u64::from_le_bytes(length.try_into().context("truncated snapshot")?);
+        let length = usize::try_from(length)
+            .ok()
+            .filter(|length| *length <= rest.len())
+            .context("truncated snapshot")?;
+
+        let (data, module) = rest.split_at(length);
+        let data = serde_json::from_slice(data).context("invalid data document in snapshot")?;
+
+        Ok(Self {
+            module: module.to_vec(),
+            data,
+        })
+    }
+
+    /// Write the snapshot to a file
+    ///
+    /// # Errors
+    ///
+    /// If the snapshot could not be serialized or written
+    pub async fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let bytes = self.to_bytes()?;
+        tokio::fs::write(path, bytes).await?;
+        Ok(())
+    }
+
+    /// Read a snapshot from a file
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be read or is not a valid snapshot
+    pub async fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let bytes = tokio::fs::read(path).await?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Get back the compiled module, without compiling it again
+    ///
+    /// # Errors
+    ///
+    /// If the module was compiled by an incompatible [`wasmtime::Engine`]
+    ///
+    /// # Safety
+    ///
+    /// The snapshot must come from a trusted source: this loads native code
+    /// which is executed as-is. See [`wasmtime::Module::deserialize`].
+    pub unsafe fn module(&self, engine: &Engine) -> Result<Module, Error> {
+        Module::deserialize(engine, &self.module).map_err(Error::Load)
+    }
+
+    /// Restore the policy, instantiating the module and loading its data
+    /// document
+    ///
+    /// To customize the [`Runtime`], use [`PolicySnapshot::module`] and
+    /// [`PolicySnapshot::data`] with a [`RuntimeBuilder`](crate::RuntimeBuilder)
+    /// instead.
+    ///
+    /// # Errors
+    ///
+    /// If the module was compiled by an incompatible [`wasmtime::Engine`], or
+    /// if it failed to instantiate the module or load the data document
+    ///
+    /// # Safety
+    ///
+    /// The snapshot must come from a trusted source: this loads native code
+    /// which is executed as-is. See [`wasmtime::Module::deserialize`].
+    pub async unsafe fn restore<T: Send>(
+        &self,
+        mut store: impl AsContextMut<Data = T>,
+    ) -> Result<Policy<DefaultContext>, Error> {
+        let engine = store.as_context().engine().clone();
+        let module = self.module(&engine)?;
+        let runtime = Runtime::new(&mut store, &module).await?;
+        runtime.with_data(&mut store, &self.data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let snapshot = PolicySnapshot {
+            module: vec![1, 2, 3],
+            data: serde_json::json!({"roles": ["admin"]}),
+        };
+
+        let bytes = snapshot.to_bytes().unwrap();
+        let parsed = PolicySnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.module, snapshot.module);
+        assert_eq!(parsed.data, snapshot.data);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(PolicySnapshot::from_bytes(b"not a snapshot").is_err());
+        assert!(PolicySnapshot::from_bytes(b"OPAWSNAP\x01\0\0\0").is_err());
+        assert!(PolicySnapshot::from_bytes(b"OPAWSNAP\x02\0\0\0\0\0\0\0\0\0\0\0").is_err());
+
+        let mut bytes = PolicySnapshot {
+            module: Vec::new(),
+            data: serde_json::json!({}),
+        }
+        .to_bytes()
+        .unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(PolicySnapshot::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright 2022 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Explanations of policy evaluations
+//!
+//! OPA's WASM target does not export hooks for the rule-level events of the
+//! Go evaluator, so a [`Trace`] only has events for the evaluated query and
+//! for the notes emitted with the `trace` builtin. There is no `Redo` event,
+//! and no event for the rules or expressions evaluated along the way.
+
+use serde::{Deserialize, Serialize};
+
+/// A single event of a [`Trace`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum TraceEvent {
+    /// The evaluation of the entrypoint started
+    Enter {
+        /// The evaluated entrypoint
+        query: String,
+    },
+
+    /// The entrypoint evaluated to a value
+    Exit {
+        /// The evaluated entrypoint
+        query: String,
+    },
+
+    /// The entrypoint was undefined
+    Fail {
+        /// The evaluated entrypoint
+        query: String,
+    },
+
+    /// A note emitted by the policy with the `trace` builtin
+    Note {
+        /// The message passed to `trace`
+        message: String,
+    },
+}
+
+/// The coarse events which happened during a policy evaluation, returned by
+/// [`Policy::evaluate_with_trace`](crate::Policy::evaluate_with_trace)
+///
+/// This is not a full explanation: only the entrypoint and the `trace` notes
+/// show up, never the rules evaluated to get to the result.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Trace {
+    events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    pub(crate) fn push(&mut self, event: TraceEvent) {
+        self.events.push(event);
+    }
+
+    /// The events, in the order they happened
+    #[must_use]
+    pub fn events(&self) -> &[TraceEvent] {
+        &self.events
+    }
+
+    /// The messages emitted with the `trace` builtin
+    pub fn notes(&self) -> impl Iterator<Item = &str> {
+        self.events.iter().filter_map(|event| match event {
+            TraceEvent::Note { message } => Some(message.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// Formats the trace one event per line. Only the query and note lines of
+/// `opa eval --explain` can show up, as there are no rule-level events.
+impl std::fmt::Display for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for event in &self.events {
+            match event {
+                TraceEvent::Enter { query } => {
+                    writeln!(f, "Enter data.{}", query.replace('/', "."))?;
+                }
+                TraceEvent::Exit { query } => writeln!(f, "Exit data.{}", query.replace('/', "."))?,
+                TraceEvent::Fail { query } => writeln!(f, "Fail data.{}", query.replace('/', "."))?,
+                TraceEvent::Note { message } => writeln!(f, "| Note {message:?}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format() {
+        let mut trace = Trace::default();
+        trace.push(TraceEvent::Enter {
+            query: "example/allow".to_owned(),
+        });
+        trace.push(TraceEvent::Note {
+            message: "user is not an admin".to_owned(),
+        });
+        trace.push(TraceEvent::Fail {
+            query: "example/allow".to_owned(),
+        });
+
+        assert_eq!(trace.notes().collect::<Vec<_>>(), ["user is not an admin"]);
+        assert_eq!(
+            trace.to_string(),
+            "Enter data.example.allow\n| Note \"user is not an admin\"\nFail data.example.allow\n"
+        );
+        assert_eq!(
+            serde_json::to_value(&trace).unwrap(),
+            serde_json::json!([
+                {"op": "Enter", "query": "example/allow"},
+                {"op": "Note", "message": "user is not an admin"},
+                {"op": "Fail", "query": "example/allow"},
+            ])
+        );
+    }
+}
@@ -18,6 +18,8 @@ use anyhow::{bail, Context, Result};
 use serde::Deserialize;
 use wasmtime::{AsContext, AsContextMut, Instance, Memory};
 
+use crate::error::UnsupportedAbi;
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde(transparent)]
 pub struct EntrypointId(pub(crate) i32);
@@ -43,15 +45,6 @@ impl Heap {
     pub const fn end(&self) -> i32 {
         self.ptr + self.len
     }
-
-    pub fn pages(&self) -> u64 {
-        let page_size = 64 * 1024;
-        let addr = self.end();
-        let page = addr / page_size;
-        if addr % page_size > 0 { page + 1 } else { page }
-            .try_into()
-            .expect("invalid heap address")
-    }
 }
 
 impl Drop for Heap {
@@ -114,7 +107,7 @@ impl OpaError {
 }
 
 /// Represents the ABI version of a WASM OPA module
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AbiVersion {
     /// Version 1.0
     V1_0,
@@ -141,14 +134,14 @@ impl AbiVersion {
     ) -> Result<Self> {
         let abi_version = instance
             .get_global(&mut store, "opa_wasm_abi_version")
-            .context("missing global opa_wasm_abi_version")?
+            .context("not an OPA policy: missing global opa_wasm_abi_version")?
             .get(&mut store)
             .i32()
             .context("opa_wasm_abi_version is not an i32")?;
 
         let abi_minor_version = instance
             .get_global(&mut store, "opa_wasm_abi_minor_version")
-            .context("missing global opa_wasm_abi_minor_version")?
+            .context("not an OPA policy: missing global opa_wasm_abi_minor_version")?
             .get(&mut store)
             .i32()
             .context("opa_wasm_abi_minor_version is not an i32")?;
@@ -163,14 +156,87 @@ impl AbiVersion {
             (1, 1) => Ok(Self::V1_1),
             (1, 2) => Ok(Self::V1_2),
             (1, n @ 2..) => Ok(Self::V1_2Plus(n)),
-            (major, minor) => bail!("unsupported ABI version {}.{}", major, minor),
+            (major, minor) => bail!(
+                "this policy was built with ABI {major}.{minor}, \
+                 but only ABI 1.x is supported"
+            ),
+        }
+    }
+
+    /// The major version number
+    #[must_use]
+    pub const fn major(self) -> i32 {
+        1
+    }
+
+    /// The minor version number
+    #[must_use]
+    pub const fn minor(self) -> i32 {
+        match self {
+            Self::V1_0 => 0,
+            Self::V1_1 => 1,
+            Self::V1_2 => 2,
+            Self::V1_2Plus(n) => n,
         }
     }
 
-    /// Check if this ABI version has support for the `eval` fastpath
+    /// Check if this ABI version has support for the given feature
     #[must_use]
-    pub(crate) const fn has_eval_fastpath(self) -> bool {
-        matches!(self, Self::V1_2 | Self::V1_2Plus(_))
+    pub const fn supports(self, feature: AbiFeature) -> bool {
+        self.minor() >= feature.min_minor_version()
+    }
+
+    /// Fail with an [`UnsupportedAbi`] error if this ABI version lacks the
+    /// given feature
+    pub(crate) fn require(self, feature: AbiFeature) -> Result<(), UnsupportedAbi> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(UnsupportedAbi {
+                version: self,
+                feature,
+            })
+        }
+    }
+}
+
+/// Features of the OPA WASM ABI which are not available in all its versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AbiFeature {
+    /// Updating parts of the data document with the `opa_value_add_path` and
+    /// `opa_value_remove_path` exports, added in ABI 1.1
+    DataPathUpdates,
+
+    /// One-shot evaluations with the `opa_eval` export, added in ABI 1.2
+    EvalFastpath,
+}
+
+impl AbiFeature {
+    /// The first minor version of ABI 1.x with this feature
+    const fn min_minor_version(self) -> i32 {
+        match self {
+            Self::DataPathUpdates => 1,
+            Self::EvalFastpath => 2,
+        }
+    }
+
+    /// The first ABI version with this feature
+    #[must_use]
+    pub const fn min_version(self) -> AbiVersion {
+        match self {
+            Self::DataPathUpdates => AbiVersion::V1_1,
+            Self::EvalFastpath => AbiVersion::V1_2,
+        }
+    }
+}
+
+impl std::fmt::Display for AbiFeature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DataPathUpdates => write!(f, "data path updates"),
+            Self::EvalFastpath => write!(f, "the eval fast path"),
+        }
     }
 }
 
@@ -184,3 +250,37 @@ impl std::fmt::Display for AbiVersion {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abi_versions() {
+        assert_eq!(AbiVersion::new(1, 0).unwrap(), AbiVersion::V1_0);
+        assert_eq!(AbiVersion::new(1, 3).unwrap(), AbiVersion::V1_2Plus(3));
+        assert_eq!(AbiVersion::new(1, 3).unwrap().minor(), 3);
+
+        let err = AbiVersion::new(2, 0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "this policy was built with ABI 2.0, but only ABI 1.x is supported"
+        );
+    }
+
+    #[test]
+    fn abi_features() {
+        assert!(!AbiVersion::V1_0.supports(AbiFeature::DataPathUpdates));
+        assert!(AbiVersion::V1_1.supports(AbiFeature::DataPathUpdates));
+        assert!(!AbiVersion::V1_1.supports(AbiFeature::EvalFastpath));
+        assert!(AbiVersion::V1_2Plus(3).supports(AbiFeature::EvalFastpath));
+
+        let err = AbiVersion::V1_0
+            .require(AbiFeature::DataPathUpdates)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "this policy was built with ABI 1.0 which lacks data path updates (requires ABI 1.1)"
+        );
+    }
+}
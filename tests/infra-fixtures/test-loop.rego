@@ -0,0 +1,14 @@
+package test
+
+import future.keywords.every
+
+values := numbers.range(1, 100000)
+
+# Checks 10^10 pairs, which never completes in a reasonable time
+test {
+	every x in values {
+		every y in values {
+			x + y > 0
+		}
+	}
+}
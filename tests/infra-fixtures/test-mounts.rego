@@ -0,0 +1,7 @@
+package test
+
+teams := data.teams
+
+hosts := data.inventory.hosts
+
+regions := data.inventory.regions
@@ -0,0 +1,7 @@
+package test
+
+greeting := "hello"
+
+printed {
+	print("greeting:", greeting)
+}
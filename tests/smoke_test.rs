@@ -16,7 +16,7 @@ use std::path::Path;
 
 use anyhow::Result as AnyResult;
 use insta::assert_yaml_snapshot;
-use opa_wasm::{read_bundle, Runtime, TestContext};
+use opa_wasm::{read_bundle, PolicyPool, PolicySnapshot, Runtime, SharedPolicy, TestContext};
 use wasmtime::{Config, Engine, Module, Store};
 
 macro_rules! integration_test {
@@ -43,15 +43,7 @@ async fn eval_policy(
     entrypoint: &str,
     input: &serde_json::Value,
 ) -> AnyResult<serde_json::Value> {
-    let module = read_bundle(bundle).await?;
-
-    // Configure the WASM runtime
-    let mut config = Config::new();
-    config.async_support(true);
-
-    let engine = Engine::new(&config)?;
-
-    let module = Module::new(&engine, module)?;
+    let (engine, module) = load_module(bundle).await?;
 
     // Create a store which will hold the module instance
     let mut store = Store::new(&engine, ());
@@ -68,6 +60,29 @@ async fn eval_policy(
     Ok(p)
 }
 
+/// Create an engine with async support, as the evaluations need
+fn async_engine() -> AnyResult<Engine> {
+    let mut config = Config::new();
+    config.async_support(true);
+    Ok(Engine::new(&config)?)
+}
+
+/// Compile the policy of a bundle with a new engine
+async fn load_module(bundle: &str) -> AnyResult<(Engine, Module)> {
+    let module = read_bundle(bundle).await?;
+    let engine = async_engine()?;
+    let module = Module::new(&engine, module)?;
+    Ok((engine, module))
+}
+
+/// Instantiate the policy of a bundle with the default evaluation context
+async fn load_runtime(bundle: &str) -> AnyResult<(Store<()>, Runtime<opa_wasm::DefaultContext>)> {
+    let (engine, module) = load_module(bundle).await?;
+    let mut store = Store::new(&engine, ());
+    let runtime = Runtime::new(&mut store, &module).await?;
+    Ok((store, runtime))
+}
+
 fn bundle(name: &str) -> String {
     Path::new("tests/infra-fixtures")
         .join(name)
@@ -109,6 +124,975 @@ async fn infra_loader_works() {
     assert_eq!(module[4..8], [0x01, 0x00, 0x00, 0x00]);
 }
 
+#[tokio::test]
+async fn snapshot_round_trip() {
+    let (_, module) = load_module("tests/infra-fixtures/test-loader.rego.tar.gz")
+        .await
+        .unwrap();
+    let input_bytes = tokio::fs::read(input("test-loader.true.json"))
+        .await
+        .unwrap();
+    let input: serde_json::Value = serde_json::from_slice(&input_bytes).unwrap();
+
+    let snapshot = PolicySnapshot::new(&module, serde_json::json!({})).unwrap();
+    let bytes = snapshot.to_bytes().unwrap();
+
+    // Restore the snapshot with a fresh engine
+    let engine = async_engine().unwrap();
+    let mut store = Store::new(&engine, ());
+    let snapshot = PolicySnapshot::from_bytes(&bytes).unwrap();
+    // SAFETY: the snapshot was created just above
+    let policy = unsafe { snapshot.restore(&mut store) }.await.unwrap();
+
+    let result: serde_json::Value = policy.evaluate(&mut store, "test", &input).await.unwrap();
+    let expected = eval_policy(
+        "tests/infra-fixtures/test-loader.rego.tar.gz",
+        "test",
+        &input,
+    )
+    .await
+    .unwrap();
+    assert_eq!(result, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shared_policy_concurrent_evaluations() {
+    let (engine, module) = load_module("tests/infra-fixtures/test-loader.rego.tar.gz")
+        .await
+        .unwrap();
+    let input_bytes = tokio::fs::read(input("test-loader.true.json"))
+        .await
+        .unwrap();
+    let input: serde_json::Value = serde_json::from_slice(&input_bytes).unwrap();
+
+    let policy = SharedPolicy::new(
+        &engine,
+        &module,
+        &serde_json::json!({}),
+        TestContext::default,
+    )
+    .await
+    .unwrap();
+    let policy = std::sync::Arc::new(policy);
+
+    let expected = eval_policy(
+        "tests/infra-fixtures/test-loader.rego.tar.gz",
+        "test",
+        &input,
+    )
+    .await
+    .unwrap();
+
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            let policy = policy.clone();
+            let input = input.clone();
+            tokio::spawn(async move {
+                let result: serde_json::Value = policy.evaluate("test", &input).await.unwrap();
+                result
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), expected);
+    }
+    assert!(policy.idle_instances() >= 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn policy_pool_evaluations() {
+    let (engine, module) = load_module("tests/infra-fixtures/test-loader.rego.tar.gz")
+        .await
+        .unwrap();
+    let input_bytes = tokio::fs::read(input("test-loader.true.json"))
+        .await
+        .unwrap();
+    let input: serde_json::Value = serde_json::from_slice(&input_bytes).unwrap();
+
+    let pool = PolicyPool::new(
+        &engine,
+        &module,
+        &serde_json::json!({}),
+        2,
+        TestContext::default,
+    )
+    .await
+    .unwrap();
+    let pool = std::sync::Arc::new(pool);
+    assert_eq!(pool.size(), 2);
+
+    let expected = eval_policy(
+        "tests/infra-fixtures/test-loader.rego.tar.gz",
+        "test",
+        &input,
+    )
+    .await
+    .unwrap();
+
+    // More evaluations than instances wait for one to be available
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            let pool = pool.clone();
+            let input = input.clone();
+            tokio::spawn(async move {
+                let result: serde_json::Value = pool.evaluate("test", &input).await.unwrap();
+                result
+            })
+        })
+        .collect();
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), expected);
+    }
+
+    // Cancelled evaluations give their instance back
+    for _ in 0..4 {
+        let evaluation = pool.evaluate::<_, serde_json::Value>("test", &input);
+        drop(tokio::time::timeout(std::time::Duration::ZERO, evaluation).await);
+    }
+    let result: serde_json::Value = pool.evaluate("test", &input).await.unwrap();
+    assert_eq!(result, expected);
+    let result: serde_json::Value = pool.evaluate("test", &input).await.unwrap();
+    assert_eq!(result, expected);
+}
+
+#[tokio::test]
+async fn evaluate_all_entrypoints() {
+    let (mut store, runtime) = load_runtime(&bundle("test-loader.rego.tar.gz"))
+        .await
+        .unwrap();
+    let entrypoints: std::collections::HashSet<String> = runtime
+        .entrypoints()
+        .into_iter()
+        .map(ToOwned::to_owned)
+        .collect();
+    let policy = runtime.without_data(&mut store).await.unwrap();
+
+    for data in ["test-loader.true", "test-loader.false"] {
+        let input: serde_json::Value = serde_json::from_slice(
+            &tokio::fs::read(input(&format!("{data}.json")))
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        let results = policy.evaluate_all(&mut store, &input).await.unwrap();
+        assert_eq!(
+            results
+                .keys()
+                .cloned()
+                .collect::<std::collections::HashSet<_>>(),
+            entrypoints
+        );
+        assert_eq!(
+            results["test"],
+            test_policy("test-loader", Some(data)).await.unwrap()
+        );
+    }
+}
+
+#[tokio::test]
+async fn typed_evaluations() {
+    #[derive(serde::Deserialize)]
+    struct Decision {
+        allow: bool,
+    }
+
+    let (mut store, runtime) = load_runtime(&bundle("test-loader.rego.tar.gz"))
+        .await
+        .unwrap();
+    let policy = runtime.without_data(&mut store).await.unwrap();
+
+    for (data, allow) in [("test-loader.true", true), ("test-loader.false", false)] {
+        let input: serde_json::Value = serde_json::from_slice(
+            &tokio::fs::read(input(&format!("{data}.json")))
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        let decision: Decision = policy
+            .evaluate_typed(&mut store, "test", &input)
+            .await
+            .unwrap();
+        assert_eq!(decision.allow, allow);
+    }
+
+    // The result is unwrapped from the result set, so it must have the
+    // expected type
+    let err = policy
+        .evaluate_bool(&mut store, "test", &serde_json::json!({}))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        opa_wasm::Error::Result(opa_wasm::ResultError::InvalidType(_))
+    ));
+}
+
+/// A context outside of the crate, only implementing the required methods
+#[derive(Default)]
+struct MinimalContext {
+    cache: std::collections::HashMap<String, serde_json::Value>,
+}
+
+impl opa_wasm::EvaluationContext for MinimalContext {
+    #[cfg(feature = "rng")]
+    type Rng = rand::rngs::StdRng;
+
+    #[cfg(feature = "rng")]
+    fn get_rng(&mut self) -> Self::Rng {
+        rand::SeedableRng::seed_from_u64(0)
+    }
+
+    #[cfg(feature = "time")]
+    fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::default()
+    }
+
+    fn evaluation_start(&mut self) {
+        self.cache.clear();
+    }
+
+    fn cache_get<K: serde::Serialize, C: serde::de::DeserializeOwned>(
+        &mut self,
+        key: &K,
+    ) -> AnyResult<Option<C>> {
+        let key = serde_json::to_string(key)?;
+        let value = self.cache.get(&key).cloned();
+        Ok(value.map(serde_json::from_value).transpose()?)
+    }
+
+    fn cache_set<K: serde::Serialize, C: serde::Serialize>(
+        &mut self,
+        key: &K,
+        content: &C,
+    ) -> AnyResult<()> {
+        let key = serde_json::to_string(key)?;
+        self.cache.insert(key, serde_json::to_value(content)?);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn minimal_context() {
+    let (engine, module) = load_module(&bundle("test-loader.rego.tar.gz"))
+        .await
+        .unwrap();
+    let mut store = Store::new(&engine, ());
+
+    let policy = Runtime::builder(MinimalContext::default())
+        .build(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let input = serde_json::json!({});
+    let result: serde_json::Value = policy.evaluate(&mut store, "test", &input).await.unwrap();
+    assert_eq!(result, test_policy("test-loader", None).await.unwrap());
+}
+
+#[tokio::test]
+async fn reloadable_policy() {
+    use opa_wasm::ReloadablePolicy;
+
+    let engine = async_engine().unwrap();
+    let loader = read_bundle(bundle("test-loader.rego.tar.gz"))
+        .await
+        .unwrap();
+    let loader = Module::new(&engine, loader).unwrap();
+    let glob = read_bundle(bundle("test-glob.rego.tar.gz")).await.unwrap();
+    let glob = Module::new(&engine, glob).unwrap();
+
+    let reloads = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let policy = ReloadablePolicy::new(
+        &engine,
+        &loader,
+        &serde_json::json!({}),
+        2,
+        TestContext::default,
+    )
+    .await
+    .unwrap()
+    .on_reload({
+        let reloads = reloads.clone();
+        move |res| reloads.lock().unwrap().push(res.is_ok())
+    });
+
+    let input = serde_json::json!({});
+    let result: serde_json::Value = policy.evaluate("test", &input).await.unwrap();
+    assert_eq!(result, test_policy("test-loader", None).await.unwrap());
+
+    // Pools checked out before a reload keep evaluating the previous version
+    let previous = policy.current();
+    policy.reload(&glob, &serde_json::json!({})).await.unwrap();
+    let result: serde_json::Value = policy.evaluate("test", &input).await.unwrap();
+    assert_eq!(result, test_policy("test-glob", None).await.unwrap());
+    let result: serde_json::Value = previous.evaluate("test", &input).await.unwrap();
+    assert_eq!(result, test_policy("test-loader", None).await.unwrap());
+
+    // A version which fails to load leaves the current one in place
+    let invalid = Module::new(&engine, b"\0asm\x01\0\0\0").unwrap();
+    assert!(policy
+        .reload(&invalid, &serde_json::json!({}))
+        .await
+        .is_err());
+    let result: serde_json::Value = policy.evaluate("test", &input).await.unwrap();
+    assert_eq!(result, test_policy("test-glob", None).await.unwrap());
+
+    assert_eq!(*reloads.lock().unwrap(), [true, false]);
+}
+
+#[cfg(feature = "bundle-client")]
+#[tokio::test(flavor = "multi_thread")]
+async fn bundle_client_reloads_policy() {
+    use opa_wasm::{BundleClient, BundleClientConfig, ReloadablePolicy};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let bundle = tokio::fs::read("tests/infra-fixtures/test-loader.rego.tar.gz")
+        .await
+        .unwrap();
+    let (engine, module) = load_module("tests/infra-fixtures/test-loader.rego.tar.gz")
+        .await
+        .unwrap();
+
+    // Serve the bundle to the client
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/bundle.tar.gz", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buffer = [0; 4096];
+            let _ = stream.read(&mut buffer).await;
+            let mut response = format!(
+                "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                bundle.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(&bundle);
+            let _ = stream.write_all(&response).await;
+        }
+    });
+
+    let (reloaded, mut on_reload) = tokio::sync::mpsc::unbounded_channel();
+    let policy = ReloadablePolicy::new(
+        &engine,
+        &module,
+        &serde_json::json!({}),
+        1,
+        TestContext::default,
+    )
+    .await
+    .unwrap()
+    .on_reload(move |res| {
+        let _ = reloaded.send(res.is_ok());
+    });
+    let policy = std::sync::Arc::new(policy);
+    let previous = policy.current();
+
+    let client = BundleClient::spawn(BundleClientConfig::new(url));
+    client.reload(policy.clone());
+    let reloaded = tokio::time::timeout(std::time::Duration::from_secs(10), on_reload.recv())
+        .await
+        .unwrap();
+    assert_eq!(reloaded, Some(true));
+    assert!(!std::sync::Arc::ptr_eq(&previous, &policy.current()));
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn blocking_evaluation() {
+    // Only use an async runtime to read the bundle
+    let (engine, module) = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(load_module("tests/infra-fixtures/test-loader.rego.tar.gz"))
+        .unwrap();
+
+    let mut store = Store::new(&engine, ());
+
+    let policy = Runtime::new_blocking(&mut store, &module)
+        .unwrap()
+        .with_data_blocking(&mut store, &serde_json::json!({}))
+        .unwrap();
+    let result: serde_json::Value = policy
+        .evaluate_blocking(&mut store, "test", &serde_json::json!({}))
+        .unwrap();
+    assert!(result.is_array());
+}
+
+#[cfg(feature = "capi")]
+#[test]
+fn capi_evaluation() {
+    use std::ffi::{CStr, CString};
+
+    use opa_wasm::capi::{opa_wasm_eval_json, opa_wasm_free, opa_wasm_load, opa_wasm_string_free};
+
+    let module = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(read_bundle("tests/infra-fixtures/test-loader.rego.tar.gz"))
+        .unwrap();
+
+    let entrypoint = CString::new("test").unwrap();
+    let input = CString::new("{}").unwrap();
+    unsafe {
+        let policy = opa_wasm_load(module.as_ptr(), module.len(), std::ptr::null());
+        assert!(!policy.is_null());
+
+        let result = opa_wasm_eval_json(policy, entrypoint.as_ptr(), input.as_ptr());
+        assert!(!result.is_null());
+        let parsed: serde_json::Value =
+            serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+        assert!(parsed.is_array());
+
+        opa_wasm_string_free(result);
+        opa_wasm_free(policy);
+    }
+}
+
+#[tokio::test]
+async fn evaluation_deadline() {
+    let module = read_bundle("tests/infra-fixtures/test-loop.rego.tar.gz")
+        .await
+        .unwrap();
+
+    let mut config = Config::new();
+    config.async_support(true);
+    config.epoch_interruption(true);
+
+    let engine = Engine::new(&config).unwrap();
+    let module = Module::new(&engine, module).unwrap();
+
+    let mut store = Store::new(&engine, ());
+    store.epoch_deadline_async_yield_and_update(1);
+    let policy = Runtime::new(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+
+    // The policy never completes, but is stopped at the deadline
+    let started = std::time::Instant::now();
+    let err = policy
+        .evaluate_with_deadline::<_, serde_json::Value, _>(
+            &mut store,
+            "test",
+            &serde_json::json!({}),
+            std::time::Duration::from_millis(100),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(err, opa_wasm::Error::Cancelled(_)));
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+    // The store traps again once it reaches its own deadline
+    store.set_epoch_deadline(1);
+    engine.increment_epoch();
+    engine.increment_epoch();
+    policy
+        .evaluate::<_, serde_json::Value, _>(&mut store, "test", &serde_json::json!({}))
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn evaluation_fuel() {
+    let mut config = Config::new();
+    config.async_support(true);
+    config.consume_fuel(true);
+    let engine = Engine::new(&config).unwrap();
+
+    // A policy which completes reports the fuel it consumed
+    let module = read_bundle(bundle("test-loader.rego.tar.gz"))
+        .await
+        .unwrap();
+    let module = Module::new(&engine, module).unwrap();
+    let mut store = Store::new(&engine, ());
+    store.set_fuel(u64::MAX).unwrap();
+    let policy = Runtime::new(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let input = serde_json::json!({});
+    let (result, consumed) = policy
+        .evaluate_with_fuel::<_, serde_json::Value, _>(&mut store, "test", &input, 10_000_000)
+        .await
+        .unwrap();
+    assert_eq!(result, test_policy("test-loader", None).await.unwrap());
+    assert!(consumed > 0 && consumed < 10_000_000);
+
+    // Evaluating again with less fuel than that runs out of it
+    let err = policy
+        .evaluate_with_fuel::<_, serde_json::Value, _>(&mut store, "test", &input, consumed / 2)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, opa_wasm::Error::OutOfFuel(ref e) if e.limit() == consumed / 2));
+
+    // A policy which never completes is stopped once its fuel is consumed
+    let module = read_bundle(bundle("test-loop.rego.tar.gz")).await.unwrap();
+    let module = Module::new(&engine, module).unwrap();
+    let mut store = Store::new(&engine, ());
+    store.set_fuel(u64::MAX).unwrap();
+    let policy = Runtime::new(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let err = policy
+        .evaluate_with_fuel::<_, serde_json::Value, _>(&mut store, "test", &input, 1_000_000)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, opa_wasm::Error::OutOfFuel(_)));
+}
+
+#[tokio::test]
+async fn large_input_memory_limit() {
+    let (engine, module) = load_module("tests/infra-fixtures/test-loader.rego.tar.gz")
+        .await
+        .unwrap();
+
+    // An input spanning multiple pages is written to memory
+    let input = serde_json::json!({ "padding": "a".repeat(256 * 1024) });
+    let mut store = Store::new(&engine, ());
+    let policy = Runtime::new(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let result: serde_json::Value = policy.evaluate(&mut store, "test", &input).await.unwrap();
+    assert!(result.is_array());
+
+    // But not if it exceeds the memory limit
+    let mut store = Store::new(&engine, ());
+    let runtime = Runtime::builder(TestContext::default())
+        .build(&mut store, &module)
+        .await
+        .unwrap();
+    let max_pages = u32::try_from(runtime.memory_pages(&store)).unwrap() + 1;
+    let mut store = Store::new(&engine, ());
+    let policy = Runtime::builder(TestContext::default())
+        .max_memory_pages(max_pages)
+        .build(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let err = policy
+        .evaluate::<_, serde_json::Value, _>(&mut store, "test", &input)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, opa_wasm::Error::MemoryLimitExceeded(_)));
+}
+
+#[tokio::test]
+async fn data_file_loading() {
+    let (engine, module) = load_module(&bundle("test-loader.rego.tar.gz"))
+        .await
+        .unwrap();
+    let mut store = Store::new(&engine, ());
+
+    let path = std::env::temp_dir().join(format!("opa-wasm-data-{}.json", std::process::id()));
+    let data =
+        serde_json::json!({"users": (0..10_000).map(|i| format!("user-{i}")).collect::<Vec<_>>()});
+    tokio::fs::write(&path, serde_json::to_vec(&data).unwrap())
+        .await
+        .unwrap();
+    let policy = Runtime::new(&mut store, &module)
+        .await
+        .unwrap()
+        .with_data_file(&mut store, &path)
+        .await
+        .unwrap();
+    let input = serde_json::json!({});
+    let res: serde_json::Value = policy.evaluate(&mut store, "test", &input).await.unwrap();
+    assert_eq!(res, test_policy("test-loader", None).await.unwrap());
+    tokio::fs::remove_file(&path).await.unwrap();
+
+    let runtime = Runtime::new(&mut store, &module).await.unwrap();
+    let truncated = runtime
+        .with_data_reader(&mut store, &b"{\"a\": 1}"[..], 64)
+        .await;
+    assert!(truncated.is_err());
+
+    let runtime = Runtime::new(&mut store, &module).await.unwrap();
+    let invalid = runtime
+        .with_data_reader(&mut store, &b"{\"a\":"[..], 6)
+        .await;
+    assert!(invalid.is_err());
+}
+
+#[tokio::test]
+async fn repeated_data_patches() {
+    let (engine, module) = load_module(&bundle("test-loader.rego.tar.gz"))
+        .await
+        .unwrap();
+
+    let mut store = Store::new(&engine, ());
+    let runtime = Runtime::builder(TestContext::default())
+        .build(&mut store, &module)
+        .await
+        .unwrap();
+    let max_pages = u32::try_from(runtime.memory_pages(&store)).unwrap() + 128;
+
+    // Each patch leaves the previous document behind, which would exceed the
+    // memory limit long before the last one if the heap was never compacted
+    let mut store = Store::new(&engine, ());
+    let mut policy = Runtime::builder(TestContext::default())
+        .max_memory_pages(max_pages)
+        .build(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    for i in 0..200 {
+        let value = serde_json::json!({ "index": i, "padding": "a".repeat(256 * 1024) });
+        policy
+            .set_data_path(&mut store, &["big"], &value)
+            .await
+            .unwrap();
+    }
+    policy
+        .apply_merge_patch(&mut store, serde_json::json!({ "big": null }))
+        .await
+        .unwrap();
+
+    let input = serde_json::json!({});
+    let res: serde_json::Value = policy.evaluate(&mut store, "test", &input).await.unwrap();
+    assert!(res.is_array());
+}
+
+#[tokio::test]
+async fn data_mounts() {
+    let (engine, module) = load_module(&bundle("test-mounts.rego.tar.gz"))
+        .await
+        .unwrap();
+    let mut store = Store::new(&engine, ());
+
+    let mut runtime = Runtime::new(&mut store, &module).await.unwrap();
+    let teams = serde_json::json!({"infra": ["alice"]});
+    runtime.add_data_at(&["teams"], &teams).unwrap();
+    runtime
+        .add_data_at(&["inventory", "hosts"], &serde_json::json!(["web-1"]))
+        .unwrap();
+
+    let err = runtime
+        .add_data_at(&["teams", "infra"], &serde_json::json!([]))
+        .unwrap_err();
+    let opa_wasm::Error::DataMountConflict(conflict) = err else {
+        panic!("unexpected error {err:?}");
+    };
+    assert_eq!(conflict.path(), "teams.infra");
+    assert_eq!(conflict.existing(), "teams");
+    assert!(runtime.add_data_at(&["inventory"], &1).is_err());
+
+    // The mounted documents sit next to what the base document holds
+    let data = serde_json::json!({"inventory": {"regions": ["eu"]}});
+    let policy = runtime.with_data(&mut store, &data).await.unwrap();
+    let input = serde_json::json!({});
+    let res: serde_json::Value = policy.evaluate(&mut store, "test", &input).await.unwrap();
+    assert_eq!(
+        res[0]["result"],
+        serde_json::json!({
+            "teams": {"infra": ["alice"]},
+            "hosts": ["web-1"],
+            "regions": ["eu"],
+        })
+    );
+
+    // Mounting over a document of the base document is a conflict
+    for (data, existing) in [
+        (serde_json::json!({"teams": {"security": ["bob"]}}), "teams"),
+        (serde_json::json!({"inventory": ["db-1"]}), "inventory"),
+    ] {
+        let mut runtime = Runtime::new(&mut store, &module).await.unwrap();
+        runtime.add_data_at(&["teams"], &teams).unwrap();
+        runtime
+            .add_data_at(&["inventory", "hosts"], &serde_json::json!(["web-1"]))
+            .unwrap();
+        let Err(opa_wasm::Error::DataMountConflict(conflict)) =
+            runtime.with_data(&mut store, &data).await
+        else {
+            panic!("expected a data mount conflict");
+        };
+        assert_eq!(conflict.existing(), existing);
+    }
+}
+
+#[tokio::test]
+async fn eval_fastpath_matches_legacy_flow() {
+    let engine = async_engine().unwrap();
+
+    for name in ["test-loader", "test-units", "test-object"] {
+        let module = read_bundle(bundle(&format!("{name}.rego.tar.gz")))
+            .await
+            .unwrap();
+        let module = Module::new(&engine, module).unwrap();
+
+        let mut results = Vec::new();
+        for fastpath in [true, false] {
+            let mut store = Store::new(&engine, ());
+            let runtime = Runtime::builder(TestContext::default())
+                .eval_fastpath(fastpath)
+                .build(&mut store, &module)
+                .await
+                .unwrap();
+            assert_eq!(runtime.uses_eval_fastpath(), fastpath);
+
+            let policy = runtime.without_data(&mut store).await.unwrap();
+            let result: serde_json::Value = policy
+                .evaluate(&mut store, "test", &serde_json::json!({}))
+                .await
+                .unwrap();
+            results.push(result);
+        }
+
+        assert_eq!(results[0], results[1], "{name}");
+    }
+}
+
+#[cfg(feature = "http-builtins")]
+#[tokio::test]
+async fn http_mock_transport() {
+    use opa_wasm::{
+        builtins::impls::http::{
+            mock::{Method, MockRequest, MockResponse, MockTransport, StatusCode},
+            HttpConfig,
+        },
+        DefaultContext,
+    };
+
+    let (engine, module) = load_module(&bundle("test-http.rego.tar.gz")).await.unwrap();
+
+    let mock = MockTransport::new().with_response(
+        MockRequest::new(Method::GET, "https://google.com"),
+        MockResponse::json(StatusCode::OK, &serde_json::json!({"ok": true})),
+    );
+    let ctx = DefaultContext::default()
+        .with_http_config(HttpConfig::default().with_mock_transport(mock.clone()));
+
+    let mut store = Store::new(&engine, ());
+    let policy = Runtime::new_with_evaluation_context(&mut store, &module, ctx)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let result: serde_json::Value = policy
+        .evaluate(&mut store, "test", &serde_json::json!({}))
+        .await
+        .unwrap();
+
+    let responses = result[0]["result"].as_object().unwrap();
+    assert!(!responses.is_empty());
+    for response in responses.values() {
+        assert_eq!(response["status_code"], 200);
+        assert_eq!(response["body"], serde_json::json!({"ok": true}));
+    }
+    assert!(!mock.received().is_empty());
+    assert!(mock
+        .received()
+        .iter()
+        .all(|request| request == "GET https://google.com"));
+}
+
+#[cfg(feature = "http-builtins")]
+#[tokio::test]
+async fn record_with_prefetch() {
+    use opa_wasm::{
+        builtins::impls::http::{
+            mock::{Method, MockRequest, MockResponse, MockTransport, StatusCode},
+            HttpConfig,
+        },
+        BuiltinRecorder, DefaultContext,
+    };
+
+    let (engine, module) = load_module(&bundle("test-http.rego.tar.gz")).await.unwrap();
+    let input = serde_json::json!({});
+
+    let record = |prefetch: bool| {
+        let engine = &engine;
+        let module = &module;
+        let input = &input;
+        async move {
+            let mock = MockTransport::new().with_response(
+                MockRequest::new(Method::GET, "https://google.com"),
+                MockResponse::json(StatusCode::OK, &serde_json::json!({"ok": true})),
+            );
+            let ctx = DefaultContext::default()
+                .with_http_config(HttpConfig::default().with_mock_transport(mock));
+            let recorder = BuiltinRecorder::new();
+            let mut store = Store::new(engine, ());
+            let policy = Runtime::builder(ctx)
+                .record(recorder.clone())
+                .build(&mut store, module)
+                .await
+                .unwrap()
+                .without_data(&mut store)
+                .await
+                .unwrap();
+            let _: serde_json::Value = if prefetch {
+                policy
+                    .evaluate_with_prefetch(&mut store, "test", input)
+                    .await
+            } else {
+                policy.evaluate(&mut store, "test", input).await
+            }
+            .unwrap();
+            recorder.take()
+        }
+    };
+
+    // The discovery pass, and its fake responses, are not recorded
+    let plain = record(false).await;
+    let prefetched = record(true).await;
+    let names = |recording: &opa_wasm::Recording| {
+        recording
+            .calls()
+            .iter()
+            .map(|call| call.name.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&prefetched), names(&plain));
+    for call in prefetched
+        .calls()
+        .iter()
+        .filter(|call| call.name == "http.send")
+    {
+        assert_eq!(call.result["status_code"], 200);
+    }
+}
+
+#[cfg(feature = "http-builtins")]
+#[tokio::test]
+async fn print_with_prefetch() {
+    use opa_wasm::{DefaultContext, PrintBuffer};
+
+    let (engine, module) = load_module(&bundle("test-print.rego.tar.gz"))
+        .await
+        .unwrap();
+
+    let buffer = PrintBuffer::new();
+    let mut store = Store::new(&engine, ());
+    let policy = Runtime::builder(DefaultContext::default())
+        .print_hook(buffer.clone())
+        .build(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let _: serde_json::Value = policy
+        .evaluate_with_prefetch(&mut store, "test", &serde_json::json!({}))
+        .await
+        .unwrap();
+
+    // The discovery pass doesn't print anything
+    assert_eq!(buffer.take(), ["greeting: hello"]);
+}
+
+#[cfg(feature = "http-builtins")]
+#[tokio::test]
+async fn context_capabilities() {
+    use opa_wasm::{
+        builtins::impls::http::{
+            mock::{Method, MockRequest, MockResponse, MockTransport, StatusCode},
+            HttpConfig,
+        },
+        BuiltinNotAllowed, Capabilities, DefaultContext, Error,
+    };
+
+    let (engine, module) = load_module(&bundle("test-http.rego.tar.gz")).await.unwrap();
+
+    let ctx = || {
+        let mock = MockTransport::new().with_response(
+            MockRequest::new(Method::GET, "https://google.com"),
+            MockResponse::new(StatusCode::OK),
+        );
+        DefaultContext::default().with_http_config(HttpConfig::default().with_mock_transport(mock))
+    };
+    let evaluate = |ctx: DefaultContext| {
+        let engine = &engine;
+        let module = &module;
+        async move {
+            let mut store = Store::new(engine, ());
+            let policy = Runtime::new_with_evaluation_context(&mut store, module, ctx)
+                .await
+                .unwrap()
+                .without_data(&mut store)
+                .await
+                .unwrap();
+            policy
+                .evaluate::<_, serde_json::Value, _>(&mut store, "test", &serde_json::json!({}))
+                .await
+        }
+    };
+
+    // The same module is allowed to call http.send with one context, but not
+    // with another
+    evaluate(ctx()).await.unwrap();
+    let err = evaluate(ctx().with_capabilities(Capabilities::default().deny_network()))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(err, Error::Builtin { ref name, ref source, .. } if name == "http.send" && source.is::<BuiltinNotAllowed>())
+    );
+}
+
+#[cfg(feature = "http-builtins")]
+#[tokio::test]
+async fn strict_builtin_errors() {
+    use opa_wasm::{
+        builtins::impls::http::{mock::MockTransport, HttpConfig},
+        DefaultContext, Error,
+    };
+
+    let (engine, module) = load_module(&bundle("test-http.rego.tar.gz")).await.unwrap();
+
+    // Every `http.send` call fails, as the transport has no mock response
+    let ctx = || {
+        DefaultContext::default()
+            .with_http_config(HttpConfig::default().with_mock_transport(MockTransport::new()))
+    };
+
+    let mut store = Store::new(&engine, ());
+    let policy = Runtime::builder(ctx())
+        .build(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let err = policy
+        .evaluate::<_, serde_json::Value, _>(&mut store, "test", &serde_json::json!({}))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::Builtin { ref name, .. } if name == "http.send"));
+    assert!(
+        matches!(err, Error::Builtin { ref location, .. } if location.as_deref() == Some("data.test"))
+    );
+
+    let mut store = Store::new(&engine, ());
+    let policy = Runtime::builder(ctx())
+        .strict_builtin_errors(false)
+        .build(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let result: serde_json::Value = policy
+        .evaluate(&mut store, "test", &serde_json::json!({}))
+        .await
+        .unwrap();
+    assert_eq!(result[0]["result"], serde_json::json!({}));
+}
+
 integration_test!(
     test_loader_false,
     "test-loader",
@@ -126,9 +1110,101 @@ integration_test!(test_urlquery, "test-urlquery");
 integration_test!(test_time, "test-time");
 integration_test!(test_object, "test-object");
 
+#[cfg(feature = "time")]
+#[tokio::test]
+async fn replay_evaluation_time() {
+    use opa_wasm::{BuiltinRecorder, BuiltinReplayer, DefaultContext, EVALUATION_TIME};
+
+    let (engine, module) = load_module(&bundle("test-loader.rego.tar.gz"))
+        .await
+        .unwrap();
+    let input = serde_json::json!({});
+
+    // The time the evaluation started is recorded…
+    let recorder = BuiltinRecorder::new();
+    let mut store = Store::new(&engine, ());
+    let policy = Runtime::builder(DefaultContext::default())
+        .record(recorder.clone())
+        .build(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let _: serde_json::Value = policy.evaluate(&mut store, "test", &input).await.unwrap();
+    let recording = recorder.take();
+    assert_eq!(recording.calls()[0].name, EVALUATION_TIME);
+
+    // …and fed back when replaying the evaluation
+    let replayer = BuiltinReplayer::new(recording);
+    let mut store = Store::new(&engine, ());
+    let policy = Runtime::builder(DefaultContext::default())
+        .replay(replayer.clone())
+        .build(&mut store, &module)
+        .await
+        .unwrap()
+        .without_data(&mut store)
+        .await
+        .unwrap();
+    let _: serde_json::Value = policy.evaluate(&mut store, "test", &input).await.unwrap();
+    assert!(replayer.is_exhausted());
+}
+
+#[cfg(feature = "time")]
+#[test]
+fn test_context_restores_time() {
+    use opa_wasm::EvaluationContext;
+
+    let mut ctx = TestContext::default();
+    let fixed = ctx.now();
+
+    // A replayed time only lasts until the next evaluation starts
+    ctx.set_now(chrono::DateTime::default());
+    assert_eq!(ctx.now(), chrono::DateTime::<chrono::Utc>::default());
+    ctx.evaluation_start();
+    assert_eq!(ctx.now(), fixed);
+}
+
 /*
 #[tokio::test]
 async fn test_uuid() {
     assert_yaml_snapshot!(test_policy("test-uuid", "test-uuid").await);
 }
 */
+
+#[cfg(feature = "input-schema")]
+#[tokio::test]
+async fn input_schema() {
+    let (mut store, mut runtime) = load_runtime(&bundle("test-loader.rego.tar.gz"))
+        .await
+        .unwrap();
+    let schema = serde_json::json!({
+        "type": "object",
+        "required": ["user"],
+        "properties": {"user": {"type": "string"}},
+    });
+    assert!(runtime.set_input_schema("missing", &schema).is_err());
+    assert!(runtime
+        .set_input_schema("test", &serde_json::json!({"type": 12}))
+        .is_err());
+    runtime.set_input_schema("test", &schema).unwrap();
+
+    let policy = runtime.without_data(&mut store).await.unwrap();
+    let input = serde_json::json!({"user": 1});
+    let err = policy
+        .evaluate::<_, serde_json::Value, _>(&mut store, "test", &input)
+        .await
+        .unwrap_err();
+    let opa_wasm::Error::InvalidInput(invalid) = err else {
+        panic!("unexpected error {err:?}");
+    };
+    assert_eq!(invalid.entrypoint(), "test");
+    assert_eq!(invalid.violations().len(), 1);
+    assert_eq!(invalid.violations()[0].instance_path(), "/user");
+
+    let input = serde_json::json!({"user": "alice"});
+    let res = policy
+        .evaluate::<_, serde_json::Value, _>(&mut store, "test", &input)
+        .await;
+    assert!(res.is_ok());
+}